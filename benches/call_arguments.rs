@@ -0,0 +1,56 @@
+use criterion::{
+    criterion_group,
+    criterion_main,
+    Criterion,
+};
+use rlox::statement::Stmt;
+
+mod common;
+
+const CALLS: usize = 100_000;
+
+// A loop making a call with four arguments, to a function or a native,
+// or passing a 1MB string and an instance through a local variable.
+fn program(call: &str) -> Vec<Stmt> {
+    let source = format!("
+        fun add(a, b, c, d) {{
+            return a + b + c + d;
+        }}
+        fun pass(s, o) {{
+            var copy = s;
+            var other = o;
+            return 1;
+        }}
+        class Box {{ }}
+        var boxed = Box();
+        var big = \"x\";
+        for (var i = 0; i < 20; i = i + 1) {{
+            big = big + big;
+        }}
+        var total = 0;
+        for (var i = 0; i < {}; i = i + 1) {{
+            total = total + {};
+        }}
+    ", CALLS, call);
+    common::program(&source)
+}
+
+fn call_arguments(c: &mut Criterion) {
+    let function = program("add(i, 1, 2, 3)");
+    let native = program("max(i, 1, 2, 3)");
+    let large = program("pass(big, boxed)");
+
+    common::report_allocations("function call", &function, CALLS);
+    common::report_allocations("native call", &native, CALLS);
+    common::report_allocations("large string and instance", &large, CALLS);
+
+    let mut group = c.benchmark_group("call arguments");
+    group.sample_size(10);
+    group.bench_function("function call", |b| b.iter(|| common::run(&function)));
+    group.bench_function("native call", |b| b.iter(|| common::run(&native)));
+    group.bench_function("large string and instance", |b| b.iter(|| common::run(&large)));
+    group.finish();
+}
+
+criterion_group!(benches, call_arguments);
+criterion_main!(benches);
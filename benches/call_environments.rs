@@ -0,0 +1,44 @@
+use criterion::{
+    criterion_group,
+    criterion_main,
+    Criterion,
+};
+use rlox::statement::Stmt;
+
+mod common;
+
+const CALLS: usize = 100_000;
+
+// A loop calling a function with a local variable, which
+// declares a closure in its body if `closure` is given.
+fn program(closure: &str) -> Vec<Stmt> {
+    let source = format!("
+        fun add(a, b) {{
+            var sum = a + b;
+            {}
+            return sum;
+        }}
+        var total = 0;
+        for (var i = 0; i < {}; i = i + 1) {{
+            total = total + add(i, 1);
+        }}
+    ", closure, CALLS);
+    common::program(&source)
+}
+
+fn call_environments(c: &mut Criterion) {
+    let plain = program("");
+    let closure = program("fun get() { return sum; }");
+
+    common::report_allocations("function without closures", &plain, CALLS);
+    common::report_allocations("function declaring a closure", &closure, CALLS);
+
+    let mut group = c.benchmark_group("call environments");
+    group.sample_size(10);
+    group.bench_function("function without closures", |b| b.iter(|| common::run(&plain)));
+    group.bench_function("function declaring a closure", |b| b.iter(|| common::run(&closure)));
+    group.finish();
+}
+
+criterion_group!(benches, call_environments);
+criterion_main!(benches);
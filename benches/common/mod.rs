@@ -0,0 +1,60 @@
+// What the benchmarks share. Each includes it with `mod common;`
+// and uses only some of it.
+#![allow(dead_code)]
+
+use rlox::{
+    compile,
+    interpreter::Interpreter,
+    resolver::Resolver,
+    statement::Stmt,
+};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+// counts allocations to show how many an operation makes
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+// The number of allocations made so far by the whole process.
+pub fn allocations() -> usize {
+    ALLOCATIONS.load(Ordering::Relaxed)
+}
+
+// Scans, parses and resolves `source`, which has to compile.
+pub fn program(source: &str) -> Vec<Stmt> {
+    match compile(source, &mut Resolver::new()) {
+        Ok(program) => program.statements,
+        Err(e) => panic!("{}", e),
+    }
+}
+
+pub fn run(stmts: &[Stmt]) {
+    let mut interp = Interpreter::new();
+    interp.execute(stmts).expect("runtime error");
+}
+
+// Runs `stmts`, which make `calls` calls, and prints the allocations per call.
+pub fn report_allocations(name: &str, stmts: &[Stmt], calls: usize) {
+    let before = allocations();
+    run(stmts);
+    let after = allocations();
+
+    println!("{}: {} allocations per call", name, (after - before) / calls);
+}
@@ -0,0 +1,41 @@
+use criterion::{
+    criterion_group,
+    criterion_main,
+    Criterion,
+};
+
+mod common;
+
+use common::{program, run};
+
+const ITERATIONS: usize = 100_000;
+
+// A closure declared, and called once, in every iteration of a loop.
+fn function_declarations(c: &mut Criterion) {
+    let declaring = program(&format!("
+        var total = 0;
+        for (var i = 0; i < {}; i = i + 1) {{
+            fun step(n) {{
+                var result = n;
+                if (i - 2 * floor(i / 2) == 0) {{
+                    result = result + i;
+                }} else {{
+                    result = result - 1;
+                }}
+                while (result > 1000000) {{
+                    result = result - 1000000;
+                }}
+                return result;
+            }}
+            total = step(total);
+        }}
+    ", ITERATIONS));
+
+    let mut group = c.benchmark_group("function declarations");
+    group.sample_size(10);
+    group.bench_function("declaring a closure in a loop", |b| b.iter(|| run(&declaring)));
+    group.finish();
+}
+
+criterion_group!(benches, function_declarations);
+criterion_main!(benches);
@@ -0,0 +1,40 @@
+use criterion::{
+    criterion_group,
+    criterion_main,
+    Criterion,
+};
+
+mod common;
+
+use common::{program, run};
+
+const ITERATIONS: usize = 1_000_000;
+
+// A top-level loop, where every variable is a global, and a loop
+// that calls a global function, where calls take most of the time.
+fn global_lookups(c: &mut Criterion) {
+    let counting = program(&format!("
+        var i = 0;
+        var total = 0;
+        while (i < {}) {{
+            total = total + i;
+            i = i + 1;
+        }}
+    ", ITERATIONS));
+    let calls = program(&format!("
+        fun inc(n) {{ return n + 1; }}
+        var total = 0;
+        for (var i = 0; i < {}; i = i + 1) {{
+            total = inc(total);
+        }}
+    ", ITERATIONS));
+
+    let mut group = c.benchmark_group("global lookups");
+    group.sample_size(10);
+    group.bench_function("top-level loop", |b| b.iter(|| run(&counting)));
+    group.bench_function("calling a global function", |b| b.iter(|| run(&calls)));
+    group.finish();
+}
+
+criterion_group!(benches, global_lookups);
+criterion_main!(benches);
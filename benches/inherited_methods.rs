@@ -0,0 +1,39 @@
+use criterion::{
+    criterion_group,
+    criterion_main,
+    Criterion,
+};
+
+mod common;
+
+use common::{program, run};
+
+const CALLS: usize = 100_000;
+
+// A method declared at the root of a five classes deep
+// hierarchy, called on an instance of the deepest one.
+fn inherited_methods(c: &mut Criterion) {
+    let calls = program(&format!("
+        class A {{
+            inc(n) {{ return n + 1; }}
+        }}
+        class B < A {{}}
+        class C < B {{}}
+        class D < C {{}}
+        class E < D {{}}
+
+        var e = E();
+        var total = 0;
+        for (var i = 0; i < {}; i = i + 1) {{
+            total = e.inc(total);
+        }}
+    ", CALLS));
+
+    let mut group = c.benchmark_group("inherited methods");
+    group.sample_size(10);
+    group.bench_function("calling a method inherited from 5 levels up", |b| b.iter(|| run(&calls)));
+    group.finish();
+}
+
+criterion_group!(benches, inherited_methods);
+criterion_main!(benches);
@@ -0,0 +1,97 @@
+use criterion::{
+    black_box,
+    criterion_group,
+    criterion_main,
+    Criterion,
+};
+use rlox::{
+    interpreter::Interpreter,
+    statement::Stmt,
+    Class,
+    Gc,
+    Instance,
+    RefCell,
+    RuntimeValue,
+};
+use std::collections::HashMap;
+
+mod common;
+
+// Instances typically have a handful of fields.
+const FIELDS: [&str; 4] = ["x", "y", "width", "height"];
+
+// Reads every field, then overwrites every field, comparing instance
+// fields with a HashMap of the same fields behind the same kind of pointer.
+fn instance_fields(c: &mut Criterion) {
+    let map: Gc<RefCell<HashMap<String, RuntimeValue>>> = Gc::new(RefCell::new(HashMap::new()));
+    let class = Gc::new(RefCell::new(Class::new("Rect", None, HashMap::new())));
+    let instance = Gc::new(RefCell::new(Instance::new(&class)));
+    for (i, name) in FIELDS.iter().enumerate() {
+        map.borrow_mut().insert(name.to_string(), RuntimeValue::Number(i as f64));
+        instance.borrow_mut().set(name, &RuntimeValue::Number(i as f64));
+    }
+
+    c.bench_function("HashMap fields", |b| b.iter(|| {
+        let mut sum = 0.0;
+        for name in FIELDS.iter() {
+            if let Some(RuntimeValue::Number(n)) = map.borrow().get(*name).cloned() {
+                sum += n;
+            }
+        }
+        for name in FIELDS.iter() {
+            if let Some(field) = map.borrow_mut().get_mut(*name) {
+                *field = RuntimeValue::Number(sum);
+            }
+        }
+        black_box(sum)
+    }));
+
+    c.bench_function("instance fields", |b| b.iter(|| {
+        let mut sum = 0.0;
+        for name in FIELDS.iter() {
+            if let Some(RuntimeValue::Number(n)) = instance.borrow().get(name, &instance) {
+                sum += n;
+            }
+        }
+        for name in FIELDS.iter() {
+            instance.borrow_mut().set(name, &RuntimeValue::Number(sum));
+        }
+        black_box(sum)
+    }));
+}
+
+const INSTANCES: usize = 1_000_000;
+
+// Creates instances with three fields and reads them back.
+fn program() -> Vec<Stmt> {
+    let source = format!("
+        class Point {{
+            init(x, y, z) {{
+                this.x = x;
+                this.y = y;
+                this.z = z;
+            }}
+        }}
+        var sum = 0;
+        for (var i = 0; i < {}; i = i + 1) {{
+            var p = Point(i, 1, 2);
+            sum = sum + p.x + p.y + p.z;
+        }}
+    ", INSTANCES);
+    common::program(&source)
+}
+
+fn lox_instances(c: &mut Criterion) {
+    let stmts = program();
+
+    let mut group = c.benchmark_group("lox instances");
+    group.sample_size(10);
+    group.bench_function("three fields", |b| b.iter(|| {
+        let mut interp = Interpreter::new();
+        interp.execute(&stmts).expect("runtime error");
+    }));
+    group.finish();
+}
+
+criterion_group!(benches, instance_fields, lox_instances);
+criterion_main!(benches);
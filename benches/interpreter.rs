@@ -0,0 +1,89 @@
+use criterion::{
+    criterion_group,
+    criterion_main,
+    Criterion,
+};
+use rlox::interpreter::Interpreter;
+
+mod common;
+
+const FIB: &str = "
+    fun fib(n) {
+        if (n < 2) return n;
+        return fib(n - 1) + fib(n - 2);
+    }
+    print fib(20);
+";
+
+const ARITHMETIC: &str = "
+    var i = 0;
+    var total = 0;
+    while (i < 100000) {
+        total = total + i * 2 - i / 2;
+        i = i + 1;
+    }
+    print total;
+";
+
+const CONCATENATION: &str = "
+    var s = \"\";
+    for (var i = 0; i < 10000; i = i + 1) {
+        s = s + \"x\";
+    }
+    print len(s);
+";
+
+const CLASSES: &str = "
+    class Point {
+        init(x, y) {
+            this.x = x;
+            this.y = y;
+        }
+        add(other) {
+            return Point(this.x + other.x, this.y + other.y);
+        }
+    }
+    var sum = Point(0, 0);
+    for (var i = 0; i < 10000; i = i + 1) {
+        sum = sum.add(Point(i, 1));
+    }
+    print sum.x;
+";
+
+const CLOSURES: &str = "
+    fun counter(start) {
+        var count = start;
+        fun next() {
+            count = count + 1;
+            return count;
+        }
+        return next;
+    }
+    var total = 0;
+    for (var i = 0; i < 10000; i = i + 1) {
+        var next = counter(i);
+        total = total + next();
+    }
+    print total;
+";
+
+// Scans, parses, resolves and runs a whole program, printing to nowhere.
+fn run(source: &str) {
+    let stmts = common::program(source);
+    let mut interp = Interpreter::new().with_output(std::io::sink());
+    interp.execute(&stmts).expect("runtime error");
+}
+
+fn interpreter(c: &mut Criterion) {
+    let mut group = c.benchmark_group("interpreter");
+    group.sample_size(10);
+    group.bench_function("recursive fib(20)", |b| b.iter(|| run(FIB)));
+    group.bench_function("arithmetic loop", |b| b.iter(|| run(ARITHMETIC)));
+    group.bench_function("string concatenation", |b| b.iter(|| run(CONCATENATION)));
+    group.bench_function("instances and method calls", |b| b.iter(|| run(CLASSES)));
+    group.bench_function("closure creation", |b| b.iter(|| run(CLOSURES)));
+    group.finish();
+}
+
+criterion_group!(benches, interpreter);
+criterion_main!(benches);
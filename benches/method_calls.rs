@@ -0,0 +1,53 @@
+use criterion::{
+    criterion_group,
+    criterion_main,
+    Criterion,
+};
+use rlox::statement::Stmt;
+
+mod common;
+
+const CALLS: usize = 10_000;
+
+fn program(call: &str) -> Vec<Stmt> {
+    let source = format!("
+        class Counter {{
+            init() {{ this.count = 0; }}
+            add(n) {{ this.count = this.count + n; }}
+        }}
+        class Other < Counter {{
+            init() {{ this.count = 0; }}
+        }}
+        var c = Counter();
+        var o = Other();
+        var add = c.add;
+        fun add_to(counter, n) {{ counter.add(n); }}
+        for (var i = 0; i < {}; i = i + 1) {{
+            {};
+        }}
+    ", CALLS, call);
+    common::program(&source)
+}
+
+fn method_calls(c: &mut Criterion) {
+    let direct = program("c.add(i)");
+    let bound = program("add(i)");
+    // the method is read, and bound, before it is called
+    let read = program("(c.add)(i)");
+    // the call site in 'add_to' finds its method in another class every time,
+    // so it looks the method up instead of using the one it found last
+    let alternating = program("add_to(c, i); add_to(o, i)");
+
+    common::report_allocations("direct method call", &direct, CALLS);
+    common::report_allocations("bound method call", &bound, CALLS);
+    common::report_allocations("method read and called", &read, CALLS);
+    common::report_allocations("two calls alternating classes", &alternating, CALLS);
+
+    c.bench_function("direct method call", |b| b.iter(|| common::run(&direct)));
+    c.bench_function("bound method call", |b| b.iter(|| common::run(&bound)));
+    c.bench_function("method read and called", |b| b.iter(|| common::run(&read)));
+    c.bench_function("two calls alternating classes", |b| b.iter(|| common::run(&alternating)));
+}
+
+criterion_group!(benches, method_calls);
+criterion_main!(benches);
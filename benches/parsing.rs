@@ -0,0 +1,55 @@
+use criterion::{
+    criterion_group,
+    criterion_main,
+    Criterion,
+};
+use rlox::{
+    parser::Parser,
+    resolver::Resolver,
+    scanner::scan,
+};
+
+mod common;
+
+const FUNCTIONS: usize = 5_000;
+
+// A large generated source, parsed and resolved, and the
+// resulting tree copied, which copies every token it holds.
+fn parsing(c: &mut Criterion) {
+    let mut source = String::new();
+    for i in 0..FUNCTIONS {
+        source.push_str(&format!("
+            fun function_{i}(first, second) {{
+                var total = first + second * {i};
+                if (total > 100 and first != nil) {{
+                    total = total - function_{i}(second, first);
+                }}
+                return total;
+            }}
+        ", i = i));
+    }
+    let tokens = scan(&source).expect("scan failed");
+    let before = common::allocations();
+    let mut stmts = Parser::new(&tokens).parse().expect("parse failed");
+    let parsed = common::allocations();
+    let result = Resolver::new().resolve(&mut stmts);
+    assert!(result.errors.is_none(), "resolution failed");
+    let copy_start = common::allocations();
+    drop(stmts.clone());
+    let copied = common::allocations();
+
+    println!("parsing: {} allocations per function", (parsed - before) / FUNCTIONS);
+    println!("copying: {} allocations per function", (copied - copy_start) / FUNCTIONS);
+
+    let mut group = c.benchmark_group("parsing");
+    group.sample_size(10);
+    group.bench_function("parsing a large source", |b| b.iter(|| {
+        let mut stmts = Parser::new(&tokens).parse().expect("parse failed");
+        Resolver::new().resolve(&mut stmts)
+    }));
+    group.bench_function("copying a large tree", |b| b.iter(|| stmts.clone()));
+    group.finish();
+}
+
+criterion_group!(benches, parsing);
+criterion_main!(benches);
@@ -0,0 +1,42 @@
+use criterion::{
+    criterion_group,
+    criterion_main,
+    Criterion,
+};
+use rlox::scanner::scan;
+
+mod common;
+
+const FUNCTIONS: usize = 1_000;
+const LINES_PER_FUNCTION: usize = 7;
+
+// A generated source of identifiers, keywords, numbers and strings.
+fn scanning(c: &mut Criterion) {
+    let mut source = String::new();
+    for i in 0..FUNCTIONS {
+        source.push_str(&format!("
+            fun function_{i}(first, second) {{
+                var total = first + second * {i}.25;
+                if (total > 100 and first != nil) {{
+                    print \"total of function_{i}: \" + tostring(total);
+                }}
+                return total;
+            }}", i = i));
+    }
+    let lines = FUNCTIONS * LINES_PER_FUNCTION;
+
+    let before = common::allocations();
+    let tokens = scan(&source).expect("scan failed");
+    let scanned = common::allocations();
+    drop(tokens);
+
+    println!("scanning: {} allocations per line", (scanned - before) / lines);
+
+    let mut group = c.benchmark_group("scanning");
+    group.sample_size(10);
+    group.bench_function("scanning a 7000 line source", |b| b.iter(|| scan(&source)));
+    group.finish();
+}
+
+criterion_group!(benches, scanning);
+criterion_main!(benches);
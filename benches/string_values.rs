@@ -0,0 +1,67 @@
+use criterion::{
+    criterion_group,
+    criterion_main,
+    Criterion,
+};
+
+mod common;
+
+use common::{program, run};
+
+const DEPTH: usize = 200;
+const READS: usize = 1_000;
+const PIECES: usize = 100_000;
+
+// A 1MB string passed down a chain of calls, at the bottom
+// of which it is read into a variable over and over.
+fn string_values(c: &mut Criterion) {
+    let passing = program(&format!("
+        var big = \"x\";
+        for (var i = 0; i < 20; i = i + 1) {{
+            big = big + big;
+        }}
+
+        fun pass(s, depth) {{
+            if (depth == 0) {{
+                var reads = 0;
+                for (var i = 0; i < {}; i = i + 1) {{
+                    var copy = s;
+                    reads = reads + 1;
+                }}
+                return reads;
+            }}
+
+            var reads = pass(s, depth - 1);
+            return reads;
+        }}
+
+        pass(big, {});
+    ", READS, DEPTH));
+
+    let mut group = c.benchmark_group("string values");
+    group.sample_size(10);
+    group.bench_function("passing and reading a 1MB string", |b| b.iter(|| run(&passing)));
+    group.finish();
+}
+
+// A 1MB string built from 10 byte pieces, which takes time linear in the
+// number of pieces only if each one is appended without copying the string.
+fn string_concatenation(c: &mut Criterion) {
+    let building = |pieces: usize| program(&format!("
+        var s = \"\";
+        for (var i = 0; i < {}; i = i + 1) {{
+            s = s + \"0123456789\";
+        }}
+    ", pieces));
+    let tenth = building(PIECES / 10);
+    let whole = building(PIECES);
+
+    let mut group = c.benchmark_group("string concatenation");
+    group.sample_size(10);
+    group.bench_function("building a 100KB string from 10k pieces", |b| b.iter(|| run(&tenth)));
+    group.bench_function("building a 1MB string from 100k pieces", |b| b.iter(|| run(&whole)));
+    group.finish();
+}
+
+criterion_group!(benches, string_values, string_concatenation);
+criterion_main!(benches);
@@ -0,0 +1,253 @@
+use crate::{
+    expression::{self, Expr},
+    statement::{self, Stmt},
+    scanner::Token,
+};
+
+// Renders statements one per line, with the statements they contain indented
+// below them, and expressions as s-expressions, e.g. `(+ a (call f 1))`.
+// With `hops` set, the variables, assignments, `this` and `super` the resolver
+// found in an enclosing scope show how many scopes up, e.g. `count@1`.
+// Globals never show them.
+pub fn render_program(statements: &[Stmt], hops: bool) -> String {
+    let mut printer = AstPrinter { hops, depth: 0, out: String::new() };
+    printer.statements(statements);
+    printer.out
+}
+
+pub fn render_expr(expr: &Expr, hops: bool) -> String {
+    let mut printer = AstPrinter { hops, depth: 0, out: String::new() };
+    expr.accept(&mut printer)
+}
+
+struct AstPrinter {
+    hops: bool,
+    depth: usize,
+    out: String,
+}
+
+impl AstPrinter {
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.depth {
+            self.out.push_str("  ");
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn statements(&mut self, statements: &[Stmt]) {
+        for s in statements {
+            s.accept(self);
+        }
+    }
+
+    // `header` followed by `body` one level deeper.
+    fn nested(&mut self, header: &str, body: &[Stmt]) {
+        self.line(header);
+        self.depth += 1;
+        self.statements(body);
+        self.depth -= 1;
+    }
+
+    fn expr(&mut self, expr: &Expr) -> String {
+        expr.accept(self)
+    }
+
+    fn name(&self, name: &Token, hops: Option<usize>) -> String {
+        match hops {
+            Some(hops) if self.hops => format!("{}@{}", name.lexeme, hops),
+            _ => name.lexeme.clone(),
+        }
+    }
+
+    fn list(&mut self, head: &str, exprs: &[&Expr]) -> String {
+        let mut text = format!("({}", head);
+        for e in exprs {
+            text.push(' ');
+            text.push_str(&self.expr(e));
+        }
+        text.push(')');
+        text
+    }
+
+    fn function(&mut self, s: &statement::Function) {
+        let params: Vec<&str> = s.params.iter().map(|p| p.lexeme.as_str()).collect();
+        let header = format!("fun {}({})", s.name.lexeme, params.join(", "));
+        self.nested(&header, &s.body);
+    }
+}
+
+impl expression::Visitor<String> for AstPrinter {
+    fn visit_literal(&mut self, e: &expression::Literal) -> String {
+        match e {
+            expression::Literal::Number(n) => n.to_string(),
+            expression::Literal::String(s) => format!("{:?}", s),
+            expression::Literal::True => "true".to_owned(),
+            expression::Literal::False => "false".to_owned(),
+            expression::Literal::Nil => "nil".to_owned(),
+        }
+    }
+
+    fn visit_unary(&mut self, e: &expression::Unary) -> String {
+        self.list(&e.operator.lexeme, &[&e.right])
+    }
+
+    fn visit_binary(&mut self, e: &expression::Binary) -> String {
+        self.list(&e.operator.lexeme, &[&e.left, &e.right])
+    }
+
+    fn visit_logical(&mut self, e: &expression::Logical) -> String {
+        self.list(&e.operator.lexeme, &[&e.left, &e.right])
+    }
+
+    fn visit_grouping(&mut self, e: &expression::Grouping) -> String {
+        self.list("group", &[&e.0])
+    }
+
+    fn visit_variable(&mut self, e: &expression::Variable) -> String {
+        self.name(&e.name, e.hops)
+    }
+
+    fn visit_assignment(&mut self, e: &expression::Assignment) -> String {
+        let name = self.name(&e.name, e.hops);
+        format!("(= {} {})", name, self.expr(&e.value))
+    }
+
+    fn visit_call(&mut self, e: &expression::Call) -> String {
+        let mut exprs = vec![&*e.callee];
+        exprs.extend(e.args.iter());
+        self.list("call", &exprs)
+    }
+
+    fn visit_get(&mut self, e: &expression::Get) -> String {
+        format!("(. {} {})", self.expr(&e.object), e.name.lexeme)
+    }
+
+    fn visit_set(&mut self, e: &expression::Set) -> String {
+        let object = self.expr(&e.object);
+        format!("(= (. {} {}) {})", object, e.name.lexeme, self.expr(&e.value))
+    }
+
+    fn visit_this(&mut self, e: &expression::This) -> String {
+        self.name(&e.keyword, e.hops)
+    }
+
+    fn visit_super(&mut self, e: &expression::Super) -> String {
+        format!("(. {} {})", self.name(&e.keyword, e.hops_to_super), e.method.lexeme)
+    }
+
+    fn visit_type_test(&mut self, e: &expression::TypeTest) -> String {
+        self.list("is", &[&e.value, &e.type_expr])
+    }
+
+    fn visit_delete(&mut self, e: &expression::Delete) -> String {
+        format!("(delete (. {} {}))", self.expr(&e.object), e.name.lexeme)
+    }
+
+    fn visit_index(&mut self, e: &expression::Index) -> String {
+        self.list("index", &[&e.object, &e.index])
+    }
+
+    fn visit_set_index(&mut self, e: &expression::SetIndex) -> String {
+        let index = self.list("index", &[&e.object, &e.index]);
+        format!("(= {} {})", index, self.expr(&e.value))
+    }
+}
+
+impl statement::Visitor<()> for AstPrinter {
+    fn visit_expr(&mut self, s: &statement::Expression) {
+        let text = format!("expression {}", self.expr(&s.expr));
+        self.line(&text);
+    }
+
+    fn visit_print(&mut self, s: &statement::Print) {
+        let text = format!("print {}", self.expr(&s.expr));
+        self.line(&text);
+    }
+
+    fn visit_variable(&mut self, s: &statement::Variable) {
+        let text = match &s.initializer {
+            Some(init) => format!("var {} {}", s.name.lexeme, self.expr(init)),
+            None => format!("var {}", s.name.lexeme),
+        };
+        self.line(&text);
+    }
+
+    fn visit_block(&mut self, s: &statement::Block) {
+        self.nested("block", &s.statements);
+    }
+
+    fn visit_if(&mut self, s: &statement::If) {
+        let header = format!("if {}", self.expr(&s.cond));
+        self.nested(&header, std::slice::from_ref(&*s.then_branch));
+        if let Some(else_branch) = &s.else_branch {
+            self.nested("else", std::slice::from_ref(&**else_branch));
+        }
+    }
+
+    fn visit_while(&mut self, s: &statement::While) {
+        let header = format!("while {}", self.expr(&s.cond));
+        self.nested(&header, std::slice::from_ref(&*s.body));
+    }
+
+    fn visit_for_in(&mut self, s: &statement::ForIn) {
+        let header = format!("for-in {} {}", s.name.lexeme, self.expr(&s.iterable));
+        self.nested(&header, std::slice::from_ref(&*s.body));
+    }
+
+    fn visit_break(&mut self, _s: &statement::Break) {
+        self.line("break");
+    }
+
+    fn visit_return(&mut self, s: &statement::Return) {
+        let text = match &s.value {
+            Some(value) => format!("return {}", self.expr(value)),
+            None => "return".to_owned(),
+        };
+        self.line(&text);
+    }
+
+    fn visit_function(&mut self, s: &statement::Function) {
+        self.function(s);
+    }
+
+    fn visit_class(&mut self, s: &statement::Class) {
+        let header = match &s.super_class {
+            Some(super_class) => format!("class {} < {}", s.name.lexeme, self.name(&super_class.name, super_class.hops)),
+            None => format!("class {}", s.name.lexeme),
+        };
+        self.line(&header);
+        self.depth += 1;
+        for method in &s.methods {
+            self.function(method);
+        }
+        self.depth -= 1;
+    }
+
+    fn visit_defer(&mut self, s: &statement::Defer) {
+        self.nested("defer", std::slice::from_ref(&*s.body));
+    }
+
+    fn visit_throw(&mut self, s: &statement::Throw) {
+        let text = format!("throw {}", self.expr(&s.value));
+        self.line(&text);
+    }
+
+    fn visit_try(&mut self, s: &statement::Try) {
+        self.nested("try", &s.body);
+        if let Some(catch) = &s.catch {
+            self.nested(&format!("catch {}", catch.name.lexeme), &catch.body);
+        }
+        if let Some(finally) = &s.finally {
+            self.nested("finally", finally);
+        }
+    }
+
+    fn visit_assert(&mut self, s: &statement::Assert) {
+        let text = match &s.message {
+            Some(message) => format!("assert {} {}", self.expr(&s.condition), self.expr(message)),
+            None => format!("assert {}", self.expr(&s.condition)),
+        };
+        self.line(&text);
+    }
+}
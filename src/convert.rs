@@ -0,0 +1,234 @@
+use crate::{
+    RuntimeValue,
+    Class,
+    Instance,
+    Map,
+    gc_stats,
+    Gc,
+    Rc,
+    RefCell,
+};
+use std::fmt::Display;
+
+// A value that does not have the type it was converted to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConversionError {
+    pub expected: &'static str,
+    pub found: &'static str,
+}
+
+impl Display for ConversionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "expected {}, found {}", self.expected, self.found)
+    }
+}
+
+impl std::error::Error for ConversionError { }
+
+impl RuntimeValue {
+    // The name 'is' tests the value against.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            RuntimeValue::Nil => "Nil",
+            RuntimeValue::Bool(_) => "Bool",
+            RuntimeValue::Number(_) => "Number",
+            RuntimeValue::String(_) => "String",
+            RuntimeValue::Callable(_) => "Function",
+            RuntimeValue::Class(_) => "Class",
+            RuntimeValue::Instance(_) => "Instance",
+            RuntimeValue::Array(_) => "Array",
+            RuntimeValue::Map(_) => "Map",
+            RuntimeValue::Range(_) => "Range",
+        }
+    }
+
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            RuntimeValue::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            RuntimeValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_instance(&self) -> Option<&Gc<RefCell<Instance>>> {
+        match self {
+            RuntimeValue::Instance(instance) => Some(instance),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Gc<RefCell<Vec<RuntimeValue>>>> {
+        match self {
+            RuntimeValue::Array(array) => Some(array),
+            _ => None,
+        }
+    }
+
+    fn conversion_error(&self, expected: &'static str) -> ConversionError {
+        ConversionError {
+            expected,
+            found: self.type_name(),
+        }
+    }
+}
+
+impl From<f64> for RuntimeValue {
+    fn from(n: f64) -> Self {
+        RuntimeValue::Number(n)
+    }
+}
+
+impl From<bool> for RuntimeValue {
+    fn from(b: bool) -> Self {
+        RuntimeValue::Bool(b)
+    }
+}
+
+impl From<String> for RuntimeValue {
+    fn from(s: String) -> Self {
+        RuntimeValue::String(s.into())
+    }
+}
+
+impl From<Rc<String>> for RuntimeValue {
+    fn from(s: Rc<String>) -> Self {
+        RuntimeValue::String(s)
+    }
+}
+
+impl From<&str> for RuntimeValue {
+    fn from(s: &str) -> Self {
+        RuntimeValue::String(Rc::new(s.to_owned()))
+    }
+}
+
+impl From<()> for RuntimeValue {
+    fn from(_: ()) -> Self {
+        RuntimeValue::Nil
+    }
+}
+
+impl From<Vec<RuntimeValue>> for RuntimeValue {
+    fn from(elements: Vec<RuntimeValue>) -> Self {
+        gc_stats::allocated();
+        RuntimeValue::Array(Gc::new(RefCell::new(elements)))
+    }
+}
+
+impl From<Map> for RuntimeValue {
+    fn from(map: Map) -> Self {
+        gc_stats::allocated();
+        RuntimeValue::Map(Gc::new(RefCell::new(map)))
+    }
+}
+
+impl TryFrom<RuntimeValue> for f64 {
+    type Error = ConversionError;
+
+    fn try_from(value: RuntimeValue) -> Result<Self, Self::Error> {
+        match value {
+            RuntimeValue::Number(n) => Ok(n),
+            _ => Err(value.conversion_error("Number")),
+        }
+    }
+}
+
+impl TryFrom<RuntimeValue> for bool {
+    type Error = ConversionError;
+
+    fn try_from(value: RuntimeValue) -> Result<Self, Self::Error> {
+        match value {
+            RuntimeValue::Bool(b) => Ok(b),
+            _ => Err(value.conversion_error("Bool")),
+        }
+    }
+}
+
+impl TryFrom<RuntimeValue> for String {
+    type Error = ConversionError;
+
+    fn try_from(value: RuntimeValue) -> Result<Self, Self::Error> {
+        match value {
+            RuntimeValue::String(s) => Ok(s.to_string()),
+            _ => Err(value.conversion_error("String")),
+        }
+    }
+}
+
+// the string itself, not a copy of it
+impl TryFrom<RuntimeValue> for Rc<String> {
+    type Error = ConversionError;
+
+    fn try_from(value: RuntimeValue) -> Result<Self, Self::Error> {
+        match value {
+            RuntimeValue::String(s) => Ok(s),
+            _ => Err(value.conversion_error("String")),
+        }
+    }
+}
+
+// the array itself, not a copy of it
+impl TryFrom<RuntimeValue> for Gc<RefCell<Vec<RuntimeValue>>> {
+    type Error = ConversionError;
+
+    fn try_from(value: RuntimeValue) -> Result<Self, Self::Error> {
+        match value {
+            RuntimeValue::Array(array) => Ok(array),
+            _ => Err(value.conversion_error("Array")),
+        }
+    }
+}
+
+impl TryFrom<RuntimeValue> for Gc<RefCell<Map>> {
+    type Error = ConversionError;
+
+    fn try_from(value: RuntimeValue) -> Result<Self, Self::Error> {
+        match value {
+            RuntimeValue::Map(map) => Ok(map),
+            _ => Err(value.conversion_error("Map")),
+        }
+    }
+}
+
+impl TryFrom<RuntimeValue> for Gc<RefCell<Instance>> {
+    type Error = ConversionError;
+
+    fn try_from(value: RuntimeValue) -> Result<Self, Self::Error> {
+        match value {
+            RuntimeValue::Instance(instance) => Ok(instance),
+            _ => Err(value.conversion_error("Instance")),
+        }
+    }
+}
+
+impl TryFrom<RuntimeValue> for Gc<RefCell<Class>> {
+    type Error = ConversionError;
+
+    fn try_from(value: RuntimeValue) -> Result<Self, Self::Error> {
+        match value {
+            RuntimeValue::Class(class) => Ok(class),
+            _ => Err(value.conversion_error("Class")),
+        }
+    }
+}
+
+// nil converts to None
+impl<T> TryFrom<RuntimeValue> for Option<T>
+where
+    T: TryFrom<RuntimeValue, Error = ConversionError>,
+{
+    type Error = ConversionError;
+
+    fn try_from(value: RuntimeValue) -> Result<Self, Self::Error> {
+        match value {
+            RuntimeValue::Nil => Ok(None),
+            _ => T::try_from(value).map(Some),
+        }
+    }
+}
@@ -1,7 +1,9 @@
 use std::fmt::Debug;
+use rlox::LoxError;
 
 pub enum Error {
     IO(std::io::Error),
+    Lox(LoxError),
 }
 
 impl From<std::io::Error> for Error {
@@ -10,10 +12,17 @@ impl From<std::io::Error> for Error {
     }
 }
 
+impl From<LoxError> for Error {
+    fn from(e: LoxError) -> Self {
+        Error::Lox(e)
+    }
+}
+
 impl Debug for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::IO(e) => write!(f, "{}", e),
+            Self::Lox(e) => write!(f, "{}", e),
         }
     }
 }
@@ -1,11 +1,13 @@
-use std::boxed::Box;
 use crate::scanner::Token;
-use crate::RuntimeResult;
+use crate::MethodCache;
+use crate::interpreter::env::Slot;
+use crate::{Rc, Cell};
 
 #[derive(Clone)]
 pub enum Literal {
     Number(f64),
-    String(String),
+    // shared with the strings it evaluates to
+    String(Rc<String>),
     True,
     False,
     Nil,
@@ -13,67 +15,74 @@ pub enum Literal {
 
 #[derive(Clone)]
 pub struct Unary {
-    pub operator: Token,
-    pub right: Box<dyn Expr>,
+    pub operator: Rc<Token>,
+    pub right: Box<Expr>,
 }
 
 #[derive(Clone)]
 pub struct Binary {
-    pub left: Box<dyn Expr>,
-    pub right: Box<dyn Expr>,
-    pub operator: Token,
+    pub left: Box<Expr>,
+    pub right: Box<Expr>,
+    pub operator: Rc<Token>,
 }
 
 #[derive(Clone)]
 pub struct Logical {
-    pub left: Box<dyn Expr>,
-    pub right: Box<dyn Expr>,
-    pub operator: Token,
+    pub left: Box<Expr>,
+    pub right: Box<Expr>,
+    pub operator: Rc<Token>,
 }
 
 #[derive(Clone)]
-pub struct Grouping(pub Box<dyn Expr>);
+pub struct Grouping(pub Box<Expr>);
 
 #[derive(Clone)]
 pub struct Variable {
-    pub name: Token,
+    pub name: Rc<Token>,
     // number of env. hops needed to find the variable
     // this expression refers to
     pub hops: Option<usize>,
+    // where the global variable was found the last time,
+    // set by the interpreter when `hops` is None
+    pub slot: Cell<Option<Slot>>,
 }
 
 #[derive(Clone)]
 pub struct Assignment {
-    pub name: Token,
+    pub name: Rc<Token>,
     // number of env. hops needed to find the variable
     // this expression assigns to
     pub hops: Option<usize>,
-    pub value: Box<dyn Expr>,
+    // like `Variable::slot`
+    pub slot: Cell<Option<Slot>>,
+    pub value: Box<Expr>,
 }
 
 #[derive(Clone)]
 pub struct Call {
-    pub right_paren: Token,
-    pub callee: Box<dyn Expr>,
-    pub args: Vec<Box<dyn Expr>>,
+    pub right_paren: Rc<Token>,
+    pub callee: Box<Expr>,
+    pub args: Vec<Expr>,
 }
 
 #[derive(Clone)]
 pub struct Get {
-    pub name: Token,
-    pub object: Box<dyn Expr>,
+    pub name: Rc<Token>,
+    pub object: Box<Expr>,
+    // the method this access found last
+    pub method: MethodCache,
 }
 
 #[derive(Clone)]
 pub struct Set {
-    pub name: Token,
-    pub object: Box<dyn Expr>,
-    pub value: Box<dyn Expr>,
+    pub name: Rc<Token>,
+    pub object: Box<Expr>,
+    pub value: Box<Expr>,
 }
 
 #[derive(Clone)]
 pub struct This {
-    pub keyword: Token,
+    pub keyword: Rc<Token>,
     // number of env. hops needed to find the variable
     // this expression refers to
     pub hops: Option<usize>,
@@ -81,12 +90,64 @@ pub struct This {
 
 #[derive(Clone)]
 pub struct Super {
-    pub keyword: Token,
-    pub method: Token,
+    pub keyword: Rc<Token>,
+    pub method: Rc<Token>,
     pub hops_to_super: Option<usize>,
     pub hops_to_this: Option<usize>,
 }
 
+#[derive(Clone)]
+pub struct TypeTest {
+    pub value: Box<Expr>,
+    pub keyword: Rc<Token>,
+    // a class or the name of a builtin type
+    pub type_expr: Box<Expr>,
+}
+
+#[derive(Clone)]
+pub struct Delete {
+    pub keyword: Rc<Token>,
+    pub object: Box<Expr>,
+    pub name: Rc<Token>,
+}
+
+#[derive(Clone)]
+pub struct Index {
+    pub object: Box<Expr>,
+    // the closing bracket
+    pub bracket: Rc<Token>,
+    pub index: Box<Expr>,
+}
+
+#[derive(Clone)]
+pub struct SetIndex {
+    pub object: Box<Expr>,
+    // the closing bracket
+    pub bracket: Rc<Token>,
+    pub index: Box<Expr>,
+    pub value: Box<Expr>,
+}
+
+#[derive(Clone)]
+pub enum Expr {
+    Literal(Literal),
+    Unary(Unary),
+    Binary(Binary),
+    Logical(Logical),
+    Grouping(Grouping),
+    Variable(Variable),
+    Assignment(Assignment),
+    Call(Call),
+    Get(Get),
+    Set(Set),
+    This(This),
+    Super(Super),
+    TypeTest(TypeTest),
+    Delete(Delete),
+    Index(Index),
+    SetIndex(SetIndex),
+}
+
 pub trait Visitor<T> {
     fn visit_literal(&mut self, e: &Literal) -> T;
     fn visit_unary(&mut self, e: &Unary) -> T;
@@ -100,6 +161,10 @@ pub trait Visitor<T> {
     fn visit_set(&mut self, e: &Set) -> T;
     fn visit_this(&mut self, e: &This) -> T;
     fn visit_super(&mut self, e: &Super) -> T;
+    fn visit_type_test(&mut self, e: &TypeTest) -> T;
+    fn visit_delete(&mut self, e: &Delete) -> T;
+    fn visit_index(&mut self, e: &Index) -> T;
+    fn visit_set_index(&mut self, e: &SetIndex) -> T;
 }
 
 pub trait MutVisitor<T> {
@@ -115,185 +180,52 @@ pub trait MutVisitor<T> {
     fn visit_set(&mut self, e: &mut Set) -> T;
     fn visit_this(&mut self, e: &mut This) -> T;
     fn visit_super(&mut self, e: &mut Super) -> T;
-}
-
-pub enum AssignTarget {
-    Var {
-        name: Token,
-    },
-    Get {
-        object: Box<dyn Expr>,
-        name: Token,
-    },
-}
-
-pub trait Expr: dyn_clone::DynClone {
-    // workaround for assignment parsing
-    fn as_assign_target(&self) -> Option<AssignTarget> { None }
-
-    fn accept_string(&self, v: &mut dyn Visitor<String>) -> String;
-    fn accept_rt_value(&self, v: &mut dyn Visitor<RuntimeResult>) -> RuntimeResult;
-    fn accept_resolve(&mut self, v: &mut dyn MutVisitor<()>);
-}
-
-dyn_clone::clone_trait_object!(Expr);
-
-impl Expr for Literal {
-    fn accept_string(&self, v: &mut dyn Visitor<String>) -> String {
-        v.visit_literal(self)
-    }
-    fn accept_rt_value(&self, v: &mut dyn Visitor<RuntimeResult>) -> RuntimeResult {
-        v.visit_literal(self)
-    }
-    fn accept_resolve(&mut self, v: &mut dyn MutVisitor<()>) {
-        v.visit_literal(self)
-    }
-}
-
-impl Expr for Unary {
-    fn accept_string(&self, v: &mut dyn Visitor<String>) -> String {
-        v.visit_unary(self)
-    }
-    fn accept_rt_value(&self, v: &mut dyn Visitor<RuntimeResult>) -> RuntimeResult {
-        v.visit_unary(self)
-    }
-    fn accept_resolve(&mut self, v: &mut dyn MutVisitor<()>) {
-        v.visit_unary(self)
-    }
-}
-
-impl Expr for Binary {
-    fn accept_string(&self, v: &mut dyn Visitor<String>) -> String {
-        v.visit_binary(self)
-    }
-    fn accept_rt_value(&self, v: &mut dyn Visitor<RuntimeResult>) -> RuntimeResult {
-        v.visit_binary(self)
-    }
-    fn accept_resolve(&mut self, v: &mut dyn MutVisitor<()>) {
-        v.visit_binary(self)
-    }
-}
-
-impl Expr for Grouping {
-    fn accept_string(&self, v: &mut dyn Visitor<String>) -> String {
-        v.visit_grouping(self)
-    }
-    fn accept_rt_value(&self, v: &mut dyn Visitor<RuntimeResult>) -> RuntimeResult {
-        v.visit_grouping(self)
-    }
-    fn accept_resolve(&mut self, v: &mut dyn MutVisitor<()>) {
-        v.visit_grouping(self)
-    }
-}
-
-impl Expr for Variable {
-    fn as_assign_target(&self) -> Option<AssignTarget> {
-        Some(AssignTarget::Var { name: self.name.clone() })
-    }
-
-    fn accept_string(&self, v: &mut dyn Visitor<String>) -> String {
-        v.visit_variable(self)
-    }
-    fn accept_rt_value(&self, v: &mut dyn Visitor<RuntimeResult>) -> RuntimeResult {
-        v.visit_variable(self)
-    }
-    fn accept_resolve(&mut self, v: &mut dyn MutVisitor<()>) {
-        v.visit_variable(self)
-    }
-}
-
-impl Expr for Assignment {
-    fn accept_string(&self, v: &mut dyn Visitor<String>) -> String {
-        v.visit_assignment(self)
-    }
-    fn accept_rt_value(&self, v: &mut dyn Visitor<RuntimeResult>) -> RuntimeResult {
-        v.visit_assignment(self)
-    }
-    fn accept_resolve(&mut self, v: &mut dyn MutVisitor<()>) {
-        v.visit_assignment(self)
-    }
-}
-
-impl Expr for Logical {
-    fn accept_string(&self, v: &mut dyn Visitor<String>) -> String {
-        v.visit_logical(self)
-    }
-
-    fn accept_rt_value(&self, v: &mut dyn Visitor<RuntimeResult>) -> RuntimeResult {
-        v.visit_logical(self)
-    }
-    fn accept_resolve(&mut self, v: &mut dyn MutVisitor<()>) {
-        v.visit_logical(self)
-    }
-}
-
-impl Expr for Call {
-    fn accept_string(&self, v: &mut dyn Visitor<String>) -> String {
-        v.visit_call(self)
-    }
-
-    fn accept_rt_value(&self, v: &mut dyn Visitor<RuntimeResult>) -> RuntimeResult {
-        v.visit_call(self)
-    }
-    fn accept_resolve(&mut self, v: &mut dyn MutVisitor<()>) {
-        v.visit_call(self)
-    }
-}
-
-impl Expr for Get {
-    fn accept_string(&self, v: &mut dyn Visitor<String>) -> String {
-        v.visit_get(self)
-    }
-
-    fn accept_rt_value(&self, v: &mut dyn Visitor<RuntimeResult>) -> RuntimeResult {
-        v.visit_get(self)
-    }
-    fn accept_resolve(&mut self, v: &mut dyn MutVisitor<()>) {
-        v.visit_get(self)
-    }
-    fn as_assign_target(&self) -> Option<AssignTarget> {
-        Some(AssignTarget::Get {
-            name: self.name.clone(),
-            object: self.object.clone(),
-        })
-    }
-}
-
-impl Expr for Set {
-    fn accept_string(&self, v: &mut dyn Visitor<String>) -> String {
-        v.visit_set(self)
-    }
-
-    fn accept_rt_value(&self, v: &mut dyn Visitor<RuntimeResult>) -> RuntimeResult {
-        v.visit_set(self)
-    }
-    fn accept_resolve(&mut self, v: &mut dyn MutVisitor<()>) {
-        v.visit_set(self)
+    fn visit_type_test(&mut self, e: &mut TypeTest) -> T;
+    fn visit_delete(&mut self, e: &mut Delete) -> T;
+    fn visit_index(&mut self, e: &mut Index) -> T;
+    fn visit_set_index(&mut self, e: &mut SetIndex) -> T;
+}
+
+impl Expr {
+    pub fn accept<T>(&self, v: &mut dyn Visitor<T>) -> T {
+        match self {
+            Expr::Literal(e) => v.visit_literal(e),
+            Expr::Unary(e) => v.visit_unary(e),
+            Expr::Binary(e) => v.visit_binary(e),
+            Expr::Logical(e) => v.visit_logical(e),
+            Expr::Grouping(e) => v.visit_grouping(e),
+            Expr::Variable(e) => v.visit_variable(e),
+            Expr::Assignment(e) => v.visit_assignment(e),
+            Expr::Call(e) => v.visit_call(e),
+            Expr::Get(e) => v.visit_get(e),
+            Expr::Set(e) => v.visit_set(e),
+            Expr::This(e) => v.visit_this(e),
+            Expr::Super(e) => v.visit_super(e),
+            Expr::TypeTest(e) => v.visit_type_test(e),
+            Expr::Delete(e) => v.visit_delete(e),
+            Expr::Index(e) => v.visit_index(e),
+            Expr::SetIndex(e) => v.visit_set_index(e),
+        }
+    }
+
+    pub fn accept_mut<T>(&mut self, v: &mut dyn MutVisitor<T>) -> T {
+        match self {
+            Expr::Literal(e) => v.visit_literal(e),
+            Expr::Unary(e) => v.visit_unary(e),
+            Expr::Binary(e) => v.visit_binary(e),
+            Expr::Logical(e) => v.visit_logical(e),
+            Expr::Grouping(e) => v.visit_grouping(e),
+            Expr::Variable(e) => v.visit_variable(e),
+            Expr::Assignment(e) => v.visit_assignment(e),
+            Expr::Call(e) => v.visit_call(e),
+            Expr::Get(e) => v.visit_get(e),
+            Expr::Set(e) => v.visit_set(e),
+            Expr::This(e) => v.visit_this(e),
+            Expr::Super(e) => v.visit_super(e),
+            Expr::TypeTest(e) => v.visit_type_test(e),
+            Expr::Delete(e) => v.visit_delete(e),
+            Expr::Index(e) => v.visit_index(e),
+            Expr::SetIndex(e) => v.visit_set_index(e),
+        }
     }
 }
-
-impl Expr for This {
-    fn accept_string(&self, v: &mut dyn Visitor<String>) -> String {
-        v.visit_this(self)
-    }
-
-    fn accept_rt_value(&self, v: &mut dyn Visitor<RuntimeResult>) -> RuntimeResult {
-        v.visit_this(self)
-    }
-    fn accept_resolve(&mut self, v: &mut dyn MutVisitor<()>) {
-        v.visit_this(self)
-    }
-}
-
-impl Expr for Super {
-    fn accept_string(&self, v: &mut dyn Visitor<String>) -> String {
-        v.visit_super(self)
-    }
-
-    fn accept_rt_value(&self, v: &mut dyn Visitor<RuntimeResult>) -> RuntimeResult {
-        v.visit_super(self)
-    }
-    fn accept_resolve(&mut self, v: &mut dyn MutVisitor<()>) {
-        v.visit_super(self)
-    }
-}
\ No newline at end of file
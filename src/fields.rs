@@ -0,0 +1,76 @@
+use crate::RuntimeValue;
+use std::collections::HashMap;
+
+// above this many fields, lookups go through an index
+const INDEX_THRESHOLD: usize = 8;
+
+// Instance fields in the order they were first set.
+// Instances usually have a handful of fields, which are found faster
+// by a linear scan than by hashing, so larger ones only get an index.
+#[derive(Clone, Default)]
+pub(crate) struct Fields {
+    entries: Vec<(String, RuntimeValue)>,
+    // the position of each entry, once there are more than INDEX_THRESHOLD
+    index: Option<HashMap<String, usize>>,
+}
+
+impl Fields {
+    fn position(&self, name: &str) -> Option<usize> {
+        match &self.index {
+            Some(index) => index.get(name).copied(),
+            None => self.entries.iter().position(|(n, _)| n == name),
+        }
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<&RuntimeValue> {
+        self.position(name).map(|i| &self.entries[i].1)
+    }
+
+    pub(crate) fn contains_key(&self, name: &str) -> bool {
+        self.position(name).is_some()
+    }
+
+    // Setting an existing field keeps its position.
+    pub(crate) fn insert(&mut self, name: &str, value: RuntimeValue) {
+        if let Some(i) = self.position(name) {
+            self.entries[i].1 = value;
+            return;
+        }
+
+        self.entries.push((name.to_owned(), value));
+        match &mut self.index {
+            Some(index) => {
+                index.insert(name.to_owned(), self.entries.len() - 1);
+            },
+            None if self.entries.len() > INDEX_THRESHOLD => self.build_index(),
+            None => { },
+        }
+    }
+
+    // Returns whether the field existed.
+    pub(crate) fn remove(&mut self, name: &str) -> bool {
+        match self.position(name) {
+            Some(i) => {
+                self.entries.remove(i);
+                if self.index.is_some() {
+                    self.build_index();
+                }
+                true
+            },
+            None => false,
+        }
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&String, &RuntimeValue)> {
+        self.entries.iter().map(|(name, value)| (name, value))
+    }
+
+    fn build_index(&mut self) {
+        let index = self.entries
+            .iter()
+            .enumerate()
+            .map(|(i, (name, _))| (name.clone(), i))
+            .collect();
+        self.index = Some(index);
+    }
+}
@@ -0,0 +1,150 @@
+use crate::{Cell, Rc};
+
+// dumpster keeps its counters to itself, so instances and environments
+// count themselves. The counts are per thread and cover every interpreter
+// running on it.
+thread_local! {
+    static COUNTERS: Rc<Counters> = Rc::default();
+}
+
+#[derive(Default)]
+struct Counters {
+    live_instances: Cell<usize>,
+    instances_created: Cell<usize>,
+    live_environments: Cell<usize>,
+    environments_created: Cell<usize>,
+    classes_created: Cell<usize>,
+    allocations: Cell<usize>,
+    collections: Cell<usize>,
+    last_collection_freed: Cell<usize>,
+}
+
+// The counts are exact for what they count, but what is live includes
+// what is only reachable through cycles until a collection frees it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GcStats {
+    pub live_instances: usize,
+    pub instances_created: usize,
+    pub live_environments: usize,
+    pub environments_created: usize,
+    pub classes_created: usize,
+    // the environments, instances, classes, arrays and maps created
+    pub allocations: usize,
+    // the collections forced with `gc_collect` or `Interpreter::collect_garbage`
+    // or run by the pacing policy
+    pub collections: usize,
+    // The instances and environments no longer live after the last collection.
+    // Only an estimate of what it freed, as it counts what was freed without it
+    // in the meantime, e.g. on other threads with the `sync-gc` feature, and
+    // leaves out classes, arrays and maps.
+    pub last_collection_freed: usize,
+}
+
+pub(crate) fn stats() -> GcStats {
+    COUNTERS.with(|c| GcStats {
+        live_instances: c.live_instances.get(),
+        instances_created: c.instances_created.get(),
+        live_environments: c.live_environments.get(),
+        environments_created: c.environments_created.get(),
+        classes_created: c.classes_created.get(),
+        allocations: c.allocations.get(),
+        collections: c.collections.get(),
+        last_collection_freed: c.last_collection_freed.get(),
+    })
+}
+
+// Counts an object the collector manages, towards the next paced collection.
+pub(crate) fn allocated() {
+    COUNTERS.with(|c| c.allocations.set(c.allocations.get() + 1));
+}
+
+pub(crate) fn class_created() {
+    COUNTERS.with(|c| {
+        c.classes_created.set(c.classes_created.get() + 1);
+        c.allocations.set(c.allocations.get() + 1);
+    });
+}
+
+pub(crate) fn allocations() -> usize {
+    COUNTERS.with(|c| c.allocations.get())
+}
+
+// Frees everything on this thread that is only reachable through cycles.
+pub(crate) fn collect() {
+    let live = || COUNTERS.with(|c| c.live_instances.get() + c.live_environments.get());
+    let before = live();
+    crate::shared::collect();
+    let freed = before.saturating_sub(live());
+
+    COUNTERS.with(|c| {
+        c.collections.set(c.collections.get() + 1);
+        c.last_collection_freed.set(freed);
+    });
+}
+
+#[derive(Clone, Copy)]
+pub(crate) enum Kind {
+    Instance,
+    Environment,
+}
+
+// Counts the value it is a part of as live from its creation to its drop.
+pub(crate) struct Tracked {
+    kind: Kind,
+    // With the `sync-gc` feature, values can be dropped on another thread
+    // than the one that created them, e.g. by a collection made there.
+    #[cfg(feature = "sync-gc")]
+    counters: Rc<Counters>,
+}
+
+impl Tracked {
+    pub(crate) fn new(kind: Kind) -> Self {
+        COUNTERS.with(|c| {
+            let (live, created) = c.counters(kind);
+            live.set(live.get() + 1);
+            created.set(created.get() + 1);
+            c.allocations.set(c.allocations.get() + 1);
+
+            Tracked {
+                kind,
+                #[cfg(feature = "sync-gc")]
+                counters: c.clone(),
+            }
+        })
+    }
+}
+
+impl Counters {
+    fn counters(&self, kind: Kind) -> (&Cell<usize>, &Cell<usize>) {
+        match kind {
+            Kind::Instance => (&self.live_instances, &self.instances_created),
+            Kind::Environment => (&self.live_environments, &self.environments_created),
+        }
+    }
+
+    fn dropped(&self, kind: Kind) {
+        let (live, _) = self.counters(kind);
+        live.set(live.get() - 1);
+    }
+}
+
+impl Clone for Tracked {
+    fn clone(&self) -> Self {
+        Tracked::new(self.kind)
+    }
+}
+
+#[cfg(not(feature = "sync-gc"))]
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        // the counters may be gone if this is dropped while the thread exits
+        let _ = COUNTERS.try_with(|c| c.dropped(self.kind));
+    }
+}
+
+#[cfg(feature = "sync-gc")]
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        self.counters.dropped(self.kind);
+    }
+}
@@ -1,27 +1,72 @@
 use std::{
     collections::HashMap,
-    cell::RefCell,
+    fmt::{self, Write},
+    sync::atomic::{AtomicU64, Ordering},
+};
+use crate::{
+    gc_stats::{Kind, Tracked},
+    RuntimeValue,
+    Gc,
+    RefCell,
 };
-use crate::RuntimeValue;
 use dumpster::{
     Trace,
-    unsync::Gc,
     Visitor,
 };
 
 #[derive(Clone)]
 pub struct Environment {
     pub parent: Option<Gc<RefCell<Environment>>>,
-    bindings: HashMap<String, RuntimeValue>,
+    bindings: Bindings,
+    _tracked: Tracked,
+}
+
+#[derive(Clone)]
+enum Bindings {
+    // the scopes of blocks and calls
+    Named(HashMap<String, RuntimeValue>),
+    // The top-level scope, whose values stay where they were first defined,
+    // so the places they are looked up from can remember where they are.
+    Slots {
+        id: u64,
+        indices: HashMap<String, usize>,
+        values: Vec<RuntimeValue>,
+    },
+}
+
+// Where a variable is in the top-level environment `id`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Slot {
+    id: u64,
+    index: usize,
+}
+
+// The variables of one scope, with their values shown as `print` shows them
+// without calling 'toString' methods. Natives are left out of the top-level
+// scope, which has all of them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScopeSnapshot {
+    // sorted by name
+    pub bindings: Vec<(String, String)>,
+    pub is_global: bool,
 }
 
+// values shown longer than this are cut off
+const MAX_SNAPSHOT_VALUE_LEN: usize = 80;
+
+// every top-level environment has an id of its own, so a slot
+// found in one of them is never used to look up another one
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
 unsafe impl Trace for Environment {
     fn accept<V: Visitor>(&self, visitor: &mut V) -> Result<(), ()> {
+        // only the parent itself is visited here; the collector
+        // decides whether and when to go on to its own parent
         if let Some(p) = &self.parent {
             p.accept(visitor)?;
         }
 
-        for (_, value) in &self.bindings {
+        for (_, value) in self.bindings() {
             value.accept(visitor)?;
         }
 
@@ -33,57 +78,237 @@ impl Environment {
     pub fn root() -> Self {
         Self {
             parent: None,
-            bindings: HashMap::new(),
+            bindings: Bindings::Slots {
+                id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+                indices: HashMap::new(),
+                values: Vec::new(),
+            },
+            _tracked: Tracked::new(Kind::Environment),
         }
     }
 
     pub fn child(parent_env: Gc<RefCell<Environment>>) -> Self {
         Self {
             parent: Some(parent_env),
-            bindings: HashMap::new(),
+            bindings: Bindings::Named(HashMap::new()),
+            _tracked: Tracked::new(Kind::Environment),
         }
     }
 
-    pub fn define(&mut self, name: &str, value: &RuntimeValue) {
+    // Empties an environment of a block or call so it can be used again,
+    // keeping the room its bindings took. It is left without a parent if
+    // `parent` is None.
+    pub fn reset(&mut self, parent: Option<Gc<RefCell<Environment>>>) {
+        self.parent = parent;
+        if let Bindings::Named(bindings) = &mut self.bindings {
+            bindings.clear();
+        }
+    }
+
+    pub fn define(&mut self, name: impl Into<String>, value: RuntimeValue) {
         // definition is always done in the current env
-        self.bindings.insert(name.to_owned(), value.clone());
+        match &mut self.bindings {
+            Bindings::Named(bindings) => {
+                bindings.insert(name.into(), value);
+            },
+            // redefining a variable keeps its slot
+            Bindings::Slots { indices, values, .. } => {
+                let name = name.into();
+                match indices.get(&name) {
+                    Some(&i) => values[i] = value,
+                    None => {
+                        indices.insert(name, values.len());
+                        values.push(value);
+                    },
+                }
+            },
+        }
     }
 
-    pub fn assign(&mut self, name: &str, value: &RuntimeValue) -> bool {
-        match self.bindings.get_mut(name) {
+    pub fn assign(&mut self, name: &str, value: RuntimeValue) -> bool {
+        let entry = match &mut self.bindings {
+            Bindings::Named(bindings) => bindings.get_mut(name),
+            Bindings::Slots { indices, values, .. } => indices.get(name).map(|&i| &mut values[i]),
+        };
+        match entry {
             Some(entry) => {
-                *entry = value.clone();
+                *entry = value;
                 true
             },
             None => false,
         }
     }
 
-    pub fn assign_at(&mut self, name: &str, value: &RuntimeValue,  hops: usize) -> bool {
+    pub fn assign_at(&mut self, name: &str, value: RuntimeValue, hops: usize) -> bool {
         if hops == 0 {
-            self.assign(name, value)
+            return self.assign(name, value);
         }
-        else {
-            match &mut self.parent {
-                Some(p) => p.borrow_mut().assign_at(name, value, hops - 1),
-                None => false,
-            }
+
+        match self.ancestor(hops) {
+            Some(env) => env.borrow_mut().assign(name, value),
+            None => false,
+        }
+    }
+
+    pub fn bindings(&self) -> Box<dyn Iterator<Item = (&String, &RuntimeValue)> + '_> {
+        match &self.bindings {
+            Bindings::Named(bindings) => Box::new(bindings.iter()),
+            Bindings::Slots { indices, values, .. } => Box::new(indices.iter().map(|(name, &i)| (name, &values[i]))),
         }
     }
 
     pub fn get(&self, name: &str) -> Option<RuntimeValue> {
-        self.bindings.get(name).map(|v| v.clone())
+        match &self.bindings {
+            Bindings::Named(bindings) => bindings.get(name).cloned(),
+            Bindings::Slots { indices, values, .. } => indices.get(name).map(|&i| values[i].clone()),
+        }
     }
 
     pub fn get_at(&self, name: &str, hops: usize) -> Option<RuntimeValue> {
         if hops == 0 {
-            self.get(name)
+            return self.get(name);
+        }
+
+        self.ancestor(hops)?.borrow().get(name)
+    }
+
+    // The environment `hops` parents up, for `hops` of at least one.
+    // Walked one environment at a time, so that long chains of scopes
+    // neither deepen the stack nor stay borrowed all at once.
+    fn ancestor(&self, hops: usize) -> Option<Gc<RefCell<Environment>>> {
+        let mut env = self.parent.clone()?;
+        for _ in 1..hops {
+            let parent = env.borrow().parent.clone()?;
+            env = parent;
+        }
+
+        Some(env)
+    }
+
+    // The scopes from this environment up to the top-level one.
+    pub fn snapshot(&self) -> Vec<ScopeSnapshot> {
+        let mut scopes = vec![self.scope_snapshot()];
+        let mut env = self.parent.clone();
+        while let Some(e) = env {
+            scopes.push(e.borrow().scope_snapshot());
+            env = e.borrow().parent.clone();
+        }
+
+        scopes
+    }
+
+    fn scope_snapshot(&self) -> ScopeSnapshot {
+        let is_global = self.parent.is_none();
+        // Values are copied out before they are shown, so showing them does
+        // not happen while this environment is borrowed.
+        let values: Vec<(String, RuntimeValue)> = self.bindings()
+            .filter(|(_, value)| !(is_global && is_native(value)))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+
+        let mut bindings: Vec<(String, String)> = values.into_iter()
+            .map(|(name, value)| (name, shorten(&value)))
+            .collect();
+        bindings.sort();
+
+        ScopeSnapshot { bindings, is_global }
+    }
+
+    // Where `name` is in a top-level environment. Slots stay valid as
+    // long as the environment does, as variables are never removed from it.
+    pub fn slot(&self, name: &str) -> Option<Slot> {
+        match &self.bindings {
+            Bindings::Named(_) => None,
+            Bindings::Slots { id, indices, .. } => indices.get(name).map(|&index| Slot { id: *id, index }),
+        }
+    }
+
+    // None if `slot` was found in another environment.
+    pub fn get_slot(&self, slot: Slot) -> Option<RuntimeValue> {
+        match &self.bindings {
+            Bindings::Slots { id, values, .. } if *id == slot.id => Some(values[slot.index].clone()),
+            _ => None,
+        }
+    }
+
+    pub fn has_slot(&self, slot: Slot) -> bool {
+        matches!(&self.bindings, Bindings::Slots { id, .. } if *id == slot.id)
+    }
+
+    // Whether `slot` is in this environment.
+    pub fn assign_slot(&mut self, slot: Slot, value: RuntimeValue) -> bool {
+        match &mut self.bindings {
+            Bindings::Slots { id, values, .. } if *id == slot.id => {
+                values[slot.index] = value;
+                true
+            },
+            _ => false,
+        }
+    }
+}
+
+// Shows `scopes`, innermost first, with a line for each variable
+// under a line naming its scope, e.g.
+//
+//   scope 0:
+//     x = 1
+//   globals:
+//     counter = <fn counter>
+pub fn format_snapshot(scopes: &[ScopeSnapshot]) -> String {
+    let mut text = String::new();
+    for (depth, scope) in scopes.iter().enumerate() {
+        if scope.is_global {
+            text.push_str("globals:\n");
         }
         else {
-            match &self.parent {
-                Some(p) => p.borrow().get_at(name, hops - 1),
-                None => None,
+            let _ = writeln!(text, "scope {}:", depth);
+        }
+        for (name, value) in &scope.bindings {
+            let _ = writeln!(text, "  {} = {}", name, value);
+        }
+    }
+
+    text
+}
+
+fn is_native(value: &RuntimeValue) -> bool {
+    match value {
+        RuntimeValue::Callable(callable) => callable.declaration().is_none(),
+        _ => false,
+    }
+}
+
+// `value` as it is displayed, cut off after `MAX_SNAPSHOT_VALUE_LEN`
+// characters. Large arrays and maps are not shown past that point.
+fn shorten(value: &RuntimeValue) -> String {
+    let mut text = Shortened { text: String::new(), len: 0, cut: false };
+    let _ = write!(text, "{}", value);
+    if text.cut {
+        text.text.push_str("...");
+    }
+
+    text.text
+}
+
+struct Shortened {
+    text: String,
+    // in characters
+    len: usize,
+    cut: bool,
+}
+
+impl Write for Shortened {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if self.len == MAX_SNAPSHOT_VALUE_LEN {
+                self.cut = true;
+                // stops the formatting
+                return Err(fmt::Error);
             }
+            self.text.push(c);
+            self.len += 1;
         }
+
+        Ok(())
     }
-}
\ No newline at end of file
+}
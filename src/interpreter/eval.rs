@@ -6,17 +6,27 @@ use crate::{
     },
     RuntimeValue,
     RuntimeError,
-    is_truthy,
     Instance,
+    Class,
     CallableWrapper,
+    Callable,
+    Map,
     bind_method,
+    Gc,
+    Rc,
+    RefCell,
+};
+use smallvec::SmallVec;
+use super::{
+    memory,
+    primitive,
+    Interpreter,
 };
-use dumpster::unsync::Gc;
-use std::cell::RefCell;
-use super::Interpreter;
 
 type EvalResult = Result<RuntimeValue, RuntimeError>;
 
+pub(super) const MAX_TO_STRING_DEPTH: usize = 16;
+
 impl expression::Visitor<EvalResult> for Interpreter {
     fn visit_literal(
         &mut self,
@@ -39,7 +49,7 @@ impl expression::Visitor<EvalResult> for Interpreter {
         &mut self,
         e: &expression::Unary,
     ) -> EvalResult {
-        let value = e.right.accept_rt_value(self)?;
+        let value = e.right.accept(self)?;
 
         match e.operator.token_type {
             TokenType::Minus => {
@@ -52,7 +62,7 @@ impl expression::Visitor<EvalResult> for Interpreter {
             },
             TokenType::Bang => {
                 Ok(RuntimeValue::Bool(
-                    is_truthy(&value) == false
+                    self.condition(&value, &e.operator)? == false
                 ))
             },
             _ => {
@@ -65,8 +75,234 @@ impl expression::Visitor<EvalResult> for Interpreter {
         &mut self,
         e: &expression::Binary,
     ) -> EvalResult {
-        let left = e.left.accept_rt_value(self)?;
-        let right = e.right.accept_rt_value(self)?;
+        let left = e.left.accept(self)?;
+        let right = e.right.accept(self)?;
+
+        self.binary(left, right, e)
+    }
+
+    fn visit_logical(&mut self, e: &expression::Logical) -> EvalResult {
+        let left = self.evaluate_expr(&e.left)?;
+        let left_truthy = self.condition(&left, &e.operator)?;
+
+        match e.operator.token_type {
+            TokenType::Or => {
+                if left_truthy { 
+                    return Ok(left);
+                }
+            },
+            TokenType::And => {
+                if left_truthy == false {
+                    return Ok(left);
+                }
+            },
+            _ => panic!("expected logical operator"),
+        }
+
+        let right = self.evaluate_expr(&e.right)?;
+        if self.options.strict_booleans {
+            self.condition(&right, &e.operator)?;
+        }
+
+        Ok(right)
+    }
+
+    fn visit_grouping(
+        &mut self,
+        e: &expression::Grouping,
+    ) -> EvalResult {
+        e.0.accept(self)
+    }
+
+    fn visit_variable(
+        &mut self,
+        e: &expression::Variable,
+    ) -> EvalResult {
+        match e.hops {
+            Some(_) => self.look_up_var(&e.name, e.hops),
+            None => self.look_up_global(&e.name, &e.slot),
+        }
+    }
+
+    fn visit_this(&mut self, e: &expression::This) -> EvalResult {
+        self.look_up_var(&e.keyword, e.hops)
+    }
+
+    fn visit_assignment(
+        &mut self,
+        e: &expression::Assignment,
+    ) -> EvalResult {
+        let v = match &*e.value {
+            expression::Expr::Binary(b) if is_append_to(e, b) => self.append(e, b)?,
+            value => self.evaluate_expr(value)?,
+        };
+
+        if self.assign_to(e, v.clone()) {
+            Ok(v)
+        }
+        else {
+            Err(RuntimeError::UndefinedVariable(e.name.clone()))
+        }
+    }
+
+    fn visit_call(&mut self, e: &expression::Call) -> EvalResult {
+        if let expression::Expr::Get(get) = &*e.callee {
+            return self.call_property(get, e);
+        }
+
+        let value = self.evaluate_expr(&e.callee)?;
+        self.call_value(value, e)
+    }
+
+    fn visit_get(&mut self, e: &expression::Get) -> EvalResult {
+        let object = self.evaluate_expr(&e.object)?;
+
+        if let RuntimeValue::Instance(instance) = &object {
+            // fields shadow methods and differ between instances
+            if let Some(value) = instance.borrow().field(&e.name.lexeme) {
+                return Ok(value.clone());
+            }
+
+            let method = self.cached_method(instance, e)?;
+            return Ok(RuntimeValue::Callable(bind_method(&method, instance)));
+        }
+
+        self.get_property(object, &e.name)
+    }
+
+    fn visit_set(&mut self, e: &expression::Set) -> EvalResult {
+        let expr = self.evaluate_expr(&e.object)?;
+
+        if let RuntimeValue::Instance(instance) = expr {
+            let v = self.evaluate_expr(&e.value)?;
+            self.charge_field(&instance, &e.name.lexeme, Some(&e.name))?;
+            instance.borrow_mut().set(&e.name.lexeme, &v);
+            Ok(v)
+        }
+        else {
+            Err(RuntimeError::OnlyInstancesHaveProperties(
+                e.name.clone(),
+            ))
+        }
+    }
+
+    fn visit_super(&mut self, e: &expression::Super) -> EvalResult {
+        let sup = match self.look_up_var(&e.keyword, e.hops_to_super)? {
+            RuntimeValue::Class(c) => c,
+            _ => return Err(RuntimeError::SuperNotAClass(e.keyword.clone())),
+        };
+
+        let this_token = Rc::new(Token {
+            token_type: TokenType::This,
+            lexeme: "this".to_owned(),
+            literal: None,
+            line: 0,
+            column: 0
+        });
+        let obj = match self.look_up_var(&this_token, e.hops_to_this) {
+            Ok(RuntimeValue::Instance(obj)) => obj,
+            _ => return Err(RuntimeError::ThisNotAnInstance(e.keyword.clone())),
+        };
+
+        let method = sup
+            .borrow()
+            .find_method(&e.method.lexeme)
+            .ok_or(RuntimeError::UndefinedProperty(e.method.clone()))?;
+
+        Ok(RuntimeValue::Callable(bind_method(&method, &obj)))
+    }
+
+    fn visit_delete(&mut self, e: &expression::Delete) -> EvalResult {
+        let object = self.evaluate_expr(&e.object)?;
+
+        if let RuntimeValue::Instance(instance) = object {
+            let existed = instance.borrow_mut().remove(&e.name.lexeme);
+            Ok(RuntimeValue::Bool(existed))
+        }
+        else {
+            Err(RuntimeError::OnlyInstancesHaveProperties(
+                e.name.clone(),
+            ))
+        }
+    }
+
+    fn visit_type_test(&mut self, e: &expression::TypeTest) -> EvalResult {
+        let value = self.evaluate_expr(&e.value)?;
+
+        // builtin type names are only used if they are not defined as classes
+        let type_value = match self.evaluate_expr(&e.type_expr) {
+            Err(RuntimeError::UndefinedVariable(name)) if matches!(*e.type_expr, expression::Expr::Variable(_)) => {
+                return match has_builtin_type(&value, &name.lexeme) {
+                    Some(result) => Ok(RuntimeValue::Bool(result)),
+                    None => Err(RuntimeError::UndefinedVariable(name)),
+                };
+            },
+            r => r?,
+        };
+
+        match type_value {
+            RuntimeValue::Class(class) => {
+                Ok(RuntimeValue::Bool(is_instance_of(&value, &class)))
+            },
+            // names like `Array` are both a native and a builtin type
+            _ => {
+                let builtin = match &*e.type_expr {
+                    expression::Expr::Variable(v) => has_builtin_type(&value, &v.name.lexeme),
+                    _ => None,
+                };
+                builtin.map(RuntimeValue::Bool)
+                    .ok_or(RuntimeError::IsOperandMustBeClass(e.keyword.clone()))
+            },
+        }
+    }
+
+    fn visit_index(&mut self, e: &expression::Index) -> EvalResult {
+        let object = self.evaluate_expr(&e.object)?;
+        let index = self.evaluate_expr(&e.index)?;
+
+        match object {
+            RuntimeValue::String(s) => {
+                let len = s.chars().count();
+                let i = as_index(&index, len, &e.bracket)?;
+                // as_index checked the index against the length
+                let c = s.chars().nth(i).unwrap();
+                Ok(RuntimeValue::String(c.to_string().into()))
+            },
+            RuntimeValue::Array(array) => {
+                let array = array.borrow();
+                let i = as_index(&index, array.len(), &e.bracket)?;
+                Ok(array[i].clone())
+            },
+            _ => Err(RuntimeError::NotIndexable(e.bracket.clone())),
+        }
+    }
+
+    fn visit_set_index(&mut self, e: &expression::SetIndex) -> EvalResult {
+        let object = self.evaluate_expr(&e.object)?;
+        let index = self.evaluate_expr(&e.index)?;
+        let value = self.evaluate_expr(&e.value)?;
+
+        match object {
+            RuntimeValue::String(_) => Err(RuntimeError::StringsAreImmutable(e.bracket.clone())),
+            RuntimeValue::Array(array) => {
+                let mut array = array.borrow_mut();
+                let i = as_index(&index, array.len(), &e.bracket)?;
+                array[i] = value.clone();
+                Ok(value)
+            },
+            _ => Err(RuntimeError::NotIndexable(e.bracket.clone())),
+        }
+    }
+}
+
+impl Interpreter {
+    // Applies the operator of `e` to its evaluated operands.
+    fn binary(&mut self, left: RuntimeValue, right: RuntimeValue, e: &expression::Binary) -> EvalResult {
+        if let RuntimeValue::Instance(instance) = &left {
+            if let Some(v) = self.call_operator_method(instance, &e.operator, &right)? {
+                return Ok(v);
+            }
+        }
 
         match e.operator.token_type {
             TokenType::EqualEqual => {
@@ -98,29 +334,33 @@ impl expression::Visitor<EvalResult> for Interpreter {
                 eval_bin_num_operator(&left, &right, |a, b| RuntimeValue::Number(a - b), &e.operator)
             },
             TokenType::Slash => {
-                match (&left, &right) {
-                    (RuntimeValue::Number(a), RuntimeValue::Number(b)) => {
-                        if *b == 0_f64 {
-                            Err(RuntimeError::DivisionByZero(e.operator.clone()))
-                        }
-                        else {
-                            Ok(RuntimeValue::Number(a / b))
-                        }
-                    },
-                    _ => {
-                        Err(RuntimeError::BinaryOperatorExpectsNumbers(e.operator.clone()))
-                    },
-                }
+                eval_division(&left, &right, |a, b| a / b, &e.operator)
+            },
+            TokenType::Div => {
+                eval_division(&left, &right, |a, b| (a / b).floor(), &e.operator)
+            },
+            TokenType::Percent => {
+                eval_division(&left, &right, |a, b| a - b * (a / b).floor(), &e.operator)
             },
             TokenType::Plus => {
-                match (&left, &right) {
+                match (left, right) {
                     (RuntimeValue::Number(a), RuntimeValue::Number(b)) => {
                         Ok(RuntimeValue::Number(a + b))
                     },
-                    (RuntimeValue::String(a), RuntimeValue::String(b)) => {
-                        let mut c = a.clone();
-                        c += b;
-                        Ok(RuntimeValue::String(c))
+                    (RuntimeValue::String(mut a), RuntimeValue::String(b)) => {
+                        self.concat(&mut a, &b, &e.operator)?;
+                        Ok(RuntimeValue::String(a))
+                    },
+                    (RuntimeValue::String(mut a), right @ RuntimeValue::Instance(_)) => {
+                        let b = self.stringify(&right, &e.operator)?;
+                        self.concat(&mut a, &b, &e.operator)?;
+                        Ok(RuntimeValue::String(a))
+                    },
+                    (left @ RuntimeValue::Instance(_), RuntimeValue::String(b)) => {
+                        let mut a = self.stringify(&left, &e.operator)?;
+                        self.charge_string(a.len() + b.len(), &e.operator)?;
+                        a.push_str(&b);
+                        Ok(RuntimeValue::String(a.into()))
                     },
                     _ => {
                         Err(RuntimeError::BinaryPlusExpectsTwoNumbersOrTwoStrings(e.operator.clone()))
@@ -136,107 +376,148 @@ impl expression::Visitor<EvalResult> for Interpreter {
         }
     }
 
-    fn visit_logical(&mut self, e: &expression::Logical) -> EvalResult {
-        let left = self.evaluate_expr(&e.left)?;
-        let left_truthy = is_truthy(&left);
+    fn assign_to(&mut self, e: &expression::Assignment, value: RuntimeValue) -> bool {
+        match e.hops {
+            Some(_) => self.assign_var(&e.name, value, e.hops),
+            None => self.assign_global(&e.name, value, &e.slot),
+        }
+    }
 
-        match e.operator.token_type {
-            TokenType::Or => {
-                if left_truthy { 
-                    return Ok(left);
-                }
-            },
-            TokenType::And => {
-                if left_truthy == false {
-                    return Ok(left);
-                }
-            },
-            _ => panic!("expected logical operator"),
+    // Evaluates `x + y` for `x = x + y`. If x holds a string, the variable lets go
+    // of it before y is added, so that `concat` can append to it in place.
+    fn append(&mut self, e: &expression::Assignment, b: &expression::Binary) -> EvalResult {
+        let left = b.left.accept(self)?;
+        let right = b.right.accept(self)?;
+
+        if let (RuntimeValue::String(a), RuntimeValue::String(piece)) = (&left, &right) {
+            // evaluating y may have assigned something else to x
+            let current = b.left.accept(self)?;
+            if matches!(&current, RuntimeValue::String(c) if Rc::ptr_eq(c, a)) {
+                // counted while x still holds the string, so that
+                // measuring the memory in use finds it
+                self.charge_string_growth(a.len(), piece.len(), &b.operator)?;
+                drop(current);
+                self.assign_to(e, RuntimeValue::Nil);
+
+                let mut a = a.clone();
+                drop(left);
+                push_str(&mut a, piece);
+                return Ok(RuntimeValue::String(a));
+            }
         }
 
-        self.evaluate_expr(&e.right)
+        self.binary(left, right, b)
     }
 
-    fn visit_grouping(
-        &mut self,
-        e: &expression::Grouping,
-    ) -> EvalResult {
-        e.0.accept_rt_value(self)
-    }
+    // Adds `b` to the end of `a` with `push_str`.
+    fn concat(&mut self, a: &mut Rc<String>, b: &str, at: &Rc<Token>) -> Result<(), RuntimeError> {
+        if Rc::get_mut(a).is_some() {
+            self.charge_string_growth(a.len(), b.len(), at)?;
+        } else {
+            self.charge_string(a.len() + b.len(), at)?;
+        }
+        push_str(a, b);
 
-    fn visit_variable(
-        &mut self,
-        e: &expression::Variable,
-    ) -> EvalResult {
-        self.look_up_var(&e.name, e.hops)
+        Ok(())
     }
 
-    fn visit_this(&mut self, e: &expression::This) -> EvalResult {
-        self.look_up_var(&e.keyword, e.hops)
+    // Strings, numbers and booleans have built-in methods but no fields.
+    fn get_property(&mut self, object: RuntimeValue, name: &Rc<Token>) -> EvalResult {
+        match object {
+            RuntimeValue::Instance(instance) => {
+                instance.borrow()
+                    .get(&name.lexeme, &instance)
+                    .ok_or(RuntimeError::UndefinedProperty(name.clone()))
+            },
+            RuntimeValue::String(_) | RuntimeValue::Number(_) | RuntimeValue::Bool(_) => {
+                primitive::method(&object, &name.lexeme)
+                    .ok_or(RuntimeError::UndefinedProperty(name.clone()))
+            },
+            _ => {
+                Err(RuntimeError::OnlyInstancesHaveProperties(
+                    name.clone(),
+                ))
+            },
+        }
     }
 
-    fn visit_assignment(
-        &mut self,
-        e: &expression::Assignment,
-    ) -> EvalResult {
-        let v = self.evaluate_expr(&e.value)?;
+    // Methods that are called right away are called on
+    // their instance directly instead of being bound first.
+    fn call_property(&mut self, get: &expression::Get, e: &expression::Call) -> EvalResult {
+        let object = self.evaluate_expr(&get.object)?;
 
-        let var_exists = self.assign_var(&e.name, &v, e.hops);
-        if var_exists {
-            Ok(v)
-        }
-        else {
-            Err(RuntimeError::UndefinedVariable(e.name.clone()))
+        if let RuntimeValue::Instance(instance) = &object {
+            let is_field = instance.borrow().fields.contains_key(&get.name.lexeme);
+            if !is_field {
+                let method = self.cached_method(instance, get)?;
+
+                self.check_arity(method.callable.as_ref(), e)?;
+                let args = self.evaluate_args(&e.args)?;
+
+                return self.call_method(&method, instance, &args, &e.right_paren);
+            }
         }
+
+        let value = self.get_property(object, &get.name)?;
+        self.call_value(value, e)
     }
 
-    fn visit_call(&mut self, e: &expression::Call) -> EvalResult {
-        let value = self.evaluate_expr(&e.callee)?;
+    // The method `get` names in the class of `instance`, which is looked
+    // up only when the class differs from the one `get` found a method in last.
+    fn cached_method(&self, instance: &Gc<RefCell<Instance>>, get: &expression::Get) -> Result<Rc<CallableWrapper>, RuntimeError> {
+        let class = instance.borrow().class().clone();
+        if let Some(method) = get.method.get(&class) {
+            return Ok(method);
+        }
 
-        match value {
-            RuntimeValue::Callable(CallableWrapper { callable, closure }) => {
-                if callable.arity() != e.args.len() {
-                    return Err(RuntimeError::CallableArityMismatch {
-                        right_paren: e.right_paren.clone(),
-                        expected: callable.arity(),
-                        found: e.args.len(),
-                    });
-                }
+        let method = class.borrow()
+            .find_method(&get.name.lexeme)
+            .ok_or_else(|| RuntimeError::UndefinedProperty(get.name.clone()))?;
+        get.method.set(&class, &method);
 
-                let mut args = Vec::new();
-                for a in &e.args {
-                    args.push(self.evaluate_expr(a)?);
-                }
+        Ok(method)
+    }
+
+    pub(super) fn call_value(&mut self, value: RuntimeValue, e: &expression::Call) -> EvalResult {
+        self.call_with(&value, e.args.len(), |interp| interp.evaluate_args(&e.args), &e.right_paren)
+    }
 
-                callable.call(&args, self, &closure)
+    // Calls `callee` with the `arg_count` arguments `args` gives, which it is
+    // asked for only once the arity of the callee is known to match. Classes
+    // make an instance and run its initializer, if any. Instances of classes
+    // with a 'call' method are called through it.
+    pub(super) fn call_with<A: AsRef<[RuntimeValue]>>(
+        &mut self,
+        callee: &RuntimeValue,
+        arg_count: usize,
+        args: impl FnOnce(&mut Self) -> Result<A, RuntimeError>,
+        call_site: &Rc<Token>,
+    ) -> EvalResult {
+        match callee {
+            RuntimeValue::Callable(CallableWrapper { callable, closure, receiver }) => {
+                callable.check_arity(arg_count).map_err(|error| error.at(call_site))?;
+                let args = args(self)?;
+
+                self.call_callable(callable.as_ref(), args.as_ref(), closure, receiver.as_ref(), call_site)
             },
             RuntimeValue::Class(class) => {
-                let instance = Gc::new(RefCell::new(Instance::new(&class)));
+                self.charge_memory(memory::INSTANCE_BYTES, Some(call_site))?;
+                let instance = Gc::new(RefCell::new(Instance::new(class)));
 
-                match class.borrow().methods.get("init") {
+                let initializer = class.borrow().methods.get("init").cloned();
+                match initializer {
                     Some(initializer) => {
-                        if initializer.callable.arity() != e.args.len() {
-                            return Err(RuntimeError::CallableArityMismatch {
-                                right_paren: e.right_paren.clone(),
-                                expected: initializer.callable.arity(),
-                                found: e.args.len(),
-                            });
-                        }
+                        initializer.callable.check_arity(arg_count).map_err(|error| error.at(call_site))?;
+                        let args = args(self)?;
 
-                        let mut args = Vec::new();
-                        for a in &e.args {
-                            args.push(self.evaluate_expr(a)?);
-                        }
-
-                        let init = crate::bind_method(initializer, &instance);
-                        init.callable.call(&args, self, &init.closure)?;
+                        self.call_method(&initializer, &instance, args.as_ref(), call_site)?;
                     },
                     None => {
-                        if e.args.len() != 0 {
+                        if arg_count != 0 {
                             return Err(RuntimeError::CallableArityMismatch {
-                                right_paren: e.right_paren.clone(),
+                                right_paren: call_site.clone(),
                                 expected: 0,
-                                found: e.args.len(),
+                                found: arg_count,
                             });
                         }
                     }
@@ -244,77 +525,184 @@ impl expression::Visitor<EvalResult> for Interpreter {
 
                 Ok(RuntimeValue::Instance(instance))
             },
+            RuntimeValue::Instance(instance) => {
+                // instances of classes with a 'call' method can be called like functions
+                let method = instance.borrow()
+                    .class
+                    .borrow()
+                    .find_method("call")
+                    .ok_or(RuntimeError::NonCallableCalled(call_site.clone()))?;
+
+                method.callable.check_arity(arg_count).map_err(|error| error.at(call_site))?;
+                let args = args(self)?;
+
+                self.call_method(&method, instance, args.as_ref(), call_site)
+            },
             _ => {
-                Err(RuntimeError::NonCallableCalled(e.right_paren.clone()))
+                Err(RuntimeError::NonCallableCalled(call_site.clone()))
             },
         }
     }
 
-    fn visit_get(&mut self, e: &expression::Get) -> EvalResult {
-        let expr = self.evaluate_expr(&e.object)?;
+    // The user-facing string for `value`. Instances of classes that
+    // define a 'toString' method are shown as the string it returns.
+    pub(super) fn stringify(&mut self, value: &RuntimeValue, at: &Rc<Token>) -> Result<String, RuntimeError> {
+        self.stringify_nested(value, at, &mut Vec::new())
+    }
 
-        if let RuntimeValue::Instance(instance) = expr {
-            instance.borrow()
-                .get(&e.name.lexeme, &instance)
-                .ok_or(RuntimeError::UndefinedProperty(e.name.clone()))
-        }
-        else {
-            Err(RuntimeError::OnlyInstancesHaveProperties(
-                e.name.clone(),
-            ))
+    // `open` holds the arrays and maps being stringified, which are
+    // shown as `[...]` and `{...}` where they contain themselves.
+    fn stringify_nested(
+        &mut self,
+        value: &RuntimeValue,
+        at: &Rc<Token>,
+        open: &mut Vec<*const ()>,
+    ) -> Result<String, RuntimeError> {
+        let instance = match value {
+            RuntimeValue::Instance(instance) => instance,
+            RuntimeValue::Array(array) => return self.stringify_array(array, at, open),
+            RuntimeValue::Map(map) => return self.stringify_map(map, at, open),
+            _ => return Ok(value.to_string()),
+        };
+
+        let method = instance.borrow()
+            .class
+            .borrow()
+            .find_method("toString");
+        let method = match method {
+            // the default is also used for objects that show themselves too deeply
+            Some(m) if m.callable.check_arity(0).is_ok() && self.to_string_depth < MAX_TO_STRING_DEPTH => m,
+            _ => return Ok(value.to_string()),
+        };
+
+        self.to_string_depth += 1;
+        let result = self.call_method(&method, instance, &[], at);
+        self.to_string_depth -= 1;
+
+        match result? {
+            RuntimeValue::String(s) => Ok(s.to_string()),
+            _ => Err(RuntimeError::ToStringMustReturnString(at.clone())),
         }
     }
 
-    fn visit_set(&mut self, e: &expression::Set) -> EvalResult {
-        let expr = self.evaluate_expr(&e.object)?;
+    fn stringify_array(
+        &mut self,
+        array: &Gc<RefCell<Vec<RuntimeValue>>>,
+        at: &Rc<Token>,
+        open: &mut Vec<*const ()>,
+    ) -> Result<String, RuntimeError> {
+        let ptr = Gc::as_ptr(array) as *const ();
+        if open.contains(&ptr) {
+            return Ok("[...]".to_owned());
+        }
 
-        if let RuntimeValue::Instance(instance) = expr {
-            let v = self.evaluate_expr(&e.value)?;
-            instance.borrow_mut().set(&e.name.lexeme, &v);
-            Ok(v)
+        // 'toString' methods may change the array
+        let elements = array.borrow().clone();
+        open.push(ptr);
+        let mut parts = Vec::with_capacity(elements.len());
+        for element in &elements {
+            parts.push(self.stringify_nested(element, at, open)?);
         }
-        else {
-            Err(RuntimeError::OnlyInstancesHaveProperties(
-                e.name.clone(),
-            ))
+        open.pop();
+
+        Ok(format!("[{}]", parts.join(", ")))
+    }
+
+    fn stringify_map(
+        &mut self,
+        map: &Gc<RefCell<Map>>,
+        at: &Rc<Token>,
+        open: &mut Vec<*const ()>,
+    ) -> Result<String, RuntimeError> {
+        let ptr = Gc::as_ptr(map) as *const ();
+        if open.contains(&ptr) {
+            return Ok("{...}".to_owned());
         }
+
+        // 'toString' methods may change the map
+        let entries: Vec<(RuntimeValue, RuntimeValue)> = map.borrow()
+            .iter()
+            .map(|(key, value)| (key.to_value(), value.clone()))
+            .collect();
+        open.push(ptr);
+        let mut parts = Vec::with_capacity(entries.len());
+        for (key, value) in &entries {
+            parts.push(format!("{}: {}", key, self.stringify_nested(value, at, open)?));
+        }
+        open.pop();
+
+        Ok(format!("{{{}}}", parts.join(", ")))
     }
 
-    fn visit_super(&mut self, e: &expression::Super) -> EvalResult {
-        let super_class = self.look_up_var(&e.keyword, e.hops_to_super)?;
-        if let RuntimeValue::Class(sup) = &super_class {
-            let this_token = Token {
-                token_type: TokenType::This,
-                lexeme: "this".to_owned(),
-                literal: None,
-                line: 0,
-                column: 0
-            };
-            let instance = self.look_up_var(&this_token, e.hops_to_this)?;
-            if let RuntimeValue::Instance(obj) = instance {
-                    let method = sup
-                    .borrow()
-                    .find_method(&e.method.lexeme)
-                    .ok_or(RuntimeError::UndefinedProperty(e.method.clone()))?;
+    // Binary operators on instances dispatch to the method of the left operand
+    // named after the operator, if its class defines one. '!=' negates 'eq'.
+    fn call_operator_method(
+        &mut self,
+        instance: &Gc<RefCell<Instance>>,
+        operator: &Rc<Token>,
+        right: &RuntimeValue,
+    ) -> Result<Option<RuntimeValue>, RuntimeError> {
+        let name = match operator_method_name(&operator.token_type) {
+            Some(name) => name,
+            None => return Ok(None),
+        };
 
-                return Ok(RuntimeValue::Callable(bind_method(&method, &obj)));
-            }
-            else {
-                panic!("'this' evaluated wrong");
-            }
+        let method = instance.borrow()
+            .class
+            .borrow()
+            .find_method(name);
+        let method = match method {
+            Some(m) => m,
+            None => return Ok(None),
+        };
+
+        method.callable
+            .check_arity(1)
+            .map_err(|e| e.at(operator))?;
+
+        let result = self.call_method(&method, instance, std::slice::from_ref(right), operator)?;
+
+        if operator.token_type == TokenType::BangEqual {
+            Ok(Some(RuntimeValue::Bool(!self.options.truthiness.is_truthy(&result))))
         }
         else {
-            // should never happen
-            panic!("'super' evaluated wrong");
+            Ok(Some(result))
         }
     }
+
+    // Calls a method on `receiver` without binding it first.
+    pub(super) fn call_method(
+        &mut self,
+        method: &CallableWrapper,
+        receiver: &Gc<RefCell<Instance>>,
+        args: &[RuntimeValue],
+        call_site: &Rc<Token>,
+    ) -> EvalResult {
+        self.call_callable(method.callable.as_ref(), args, &method.closure, Some(receiver), call_site)
+    }
+
+    pub(super) fn check_arity(&self, callable: &dyn Callable, e: &expression::Call) -> Result<(), RuntimeError> {
+        callable
+            .check_arity(e.args.len())
+            .map_err(|error| error.at(&e.right_paren))
+    }
+
+    // Calls with few arguments, which most are, keep them on the stack.
+    pub(super) fn evaluate_args(&mut self, args: &[expression::Expr]) -> Result<SmallVec<[RuntimeValue; 4]>, RuntimeError> {
+        let mut values = SmallVec::new();
+        for a in args {
+            values.push(self.evaluate_expr(a)?);
+        }
+
+        Ok(values)
+    }
 }
 
 fn eval_bin_num_operator(
     left: &RuntimeValue,
     right: &RuntimeValue,
     f: impl Fn(f64, f64) -> RuntimeValue,
-    op: &Token,
+    op: &Rc<Token>,
 ) -> EvalResult {
     match (left, right) {
         (RuntimeValue::Number(a), RuntimeValue::Number(b)) => {
@@ -326,12 +714,128 @@ fn eval_bin_num_operator(
     }
 }
 
-fn are_equal(a: &RuntimeValue, b: &RuntimeValue) -> bool {
+// `div` and `%` floor, so the remainder takes the sign of the divisor
+// and `a == (a div b) * b + a % b` holds for negative operands too.
+fn eval_division(
+    left: &RuntimeValue,
+    right: &RuntimeValue,
+    f: impl Fn(f64, f64) -> f64,
+    op: &Rc<Token>,
+) -> EvalResult {
+    match (left, right) {
+        (RuntimeValue::Number(a), RuntimeValue::Number(b)) => {
+            if *b == 0_f64 {
+                Err(RuntimeError::DivisionByZero(op.clone()))
+            }
+            else {
+                Ok(RuntimeValue::Number(f(*a, *b)))
+            }
+        },
+        _ => {
+            Err(RuntimeError::BinaryOperatorExpectsNumbers(op.clone()))
+        },
+    }
+}
+
+// Indices count characters or elements from the start, so negative ones are out of range.
+pub(super) fn as_index(index: &RuntimeValue, len: usize, bracket: &Rc<Token>) -> Result<usize, RuntimeError> {
+    let n = match index {
+        RuntimeValue::Number(n) if n.fract() == 0_f64 => *n,
+        _ => return Err(RuntimeError::IndexMustBeInteger(bracket.clone())),
+    };
+
+    if n < 0_f64 || n >= len as f64 {
+        return Err(RuntimeError::IndexOutOfRange {
+            index: n as i64,
+            len,
+            bracket: bracket.clone(),
+        });
+    }
+
+    Ok(n as usize)
+}
+
+fn operator_method_name(operator: &TokenType) -> Option<&'static str> {
+    let name = match operator {
+        TokenType::Plus => "plus",
+        TokenType::Minus => "minus",
+        TokenType::Star => "times",
+        TokenType::Slash => "divide",
+        TokenType::Div => "div",
+        TokenType::Percent => "mod",
+        TokenType::EqualEqual | TokenType::BangEqual => "eq",
+        TokenType::Less => "lt",
+        TokenType::LessEqual => "le",
+        TokenType::Greater => "gt",
+        TokenType::GreaterEqual => "ge",
+        _ => return None,
+    };
+
+    Some(name)
+}
+
+fn is_instance_of(value: &RuntimeValue, class: &Gc<RefCell<Class>>) -> bool {
+    let mut current = match value {
+        RuntimeValue::Instance(instance) => Some(instance.borrow().class.clone()),
+        _ => None,
+    };
+
+    while let Some(c) = current {
+        if Gc::ptr_eq(&c, class) {
+            return true;
+        }
+        current = c.borrow().super_class.clone();
+    }
+
+    false
+}
+
+// `None` if `type_name` is not a builtin type
+fn has_builtin_type(value: &RuntimeValue, type_name: &str) -> Option<bool> {
+    let result = match type_name {
+        "Number" => matches!(value, RuntimeValue::Number(_)),
+        "String" => matches!(value, RuntimeValue::String(_)),
+        "Bool" => matches!(value, RuntimeValue::Bool(_)),
+        "Nil" => matches!(value, RuntimeValue::Nil),
+        "Function" => matches!(value, RuntimeValue::Callable(_)),
+        "Array" => matches!(value, RuntimeValue::Array(_)),
+        "Map" => matches!(value, RuntimeValue::Map(_)),
+        "Range" => matches!(value, RuntimeValue::Range(_)),
+        _ => return None,
+    };
+
+    Some(result)
+}
+
+pub(super) fn are_equal(a: &RuntimeValue, b: &RuntimeValue) -> bool {
     match (a, b) {
         (RuntimeValue::Nil, RuntimeValue::Nil) => true,
         (RuntimeValue::Bool(x), RuntimeValue::Bool(y)) => x == y,
         (RuntimeValue::Number(x), RuntimeValue::Number(y)) => x == y,
         (RuntimeValue::String(x), RuntimeValue::String(y)) => x == y,
+        (RuntimeValue::Class(x), RuntimeValue::Class(y)) => Gc::ptr_eq(x, y),
+        (RuntimeValue::Instance(x), RuntimeValue::Instance(y)) => Gc::ptr_eq(x, y),
+        (RuntimeValue::Array(x), RuntimeValue::Array(y)) => Gc::ptr_eq(x, y),
+        (RuntimeValue::Map(x), RuntimeValue::Map(y)) => Gc::ptr_eq(x, y),
+        (RuntimeValue::Range(x), RuntimeValue::Range(y)) => x == y,
+        (RuntimeValue::Callable(x), RuntimeValue::Callable(y)) => x.is_same(y),
         _ => false,
     }
-}
\ No newline at end of file
+}
+
+// Whether `value` of `e` is `x + y`, where x is the variable `e` assigns to.
+fn is_append_to(e: &expression::Assignment, value: &expression::Binary) -> bool {
+    value.operator.token_type == TokenType::Plus && matches!(
+        &*value.left,
+        expression::Expr::Variable(v) if v.name.lexeme == e.name.lexeme && v.hops == e.hops
+    )
+}
+
+// Adds `b` to the end of `a`, in place if nothing else holds the string
+// of `a`, which makes building a string piece by piece take linear time.
+fn push_str(a: &mut Rc<String>, b: &str) {
+    match Rc::get_mut(a) {
+        Some(s) => s.push_str(b),
+        None => *a = Rc::new([a.as_str(), b].concat()),
+    }
+}
@@ -0,0 +1,186 @@
+use crate::{
+    RuntimeValue,
+    CallableWrapper,
+    Class,
+    Instance,
+    Map,
+    MapKey,
+    gc_stats,
+    Gc,
+    Rc,
+    RefCell,
+};
+use std::collections::HashMap;
+use super::{env::Environment, memory};
+
+// Deep copies values. Objects reachable through several paths, or
+// through cycles, are copied once and the copies are shared the same way.
+#[derive(Default)]
+pub(super) struct Copier {
+    // classes and functions, with the environments they close over,
+    // are shared with the original instead of copied
+    share_code: bool,
+    envs: HashMap<*const RefCell<Environment>, Gc<RefCell<Environment>>>,
+    classes: HashMap<*const RefCell<Class>, Gc<RefCell<Class>>>,
+    instances: HashMap<*const RefCell<Instance>, Gc<RefCell<Instance>>>,
+    arrays: HashMap<*const RefCell<Vec<RuntimeValue>>, Gc<RefCell<Vec<RuntimeValue>>>>,
+    maps: HashMap<*const RefCell<Map>, Gc<RefCell<Map>>>,
+    // what the instances, arrays and maps copied so far count against the memory limit
+    bytes: usize,
+}
+
+impl Copier {
+    // A copier for data: only instances, arrays and maps are copied.
+    pub(super) fn data_only() -> Self {
+        Self { share_code: true, ..Self::default() }
+    }
+
+    pub(super) fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub(super) fn env(&mut self, env: &Gc<RefCell<Environment>>) -> Gc<RefCell<Environment>> {
+        let key = Gc::as_ptr(env);
+        if let Some(copy) = self.envs.get(&key) {
+            return copy.clone();
+        }
+
+        let parent = env.borrow().parent.clone();
+        let copy = match parent {
+            Some(parent) => Environment::child(self.env(&parent)),
+            None => Environment::root(),
+        };
+        // registered before copying the bindings, which may refer back to it
+        let copy = Gc::new(RefCell::new(copy));
+        self.envs.insert(key, copy.clone());
+
+        let bindings: Vec<(String, RuntimeValue)> = env.borrow()
+            .bindings()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        for (name, value) in bindings {
+            let value = self.value(&value);
+            copy.borrow_mut().define(name, value);
+        }
+
+        copy
+    }
+
+    pub(super) fn class(&mut self, class: &Gc<RefCell<Class>>) -> Gc<RefCell<Class>> {
+        let key = Gc::as_ptr(class);
+        if let Some(copy) = self.classes.get(&key) {
+            return copy.clone();
+        }
+
+        let (name, super_class) = {
+            let class = class.borrow();
+            (class.name.clone(), class.super_class.clone())
+        };
+        let super_class = super_class.map(|s| self.class(&s));
+        let copy = Gc::new(RefCell::new(
+            Class::new(&name, super_class, HashMap::new())
+        ));
+        self.classes.insert(key, copy.clone());
+
+        let methods: Vec<(String, Rc<CallableWrapper>)> = class.borrow()
+            .methods
+            .iter()
+            .map(|(name, method)| (name.clone(), method.clone()))
+            .collect();
+        for (name, method) in methods {
+            let method = self.callable(&method);
+            copy.borrow_mut().methods.insert(name, Rc::new(method));
+        }
+
+        copy
+    }
+
+    fn instance(&mut self, instance: &Gc<RefCell<Instance>>) -> Gc<RefCell<Instance>> {
+        let key = Gc::as_ptr(instance);
+        if let Some(copy) = self.instances.get(&key) {
+            return copy.clone();
+        }
+
+        let class = instance.borrow().class.clone();
+        let class = if self.share_code { class } else { self.class(&class) };
+        let copy = Gc::new(RefCell::new(Instance::new(&class)));
+        self.instances.insert(key, copy.clone());
+
+        let fields: Vec<(String, RuntimeValue)> = instance.borrow()
+            .fields
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        self.bytes += memory::INSTANCE_BYTES;
+        for (name, value) in fields {
+            self.bytes += memory::field_bytes(&name);
+            let value = self.value(&value);
+            copy.borrow_mut().set(&name, &value);
+        }
+
+        copy
+    }
+
+    fn array(&mut self, array: &Gc<RefCell<Vec<RuntimeValue>>>) -> Gc<RefCell<Vec<RuntimeValue>>> {
+        let key = Gc::as_ptr(array);
+        if let Some(copy) = self.arrays.get(&key) {
+            return copy.clone();
+        }
+
+        gc_stats::allocated();
+        let copy = Gc::new(RefCell::new(Vec::new()));
+        self.arrays.insert(key, copy.clone());
+
+        let elements = array.borrow().clone();
+        self.bytes += memory::ARRAY_BYTES + elements.len() * memory::VALUE_BYTES;
+        let elements = elements.iter().map(|e| self.value(e)).collect();
+        *copy.borrow_mut() = elements;
+
+        copy
+    }
+
+    fn map(&mut self, map: &Gc<RefCell<Map>>) -> Gc<RefCell<Map>> {
+        let key = Gc::as_ptr(map);
+        if let Some(copy) = self.maps.get(&key) {
+            return copy.clone();
+        }
+
+        gc_stats::allocated();
+        let copy = Gc::new(RefCell::new(Map::default()));
+        self.maps.insert(key, copy.clone());
+
+        let entries = map.borrow().clone();
+        self.bytes += memory::MAP_BYTES + entries.len() * memory::ENTRY_BYTES;
+        for (key, value) in entries.iter() {
+            // a copy of an instance has the same fields, so it has the same hash code
+            let key = match key {
+                MapKey::Instance(code, instance) => MapKey::Instance(*code, self.instance(instance)),
+                key => key.clone(),
+            };
+            let value = self.value(value);
+            copy.borrow_mut().insert(key, value);
+        }
+
+        copy
+    }
+
+    fn callable(&mut self, wrapper: &CallableWrapper) -> CallableWrapper {
+        CallableWrapper {
+            callable: wrapper.callable.clone(),
+            closure: wrapper.closure.as_ref().map(|c| self.env(c)),
+            receiver: wrapper.receiver.as_ref().map(|r| self.instance(r)),
+        }
+    }
+
+    pub(super) fn value(&mut self, value: &RuntimeValue) -> RuntimeValue {
+        match value {
+            RuntimeValue::Callable(_) | RuntimeValue::Class(_) if self.share_code => value.clone(),
+            RuntimeValue::Callable(wrapper) => RuntimeValue::Callable(self.callable(wrapper)),
+            RuntimeValue::Class(class) => RuntimeValue::Class(self.class(class)),
+            RuntimeValue::Instance(instance) => RuntimeValue::Instance(self.instance(instance)),
+            RuntimeValue::Array(array) => RuntimeValue::Array(self.array(array)),
+            RuntimeValue::Map(map) => RuntimeValue::Map(self.map(map)),
+            _ => value.clone(),
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use crate::{
+    RuntimeValue,
+    RuntimeError,
+    Range,
+    CallableWrapper,
+    Instance,
+    scanner::Token,
+    Gc,
+    Rc,
+    RefCell,
+};
+use super::Interpreter;
+
+// The values a for-in loop goes through.
+pub(super) enum Iteration {
+    // The elements of an array, the keys of a map or the characters of a string,
+    // taken when the loop starts. Changing the collection in the body of the
+    // loop does not change the values the loop goes through.
+    Values(std::vec::IntoIter<RuntimeValue>),
+    // The numbers of a range, computed one at a time.
+    Range {
+        range: Range,
+        index: usize,
+    },
+    // An object returned by the 'iter' method of an instance,
+    // whose 'next' method is called until it returns nil.
+    Iterator {
+        iterator: Gc<RefCell<Instance>>,
+        next: Rc<CallableWrapper>,
+    },
+}
+
+impl Interpreter {
+    pub(super) fn iteration(&mut self, iterable: &RuntimeValue, keyword: &Rc<Token>) -> Result<Iteration, RuntimeError> {
+        let values = match iterable {
+            RuntimeValue::Array(array) => array.borrow().clone(),
+            RuntimeValue::Map(map) => map.borrow().iter().map(|(key, _)| key.to_value()).collect(),
+            RuntimeValue::String(s) => s.chars().map(|c| c.to_string().into()).collect(),
+            RuntimeValue::Range(range) => return Ok(Iteration::Range { range: *range, index: 0 }),
+            RuntimeValue::Instance(instance) => return self.instance_iteration(instance, keyword),
+            _ => return Err(RuntimeError::NotIterable(keyword.clone())),
+        };
+
+        Ok(Iteration::Values(values.into_iter()))
+    }
+
+    fn instance_iteration(&mut self, instance: &Gc<RefCell<Instance>>, keyword: &Rc<Token>) -> Result<Iteration, RuntimeError> {
+        let iter = find_method(instance, "iter")
+            .ok_or_else(|| RuntimeError::NotIterable(keyword.clone()))?;
+        iter.callable.check_arity(0).map_err(|e| e.at(keyword))?;
+
+        let iterator = match self.call_method(&iter, instance, &[], keyword)? {
+            RuntimeValue::Instance(iterator) => iterator,
+            _ => return Err(RuntimeError::IteratorWithoutNext(keyword.clone())),
+        };
+        let next = find_method(&iterator, "next")
+            .ok_or_else(|| RuntimeError::IteratorWithoutNext(keyword.clone()))?;
+        next.callable.check_arity(0).map_err(|e| e.at(keyword))?;
+
+        Ok(Iteration::Iterator { iterator, next })
+    }
+
+    // None once the iteration is over.
+    pub(super) fn next_value(&mut self, iteration: &mut Iteration, keyword: &Rc<Token>) -> Result<Option<RuntimeValue>, RuntimeError> {
+        match iteration {
+            Iteration::Values(values) => Ok(values.next()),
+            Iteration::Range { range, index } => {
+                let n = range.get(*index);
+                *index += 1;
+                Ok(n.map(RuntimeValue::Number))
+            },
+            Iteration::Iterator { iterator, next } => {
+                match self.call_method(next, iterator, &[], keyword)? {
+                    RuntimeValue::Nil => Ok(None),
+                    value => Ok(Some(value)),
+                }
+            },
+        }
+    }
+}
+
+fn find_method(instance: &Gc<RefCell<Instance>>, name: &str) -> Option<Rc<CallableWrapper>> {
+    instance.borrow()
+        .class
+        .borrow()
+        .find_method(name)
+}
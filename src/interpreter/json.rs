@@ -0,0 +1,389 @@
+use crate::{
+    Map,
+    MapKey,
+    RuntimeValue,
+    Gc,
+};
+use std::fmt::Write;
+
+// deeper documents are rejected rather than risking a stack overflow
+const MAX_DEPTH: usize = 512;
+
+// Why a document could not be parsed, and the byte it was noticed at.
+#[derive(Debug)]
+pub(super) struct ParseError {
+    pub offset: usize,
+    pub message: String,
+}
+
+// Parses a JSON document into nested maps, arrays, strings, numbers, booleans and nil.
+pub(super) fn parse(source: &str) -> Result<RuntimeValue, ParseError> {
+    let mut parser = Parser { source: source.as_bytes(), pos: 0, depth: 0 };
+    parser.skip_whitespace();
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.pos < source.len() {
+        return Err(parser.error("unexpected data after the document"));
+    }
+
+    Ok(value)
+}
+
+struct Parser<'a> {
+    source: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> ParseError {
+        ParseError { offset: self.pos, message: message.to_owned() }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.source.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), ParseError> {
+        if self.peek() != Some(byte) {
+            return Err(self.error(&format!("expected '{}'", byte as char)));
+        }
+
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<RuntimeValue, ParseError> {
+        match self.peek() {
+            Some(b'{') => self.nested(Self::object),
+            Some(b'[') => self.nested(Self::array),
+            Some(b'"') => Ok(RuntimeValue::String(self.string()?.into())),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(b't') => self.keyword("true", RuntimeValue::Bool(true)),
+            Some(b'f') => self.keyword("false", RuntimeValue::Bool(false)),
+            Some(b'n') => self.keyword("null", RuntimeValue::Nil),
+            Some(_) => Err(self.error("expected a value")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<RuntimeValue, ParseError>) -> Result<RuntimeValue, ParseError> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error("document nested too deeply"));
+        }
+
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+
+        value
+    }
+
+    fn keyword(&mut self, word: &str, value: RuntimeValue) -> Result<RuntimeValue, ParseError> {
+        if !self.source[self.pos..].starts_with(word.as_bytes()) {
+            return Err(self.error("expected a value"));
+        }
+
+        self.pos += word.len();
+        Ok(value)
+    }
+
+    fn object(&mut self) -> Result<RuntimeValue, ParseError> {
+        self.expect(b'{')?;
+        let mut map = Map::default();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(RuntimeValue::from(map));
+        }
+
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.error("expected a string key"));
+            }
+            let key = self.string()?;
+            self.skip_whitespace();
+            self.expect(b':')?;
+            self.skip_whitespace();
+            let value = self.value()?;
+            // later duplicates win, as in most parsers
+            map.insert(MapKey::String(key.into()), value);
+
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(RuntimeValue::from(map));
+                },
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<RuntimeValue, ParseError> {
+        self.expect(b'[')?;
+        let mut elements = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(RuntimeValue::from(elements));
+        }
+
+        loop {
+            self.skip_whitespace();
+            elements.push(self.value()?);
+
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(RuntimeValue::from(elements));
+                },
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, ParseError> {
+        self.expect(b'"')?;
+        let mut result = String::new();
+
+        loop {
+            // copy everything up to the next quote, escape or control character at once
+            let start = self.pos;
+            while let Some(b) = self.peek() {
+                if b == b'"' || b == b'\\' || b < 0x20 {
+                    break;
+                }
+                self.pos += 1;
+            }
+            // the source is a &str and the run ends at an ASCII byte, so it is valid UTF-8
+            result.push_str(std::str::from_utf8(&self.source[start..self.pos]).unwrap());
+
+            match self.peek() {
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(result);
+                },
+                Some(b'\\') => {
+                    self.pos += 1;
+                    result.push(self.escape()?);
+                },
+                Some(_) => return Err(self.error("control character in string")),
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    // the character of the escape sequence after a backslash
+    fn escape(&mut self) -> Result<char, ParseError> {
+        let c = match self.peek() {
+            Some(b'"') => '"',
+            Some(b'\\') => '\\',
+            Some(b'/') => '/',
+            Some(b'b') => '\u{8}',
+            Some(b'f') => '\u{c}',
+            Some(b'n') => '\n',
+            Some(b'r') => '\r',
+            Some(b't') => '\t',
+            Some(b'u') => {
+                self.pos += 1;
+                return self.unicode_escape();
+            },
+            _ => return Err(self.error("invalid escape sequence")),
+        };
+
+        self.pos += 1;
+        Ok(c)
+    }
+
+    // \uXXXX, where characters outside the Basic Multilingual
+    // Plane are written as a pair of surrogates
+    fn unicode_escape(&mut self) -> Result<char, ParseError> {
+        let start = self.pos;
+        let high = self.hex4()?;
+        let code = match high {
+            0xD800..=0xDBFF => {
+                if !self.source[self.pos..].starts_with(b"\\u") {
+                    return Err(ParseError { offset: start, message: "unpaired surrogate".to_owned() });
+                }
+                self.pos += 2;
+                let low = self.hex4()?;
+                if !(0xDC00..=0xDFFF).contains(&low) {
+                    return Err(ParseError { offset: start, message: "unpaired surrogate".to_owned() });
+                }
+                0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+            },
+            0xDC00..=0xDFFF => {
+                return Err(ParseError { offset: start, message: "unpaired surrogate".to_owned() });
+            },
+            code => code,
+        };
+
+        Ok(char::from_u32(code).expect("a valid code point"))
+    }
+
+    fn hex4(&mut self) -> Result<u32, ParseError> {
+        let digits = self.source.get(self.pos..self.pos + 4)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .filter(|digits| digits.bytes().all(|b| b.is_ascii_hexdigit()))
+            .ok_or_else(|| self.error("expected 4 hexadecimal digits"))?;
+        let value = u32::from_str_radix(digits, 16).unwrap();
+
+        self.pos += 4;
+        Ok(value)
+    }
+
+    fn number(&mut self) -> Result<RuntimeValue, ParseError> {
+        let start = self.pos;
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+
+        match self.peek() {
+            Some(b'0') => self.pos += 1,
+            Some(b'1'..=b'9') => self.digits(),
+            _ => return Err(self.error("expected a digit")),
+        }
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            self.required_digits()?;
+        }
+        if let Some(b'e' | b'E') = self.peek() {
+            self.pos += 1;
+            if let Some(b'+' | b'-') = self.peek() {
+                self.pos += 1;
+            }
+            self.required_digits()?;
+        }
+
+        // the grammar above only accepts ASCII, which Rust parses as a float
+        let text = std::str::from_utf8(&self.source[start..self.pos]).unwrap();
+        Ok(RuntimeValue::Number(text.parse().unwrap()))
+    }
+
+    fn digits(&mut self) {
+        while let Some(b'0'..=b'9') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn required_digits(&mut self) -> Result<(), ParseError> {
+        if !matches!(self.peek(), Some(b'0'..=b'9')) {
+            return Err(self.error("expected a digit"));
+        }
+
+        self.digits();
+        Ok(())
+    }
+}
+
+// Writes `value` as compact JSON. Maps need string keys, instances are written
+// as objects of their fields, and numbers need to be finite. The error is
+// a description of the first value that cannot be written.
+pub(super) fn stringify(value: &RuntimeValue) -> Result<String, String> {
+    let mut out = String::new();
+    write_value(&mut out, value, &mut Vec::new())?;
+    Ok(out)
+}
+
+// `open` holds the arrays, maps and instances being written, to reject cycles.
+fn write_value(out: &mut String, value: &RuntimeValue, open: &mut Vec<*const ()>) -> Result<(), String> {
+    match value {
+        RuntimeValue::Nil => out.push_str("null"),
+        RuntimeValue::Bool(b) => write!(out, "{}", b).unwrap(),
+        RuntimeValue::Number(n) if n.is_finite() => write!(out, "{}", n).unwrap(),
+        RuntimeValue::Number(n) => return Err(format!("{} is not a JSON number", value_name(*n))),
+        RuntimeValue::String(s) => write_string(out, s),
+        RuntimeValue::Array(array) => {
+            enter(open, Gc::as_ptr(array) as *const ())?;
+            out.push('[');
+            for (i, element) in array.borrow().iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_value(out, element, open)?;
+            }
+            out.push(']');
+            open.pop();
+        },
+        RuntimeValue::Map(map) => {
+            enter(open, Gc::as_ptr(map) as *const ())?;
+            out.push('{');
+            for (i, (key, value)) in map.borrow().iter().enumerate() {
+                let MapKey::String(key) = key else {
+                    return Err(format!("map key {} is not a string", key.to_value()));
+                };
+                if i > 0 {
+                    out.push(',');
+                }
+                write_string(out, key);
+                out.push(':');
+                write_value(out, value, open)?;
+            }
+            out.push('}');
+            open.pop();
+        },
+        RuntimeValue::Instance(instance) => {
+            enter(open, Gc::as_ptr(instance) as *const ())?;
+            out.push('{');
+            for (i, (name, value)) in instance.borrow().fields().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_string(out, name);
+                out.push(':');
+                write_value(out, value, open)?;
+            }
+            out.push('}');
+            open.pop();
+        },
+        RuntimeValue::Callable(_) |
+        RuntimeValue::Class(_) |
+        RuntimeValue::Range(_) => return Err(format!("{} is not a JSON value", value)),
+    }
+
+    Ok(())
+}
+
+fn value_name(n: f64) -> &'static str {
+    match n {
+        n if n.is_nan() => "nan",
+        n if n > 0_f64 => "infinity",
+        _ => "-infinity",
+    }
+}
+
+fn enter(open: &mut Vec<*const ()>, ptr: *const ()) -> Result<(), String> {
+    if open.contains(&ptr) {
+        return Err("a value that contains itself cannot be written".to_owned());
+    }
+
+    open.push(ptr);
+    Ok(())
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
@@ -0,0 +1,255 @@
+use crate::{
+    RuntimeValue,
+    RuntimeError,
+    Instance,
+    Map,
+    MapKey,
+    scanner::Token,
+    gc_stats,
+    Gc,
+    Rc,
+    RefCell,
+};
+use std::{
+    collections::HashSet,
+    mem::size_of,
+};
+use super::{Interpreter, env::Environment};
+
+// Strings shorter than this are not counted against the memory limit.
+// Most of them are names and short-lived pieces of text.
+pub(super) const STRING_THRESHOLD: usize = 64;
+
+// What the memory limit counts objects as. The sizes are of the objects
+// themselves and the slots of their elements, not of what allocators add.
+pub(crate) const ENV_BYTES: usize = size_of::<RefCell<Environment>>();
+pub(crate) const VALUE_BYTES: usize = size_of::<RuntimeValue>();
+pub(super) const INSTANCE_BYTES: usize = size_of::<RefCell<Instance>>();
+pub(super) const ARRAY_BYTES: usize = size_of::<RefCell<Vec<RuntimeValue>>>();
+pub(super) const MAP_BYTES: usize = size_of::<RefCell<Map>>();
+pub(super) const ENTRY_BYTES: usize = size_of::<(MapKey, RuntimeValue)>();
+
+impl Interpreter {
+    // Counts `bytes` against `options.max_memory_bytes`, failing instead if
+    // they would go over it. Nothing is credited back when objects are freed,
+    // so before failing, once at least half of what was left at the last
+    // measurement has been used, garbage is collected and what the script
+    // can still reach is measured again. The error is reported at `at`,
+    // or at the innermost call site if there is none.
+    pub(crate) fn charge_memory(&mut self, bytes: usize, at: Option<&Rc<Token>>) -> Result<(), RuntimeError> {
+        let limit = match self.options.max_memory_bytes {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+
+        if self.memory_used + bytes > limit {
+            let charged = self.memory_used.saturating_sub(self.memory_measured);
+            if charged > 0 && charged >= limit.saturating_sub(self.memory_measured) / 2 {
+                gc_stats::collect();
+                self.collected_at = gc_stats::allocations();
+                self.memory_used = self.reachable_bytes();
+                self.memory_measured = self.memory_used;
+            }
+
+            if self.memory_used + bytes > limit {
+                let token = match at {
+                    Some(token) => token.clone(),
+                    None => self.current_call_site(),
+                };
+                return Err(RuntimeError::MemoryLimitExceeded(token));
+            }
+        }
+
+        self.memory_used += bytes;
+        Ok(())
+    }
+
+    // Counts a new string of `len` bytes, if it is long enough to be counted.
+    pub(super) fn charge_string(&mut self, len: usize, at: &Rc<Token>) -> Result<(), RuntimeError> {
+        if len < STRING_THRESHOLD {
+            return Ok(());
+        }
+
+        self.charge_memory(len, Some(at))
+    }
+
+    // Counts the bytes a string of `len` bytes grows by in place,
+    // or all of its bytes if it was too short to be counted before.
+    pub(super) fn charge_string_growth(&mut self, len: usize, added: usize, at: &Rc<Token>) -> Result<(), RuntimeError> {
+        if len < STRING_THRESHOLD {
+            return self.charge_string(len + added, at);
+        }
+
+        self.charge_memory(added, Some(at))
+    }
+
+    // What a value returned by a native is counted as. Only the value itself is,
+    // as most natives return values built from ones counted when they were
+    // created. Natives that build new objects below it, like `clone` and
+    // `json_parse`, count those with `charge_contents`.
+    pub(super) fn charge_result(&mut self, value: &RuntimeValue) -> Result<(), RuntimeError> {
+        match result_bytes(value) {
+            0 => Ok(()),
+            bytes => self.charge_memory(bytes, None),
+        }
+    }
+
+    // Counts the `bytes` of the new objects a native built for `value`, but
+    // for `value` itself, which `charge_result` counts once the native returns.
+    pub(super) fn charge_contents(&mut self, value: &RuntimeValue, bytes: usize) -> Result<(), RuntimeError> {
+        self.charge_memory(bytes.saturating_sub(result_bytes(value)), None)
+    }
+
+    // Counts a field `instance` does not have yet, before it is set.
+    pub(super) fn charge_field(&mut self, instance: &Gc<RefCell<Instance>>, name: &str, at: Option<&Rc<Token>>) -> Result<(), RuntimeError> {
+        if instance.borrow().has_field(name) {
+            return Ok(());
+        }
+
+        self.charge_memory(field_bytes(name), at)
+    }
+
+    // The bytes of the objects reachable from the globals, the environment
+    // being executed in and those the calls in progress were made from.
+    // Objects that are borrowed mutably right now are skipped.
+    fn reachable_bytes(&self) -> usize {
+        let mut walk = Walk::default();
+        walk.envs.push(self.globals_env.clone());
+        walk.envs.push(self.current_env.clone());
+        walk.envs.extend(self.caller_envs.iter().cloned());
+        for (_, env) in self.deferred.iter().flatten() {
+            walk.envs.push(env.clone());
+        }
+
+        walk.run()
+    }
+}
+
+// A field is counted with its name, as every instance keeps its own.
+pub(super) fn field_bytes(name: &str) -> usize {
+    VALUE_BYTES + name.len()
+}
+
+fn result_bytes(value: &RuntimeValue) -> usize {
+    match value {
+        RuntimeValue::String(s) if s.len() >= STRING_THRESHOLD => s.len(),
+        RuntimeValue::Array(array) => ARRAY_BYTES + array.borrow().len() * VALUE_BYTES,
+        RuntimeValue::Map(map) => MAP_BYTES + map.borrow().len() * ENTRY_BYTES,
+        _ => 0,
+    }
+}
+
+// The bytes of the objects reachable from `value`. Functions and the
+// environments they close over are counted too, so this is meant for
+// plain data, e.g. a value parsed from JSON.
+pub(super) fn data_bytes(value: &RuntimeValue) -> usize {
+    let mut walk = Walk::default();
+    walk.values.push(value.clone());
+    walk.run()
+}
+
+// Goes through objects with explicit worklists, as
+// nested arrays and long chains of instances can be deep.
+#[derive(Default)]
+struct Walk {
+    seen: HashSet<*const ()>,
+    envs: Vec<Gc<RefCell<Environment>>>,
+    values: Vec<RuntimeValue>,
+    bytes: usize,
+}
+
+impl Walk {
+    fn run(mut self) -> usize {
+        loop {
+            if let Some(env) = self.envs.pop() {
+                self.env(&env);
+            } else if let Some(value) = self.values.pop() {
+                self.value(&value);
+            } else {
+                return self.bytes;
+            }
+        }
+    }
+
+    fn first_visit<T: ?Sized>(&mut self, ptr: *const T) -> bool {
+        self.seen.insert(ptr as *const ())
+    }
+
+    fn env(&mut self, env: &Gc<RefCell<Environment>>) {
+        if !self.first_visit(Gc::as_ptr(env)) {
+            return;
+        }
+        let env = match env.try_borrow() {
+            Ok(env) => env,
+            Err(_) => return,
+        };
+
+        self.bytes += ENV_BYTES;
+        for (_, value) in env.bindings() {
+            self.bytes += VALUE_BYTES;
+            self.values.push(value.clone());
+        }
+        if let Some(parent) = &env.parent {
+            self.envs.push(parent.clone());
+        }
+    }
+
+    fn value(&mut self, value: &RuntimeValue) {
+        match value {
+            RuntimeValue::String(s) if s.len() >= STRING_THRESHOLD && self.first_visit(Rc::as_ptr(s)) => {
+                self.bytes += s.len();
+            },
+            RuntimeValue::Callable(wrapper) => {
+                if let Some(closure) = &wrapper.closure {
+                    self.envs.push(closure.clone());
+                }
+                if let Some(receiver) = &wrapper.receiver {
+                    self.values.push(RuntimeValue::Instance(receiver.clone()));
+                }
+            },
+            RuntimeValue::Instance(instance) => {
+                if !self.first_visit(Gc::as_ptr(instance)) {
+                    return;
+                }
+                let instance = match instance.try_borrow() {
+                    Ok(instance) => instance,
+                    Err(_) => return,
+                };
+
+                self.bytes += INSTANCE_BYTES;
+                for (name, value) in instance.fields() {
+                    self.bytes += field_bytes(name);
+                    self.values.push(value.clone());
+                }
+            },
+            RuntimeValue::Array(array) => {
+                if !self.first_visit(Gc::as_ptr(array)) {
+                    return;
+                }
+                let array = match array.try_borrow() {
+                    Ok(array) => array,
+                    Err(_) => return,
+                };
+
+                self.bytes += ARRAY_BYTES + array.len() * VALUE_BYTES;
+                self.values.extend(array.iter().cloned());
+            },
+            RuntimeValue::Map(map) => {
+                if !self.first_visit(Gc::as_ptr(map)) {
+                    return;
+                }
+                let map = match map.try_borrow() {
+                    Ok(map) => map,
+                    Err(_) => return,
+                };
+
+                self.bytes += MAP_BYTES + map.len() * ENTRY_BYTES;
+                for (key, value) in map.iter() {
+                    self.values.push(key.to_value());
+                    self.values.push(value.clone());
+                }
+            },
+            _ => {},
+        }
+    }
+}
@@ -1,38 +1,218 @@
 mod eval;
+mod fork;
+mod iterate;
+mod json;
+mod memory;
+mod number_format;
+mod primitive;
 pub mod env;
+pub mod native;
+pub mod profile;
 
-use env::Environment;
+use env::{Environment, ScopeSnapshot, Slot};
+pub(crate) use memory::{ENV_BYTES, VALUE_BYTES};
+use profile::{ProfileEntry, Profiler};
 
 use crate::{
     expression,
     statement,
     RuntimeValue,
     RuntimeError,
-    is_truthy,
+    Truthiness,
     statement::StmtEffect,
-    scanner::Token,
+    scanner::{
+        Token,
+        TokenType,
+    },
     Class,
+    Instance,
     CallableWrapper,
+    Callable,
+    GcStats,
+    gc_stats,
+    Gc,
+    Rc,
+    RefCell,
+    Cell,
+    MaybeSendSync,
+    shared::{DynBufRead, DynWrite},
 };
 use dumpster::{
     Trace,
-    unsync::Gc,
     Visitor,
 };
 use std::{
-    cell::RefCell,
     collections::HashMap,
+    fmt,
+    io::{BufRead, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
 };
 
 pub struct Interpreter {
     globals_env: Gc<RefCell<Environment>>,
     current_env: Gc<RefCell<Environment>>,
+    options: InterpreterOptions,
+    call_stack: Vec<Frame>,
+    // the environment each call on `call_stack` was made from, which
+    // what the interpreter holds otherwise may not reach while it runs
+    caller_envs: Vec<Gc<RefCell<Environment>>>,
+    // statements deferred by each block being executed,
+    // along with the environment they were deferred in
+    deferred: Vec<Vec<Deferred>>,
+    // number of nested 'toString' calls made while stringifying
+    to_string_depth: usize,
+    // snapshot of `call_stack` taken where the last error was raised
+    error_stack: Option<Vec<Frame>>,
+    // the class of the values built-in errors are caught as
+    error_class: Gc<RefCell<Class>>,
+    // steps taken against `options.step_limit`
+    steps: u64,
+    // the loop or call site of the last step that had one,
+    // only tracked while there is a step limit
+    last_step_site: Option<Rc<Token>>,
+    // where 'print', 'write' and the execution trace write to
+    output: Box<DynWrite>,
+    // where 'eprint' writes to
+    error_output: Box<DynWrite>,
+    // where 'readline' reads from, the standard input if None,
+    // which is read without buffering ahead so the REPL can share it
+    input: Option<Box<DynBufRead>>,
+    // set while profiling is enabled
+    profiler: Option<Profiler>,
+    // the value of the last top-level expression statement,
+    // cleared by any other top-level statement
+    last_value: Option<RuntimeValue>,
+    // natives defined with `define_native`, kept across resets
+    embedder_natives: Vec<native::ClosureNative>,
+    // set by the host to stop the script, see `cancellation_flag`
+    cancelled: Arc<AtomicBool>,
+    // environments of finished calls to functions that create no closures,
+    // which nothing refers to once the call returns, kept for later calls
+    env_pool: Vec<Gc<RefCell<Environment>>>,
+    // the count of objects the collector manages at the last
+    // collection made for `options.gc_interval`
+    collected_at: usize,
+    // approximate bytes the script holds, for `options.max_memory_bytes`
+    memory_used: usize,
+    // what `memory_used` was when it was last measured
+    memory_measured: usize,
+    // variables set with 'setenv', which 'getenv' reads before
+    // the environment of the process, left unchanged as other
+    // threads may be reading it
+    env_vars: HashMap<String, String>,
+}
+
+// the most environments kept for reuse, enough for calls
+// nested this deep to need no new ones
+const ENV_POOL_SIZE: usize = 64;
+
+#[derive(Clone, Debug)]
+pub struct InterpreterOptions {
+    // Lox calls map onto Rust recursion, so deep
+    // recursion is reported as an error instead of
+    // overflowing the native stack.
+    pub max_call_depth: usize,
+    // Every executed statement, loop iteration and call is a step.
+    // Running out of steps stops the script, None means no limit.
+    pub step_limit: Option<u64>,
+    // Whether each top-level `execute` starts with a full budget
+    // instead of continuing with what previous calls left.
+    pub reset_steps_on_execute: bool,
+    // Whether to log statements and calls to the output as they execute.
+    pub trace: bool,
+    // Whether conditions and the operands of 'and', 'or' and '!'
+    // must be booleans, in which case 'and' and 'or' return booleans.
+    // Only booleans are accepted then, so `truthiness` has no effect.
+    pub strict_booleans: bool,
+    // Which values conditions and the operands of 'and', 'or' and '!'
+    // treat as true. Ignored when booleans are strict.
+    pub truthiness: Truthiness,
+    // Cycles are only freed when the collector decides to run, so a loop
+    // creating cyclic objects can grow without bound. Every time this many
+    // environments, instances, classes, arrays and maps have been created,
+    // a collection runs before the next statement. None leaves collecting
+    // to the collector alone.
+    pub gc_interval: Option<usize>,
+    // Roughly how many bytes of environments, instances, arrays, maps and long
+    // strings the script may hold. Creating one that goes over the limit stops
+    // the script. Objects are counted by their own size and what they no longer
+    // hold is only measured when the limit is close, so this is an estimate
+    // rather than the memory of the process. None means no limit.
+    pub max_memory_bytes: Option<usize>,
+}
+
+impl Default for InterpreterOptions {
+    fn default() -> Self {
+        Self {
+            max_call_depth: 255,
+            step_limit: None,
+            reset_steps_on_execute: false,
+            trace: false,
+            strict_booleans: false,
+            truthiness: Truthiness::Lox,
+            gc_interval: Some(10_000),
+            max_memory_bytes: None,
+        }
+    }
+}
+
+type Deferred = (Box<statement::Stmt>, Gc<RefCell<Environment>>);
+
+// A single active call.
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub function: String,
+    pub declaration: Option<Rc<Token>>,
+    pub call_site: Rc<Token>,
+}
+
+// An error along with the calls that were active where it was raised,
+// outermost call first. The stack is empty for errors raised in global code.
+#[derive(Debug)]
+pub struct Traced<E> {
+    pub error: E,
+    pub stack: Vec<Frame>,
+}
+
+// The error, followed by a line per call, innermost first.
+impl<E: fmt::Display> fmt::Display for Traced<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.error)?;
+        for frame in self.stack.iter().rev() {
+            write!(
+                f,
+                "\n    in '{}' called from line {}, column {}",
+                frame.function,
+                frame.call_site.line,
+                frame.call_site.column,
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for Traced<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
 }
 
 unsafe impl Trace for Interpreter {
     fn accept<V: Visitor>(&self, visitor: &mut V) -> Result<(), ()> {
         self.globals_env.accept(visitor)?;
         self.current_env.accept(visitor)?;
+        self.error_class.accept(visitor)?;
+
+        for (_, env) in self.deferred.iter().flatten() {
+            env.accept(visitor)?;
+        }
+        for env in &self.caller_envs {
+            env.accept(visitor)?;
+        }
 
         Ok(())
     }
@@ -42,26 +222,302 @@ pub type ExecResult = Result<Option<StmtEffect>, RuntimeError>;
 
 impl Interpreter {
     pub fn new() -> Self {
-        let globals = Gc::new(RefCell::new(
-            Environment::root()
+        Self::with_options(InterpreterOptions::default())
+    }
+
+    pub fn with_options(options: InterpreterOptions) -> Self {
+        let error_class = Gc::new(RefCell::new(
+            Class::new("Error", None, HashMap::new())
         ));
+        let globals = initial_globals(&error_class);
 
         Interpreter {
             globals_env: globals.clone(),
             current_env: globals,
+            options,
+            call_stack: Vec::new(),
+            caller_envs: Vec::new(),
+            deferred: Vec::new(),
+            to_string_depth: 0,
+            error_stack: None,
+            error_class,
+            steps: 0,
+            last_step_site: None,
+            output: Box::new(std::io::stdout()),
+            error_output: Box::new(std::io::stderr()),
+            input: None,
+            profiler: None,
+            last_value: None,
+            embedder_natives: Vec::new(),
+            cancelled: Arc::new(AtomicBool::new(false)),
+            env_pool: Vec::new(),
+            collected_at: gc_stats::allocations(),
+            memory_used: 0,
+            memory_measured: 0,
+            env_vars: HashMap::new(),
+        }
+    }
+
+    // Drops every global defined since the interpreter was created,
+    // except natives defined with `define_native`.
+    // Closures that are still referenced keep the globals they were created with.
+    pub fn reset(&mut self) {
+        self.globals_env = initial_globals(&self.error_class);
+        for native in &self.embedder_natives {
+            define_global_native(&self.globals_env, native.clone());
+        }
+        self.current_env = self.globals_env.clone();
+        self.call_stack.clear();
+        self.caller_envs.clear();
+        self.deferred.clear();
+        self.error_stack = None;
+        self.steps = 0;
+        self.last_step_site = None;
+        self.last_value = None;
+        self.memory_used = 0;
+        self.memory_measured = 0;
+    }
+
+    // An interpreter with a deep copy of the globals, sharing no mutable state
+    // with this one. Instances, classes and environments are copied, natives
+    // are shared as they have no state. The fork reads from the standard input
+    // and writes to the standard output and error.
+    pub fn fork(&self) -> Self {
+        let mut copier = fork::Copier::default();
+        let globals_env = copier.env(&self.globals_env);
+        let error_class = copier.class(&self.error_class);
+
+        Interpreter {
+            globals_env: globals_env.clone(),
+            current_env: globals_env,
+            options: self.options.clone(),
+            call_stack: Vec::new(),
+            caller_envs: Vec::new(),
+            deferred: Vec::new(),
+            to_string_depth: 0,
+            error_stack: None,
+            error_class,
+            steps: self.steps,
+            last_step_site: None,
+            output: Box::new(std::io::stdout()),
+            error_output: Box::new(std::io::stderr()),
+            input: None,
+            profiler: None,
+            last_value: None,
+            embedder_natives: self.embedder_natives.clone(),
+            cancelled: Arc::new(AtomicBool::new(false)),
+            env_pool: Vec::new(),
+            collected_at: gc_stats::allocations(),
+            memory_used: 0,
+            memory_measured: 0,
+            env_vars: self.env_vars.clone(),
+        }
+    }
+
+    // Defines a global function implemented by `f`, which is given the
+    // evaluated arguments. `f` may call back into the interpreter, e.g. to call
+    // a lox function it was given, and can report errors at the call site with
+    // `invalid_argument`. The closure is not traced by the garbage collector,
+    // so it must not capture values that hold Gc pointers.
+    pub fn define_native(
+        &mut self,
+        name: &str,
+        arity: usize,
+        f: impl Fn(&[RuntimeValue], &mut Interpreter) -> Result<RuntimeValue, RuntimeError> + MaybeSendSync + 'static,
+    ) {
+        let native = native::ClosureNative::new(name, arity, f);
+        define_global_native(&self.globals_env, native.clone());
+
+        self.embedder_natives.retain(|n| n.name != name);
+        self.embedder_natives.push(native);
+    }
+
+    // Defines a global variable the way a `var` declaration
+    // at the top level would, replacing one with the same name.
+    pub fn define_global(&mut self, name: &str, value: RuntimeValue) {
+        self.globals_env.borrow_mut().define(name, value);
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<RuntimeValue> {
+        self.globals_env.borrow().get(name)
+    }
+
+    // A flag that stops the script with RuntimeError::Cancelled when it is set,
+    // at the next step or while sleeping. It can be set from another thread.
+    // It stays set until it is cleared, so every later execution is cancelled too.
+    pub fn cancellation_flag(&self) -> Arc<AtomicBool> {
+        self.cancelled.clone()
+    }
+
+    pub(super) fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub fn with_max_call_depth(mut self, depth: usize) -> Self {
+        self.options.max_call_depth = depth;
+        self
+    }
+
+    pub fn with_strict_booleans(mut self, strict: bool) -> Self {
+        self.options.strict_booleans = strict;
+        self
+    }
+
+    pub fn with_truthiness(mut self, truthiness: Truthiness) -> Self {
+        self.options.truthiness = truthiness;
+        self
+    }
+
+    pub fn with_step_limit(mut self, limit: u64) -> Self {
+        self.set_step_limit(Some(limit));
+        self
+    }
+
+    pub fn with_gc_interval(mut self, interval: Option<usize>) -> Self {
+        self.options.gc_interval = interval;
+        self
+    }
+
+    pub fn with_max_memory_bytes(mut self, limit: usize) -> Self {
+        self.options.max_memory_bytes = Some(limit);
+        self
+    }
+
+    pub fn with_steps_reset_on_execute(mut self, reset: bool) -> Self {
+        self.options.reset_steps_on_execute = reset;
+        self
+    }
+
+    pub fn with_output(mut self, output: impl Write + MaybeSendSync + 'static) -> Self {
+        self.output = Box::new(output);
+        self
+    }
+
+    pub fn with_error_output(mut self, output: impl Write + MaybeSendSync + 'static) -> Self {
+        self.error_output = Box::new(output);
+        self
+    }
+
+    pub fn with_input(mut self, input: impl BufRead + MaybeSendSync + 'static) -> Self {
+        self.input = Some(Box::new(input));
+        self
+    }
+
+    pub fn set_trace(&mut self, trace: bool) {
+        self.options.trace = trace;
+    }
+
+    // Disabling profiling discards what was recorded.
+    pub fn set_profiling(&mut self, enabled: bool) {
+        if !enabled {
+            self.profiler = None;
+        }
+        else if self.profiler.is_none() {
+            self.profiler = Some(Profiler::default());
         }
     }
 
-    pub fn evaluate_expr(&mut self, expr: &Box<dyn expression::Expr>) -> Result<RuntimeValue, RuntimeError> {
-        expr.accept_rt_value(self)
+    // The calls recorded since profiling was enabled or the profile was last taken,
+    // per function and sorted by self time. Empty if profiling is disabled.
+    pub fn take_profile(&mut self) -> Vec<ProfileEntry> {
+        match &mut self.profiler {
+            Some(profiler) => profiler.take(),
+            None => Vec::new(),
+        }
     }
 
-    pub fn execute(&mut self, statements: &Vec<Box<dyn statement::Stmt>>) -> ExecResult {
+    // The variables of the scopes code being executed can see, innermost
+    // first, e.g. those of a native's caller if called from a native.
+    pub fn dump_environment(&self) -> Vec<ScopeSnapshot> {
+        self.current_env.borrow().snapshot()
+    }
+
+    // The counts of objects the collector manages and of the collections made,
+    // which like the collector are shared by every interpreter on this thread.
+    pub fn gc_stats(&self) -> GcStats {
+        gc_stats::stats()
+    }
+
+    // Collects the cycles no longer reachable now instead of when the pacing
+    // policy or the collector would. See `GcStats::last_collection_freed`.
+    pub fn collect_garbage(&mut self) {
+        gc_stats::collect();
+        self.collected_at = gc_stats::allocations();
+    }
+
+    // The number of objects to create between collections, counting from now.
+    // With None cycles are only collected when the collector decides to.
+    pub fn set_collection_threshold(&mut self, threshold: Option<usize>) {
+        self.options.gc_interval = threshold;
+        self.collected_at = gc_stats::allocations();
+    }
+
+    // Also restores the full budget.
+    pub fn set_step_limit(&mut self, limit: Option<u64>) {
+        self.options.step_limit = limit;
+        self.steps = 0;
+    }
+
+    // The call stack at the point where the last runtime error was raised,
+    // outermost call first. Empty for errors raised in global code.
+    pub fn stack_trace(&self) -> &[Frame] {
+        match &self.error_stack {
+            Some(stack) => stack,
+            None => &[],
+        }
+    }
+
+    // `error` with the call stack of the last runtime error, for errors
+    // returned by `execute` or `run`.
+    pub fn traced(&self, error: RuntimeError) -> Traced<RuntimeError> {
+        Traced { error, stack: self.stack_trace().to_vec() }
+    }
+
+    pub fn evaluate_expr(&mut self, expr: &expression::Expr) -> Result<RuntimeValue, RuntimeError> {
+        expr.accept(self)
+    }
+
+    // Statements deferred at the top level run
+    // once all of `statements` have executed.
+    pub fn execute(&mut self, statements: &[statement::Stmt]) -> ExecResult {
+        if self.call_stack.is_empty() {
+            self.error_stack = None;
+            if self.options.reset_steps_on_execute {
+                self.steps = 0;
+            }
+        }
+
+        self.deferred.push(Vec::new());
+        let result = self.execute_statements(statements);
+
+        match self.run_deferred(result) {
+            Ok(Some(StmtEffect::Throw(value, keyword))) => {
+                Err(RuntimeError::Thrown { keyword, value: Box::new(value) })
+            },
+            Ok(Some(StmtEffect::Break(keyword))) => {
+                Err(RuntimeError::BreakOutsideLoop(keyword))
+            },
+            r => r,
+        }
+    }
+
+    // Like `execute`, but returns the value of the
+    // last statement if it is an expression statement.
+    pub fn run(&mut self, statements: &[statement::Stmt]) -> Result<Option<RuntimeValue>, RuntimeError> {
+        self.last_value = None;
+        self.execute(statements)?;
+
+        Ok(self.last_value.take())
+    }
+
+    fn execute_statements(&mut self, statements: &[statement::Stmt]) -> ExecResult {
         for s in statements.iter() {
             let effect = self.execute_statement(s)?;
             match effect {
-                Some(StmtEffect::Break) |
-                Some(StmtEffect::Return(_)) => {
+                Some(StmtEffect::Break(_)) |
+                Some(StmtEffect::Return(_)) |
+                Some(StmtEffect::TailCall(_)) |
+                Some(StmtEffect::Throw(..)) => {
                     return Ok(effect);
                 },
                 None => { },
@@ -73,24 +529,351 @@ impl Interpreter {
 
     pub fn execute_block(
         &mut self,
-        s: &Vec<Box<dyn statement::Stmt>>,
+        s: &[statement::Stmt],
         env: Gc<RefCell<Environment>>,
     ) -> ExecResult {
         let prev_env = self.current_env.clone();
 
         self.current_env = env;
-        let r = self.execute(s);
+        self.deferred.push(Vec::new());
+        let r = self.execute_statements(s);
+        let r = self.run_deferred(r);
 
         self.current_env = prev_env;
 
         r
     }
 
-    fn execute_statement(&mut self, s: &Box<dyn statement::Stmt>) -> ExecResult {
-        s.accept_exec(self)
+    // Runs the statements deferred by the innermost block in reverse order.
+    // They run even if the block failed or threw, in which case the original error wins.
+    // Effects of deferred statements other than throwing are ignored.
+    // Like Go's os.Exit, 'exit' skips them, and so does cancellation.
+    fn run_deferred(&mut self, mut result: ExecResult) -> ExecResult {
+        let deferred = self.deferred.pop().unwrap_or_default();
+        if deferred.is_empty() || is_exit(&result) {
+            return result;
+        }
+
+        // a call is not in tail position while deferred statements are pending
+        result = self.finish_tail_call(result);
+
+        // deferred statements run after the last one
+        let last_value = self.last_value.take();
+        for (stmt, env) in deferred.into_iter().rev() {
+            let prev_env = std::mem::replace(&mut self.current_env, env);
+            let r = self.execute_statement(&stmt);
+            self.current_env = prev_env;
+
+            if is_failure(&r) && !is_failure(&result) {
+                result = r;
+            }
+        }
+        self.last_value = last_value;
+
+        result
+    }
+
+    // Makes a pending tail call in the current frame.
+    fn finish_tail_call(&mut self, result: ExecResult) -> ExecResult {
+        match result {
+            Ok(Some(StmtEffect::TailCall(tail))) => {
+                let value = self.call_callable(
+                    tail.callee.callable.as_ref(),
+                    &tail.args,
+                    &tail.callee.closure,
+                    tail.callee.receiver.as_ref(),
+                    &tail.call_site,
+                )?;
+                Ok(Some(StmtEffect::Return(value)))
+            },
+            _ => result,
+        }
+    }
+
+    fn execute_statement(&mut self, s: &statement::Stmt) -> ExecResult {
+        self.take_step(None)?;
+        self.pace_collections();
+        if self.call_stack.is_empty() {
+            self.last_value = None;
+        }
+        if self.options.trace {
+            match s.position() {
+                Some(token) => self.trace(self.call_stack.len(), format_args!("{} at {}:{}", s.kind(), token.line, token.column))?,
+                None => self.trace(self.call_stack.len(), format_args!("{}", s.kind()))?,
+            }
+        }
+
+        s.accept(self)
+    }
+
+    fn call_callable(
+        &mut self,
+        callable: &dyn Callable,
+        args: &[RuntimeValue],
+        closure: &Option<Gc<RefCell<Environment>>>,
+        receiver: Option<&Gc<RefCell<Instance>>>,
+        call_site: &Rc<Token>,
+    ) -> Result<RuntimeValue, RuntimeError> {
+        if self.call_stack.len() >= self.options.max_call_depth {
+            if self.error_stack.is_none() {
+                self.error_stack = Some(self.call_stack.clone());
+            }
+            return Err(RuntimeError::StackOverflow(call_site.clone()));
+        }
+        self.take_step(Some(call_site))?;
+
+        if self.options.trace {
+            let args = args.iter().map(|a| a.to_string()).collect::<Vec<_>>().join(", ");
+            self.trace(self.call_stack.len(), format_args!("enter {}({})", callable.name(), args))?;
+        }
+
+        self.call_stack.push(Frame {
+            function: callable.name(),
+            declaration: callable.declaration().cloned(),
+            call_site: call_site.clone(),
+        });
+        self.caller_envs.push(self.current_env.clone());
+
+        if let Some(profiler) = &mut self.profiler {
+            profiler.enter(&callable.name(), callable.declaration());
+        }
+        let mut result = callable.call(args, self, closure, receiver);
+        // natives create their results without going through
+        // the interpreter, so they are counted once they return
+        if let (Ok(value), None) = (&result, callable.declaration()) {
+            if let Err(e) = self.charge_result(value) {
+                result = Err(e);
+            }
+        }
+        if let Some(profiler) = &mut self.profiler {
+            profiler.exit();
+        }
+        if result.is_err() && self.error_stack.is_none() {
+            self.error_stack = Some(self.call_stack.clone());
+        }
+
+        // a tail call may have replaced the frame
+        let frame = self.call_stack.pop();
+        self.caller_envs.pop();
+
+        if self.options.trace {
+            let name = frame.map(|f| f.function).unwrap_or_default();
+            let traced = match &result {
+                Ok(value) => self.trace(self.call_stack.len(), format_args!("exit {} => {}", name, value)),
+                Err(_) => self.trace(self.call_stack.len(), format_args!("exit {} with error", name)),
+            };
+            // the error of the call wins
+            if let (Ok(_), Err(e)) = (&result, traced) {
+                return Err(e);
+            }
+        }
+
+        result
+    }
+
+    // Writes a line of the execution trace indented by `depth`.
+    // Trace lines have no position of their own and
+    // failures to write them are reported at the innermost call site.
+    fn trace(&mut self, depth: usize, line: fmt::Arguments) -> Result<(), RuntimeError> {
+        let indent = "  ".repeat(depth);
+        writeln!(self.output, "{}{}", indent, line)
+            .map_err(|error| RuntimeError::IoError { token: self.current_call_site(), error })
+    }
+
+    // Writes `text` to the output, or the error output if `to_error` is set,
+    // and flushes it so text without a line ending shows up right away.
+    pub(super) fn write_text(&mut self, text: &str, to_error: bool) -> Result<(), RuntimeError> {
+        let output = if to_error { &mut self.error_output } else { &mut self.output };
+        output.write_all(text.as_bytes())
+            .and_then(|_| output.flush())
+            .map_err(|error| RuntimeError::IoError { token: self.current_call_site(), error })
+    }
+
+    // Reads the next line of the input without its line ending,
+    // blocking until there is one. None at the end of the input.
+    pub(super) fn read_line(&mut self) -> Result<Option<String>, RuntimeError> {
+        let mut line = String::new();
+        let read = match &mut self.input {
+            Some(input) => input.read_line(&mut line),
+            None => std::io::stdin().read_line(&mut line),
+        };
+
+        match read {
+            Ok(0) => Ok(None),
+            Ok(_) => {
+                let end = line.trim_end_matches(['\n', '\r']).len();
+                line.truncate(end);
+                Ok(Some(line))
+            },
+            Err(error) => Err(RuntimeError::IoError { token: self.current_call_site(), error }),
+        }
+    }
+
+    // Calls `callee` with arguments that are already evaluated, e.g. when a
+    // native function calls back into lox code. Anything a call expression
+    // can call can be called, classes included.
+    pub fn call(&mut self, callee: &RuntimeValue, args: &[RuntimeValue]) -> Result<RuntimeValue, RuntimeError> {
+        let call_site = self.current_call_site();
+        self.call_with(callee, args.len(), |_| Ok(args), &call_site)
+    }
+
+    // The call site of the innermost call, which
+    // natives use to report errors in their arguments.
+    fn current_call_site(&self) -> Rc<Token> {
+        match self.call_stack.last() {
+            Some(frame) => frame.call_site.clone(),
+            None => Rc::new(Token::single_character(TokenType::RightParen, ')', 0, 0)),
+        }
+    }
+
+    // An error for natives to report arguments they cannot handle,
+    // positioned at the call site of the native.
+    pub fn invalid_argument(&self, message: &str) -> RuntimeError {
+        RuntimeError::InvalidArgument {
+            call_site: self.current_call_site(),
+            message: message.to_owned(),
+        }
+    }
+
+    // Statements carry no position, so running out of steps in one
+    // is reported at the last loop or call site that was reached.
+    fn take_step(&mut self, at: Option<&Rc<Token>>) -> Result<(), RuntimeError> {
+        if self.is_cancelled() {
+            let token = match at.or(self.last_step_site.as_ref()) {
+                Some(token) => token.clone(),
+                None => self.current_call_site(),
+            };
+            return Err(RuntimeError::Cancelled(token));
+        }
+
+        if let Some(limit) = self.options.step_limit {
+            if let Some(token) = at {
+                self.last_step_site = Some(token.clone());
+            }
+
+            if self.steps >= limit {
+                let token = match &self.last_step_site {
+                    Some(token) => token.clone(),
+                    None => self.current_call_site(),
+                };
+                return Err(RuntimeError::ExecutionBudgetExceeded(token));
+            }
+            self.steps += 1;
+        }
+
+        Ok(())
+    }
+
+    // Collects cycles once `options.gc_interval` objects have been created
+    // since the last time. Statements start with no borrows of objects
+    // in progress, so the collector can look into all of them.
+    fn pace_collections(&mut self) {
+        if let Some(interval) = self.options.gc_interval {
+            let allocations = gc_stats::allocations();
+            if allocations.saturating_sub(self.collected_at) >= interval {
+                gc_stats::collect();
+                self.collected_at = allocations;
+            }
+        }
+    }
+
+    // Whether `value` counts as true where a condition is expected.
+    pub(super) fn condition(&self, value: &RuntimeValue, at: &Rc<Token>) -> Result<bool, RuntimeError> {
+        if !self.options.strict_booleans {
+            return Ok(self.options.truthiness.is_truthy(value));
+        }
+
+        match value {
+            RuntimeValue::Bool(b) => Ok(*b),
+            _ => Err(RuntimeError::ConditionMustBeBoolean(at.clone())),
+        }
+    }
+
+    // Used when a tail call reuses the frame of the current call.
+    pub(crate) fn replace_frame(&mut self, callable: &dyn Callable, args: &[RuntimeValue], call_site: &Rc<Token>) -> Result<(), RuntimeError> {
+        if self.options.trace {
+            let args = args.iter().map(|a| a.to_string()).collect::<Vec<_>>().join(", ");
+            let depth = self.call_stack.len().saturating_sub(1);
+            self.trace(depth, format_args!("tail call {}({})", callable.name(), args))?;
+        }
+
+        if let Some(frame) = self.call_stack.last_mut() {
+            *frame = Frame {
+                function: callable.name(),
+                declaration: callable.declaration().cloned(),
+                call_site: call_site.clone(),
+            };
+        }
+
+        Ok(())
+    }
+
+    // An environment for a call, reusing the one of a finished call if there is one.
+    pub(crate) fn call_env(&mut self, parent: &Gc<RefCell<Environment>>) -> Gc<RefCell<Environment>> {
+        match self.env_pool.pop() {
+            Some(env) => {
+                env.borrow_mut().reset(Some(parent.clone()));
+                env
+            },
+            None => Gc::new(RefCell::new(Environment::child(parent.clone()))),
+        }
+    }
+
+    // Keeps the environment of a call that returned for later calls, which is
+    // only correct if nothing else refers to it. It is emptied right away, so
+    // it keeps no values alive while it waits.
+    pub(crate) fn recycle_env(&mut self, env: Gc<RefCell<Environment>>) {
+        if self.env_pool.len() < ENV_POOL_SIZE {
+            env.borrow_mut().reset(None);
+            self.env_pool.push(env);
+        }
+    }
+
+    fn tail_call(&mut self, call: &expression::Call) -> ExecResult {
+        use crate::{Function, statement::TailCall};
+
+        let callee = self.evaluate_expr(&call.callee)?;
+        match callee {
+            RuntimeValue::Callable(wrapper) if wrapper.callable.as_any().is::<Function>() => {
+                self.check_arity(wrapper.callable.as_ref(), call)?;
+                let args = self.evaluate_args(&call.args)?;
+
+                Ok(Some(StmtEffect::TailCall(TailCall {
+                    callee: wrapper,
+                    args: args.into_vec(),
+                    call_site: call.right_paren.clone(),
+                })))
+            },
+            _ => {
+                let value = self.call_value(callee, call)?;
+                Ok(Some(StmtEffect::Return(value)))
+            },
+        }
+    }
+
+    // The value a 'catch' binds for the outcome of a 'try' block, if it failed.
+    fn caught_value(&self, result: &ExecResult) -> Option<RuntimeValue> {
+        match result {
+            Ok(Some(StmtEffect::Throw(value, _))) => Some(value.clone()),
+            Err(RuntimeError::Thrown { value, .. }) => Some(value.as_ref().clone()),
+            Err(e) if e.is_catchable() => Some(self.error_value(e)),
+            _ => None,
+        }
     }
 
-    fn look_up_var(&self, name: &Token, hops: Option<usize>) -> Result<RuntimeValue, RuntimeError> {
+    // Built-in errors are caught as instances of 'Error'
+    // with 'message', 'line' and 'column' fields.
+    fn error_value(&self, error: &RuntimeError) -> RuntimeValue {
+        let token = error.token();
+        let mut instance = Instance::new(&self.error_class);
+        instance.set("message", &error.message().into());
+        instance.set("line", &RuntimeValue::Number(token.line as f64));
+        instance.set("column", &RuntimeValue::Number(token.column as f64));
+
+        RuntimeValue::Instance(Gc::new(RefCell::new(instance)))
+    }
+
+    fn look_up_var(&self, name: &Rc<Token>, hops: Option<usize>) -> Result<RuntimeValue, RuntimeError> {
         let value = match hops {
             Some(h) => {
                 self.current_env
@@ -108,7 +891,38 @@ impl Interpreter {
              .map(|v| v.clone())
     }
 
-    fn assign_var(&mut self, name: &Token, value: &RuntimeValue, hops: Option<usize>) -> bool {
+    // Looks up a global by its slot if it was found before, which saves
+    // hashing its name. Globals keep their slots when they are redefined,
+    // so only the first lookup in each top-level environment is by name.
+    fn look_up_global(&self, name: &Rc<Token>, slot: &Cell<Option<Slot>>) -> Result<RuntimeValue, RuntimeError> {
+        let globals = self.globals_env.borrow();
+        if let Some(value) = slot.get().and_then(|slot| globals.get_slot(slot)) {
+            return Ok(value);
+        }
+
+        let found = globals.slot(&name.lexeme)
+            .ok_or_else(|| RuntimeError::UndefinedVariable(name.clone()))?;
+        slot.set(Some(found));
+        Ok(globals.get_slot(found).expect("a slot of the globals"))
+    }
+
+    fn assign_global(&mut self, name: &Rc<Token>, value: RuntimeValue, slot: &Cell<Option<Slot>>) -> bool {
+        let mut globals = self.globals_env.borrow_mut();
+        let found = match slot.get() {
+            Some(slot) if globals.has_slot(slot) => slot,
+            _ => match globals.slot(&name.lexeme) {
+                Some(found) => {
+                    slot.set(Some(found));
+                    found
+                },
+                None => return false,
+            },
+        };
+
+        globals.assign_slot(found, value)
+    }
+
+    fn assign_var(&mut self, name: &Rc<Token>, value: RuntimeValue, hops: Option<usize>) -> bool {
         match hops {
             Some(h) => {
                 self.current_env
@@ -126,13 +940,22 @@ impl Interpreter {
 
 impl statement::Visitor<ExecResult> for Interpreter {
     fn visit_expr(&mut self, s: &statement::Expression) -> ExecResult {
-        self.evaluate_expr(&s.expr)
-            .map(|_| None)
+        let value = self.evaluate_expr(&s.expr)?;
+        if self.options.trace {
+            self.trace(self.call_stack.len(), format_args!("=> {}", value))?;
+        }
+        if self.call_stack.is_empty() {
+            self.last_value = Some(value);
+        }
+
+        Ok(None)
     }
 
     fn visit_print(&mut self, s: &statement::Print) -> ExecResult {
         let v = self.evaluate_expr(&s.expr)?;
-        println!("{}", &v);
+        let text = self.stringify(&v, &s.keyword)?;
+        writeln!(self.output, "{}", text)
+            .map_err(|error| RuntimeError::IoError { token: s.keyword.clone(), error })?;
 
         Ok(None)
     }
@@ -145,21 +968,26 @@ impl statement::Visitor<ExecResult> for Interpreter {
             },
         };
 
-        self.current_env.borrow_mut().define(&s.name.lexeme, &v);
+        self.current_env.borrow_mut().define(&s.name.lexeme, v);
 
         Ok(None)
     }
 
     fn visit_block(&mut self, s: &statement::Block) -> ExecResult {
-        let block_env = Gc::new(RefCell::new(
-            Environment::child(self.current_env.clone())
-        ));
+        // blocks without a scope still run what they defer when they end
+        let block_env = if s.needs_scope() {
+            self.charge_memory(ENV_BYTES, None)?;
+            Gc::new(RefCell::new(Environment::child(self.current_env.clone())))
+        }
+        else {
+            self.current_env.clone()
+        };
         self.execute_block(&s.statements, block_env)
     }
 
     fn visit_if(&mut self, s: &statement::If) -> ExecResult {
         let cond = self.evaluate_expr(&s.cond)?;
-        if is_truthy(&cond) {
+        if self.condition(&cond, &s.keyword)? {
             self.execute_statement(&s.then_branch)
         }
         else {
@@ -172,15 +1000,18 @@ impl statement::Visitor<ExecResult> for Interpreter {
 
     fn visit_while(&mut self, s: &statement::While) -> ExecResult {
         loop {
+            self.take_step(Some(&s.keyword))?;
             let cond = self.evaluate_expr(&s.cond)?;
 
-            if is_truthy(&cond) {
+            if self.condition(&cond, &s.keyword)? {
                 let effect = self.execute_statement(&s.body)?;
                 match effect {
-                    Some(StmtEffect::Break) => {
+                    Some(StmtEffect::Break(_)) => {
                         break;
                     },
-                    Some(StmtEffect::Return(_)) => {
+                    Some(StmtEffect::Return(_)) |
+                    Some(StmtEffect::TailCall(_)) |
+                    Some(StmtEffect::Throw(..)) => {
                         return Ok(effect);
                     },
                     None => { },
@@ -194,27 +1025,71 @@ impl statement::Visitor<ExecResult> for Interpreter {
         Ok(None)
     }
 
+    fn visit_for_in(&mut self, s: &statement::ForIn) -> ExecResult {
+        let iterable = self.evaluate_expr(&s.iterable)?;
+        let mut iteration = self.iteration(&iterable, &s.keyword)?;
+
+        loop {
+            self.take_step(Some(&s.keyword))?;
+            let value = match self.next_value(&mut iteration, &s.keyword)? {
+                Some(value) => value,
+                None => break,
+            };
+
+            // each iteration has its own variable, so closures
+            // created in the body see the value they were created with
+            self.charge_memory(ENV_BYTES + VALUE_BYTES, Some(&s.keyword))?;
+            let mut env = Environment::child(self.current_env.clone());
+            env.define(&s.name.lexeme, value);
+            let prev_env = std::mem::replace(&mut self.current_env, Gc::new(RefCell::new(env)));
+            let effect = self.execute_statement(&s.body);
+            self.current_env = prev_env;
+
+            match effect? {
+                Some(StmtEffect::Break(_)) => {
+                    break;
+                },
+                effect @ Some(StmtEffect::Return(_)) |
+                effect @ Some(StmtEffect::TailCall(_)) |
+                effect @ Some(StmtEffect::Throw(..)) => {
+                    return Ok(effect);
+                },
+                None => { },
+            }
+        }
+
+        Ok(None)
+    }
+
     fn visit_function(&mut self, s: &statement::Function) -> ExecResult {
         use crate::{Callable, Function};
 
         let closure = self.current_env.clone();
         let callable: Box<dyn Callable> = Box::new(Function {
-            decl: s.clone(),
+            decl: s.shared(),
             is_initializer: false,
         });
         let value = RuntimeValue::Callable(CallableWrapper {
             callable: callable,
-            closure: Some(closure)
+            closure: Some(closure),
+            receiver: None,
         });
 
-        self.current_env.borrow_mut().define(&s.name.lexeme, &value);
+        self.current_env.borrow_mut().define(&s.name.lexeme, value);
 
         Ok(None)
     }
 
     fn visit_return(&mut self, s: &statement::Return) -> ExecResult {
         let value = match &s.value {
-            Some(expr) => self.evaluate_expr(expr)?,
+            Some(expr) => {
+                match expr {
+                    expression::Expr::Call(call) if !self.call_stack.is_empty() => {
+                        return self.tail_call(call);
+                    },
+                    _ => self.evaluate_expr(expr)?,
+                }
+            },
             None => RuntimeValue::Nil,
         };
 
@@ -223,8 +1098,91 @@ impl statement::Visitor<ExecResult> for Interpreter {
         ))
     }
 
-    fn visit_break(&mut self, _: &statement::Break) -> ExecResult {
-        Ok(Some(StmtEffect::Break))
+    fn visit_break(&mut self, s: &statement::Break) -> ExecResult {
+        Ok(Some(StmtEffect::Break(s.keyword.clone())))
+    }
+
+    fn visit_defer(&mut self, s: &statement::Defer) -> ExecResult {
+        let env = self.current_env.clone();
+        if let Some(block) = self.deferred.last_mut() {
+            block.push((s.body.clone(), env));
+        }
+
+        Ok(None)
+    }
+
+    fn visit_throw(&mut self, s: &statement::Throw) -> ExecResult {
+        let value = self.evaluate_expr(&s.value)?;
+
+        Ok(Some(
+            StmtEffect::Throw(value, s.keyword.clone())
+        ))
+    }
+
+    fn visit_assert(&mut self, s: &statement::Assert) -> ExecResult {
+        let condition = self.evaluate_expr(&s.condition)?;
+        if self.condition(&condition, &s.keyword)? {
+            return Ok(None);
+        }
+
+        let message = match &s.message {
+            Some(message) => {
+                // string messages are reported without quotes
+                match self.evaluate_expr(message)? {
+                    RuntimeValue::String(message) => Some(message.to_string()),
+                    value => Some(self.stringify(&value, &s.keyword)?),
+                }
+            },
+            None => None,
+        };
+
+        Err(RuntimeError::AssertionFailed {
+            keyword: s.keyword.clone(),
+            message,
+        })
+    }
+
+    fn visit_try(&mut self, s: &statement::Try) -> ExecResult {
+        // tail calls are finished inside the 'try',
+        // otherwise what they throw would not be caught
+        self.charge_memory(ENV_BYTES, Some(&s.keyword))?;
+        let env = Gc::new(RefCell::new(
+            Environment::child(self.current_env.clone())
+        ));
+        let r = self.execute_block(&s.body, env);
+        let mut result = self.finish_tail_call(r);
+
+        if let Some(catch) = &s.catch {
+            if let Some(value) = self.caught_value(&result) {
+                self.error_stack = None;
+
+                self.charge_memory(ENV_BYTES + VALUE_BYTES, Some(&catch.name))?;
+                let mut env = Environment::child(self.current_env.clone());
+                env.define(&catch.name.lexeme, value);
+
+                let r = self.execute_block(&catch.body, Gc::new(RefCell::new(env)));
+                result = self.finish_tail_call(r);
+            }
+        }
+
+        if is_exit(&result) {
+            return result;
+        }
+
+        if let Some(finally) = &s.finally {
+            self.charge_memory(ENV_BYTES, Some(&s.keyword))?;
+            let env = Gc::new(RefCell::new(
+                Environment::child(self.current_env.clone())
+            ));
+            let r = self.execute_block(finally, env);
+
+            // leaving 'finally' early overrides the outcome of the blocks before it
+            if !matches!(r, Ok(None)) {
+                return r;
+            }
+        }
+
+        result
     }
 
     fn visit_class(&mut self, s: &statement::Class) -> ExecResult {
@@ -232,8 +1190,7 @@ impl statement::Visitor<ExecResult> for Interpreter {
 
         let mut super_class = None;
         if let Some(sup) = &s.super_class {
-            let sup_expr: Box<dyn expression::Expr> = Box::new(sup.clone());
-            if let RuntimeValue::Class(c) = self.evaluate_expr(&sup_expr)? {
+            if let RuntimeValue::Class(c) = expression::Visitor::visit_variable(self, sup)? {
                 super_class = Some(c);
             }
             else {
@@ -241,11 +1198,11 @@ impl statement::Visitor<ExecResult> for Interpreter {
             }
         }
 
-        self.current_env.borrow_mut().define(&s.name.lexeme, &RuntimeValue::Nil);
+        self.current_env.borrow_mut().define(&s.name.lexeme, RuntimeValue::Nil);
 
         if let Some(sup) = &super_class {
             let mut env = Environment::child(self.current_env.clone());
-            env.define("super", &RuntimeValue::Class(sup.clone()));
+            env.define("super", RuntimeValue::Class(sup.clone()));
 
             self.current_env = Gc::new(RefCell::new(env));
         }
@@ -255,12 +1212,13 @@ impl statement::Visitor<ExecResult> for Interpreter {
             let is_initializer = f.name.lexeme == "init";
             let closure = self.current_env.clone();
             let callable: Box<dyn Callable> = Box::new(Function {
-                decl: f.clone(),
+                decl: f.shared(),
                 is_initializer,
             });
             let method = CallableWrapper {
                 callable: callable,
-                closure: Some(closure)
+                closure: Some(closure),
+                receiver: None,
             };
 
             class_methods.insert(f.name.lexeme.clone(), method);
@@ -278,8 +1236,53 @@ impl statement::Visitor<ExecResult> for Interpreter {
         let class =  RuntimeValue::Class(Gc::new(RefCell::new(
             Class::new(&s.name.lexeme, super_class, class_methods)
         )));
-        self.current_env.borrow_mut().assign(&s.name.lexeme, &class);
+        self.current_env.borrow_mut().assign(&s.name.lexeme, class);
 
         Ok(None)
     }
 }
+
+// The global environment of a new interpreter.
+fn initial_globals(error_class: &Gc<RefCell<Class>>) -> Gc<RefCell<Environment>> {
+    let globals = Gc::new(RefCell::new(
+        Environment::root()
+    ));
+    globals.borrow_mut().define("Error", RuntimeValue::Class(error_class.clone()));
+
+    for native in native::globals() {
+        let value = RuntimeValue::Callable(CallableWrapper {
+            callable: Box::new(native.clone()),
+            closure: None,
+            receiver: None,
+        });
+        globals.borrow_mut().define(native.name, value);
+    }
+    for (name, value) in native::constants() {
+        globals.borrow_mut().define(name, RuntimeValue::Number(value));
+    }
+
+    globals
+}
+
+fn define_global_native(globals: &Gc<RefCell<Environment>>, native: native::ClosureNative) {
+    let name = native.name.clone();
+    let value = RuntimeValue::Callable(CallableWrapper {
+        callable: Box::new(native),
+        closure: None,
+        receiver: None,
+    });
+    globals.borrow_mut().define(name, value);
+}
+
+fn is_failure(result: &ExecResult) -> bool {
+    matches!(result, Err(_) | Ok(Some(StmtEffect::Throw(..))))
+}
+
+// Exits and cancellations stop the script without running 'defer' or 'finally'.
+fn is_exit(result: &ExecResult) -> bool {
+    matches!(result, Err(RuntimeError::Exit { .. } | RuntimeError::Cancelled(_)))
+}
+
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,1754 @@
+use crate::{
+    RuntimeValue,
+    RuntimeError,
+    ArityError,
+    Callable,
+    CallableWrapper,
+    Class,
+    ConversionError,
+    Instance,
+    Map,
+    MapKey,
+    Range,
+    bind_method,
+    gc_stats,
+    Gc,
+    Rc,
+    RefCell,
+    MaybeSendSync,
+};
+use std::{
+    any::Any,
+    cmp::Ordering,
+    collections::BTreeSet,
+    fmt::Display,
+    fs,
+    io::{self, Write},
+    path::Path,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use super::{
+    env::{format_snapshot, Environment},
+    fork::Copier,
+    eval::{as_index, are_equal},
+    json,
+    memory,
+    number_format,
+    Interpreter,
+};
+
+type NativeResult = Result<RuntimeValue, RuntimeError>;
+
+// The numbers of arguments a native accepts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Arity {
+    Exact(usize),
+    AtLeast(usize),
+    // both ends included
+    Between(usize, usize),
+}
+
+impl Arity {
+    pub fn check(&self, found: usize) -> Result<(), ArityError> {
+        let expected = match *self {
+            Arity::Exact(n) if found != n => n,
+            Arity::AtLeast(n) if found < n => n,
+            Arity::Between(n, _) if found < n => n,
+            Arity::Between(_, m) if found > m => m,
+            _ => return Ok(()),
+        };
+
+        Err(ArityError { expected, found })
+    }
+
+    // the fewest arguments accepted
+    pub fn min(&self) -> usize {
+        match *self {
+            Arity::Exact(n) | Arity::AtLeast(n) | Arity::Between(n, _) => n,
+        }
+    }
+}
+
+impl Display for Arity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Arity::Exact(n) => write!(f, "{}", n),
+            Arity::AtLeast(n) => write!(f, "{}+", n),
+            Arity::Between(n, m) => write!(f, "{}-{}", n, m),
+        }
+    }
+}
+
+// A function implemented in Rust.
+#[derive(Clone)]
+pub struct NativeFunction {
+    pub name: &'static str,
+    pub arity: Arity,
+    pub fun: fn(&mut Interpreter, &[RuntimeValue]) -> NativeResult,
+}
+
+// Natives that take a fixed number of arguments are shown by name only,
+// others with the numbers of arguments they take, e.g. `<native fn max/1+>`.
+impl Display for NativeFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.arity {
+            Arity::Exact(_) => write!(f, "<native fn {}>", self.name),
+            arity => write!(f, "<native fn {}/{}>", self.name, arity),
+        }
+    }
+}
+
+impl Callable for NativeFunction {
+    fn arity(&self) -> usize {
+        self.arity.min()
+    }
+
+    fn check_arity(&self, found: usize) -> Result<(), ArityError> {
+        self.arity.check(found)
+    }
+
+    fn fixed_arity(&self) -> Option<usize> {
+        match self.arity {
+            Arity::Exact(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    fn call(
+        &self,
+        args: &[RuntimeValue],
+        interp: &mut Interpreter,
+        _closure: &Option<Gc<RefCell<Environment>>>,
+        _receiver: Option<&Gc<RefCell<crate::Instance>>>,
+    ) -> NativeResult {
+        (self.fun)(interp, args)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> String {
+        self.name.to_owned()
+    }
+
+    fn same_declaration(&self, other: &dyn Callable) -> bool {
+        match other.as_any().downcast_ref::<NativeFunction>() {
+            Some(f) => f.name == self.name,
+            None => false,
+        }
+    }
+}
+
+// What `define_native` is given, which with the `sync-gc` feature
+// must be Send and Sync like the rest of the interpreter.
+trait NativeClosure: Fn(&[RuntimeValue], &mut Interpreter) -> NativeResult + MaybeSendSync {}
+
+impl<F> NativeClosure for F
+where
+    F: Fn(&[RuntimeValue], &mut Interpreter) -> NativeResult + MaybeSendSync {}
+
+// A function defined by the embedder with Interpreter::define_native.
+// The closure is not traced by the garbage collector,
+// so it must not capture values that hold Gc pointers.
+#[derive(Clone)]
+pub struct ClosureNative {
+    pub(super) name: String,
+    arity: usize,
+    fun: Rc<dyn NativeClosure>,
+}
+
+impl ClosureNative {
+    pub(super) fn new(
+        name: &str,
+        arity: usize,
+        fun: impl Fn(&[RuntimeValue], &mut Interpreter) -> NativeResult + MaybeSendSync + 'static,
+    ) -> Self {
+        Self {
+            name: name.to_owned(),
+            arity,
+            fun: Rc::new(fun),
+        }
+    }
+}
+
+impl Display for ClosureNative {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+impl Callable for ClosureNative {
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn call(
+        &self,
+        args: &[RuntimeValue],
+        interp: &mut Interpreter,
+        _closure: &Option<Gc<RefCell<Environment>>>,
+        _receiver: Option<&Gc<RefCell<crate::Instance>>>,
+    ) -> NativeResult {
+        (self.fun)(args, interp)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn same_declaration(&self, other: &dyn Callable) -> bool {
+        match other.as_any().downcast_ref::<ClosureNative>() {
+            Some(f) => Rc::ptr_eq(&f.fun, &self.fun),
+            None => false,
+        }
+    }
+}
+
+// The natives defined in the global environment.
+pub(super) fn globals() -> Vec<NativeFunction> {
+    vec![
+        NativeFunction { name: "each_field", arity: Arity::Exact(2), fun: each_field },
+        NativeFunction { name: "has_field", arity: Arity::Exact(2), fun: has_field },
+        NativeFunction { name: "get_field", arity: Arity::Exact(2), fun: get_field },
+        NativeFunction { name: "set_field", arity: Arity::Exact(3), fun: set_field },
+        NativeFunction { name: "class_of", arity: Arity::Exact(1), fun: class_of },
+        NativeFunction { name: "superclass", arity: Arity::Exact(1), fun: superclass },
+        NativeFunction { name: "class_name", arity: Arity::Exact(1), fun: class_name },
+        NativeFunction { name: "bind", arity: Arity::Exact(2), fun: bind },
+        NativeFunction { name: "arity", arity: Arity::Exact(1), fun: arity },
+        NativeFunction { name: "param_names", arity: Arity::Exact(1), fun: param_names },
+        NativeFunction { name: "method_names", arity: Arity::Between(1, 2), fun: method_names },
+        NativeFunction { name: "is_nan", arity: Arity::Exact(1), fun: is_nan },
+        NativeFunction { name: "is_finite", arity: Arity::Exact(1), fun: is_finite },
+        NativeFunction { name: "error", arity: Arity::Exact(1), fun: error },
+        NativeFunction { name: "pcall", arity: Arity::AtLeast(1), fun: pcall },
+        NativeFunction { name: "assert_eq", arity: Arity::Between(2, 3), fun: assert_eq },
+        NativeFunction { name: "exit", arity: Arity::Between(0, 1), fun: exit },
+        NativeFunction { name: "clone", arity: Arity::Exact(1), fun: clone },
+        NativeFunction { name: "len", arity: Arity::Exact(1), fun: len },
+        NativeFunction { name: "clock", arity: Arity::Exact(0), fun: clock },
+        NativeFunction { name: "floor", arity: Arity::Exact(1), fun: floor },
+        NativeFunction { name: "ceil", arity: Arity::Exact(1), fun: ceil },
+        NativeFunction { name: "round", arity: Arity::Exact(1), fun: round },
+        NativeFunction { name: "abs", arity: Arity::Exact(1), fun: abs },
+        NativeFunction { name: "sqrt", arity: Arity::Exact(1), fun: sqrt },
+        NativeFunction { name: "pow", arity: Arity::Exact(2), fun: pow },
+        NativeFunction { name: "min", arity: Arity::AtLeast(2), fun: min },
+        NativeFunction { name: "max", arity: Arity::AtLeast(2), fun: max },
+        NativeFunction { name: "log", arity: Arity::Exact(1), fun: log },
+        NativeFunction { name: "exp", arity: Arity::Exact(1), fun: exp },
+        NativeFunction { name: "to_fixed", arity: Arity::Exact(2), fun: to_fixed },
+        NativeFunction { name: "to_precision", arity: Arity::Exact(2), fun: to_precision },
+        NativeFunction { name: "tostring", arity: Arity::Exact(1), fun: tostring },
+        NativeFunction { name: "split", arity: Arity::Exact(2), fun: split },
+        NativeFunction { name: "join", arity: Arity::Exact(2), fun: join },
+        NativeFunction { name: "index_of", arity: Arity::Exact(2), fun: index_of },
+        NativeFunction { name: "replace", arity: Arity::Exact(3), fun: replace },
+        NativeFunction { name: "replace_first", arity: Arity::Exact(3), fun: replace_first },
+        NativeFunction { name: "parse_int", arity: Arity::Exact(2), fun: parse_int },
+        NativeFunction { name: "ord", arity: Arity::Exact(1), fun: ord },
+        NativeFunction { name: "chr", arity: Arity::Exact(1), fun: chr },
+        NativeFunction { name: "format", arity: Arity::AtLeast(1), fun: format },
+        NativeFunction { name: "tonumber", arity: Arity::Exact(1), fun: tonumber },
+        NativeFunction { name: "write", arity: Arity::Exact(1), fun: write },
+        NativeFunction { name: "writeln", arity: Arity::Exact(1), fun: writeln },
+        NativeFunction { name: "eprint", arity: Arity::Exact(1), fun: eprint },
+        NativeFunction { name: "readline", arity: Arity::Exact(0), fun: readline },
+        NativeFunction { name: "readnumber", arity: Arity::Exact(0), fun: readnumber },
+        NativeFunction { name: "read_file", arity: Arity::Exact(1), fun: read_file },
+        NativeFunction { name: "write_file", arity: Arity::Exact(2), fun: write_file },
+        NativeFunction { name: "append_file", arity: Arity::Exact(2), fun: append_file },
+        NativeFunction { name: "file_exists", arity: Arity::Exact(1), fun: file_exists },
+        NativeFunction { name: "getenv", arity: Arity::Exact(1), fun: getenv },
+        NativeFunction { name: "setenv", arity: Arity::Exact(2), fun: setenv },
+        NativeFunction { name: "platform", arity: Arity::Exact(0), fun: platform },
+        NativeFunction { name: "sleep", arity: Arity::Exact(1), fun: sleep },
+        NativeFunction { name: "gc_collect", arity: Arity::Exact(0), fun: gc_collect },
+        NativeFunction { name: "gc_stats", arity: Arity::Exact(0), fun: gc_stats },
+        NativeFunction { name: "backtrace", arity: Arity::Exact(0), fun: backtrace },
+        NativeFunction { name: "dump_env", arity: Arity::Exact(0), fun: dump_env },
+        NativeFunction { name: "Array", arity: Arity::AtLeast(0), fun: array },
+        NativeFunction { name: "push", arity: Arity::Exact(2), fun: push },
+        NativeFunction { name: "pop", arity: Arity::Exact(1), fun: pop },
+        NativeFunction { name: "get", arity: Arity::Exact(2), fun: get },
+        NativeFunction { name: "set", arity: Arity::Exact(3), fun: set },
+        NativeFunction { name: "insert", arity: Arity::Exact(3), fun: insert },
+        NativeFunction { name: "remove", arity: Arity::Exact(2), fun: remove },
+        NativeFunction { name: "sort", arity: Arity::Between(1, 2), fun: sort },
+        NativeFunction { name: "map", arity: Arity::Exact(2), fun: map },
+        NativeFunction { name: "filter", arity: Arity::Exact(2), fun: filter },
+        NativeFunction { name: "reduce", arity: Arity::Exact(3), fun: reduce },
+        NativeFunction { name: "range", arity: Arity::Between(1, 3), fun: range },
+        NativeFunction { name: "slice", arity: Arity::Between(2, 3), fun: slice },
+        NativeFunction { name: "json_parse", arity: Arity::Exact(1), fun: json_parse },
+        NativeFunction { name: "json_stringify", arity: Arity::Exact(1), fun: json_stringify },
+        NativeFunction { name: "Map", arity: Arity::Exact(0), fun: new_map },
+        NativeFunction { name: "hash", arity: Arity::Exact(1), fun: hash },
+        NativeFunction { name: "map_get", arity: Arity::Exact(2), fun: map_get },
+        NativeFunction { name: "map_set", arity: Arity::Exact(3), fun: map_set },
+        NativeFunction { name: "map_has", arity: Arity::Exact(2), fun: map_has },
+        NativeFunction { name: "map_remove", arity: Arity::Exact(2), fun: map_remove },
+        NativeFunction { name: "map_keys", arity: Arity::Exact(1), fun: map_keys },
+        NativeFunction { name: "map_len", arity: Arity::Exact(1), fun: map_len },
+    ]
+}
+
+// The numbers defined in the global environment.
+pub(super) fn constants() -> Vec<(&'static str, f64)> {
+    vec![
+        ("PI", std::f64::consts::PI),
+        ("E", std::f64::consts::E),
+    ]
+}
+
+// Converts an argument of the native `name`.
+pub(super) fn argument<T>(interp: &Interpreter, name: &str, value: &RuntimeValue) -> Result<T, RuntimeError>
+where
+    T: TryFrom<RuntimeValue, Error = ConversionError>,
+{
+    T::try_from(value.clone())
+        .map_err(|e| interp.invalid_argument(&format!("{}: {}", name, e)))
+}
+
+// each_field(obj, f) calls f(name, value) for every field of obj, in the order
+// the fields were first set. Fields set or deleted by f do not affect the iteration.
+fn each_field(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let instance: Gc<RefCell<Instance>> = argument(interp, "each_field", &args[0])?;
+
+    let fields: Vec<(String, RuntimeValue)> = instance.borrow()
+        .fields
+        .iter()
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect();
+
+    for (name, value) in fields {
+        interp.call(&args[1], &[name.into(), value])?;
+    }
+
+    Ok(RuntimeValue::Nil)
+}
+
+// has_field(obj, name) is whether the instance obj has the field name. Methods are not fields.
+fn has_field(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let instance: Gc<RefCell<Instance>> = argument(interp, "has_field", &args[0])?;
+    let name: String = argument(interp, "has_field", &args[1])?;
+
+    let has_field = instance.borrow().has_field(&name);
+    Ok(has_field.into())
+}
+
+// get_field(obj, name) is the field name of the instance obj, nil if it has no such field.
+// Unlike obj.name, it does not find methods.
+fn get_field(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let instance: Gc<RefCell<Instance>> = argument(interp, "get_field", &args[0])?;
+    let name: String = argument(interp, "get_field", &args[1])?;
+
+    let value = instance.borrow().field(&name).cloned();
+    Ok(value.unwrap_or(RuntimeValue::Nil))
+}
+
+// set_field(obj, name, v) returns v, like assigning to obj.name.
+fn set_field(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let instance: Gc<RefCell<Instance>> = argument(interp, "set_field", &args[0])?;
+    let name: String = argument(interp, "set_field", &args[1])?;
+    interp.charge_field(&instance, &name, None)?;
+    instance.borrow_mut().set(&name, &args[2]);
+
+    Ok(args[2].clone())
+}
+
+fn class_of(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let instance: Gc<RefCell<Instance>> = argument(interp, "class_of", &args[0])?;
+    let class = instance.borrow().class().clone();
+
+    Ok(RuntimeValue::Class(class))
+}
+
+// superclass(cls) is the class cls inherits from, nil if there is none.
+fn superclass(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let class: Gc<RefCell<Class>> = argument(interp, "superclass", &args[0])?;
+    let super_class = class.borrow().super_class().cloned();
+
+    Ok(super_class.map_or(RuntimeValue::Nil, RuntimeValue::Class))
+}
+
+fn class_name(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let class: Gc<RefCell<Class>> = argument(interp, "class_name", &args[0])?;
+    let name = class.borrow().name.clone();
+
+    Ok(name.into())
+}
+
+// method_names(cls) is a sorted array of the names of the methods declared in
+// the class cls. method_names(cls, true) includes the methods it inherits too.
+fn method_names(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let class: Gc<RefCell<Class>> = argument(interp, "method_names", &args[0])?;
+    let inherited: bool = match args.get(1) {
+        Some(inherited) => argument(interp, "method_names", inherited)?,
+        None => false,
+    };
+
+    let mut names = BTreeSet::new();
+    let mut next = Some(class);
+    while let Some(class) = next {
+        let class = class.borrow();
+        names.extend(class.method_names().cloned());
+        next = class.super_class().cloned().filter(|_| inherited);
+    }
+
+    let names: Vec<RuntimeValue> = names.into_iter().map(RuntimeValue::from).collect();
+    Ok(RuntimeValue::from(names))
+}
+
+// bind(method, obj) is the method bound to the instance obj instead, as if it had
+// been looked up on obj. Only methods are accepted, as functions declared outside
+// of classes cannot refer to 'this' and natives have no use for it.
+fn bind(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let method = match &args[0] {
+        RuntimeValue::Callable(f) if f.receiver().is_some() => f,
+        value => {
+            let message = format!("bind: expected a method, found {}", value);
+            return Err(interp.invalid_argument(&message));
+        },
+    };
+    let instance: Gc<RefCell<Instance>> = argument(interp, "bind", &args[1])?;
+
+    Ok(RuntimeValue::Callable(bind_method(method, &instance)))
+}
+
+// arity(f) is the number of arguments the function f takes, or nil for natives
+// that accept different numbers of arguments. Classes take the arguments of
+// their 'init' method, or none if they don't declare one.
+fn arity(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let arity = match &args[0] {
+        RuntimeValue::Callable(f) => f.fixed_arity(),
+        RuntimeValue::Class(class) => class.borrow().initializer().map_or(Some(0), |init| init.fixed_arity()),
+        value => return Err(not_a_function(interp, "arity", value)),
+    };
+
+    Ok(arity.map_or(RuntimeValue::Nil, |n| RuntimeValue::Number(n as f64)))
+}
+
+// param_names(f) is an array of the names of the parameters of the function f,
+// or nil for natives, which have no names for them. Classes have the
+// parameters of their 'init' method, or none if they don't declare one.
+fn param_names(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let names = match &args[0] {
+        RuntimeValue::Callable(f) => f.param_names(),
+        RuntimeValue::Class(class) => class.borrow().initializer().map_or(Some(Vec::new()), |init| init.param_names()),
+        value => return Err(not_a_function(interp, "param_names", value)),
+    };
+
+    Ok(names.map_or(RuntimeValue::Nil, |names| {
+        let names: Vec<RuntimeValue> = names.into_iter().map(RuntimeValue::from).collect();
+        RuntimeValue::from(names)
+    }))
+}
+
+fn not_a_function(interp: &Interpreter, name: &str, value: &RuntimeValue) -> RuntimeError {
+    let message = format!("{}: expected a function or class, found {}", name, value.type_name());
+    interp.invalid_argument(&message)
+}
+
+fn is_nan(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let n: f64 = argument(interp, "is_nan", &args[0])?;
+    Ok(n.is_nan().into())
+}
+
+fn is_finite(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let n: f64 = argument(interp, "is_finite", &args[0])?;
+    Ok(n.is_finite().into())
+}
+
+// error(message) fails with the message, or the value as tostring shows it,
+// reported at the call. 'try' catches it like the other runtime errors.
+fn error(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let message = tostring(interp, args)?;
+    Err(RuntimeError::UserError {
+        token: interp.current_call_site(),
+        message: message.as_str().expect("tostring returns strings").to_owned(),
+    })
+}
+
+// pcall(f, ...) calls f with the arguments after it and returns Array(true, result),
+// or Array(false, message) if the call fails with an error 'try' could catch.
+// Thrown values take the place of the message, as they would in a 'catch'.
+fn pcall(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let (success, value) = match interp.call(&args[0], &args[1..]) {
+        Ok(result) => (true, result),
+        Err(RuntimeError::Thrown { value, .. }) => (false, *value),
+        Err(e) if e.is_catchable() => (false, e.message().into()),
+        Err(e) => return Err(e),
+    };
+    interp.error_stack = None;
+
+    let result = vec![RuntimeValue::Bool(success), value];
+    Ok(RuntimeValue::from(result))
+}
+
+// assert_eq(actual, expected, label) fails with a user error showing where the values
+// differ, as tostring shows them, unless they are equal. Arrays and maps are compared
+// element by element, and instances of classes with an 'eq' method by calling it.
+fn assert_eq(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let label: Option<String> = match args.get(2) {
+        Some(label) => Some(argument(interp, "assert_eq", label)?),
+        None => None,
+    };
+
+    let mut comparison = Comparison::default();
+    let Some((actual, expected)) = comparison.difference(interp, &args[0], &args[1])? else {
+        return Ok(RuntimeValue::Nil);
+    };
+
+    let mut message = "assert_eq failed".to_owned();
+    if let Some(label) = label {
+        message.push_str(&format!(" ({})", label));
+    }
+    if !comparison.path.is_empty() {
+        message.push_str(&format!(" at {}", comparison.path));
+    }
+    let actual = tostring(interp, &[actual])?;
+    let expected = tostring(interp, &[expected])?;
+    message.push_str(&format!(
+        ": expected {}, found {}",
+        expected.as_str().expect("tostring returns strings"),
+        actual.as_str().expect("tostring returns strings"),
+    ));
+
+    Err(RuntimeError::UserError {
+        token: interp.current_call_site(),
+        message,
+    })
+}
+
+// the actual and expected values where two values differ
+type Difference = (RuntimeValue, RuntimeValue);
+
+// A deep comparison of two values for assert_eq.
+#[derive(Default)]
+struct Comparison {
+    // where the values differ, like [1]["key"]
+    path: String,
+    // The pairs of arrays and maps being compared, which are
+    // taken to be equal when a cycle leads back to them.
+    open: Vec<(*const (), *const ())>,
+}
+
+impl Comparison {
+    // The first pair of values that differ, None if there is none.
+    fn difference(
+        &mut self,
+        interp: &mut Interpreter,
+        a: &RuntimeValue,
+        b: &RuntimeValue,
+    ) -> Result<Option<Difference>, RuntimeError> {
+        let pair = match (a, b) {
+            (RuntimeValue::Array(x), RuntimeValue::Array(y)) => (Gc::as_ptr(x) as *const (), Gc::as_ptr(y) as *const ()),
+            (RuntimeValue::Map(x), RuntimeValue::Map(y)) => (Gc::as_ptr(x) as *const (), Gc::as_ptr(y) as *const ()),
+            (RuntimeValue::Instance(x), _) => {
+                let equal = instances_equal(interp, x, b)?;
+                return Ok(self.unless(equal, a, b));
+            },
+            _ => return Ok(self.unless(are_equal(a, b), a, b)),
+        };
+        if pair.0 == pair.1 || self.open.contains(&pair) {
+            return Ok(None);
+        }
+
+        self.open.push(pair);
+        let difference = match (a, b) {
+            (RuntimeValue::Array(x), RuntimeValue::Array(y)) => {
+                // 'eq' methods called along the way may change the arrays
+                let (x, y) = (x.borrow().clone(), y.borrow().clone());
+                self.elements_difference(interp, &x, &y)?
+            },
+            (RuntimeValue::Map(x), RuntimeValue::Map(y)) => {
+                let (x, y) = (x.borrow().clone(), y.borrow().clone());
+                self.entries_difference(interp, &x, &y)?
+            },
+            _ => unreachable!("only arrays and maps are compared element by element"),
+        };
+        self.open.pop();
+
+        // arrays and maps of different sizes are shown whole
+        Ok(difference.map(|difference| difference.unwrap_or_else(|| (a.clone(), b.clone()))))
+    }
+
+    fn unless(&self, equal: bool, a: &RuntimeValue, b: &RuntimeValue) -> Option<Difference> {
+        (!equal).then(|| (a.clone(), b.clone()))
+    }
+
+    // Some(None) if the arrays differ in size
+    fn elements_difference(
+        &mut self,
+        interp: &mut Interpreter,
+        a: &[RuntimeValue],
+        b: &[RuntimeValue],
+    ) -> Result<Option<Option<Difference>>, RuntimeError> {
+        for (i, (x, y)) in a.iter().zip(b).enumerate() {
+            let len = self.path.len();
+            self.path.push_str(&format!("[{}]", i));
+            if let Some(difference) = self.difference(interp, x, y)? {
+                return Ok(Some(Some(difference)));
+            }
+            self.path.truncate(len);
+        }
+
+        Ok((a.len() != b.len()).then_some(None))
+    }
+
+    // Some(None) if the maps differ in their keys
+    fn entries_difference(
+        &mut self,
+        interp: &mut Interpreter,
+        a: &Map,
+        b: &Map,
+    ) -> Result<Option<Option<Difference>>, RuntimeError> {
+        if a.len() != b.len() {
+            return Ok(Some(None));
+        }
+
+        for (key, x) in a.iter() {
+            let Some(y) = b.get(key) else {
+                return Ok(Some(None));
+            };
+            let len = self.path.len();
+            self.path.push_str(&format!("[{}]", key.to_value()));
+            if let Some(difference) = self.difference(interp, x, y)? {
+                return Ok(Some(Some(difference)));
+            }
+            self.path.truncate(len);
+        }
+
+        Ok(None)
+    }
+}
+
+// By the 'eq' method of the class of `instance`, or by identity if it has none.
+fn instances_equal(interp: &mut Interpreter, instance: &Gc<RefCell<Instance>>, other: &RuntimeValue) -> Result<bool, RuntimeError> {
+    let eq = instance.borrow().class().borrow().find_method("eq");
+    match eq {
+        Some(eq) if eq.callable.check_arity(1).is_ok() => {
+            let call_site = interp.current_call_site();
+            let result = interp.call_method(&eq, instance, std::slice::from_ref(other), &call_site)?;
+            interp.condition(&result, &call_site)
+        },
+        _ => Ok(are_equal(&RuntimeValue::Instance(instance.clone()), other)),
+    }
+}
+
+// exit(code) stops the script. Interpreter::execute returns
+// RuntimeError::Exit with the code truncated to an integer, 0 if it is left out.
+fn exit(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let code: f64 = match args.first() {
+        Some(code) => argument(interp, "exit", code)?,
+        None => 0_f64,
+    };
+
+    Err(RuntimeError::Exit {
+        call_site: interp.current_call_site(),
+        code: code as i32,
+    })
+}
+
+// len(x) is the number of characters of a string, the same ones x[i] indexes,
+// or the number of elements of an array.
+fn len(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let len = match &args[0] {
+        RuntimeValue::String(s) => s.chars().count(),
+        RuntimeValue::Array(array) => array.borrow().len(),
+        value => {
+            let message = format!("len: expected String or Array, found {}", value.type_name());
+            return Err(interp.invalid_argument(&message));
+        },
+    };
+
+    Ok(RuntimeValue::Number(len as f64))
+}
+
+// clock() is the number of seconds since the Unix epoch, with a fractional part.
+fn clock(_interp: &mut Interpreter, _args: &[RuntimeValue]) -> NativeResult {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0_f64);
+
+    Ok(RuntimeValue::Number(seconds))
+}
+
+// The math natives follow IEEE semantics instead of raising errors
+// outside their domain, e.g. sqrt(-1) is NaN and log(0) is -infinity.
+fn unary_math(interp: &Interpreter, name: &str, args: &[RuntimeValue], f: fn(f64) -> f64) -> NativeResult {
+    let n: f64 = argument(interp, name, &args[0])?;
+    Ok(RuntimeValue::Number(f(n)))
+}
+
+fn floor(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    unary_math(interp, "floor", args, f64::floor)
+}
+
+fn ceil(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    unary_math(interp, "ceil", args, f64::ceil)
+}
+
+// halfway cases are rounded away from zero
+fn round(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    unary_math(interp, "round", args, f64::round)
+}
+
+fn abs(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    unary_math(interp, "abs", args, f64::abs)
+}
+
+fn sqrt(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    unary_math(interp, "sqrt", args, f64::sqrt)
+}
+
+// the natural logarithm
+fn log(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    unary_math(interp, "log", args, f64::ln)
+}
+
+fn exp(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    unary_math(interp, "exp", args, f64::exp)
+}
+
+fn pow(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let base: f64 = argument(interp, "pow", &args[0])?;
+    let exponent: f64 = argument(interp, "pow", &args[1])?;
+    Ok(RuntimeValue::Number(base.powf(exponent)))
+}
+
+// min and max are NaN if any of their arguments is
+fn min(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    fold_numbers(interp, "min", args, f64::min)
+}
+
+fn max(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    fold_numbers(interp, "max", args, f64::max)
+}
+
+fn fold_numbers(interp: &Interpreter, name: &str, args: &[RuntimeValue], f: fn(f64, f64) -> f64) -> NativeResult {
+    let mut result: f64 = argument(interp, name, &args[0])?;
+    for arg in &args[1..] {
+        let n: f64 = argument(interp, name, arg)?;
+        result = if n.is_nan() || result.is_nan() { f64::NAN } else { f(result, n) };
+    }
+
+    Ok(RuntimeValue::Number(result))
+}
+
+// the most decimals or significant digits to_fixed and to_precision give
+const MAX_FORMAT_DIGITS: f64 = 100_f64;
+
+// to_fixed(n, decimals) is n with exactly that many digits after the decimal point,
+// rounded half away from zero. More than MAX_FORMAT_DIGITS decimals are capped.
+fn to_fixed(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let n: f64 = argument(interp, "to_fixed", &args[0])?;
+    let decimals = digit_count(interp, "to_fixed", &args[1], 0_f64)?;
+
+    Ok(number_format::to_fixed(n, decimals).into())
+}
+
+// to_precision(n, significant) is n with that many significant digits, rounded half
+// away from zero and in exponent form if the exponent is below -6 or not below
+// significant. More than MAX_FORMAT_DIGITS significant digits are capped.
+fn to_precision(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let n: f64 = argument(interp, "to_precision", &args[0])?;
+    let significant = digit_count(interp, "to_precision", &args[1], 1_f64)?;
+
+    Ok(number_format::to_precision(n, significant).into())
+}
+
+fn digit_count(interp: &Interpreter, name: &str, value: &RuntimeValue, min: f64) -> Result<usize, RuntimeError> {
+    let count: f64 = argument(interp, name, value)?;
+    if count.fract() != 0_f64 || count < min {
+        let message = format!("{}: expected an integer of at least {}, found {}", name, min, count);
+        return Err(interp.invalid_argument(&message));
+    }
+
+    Ok(count.min(MAX_FORMAT_DIGITS) as usize)
+}
+
+// tostring(x) is x as print shows it, except that strings are not quoted.
+fn tostring(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    match &args[0] {
+        RuntimeValue::String(_) => Ok(args[0].clone()),
+        value => {
+            let call_site = interp.current_call_site();
+            Ok(interp.stringify(value, &call_site)?.into())
+        },
+    }
+}
+
+// split(s, sep) is an array of the parts of s between occurrences of sep. Separators
+// at either end give empty parts, and s is the only part if sep is not found.
+// An empty sep splits s into its characters, so the empty string has no parts.
+fn split(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let s: String = argument(interp, "split", &args[0])?;
+    let separator: String = argument(interp, "split", &args[1])?;
+
+    let parts: Vec<RuntimeValue> = if separator.is_empty() {
+        s.chars().map(|c| c.to_string().into()).collect()
+    } else {
+        s.split(separator.as_str()).map(|part| part.into()).collect()
+    };
+
+    Ok(RuntimeValue::from(parts))
+}
+
+// join(arr, sep) is the elements of arr as tostring shows them, separated by sep.
+fn join(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let array: Array = argument(interp, "join", &args[0])?;
+    let separator: String = argument(interp, "join", &args[1])?;
+
+    // 'toString' methods may change the array
+    let elements = array.borrow().clone();
+    let mut parts = Vec::with_capacity(elements.len());
+    for element in &elements {
+        let part = tostring(interp, std::slice::from_ref(element))?;
+        parts.push(part.as_str().expect("tostring returns strings").to_owned());
+    }
+
+    Ok(parts.join(&separator).into())
+}
+
+// index_of(s, needle) is the index of the first occurrence of needle in s, -1 if
+// there is none. Like s[i], it counts characters, so s.substr(i, i + len(needle))
+// is needle. The empty string is found at 0.
+fn index_of(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let s: String = argument(interp, "index_of", &args[0])?;
+    let needle: String = argument(interp, "index_of", &args[1])?;
+
+    let index = match s.find(needle.as_str()) {
+        Some(byte) => s[..byte].chars().count() as f64,
+        None => -1_f64,
+    };
+
+    Ok(RuntimeValue::Number(index))
+}
+
+// replace(s, from, to) replaces the occurrences of from in s with to,
+// from left to right, so occurrences that overlap a replaced one are kept.
+fn replace(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let (s, from, to) = replace_arguments(interp, "replace", args)?;
+    Ok(s.replace(from.as_str(), &to).into())
+}
+
+// replace_first(s, from, to) replaces the first occurrence of from in s with to.
+fn replace_first(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let (s, from, to) = replace_arguments(interp, "replace_first", args)?;
+    Ok(s.replacen(from.as_str(), &to, 1).into())
+}
+
+// The empty string occurs between every two characters,
+// so replacing it is more likely a mistake than intended.
+fn replace_arguments(interp: &Interpreter, name: &str, args: &[RuntimeValue]) -> Result<(String, String, String), RuntimeError> {
+    let s: String = argument(interp, name, &args[0])?;
+    let from: String = argument(interp, name, &args[1])?;
+    let to: String = argument(interp, name, &args[2])?;
+    if from.is_empty() {
+        return Err(interp.invalid_argument(&format!("{}: cannot replace the empty string", name)));
+    }
+
+    Ok((s, from, to))
+}
+
+// parse_int(s, radix) is the integer written in base radix in the string s, with
+// an optional sign and surrounding whitespace, or nil if s is not such an integer.
+// Digits above 9 are letters of either case, and radix is an integer from 2 to 36.
+fn parse_int(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let s: String = argument(interp, "parse_int", &args[0])?;
+    let radix: f64 = argument(interp, "parse_int", &args[1])?;
+    if radix.fract() != 0_f64 || !(2_f64..=36_f64).contains(&radix) {
+        let message = format!("parse_int: expected a radix from 2 to 36, found {}", radix);
+        return Err(interp.invalid_argument(&message));
+    }
+
+    let s = s.trim();
+    let (sign, digits) = match s.strip_prefix('-') {
+        Some(digits) => (-1_f64, digits),
+        None => (1_f64, s.strip_prefix('+').unwrap_or(s)),
+    };
+    if digits.is_empty() {
+        return Ok(RuntimeValue::Nil);
+    }
+
+    let mut value = 0_f64;
+    for c in digits.chars() {
+        match c.to_digit(radix as u32) {
+            Some(digit) => value = value * radix + digit as f64,
+            None => return Ok(RuntimeValue::Nil),
+        }
+    }
+
+    Ok(RuntimeValue::Number(sign * value))
+}
+
+// ord(c) is the code point of the one-character string c. Characters
+// are what s[i] and len(s) count, so ord(s[i]) works for any i.
+fn ord(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let s: String = argument(interp, "ord", &args[0])?;
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(RuntimeValue::Number(c as u32 as f64)),
+        _ => {
+            let message = format!("ord: expected a string of one character, found {}", s.chars().count());
+            Err(interp.invalid_argument(&message))
+        },
+    }
+}
+
+// chr(n) is the one-character string of the code point n,
+// which cannot be a surrogate or above 0x10FFFF.
+fn chr(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let n: f64 = argument(interp, "chr", &args[0])?;
+    let c = Some(n)
+        .filter(|n| n.fract() == 0_f64 && (0_f64..=u32::MAX as f64).contains(n))
+        .and_then(|n| char::from_u32(n as u32));
+
+    match c {
+        Some(c) => Ok(c.to_string().into()),
+        None => Err(interp.invalid_argument(&format!("chr: {} is not a valid code point", n))),
+    }
+}
+
+// format(fmt, ...) replaces the placeholders in fmt with the arguments after it,
+// in order, as tostring shows them. Placeholders are '{}' or '{:spec}', where spec is
+// [[fill]align][width][.precision]: align is '<', '>' or '^', width is the least number
+// of characters, padded with fill, which defaults to a space, and precision is the number
+// of decimals of a number or the most characters of anything else. '{{' and '}}' are
+// literal braces. There have to be as many arguments as placeholders. Widths above
+// MAX_FORMAT_WIDTH and precisions of numbers above MAX_FORMAT_DIGITS are errors.
+fn format(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let fmt: String = argument(interp, "format", &args[0])?;
+    let values = &args[1..];
+
+    let mut result = String::new();
+    let mut placeholders = 0;
+    let mut chars = fmt.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.next_if_eq(&'{').is_some() => result.push('{'),
+            '}' if chars.next_if_eq(&'}').is_some() => result.push('}'),
+            '{' => {
+                let mut spec = String::new();
+                let closed = loop {
+                    match chars.next() {
+                        Some('}') => break true,
+                        Some(c) => spec.push(c),
+                        None => break false,
+                    }
+                };
+                if !closed {
+                    let message = format!("format: placeholder {} is not closed", placeholders);
+                    return Err(interp.invalid_argument(&message));
+                }
+
+                let spec = match spec.strip_prefix(':') {
+                    Some(spec) => FormatSpec::parse(spec),
+                    None if spec.is_empty() => Some(FormatSpec::default()),
+                    None => None,
+                };
+                let Some(spec) = spec else {
+                    let message = format!("format: placeholder {} has an invalid spec", placeholders);
+                    return Err(interp.invalid_argument(&message));
+                };
+                let Some(value) = values.get(placeholders) else {
+                    let message = format!("format: placeholder {} has no argument", placeholders);
+                    return Err(interp.invalid_argument(&message));
+                };
+
+                if spec.width > MAX_FORMAT_WIDTH {
+                    let message = format!("format: placeholder {} has a width above {}", placeholders, MAX_FORMAT_WIDTH);
+                    return Err(interp.invalid_argument(&message));
+                }
+                let text = match (value, spec.precision) {
+                    (RuntimeValue::Number(_), Some(precision)) if precision as f64 > MAX_FORMAT_DIGITS => {
+                        let message = format!("format: placeholder {} has a precision above {}", placeholders, MAX_FORMAT_DIGITS);
+                        return Err(interp.invalid_argument(&message));
+                    },
+                    (RuntimeValue::Number(n), Some(precision)) => format!("{:.*}", precision, n),
+                    (value, precision) => {
+                        let text = tostring(interp, std::slice::from_ref(value))?;
+                        let text = text.as_str().expect("tostring returns strings");
+                        text.chars().take(precision.unwrap_or(usize::MAX)).collect()
+                    },
+                };
+                spec.pad(&text, matches!(value, RuntimeValue::Number(_)), &mut result);
+                placeholders += 1;
+            },
+            '}' => {
+                let message = format!("format: unmatched '}}' after placeholder {}", placeholders);
+                return Err(interp.invalid_argument(&message));
+            },
+            c => result.push(c),
+        }
+    }
+
+    if values.len() > placeholders {
+        let message = format!("format: {} arguments for {} placeholders", values.len(), placeholders);
+        return Err(interp.invalid_argument(&message));
+    }
+
+    Ok(result.into())
+}
+
+// the widest a placeholder of format can be padded to
+const MAX_FORMAT_WIDTH: usize = 1000;
+
+#[derive(Default)]
+struct FormatSpec {
+    fill: Option<char>,
+    align: Option<char>,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl FormatSpec {
+    // None if `spec` is not [[fill]align][width][.precision]
+    fn parse(spec: &str) -> Option<Self> {
+        let is_align = |c: char| matches!(c, '<' | '>' | '^');
+        let mut result = FormatSpec::default();
+        let mut rest = spec;
+
+        let mut chars = rest.chars();
+        match (chars.next(), chars.next()) {
+            (Some(fill), Some(align)) if is_align(align) => {
+                result.fill = Some(fill);
+                result.align = Some(align);
+                rest = chars.as_str();
+            },
+            (Some(align), _) if is_align(align) => {
+                result.align = Some(align);
+                rest = &rest[1..];
+            },
+            _ => { },
+        }
+
+        let (width, precision) = match rest.split_once('.') {
+            Some((width, precision)) => (width, Some(precision)),
+            None => (rest, None),
+        };
+        if !width.is_empty() {
+            result.width = width.parse().ok()?;
+        }
+        if let Some(precision) = precision {
+            result.precision = Some(precision.parse().ok()?);
+        }
+
+        Some(result)
+    }
+
+    // Numbers are aligned right by default and everything else left, like in Rust.
+    fn pad(&self, text: &str, is_number: bool, out: &mut String) {
+        let padding = self.width.saturating_sub(text.chars().count());
+        let fill = self.fill.unwrap_or(' ');
+        let default_align = if is_number { '>' } else { '<' };
+        let (before, after) = match self.align.unwrap_or(default_align) {
+            '>' => (padding, 0),
+            '^' => (padding / 2, padding - padding / 2),
+            _ => (0, padding),
+        };
+
+        out.extend(std::iter::repeat_n(fill, before));
+        out.push_str(text);
+        out.extend(std::iter::repeat_n(fill, after));
+    }
+}
+
+// tonumber(x) converts strings holding a number literal, optionally signed and
+// surrounded by whitespace, and booleans to 1 and 0. It is nil for other values.
+fn tonumber(_interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let n = match &args[0] {
+        RuntimeValue::Number(n) => Some(*n),
+        RuntimeValue::Bool(b) => Some(if *b { 1_f64 } else { 0_f64 }),
+        RuntimeValue::String(s) => parse_number(s),
+        _ => None,
+    };
+
+    Ok(n.map_or(RuntimeValue::Nil, RuntimeValue::Number))
+}
+
+// Accepts the number literals of the scanner, digits with an optional fraction.
+fn parse_number(s: &str) -> Option<f64> {
+    let s = s.trim();
+    let (sign, digits) = match s.strip_prefix('-') {
+        Some(rest) => (-1_f64, rest),
+        None => (1_f64, s.strip_prefix('+').unwrap_or(s)),
+    };
+
+    let (whole, fraction) = match digits.split_once('.') {
+        Some((whole, fraction)) => (whole, Some(fraction)),
+        None => (digits, None),
+    };
+    let is_digits = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+    if !is_digits(whole) || !fraction.is_none_or(is_digits) {
+        return None;
+    }
+
+    digits.parse::<f64>().ok().map(|n| sign * n)
+}
+
+// write(v) writes v to the output as tostring shows it, without a line ending.
+fn write(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    write_value(interp, &args[0], "", false)
+}
+
+// writeln(v) is write(v) followed by a line ending.
+fn writeln(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    write_value(interp, &args[0], "\n", false)
+}
+
+// eprint(v) is writeln(v) to the error output.
+fn eprint(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    write_value(interp, &args[0], "\n", true)
+}
+
+fn write_value(interp: &mut Interpreter, value: &RuntimeValue, end: &str, to_error: bool) -> NativeResult {
+    let text = tostring(interp, std::slice::from_ref(value))?;
+    let text = text.as_str().expect("tostring returns strings");
+    interp.write_text(&format!("{}{}", text, end), to_error)?;
+    Ok(RuntimeValue::Nil)
+}
+
+// readline() is the next line of the input without its line ending, or nil at
+// the end of the input. It blocks until a whole line is available. Lines of
+// whitespace are returned as they are and empty lines as empty strings.
+fn readline(interp: &mut Interpreter, _args: &[RuntimeValue]) -> NativeResult {
+    let line = interp.read_line()?;
+    Ok(line.map_or(RuntimeValue::Nil, RuntimeValue::from))
+}
+
+// readnumber() reads a line like readline and converts it like tonumber,
+// so it is nil at the end of the input and for lines that are not numbers.
+fn readnumber(interp: &mut Interpreter, _args: &[RuntimeValue]) -> NativeResult {
+    let n = interp.read_line()?.and_then(|line| parse_number(&line));
+    Ok(n.map_or(RuntimeValue::Nil, RuntimeValue::Number))
+}
+
+// Failures of the file natives are reported at their call site
+// with the path they failed on.
+fn file_error(interp: &Interpreter, path: &str, error: io::Error) -> RuntimeError {
+    RuntimeError::IoError {
+        token: interp.current_call_site(),
+        error: io::Error::new(error.kind(), format!("{}: {}", path, error)),
+    }
+}
+
+fn read_file(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let path: String = argument(interp, "read_file", &args[0])?;
+    let contents = fs::read_to_string(&path)
+        .map_err(|e| file_error(interp, &path, e))?;
+
+    Ok(contents.into())
+}
+
+// write_file(path, contents) creates the file or replaces what it contains.
+fn write_file(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let path: String = argument(interp, "write_file", &args[0])?;
+    let contents: String = argument(interp, "write_file", &args[1])?;
+    fs::write(&path, contents)
+        .map_err(|e| file_error(interp, &path, e))?;
+
+    Ok(RuntimeValue::Nil)
+}
+
+// append_file(path, contents) creates the file if it does not exist.
+fn append_file(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let path: String = argument(interp, "append_file", &args[0])?;
+    let contents: String = argument(interp, "append_file", &args[1])?;
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .map_err(|e| file_error(interp, &path, e))?;
+
+    Ok(RuntimeValue::Nil)
+}
+
+fn file_exists(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let path: String = argument(interp, "file_exists", &args[0])?;
+    Ok(Path::new(&path).exists().into())
+}
+
+// getenv(name) is nil for variables that are not set or not unicode.
+// Variables set with setenv are found before those of the process.
+fn getenv(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let name: String = argument(interp, "getenv", &args[0])?;
+    let value = match interp.env_vars.get(&name) {
+        Some(value) => Some(value.clone()),
+        None => std::env::var(&name).ok(),
+    };
+
+    Ok(value.map_or(RuntimeValue::Nil, RuntimeValue::from))
+}
+
+// setenv(name, value) sets the variable for this interpreter and its forks.
+// The environment of the process is left alone, as changing it is unsound
+// while other threads may read it.
+fn setenv(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let name: String = argument(interp, "setenv", &args[0])?;
+    let value: String = argument(interp, "setenv", &args[1])?;
+    if name.is_empty() || name.contains(['=', '\0']) || value.contains('\0') {
+        return Err(interp.invalid_argument("setenv: invalid variable name or value"));
+    }
+    interp.env_vars.insert(name, value);
+
+    Ok(RuntimeValue::Nil)
+}
+
+// platform() names the operating system, e.g. "linux", "macos" or "windows".
+fn platform(_interp: &mut Interpreter, _args: &[RuntimeValue]) -> NativeResult {
+    Ok(std::env::consts::OS.into())
+}
+
+// how often a sleep checks whether the script was cancelled
+const SLEEP_SLICE: Duration = Duration::from_millis(10);
+
+// sleep(seconds) blocks for a non-negative number of seconds,
+// unless the script is cancelled in the meantime.
+fn sleep(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let seconds: f64 = argument(interp, "sleep", &args[0])?;
+    let duration = match Duration::try_from_secs_f64(seconds) {
+        Ok(duration) => duration,
+        Err(_) => {
+            let message = format!("sleep: expected a non-negative number of seconds, found {}", seconds);
+            return Err(interp.invalid_argument(&message));
+        },
+    };
+
+    let end = Instant::now() + duration;
+    loop {
+        if interp.is_cancelled() {
+            return Err(RuntimeError::Cancelled(interp.current_call_site()));
+        }
+
+        let left = end.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Ok(RuntimeValue::Nil);
+        }
+        std::thread::sleep(left.min(SLEEP_SLICE));
+    }
+}
+
+// gc_collect() frees the objects that are only reachable through cycles,
+// which would otherwise wait for the collector to decide to run.
+fn gc_collect(_interp: &mut Interpreter, _args: &[RuntimeValue]) -> NativeResult {
+    gc_stats::collect();
+    Ok(RuntimeValue::Nil)
+}
+
+// gc_stats() is a map of the numbers of instances and environments that are
+// live and that were created, and of the collections run, on this thread.
+// Collections count those made by gc_collect as well as the ones the
+// interpreter makes on its own, every `gc_interval` objects or when the
+// memory limit is reached.
+fn gc_stats(_interp: &mut Interpreter, _args: &[RuntimeValue]) -> NativeResult {
+    let stats = gc_stats::stats();
+    let counters = [
+        ("live_instances", stats.live_instances),
+        ("instances_created", stats.instances_created),
+        ("live_environments", stats.live_environments),
+        ("environments_created", stats.environments_created),
+        ("collections", stats.collections),
+    ];
+
+    let mut map = Map::default();
+    for (name, count) in counters {
+        map.insert(MapKey::String(Rc::new(name.to_owned())), RuntimeValue::Number(count as f64));
+    }
+
+    Ok(RuntimeValue::from(map))
+}
+
+// dump_env() writes the variables of every scope visible where it is
+// called to the output, innermost first, as `format_snapshot` shows them.
+fn dump_env(interp: &mut Interpreter, _args: &[RuntimeValue]) -> NativeResult {
+    let text = format_snapshot(&interp.dump_environment());
+    interp.write_text(&text, false)?;
+    Ok(RuntimeValue::Nil)
+}
+
+// backtrace() is an array of the calls in progress, innermost first, without the
+// call of backtrace itself, so it is empty at the top level. Each call is a map
+// of the name of the function, the line and column it was called from and the
+// line the function was declared on, which is nil for natives.
+fn backtrace(interp: &mut Interpreter, _args: &[RuntimeValue]) -> NativeResult {
+    let frames = &interp.call_stack[..interp.call_stack.len() - 1];
+    let calls: Vec<RuntimeValue> = frames.iter()
+        .rev()
+        .map(|frame| {
+            let declared = frame.declaration
+                .as_ref()
+                .map_or(RuntimeValue::Nil, |token| RuntimeValue::Number(token.line as f64));
+            let entries = [
+                ("function", frame.function.as_str().into()),
+                ("line", RuntimeValue::Number(frame.call_site.line as f64)),
+                ("column", RuntimeValue::Number(frame.call_site.column as f64)),
+                ("declared", declared),
+            ];
+
+            let mut map = Map::default();
+            for (key, value) in entries {
+                map.insert(MapKey::String(Rc::new(key.to_owned())), value);
+            }
+            RuntimeValue::from(map)
+        })
+        .collect();
+
+    Ok(RuntimeValue::from(calls))
+}
+
+type Array = Gc<RefCell<Vec<RuntimeValue>>>;
+
+// Array(...) is a new array of its arguments.
+fn array(_interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    Ok(RuntimeValue::from(args.to_vec()))
+}
+
+fn push(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let array: Array = argument(interp, "push", &args[0])?;
+    interp.charge_memory(memory::VALUE_BYTES, None)?;
+    array.borrow_mut().push(args[1].clone());
+
+    Ok(RuntimeValue::Nil)
+}
+
+// pop(arr) removes the last element and returns it, nil if arr is empty.
+fn pop(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let array: Array = argument(interp, "pop", &args[0])?;
+    let last = array.borrow_mut().pop();
+
+    Ok(last.unwrap_or(RuntimeValue::Nil))
+}
+
+fn get(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let array: Array = argument(interp, "get", &args[0])?;
+    let array = array.borrow();
+    let i = as_index(&args[1], array.len(), &interp.current_call_site())?;
+
+    Ok(array[i].clone())
+}
+
+// set(arr, i, v) returns v, like assigning to arr[i].
+fn set(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let array: Array = argument(interp, "set", &args[0])?;
+    let mut array = array.borrow_mut();
+    let i = as_index(&args[1], array.len(), &interp.current_call_site())?;
+    array[i] = args[2].clone();
+
+    Ok(args[2].clone())
+}
+
+// insert(arr, i, v) moves the elements from i on one place up to make room for v.
+// i may be the length of the array, to insert at its end.
+fn insert(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let array: Array = argument(interp, "insert", &args[0])?;
+    interp.charge_memory(memory::VALUE_BYTES, None)?;
+    let mut array = array.borrow_mut();
+    let len = array.len();
+    let i = as_index(&args[1], len + 1, &interp.current_call_site())
+        .map_err(|e| match e {
+            RuntimeError::IndexOutOfRange { index, bracket, .. } => {
+                RuntimeError::IndexOutOfRange { index, len, bracket }
+            },
+            e => e,
+        })?;
+    array.insert(i, args[2].clone());
+
+    Ok(RuntimeValue::Nil)
+}
+
+// remove(arr, i) removes the element at i and returns it.
+fn remove(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let array: Array = argument(interp, "remove", &args[0])?;
+    let mut array = array.borrow_mut();
+    let i = as_index(&args[1], array.len(), &interp.current_call_site())?;
+
+    Ok(array.remove(i))
+}
+
+// sort(arr) sorts an array of numbers or of strings in ascending order.
+// sort(arr, cmp) orders the elements by cmp(a, b), which returns a negative number
+// if a goes before b, a positive one if it goes after b and 0 if either order works.
+// The sort is stable. The array is only changed once the sort is done, so it is
+// left as it was if the comparator fails, and changes the comparator makes to it
+// are overwritten.
+fn sort(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let array: Array = argument(interp, "sort", &args[0])?;
+    let elements = array.borrow().clone();
+
+    let sorted = match args.get(1) {
+        Some(cmp) => merge_sort(elements, &mut |a, b| {
+            let order = interp.call(cmp, &[a.clone(), b.clone()])?;
+            match order {
+                RuntimeValue::Number(n) if !n.is_nan() => Ok(n.partial_cmp(&0_f64).unwrap()),
+                value => {
+                    let message = format!("sort: the comparator must return a number, found {}", value.type_name());
+                    Err(interp.invalid_argument(&message))
+                },
+            }
+        })?,
+        None => merge_sort(elements, &mut |a, b| {
+            let order = match (a, b) {
+                (RuntimeValue::Number(a), RuntimeValue::Number(b)) => a.partial_cmp(b),
+                (RuntimeValue::String(a), RuntimeValue::String(b)) => Some(a.cmp(b)),
+                _ => None,
+            };
+            order.ok_or_else(|| {
+                let message = match (a, b) {
+                    (RuntimeValue::Number(_), RuntimeValue::Number(_)) => "sort: cannot order NaN".to_owned(),
+                    _ => format!("sort: expected only numbers or only strings, found {} and {}", a.type_name(), b.type_name()),
+                };
+                interp.invalid_argument(&message)
+            })
+        })?,
+    };
+    *array.borrow_mut() = sorted;
+
+    Ok(RuntimeValue::Nil)
+}
+
+// A stable merge sort that stops at the first error of `compare`.
+fn merge_sort<F>(mut values: Vec<RuntimeValue>, compare: &mut F) -> Result<Vec<RuntimeValue>, RuntimeError>
+where
+    F: FnMut(&RuntimeValue, &RuntimeValue) -> Result<Ordering, RuntimeError>,
+{
+    if values.len() <= 1 {
+        return Ok(values);
+    }
+
+    let right = values.split_off(values.len() / 2);
+    let left = merge_sort(values, compare)?;
+    let right = merge_sort(right, compare)?;
+
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        // equal elements are taken from the left, which keeps their order
+        let next = if compare(r, l)? == Ordering::Less { right.next() } else { left.next() };
+        merged.extend(next);
+    }
+    merged.extend(left);
+    merged.extend(right);
+
+    Ok(merged)
+}
+
+// Checks that `f` can be called with `arity` arguments before a native
+// calls it, so that it does not fail after calling it on some elements.
+fn check_callback(interp: &Interpreter, name: &str, f: &RuntimeValue, arity: usize) -> Result<(), RuntimeError> {
+    let accepts = |wrapper: &CallableWrapper| wrapper.callable.check_arity(arity).is_ok();
+    let accepted = match f {
+        RuntimeValue::Callable(wrapper) => Some(accepts(wrapper)),
+        // classes take the arguments of their initializer, if they have one
+        RuntimeValue::Class(class) => match class.borrow().methods.get("init") {
+            Some(initializer) => Some(accepts(initializer)),
+            None => Some(arity == 0),
+        },
+        RuntimeValue::Instance(instance) => instance.borrow()
+            .class
+            .borrow()
+            .find_method("call")
+            .map(|method| accepts(&method)),
+        _ => None,
+    };
+
+    match accepted {
+        Some(true) => Ok(()),
+        Some(false) => {
+            let noun = if arity == 1 { "argument" } else { "arguments" };
+            let message = format!("{}: expected a function taking {} {}, found {}", name, arity, noun, f);
+            Err(interp.invalid_argument(&message))
+        },
+        None => {
+            let message = format!("{}: expected a function, found {}", name, f.type_name());
+            Err(interp.invalid_argument(&message))
+        },
+    }
+}
+
+// map(arr, f) is a new array of f(x) for each element x of arr.
+// Like the other natives that take a function, it goes through
+// the elements arr has when it is called.
+fn map(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let array: Array = argument(interp, "map", &args[0])?;
+    check_callback(interp, "map", &args[1], 1)?;
+
+    let elements = array.borrow().clone();
+    let mut result = Vec::with_capacity(elements.len());
+    for x in elements {
+        result.push(interp.call(&args[1], &[x])?);
+    }
+
+    Ok(RuntimeValue::from(result))
+}
+
+// filter(arr, pred) is a new array of the elements x of arr for which pred(x)
+// is true, as a condition of an 'if' would be.
+fn filter(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let array: Array = argument(interp, "filter", &args[0])?;
+    check_callback(interp, "filter", &args[1], 1)?;
+
+    let elements = array.borrow().clone();
+    let mut result = Vec::new();
+    for x in elements {
+        let keep = interp.call(&args[1], std::slice::from_ref(&x))?;
+        if interp.condition(&keep, &interp.current_call_site())? {
+            result.push(x);
+        }
+    }
+
+    Ok(RuntimeValue::from(result))
+}
+
+// reduce(arr, f, init) folds arr from the left: f(...f(f(init, x0), x1)..., xn).
+fn reduce(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let array: Array = argument(interp, "reduce", &args[0])?;
+    check_callback(interp, "reduce", &args[1], 2)?;
+
+    let elements = array.borrow().clone();
+    let mut acc = args[2].clone();
+    for x in elements {
+        acc = interp.call(&args[1], &[acc, x])?;
+    }
+
+    Ok(acc)
+}
+
+// range(end), range(start, end) and range(start, end, step) are the numbers from start,
+// 0 if it is left out, up to but not including end, step apart. The step is 1 by
+// default and counts down when it is negative. Ranges are computed as a for-in
+// loop goes through them, so end may be infinite and large ranges take no memory.
+fn range(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let mut numbers = Vec::with_capacity(args.len());
+    for arg in args {
+        let n: f64 = argument(interp, "range", arg)?;
+        if n.is_nan() {
+            return Err(interp.invalid_argument("range: expected a number, found nan"));
+        }
+        numbers.push(n);
+    }
+
+    let (start, end, step) = match numbers[..] {
+        [end] => (0_f64, end, 1_f64),
+        [start, end] => (start, end, 1_f64),
+        [start, end, step] => (start, end, step),
+        _ => unreachable!("range takes 1 to 3 arguments"),
+    };
+    if !start.is_finite() || !step.is_finite() {
+        return Err(interp.invalid_argument("range: expected a finite start and step"));
+    }
+    if step == 0_f64 {
+        return Err(interp.invalid_argument("range: the step cannot be 0"));
+    }
+
+    Ok(RuntimeValue::Range(Range::new(start, end, step)))
+}
+
+// slice(x, start, end) is a new array of the elements of the array x, or a string
+// of the characters of the string x, from start up to, but not including, end.
+// Negative bounds count from the end, bounds past either end are clamped to it,
+// and end defaults to the length. start at or after end gives an empty result.
+fn slice(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let len = match &args[0] {
+        RuntimeValue::String(s) => s.chars().count(),
+        RuntimeValue::Array(array) => array.borrow().len(),
+        value => {
+            let message = format!("slice: expected String or Array, found {}", value.type_name());
+            return Err(interp.invalid_argument(&message));
+        },
+    };
+    let start = slice_bound(interp, &args[1], len)?;
+    let end = match args.get(2) {
+        Some(end) => slice_bound(interp, end, len)?,
+        None => len,
+    };
+    let count = end.saturating_sub(start);
+
+    match &args[0] {
+        RuntimeValue::String(s) => {
+            let result: String = s.chars().skip(start).take(count).collect();
+            Ok(result.into())
+        },
+        RuntimeValue::Array(array) => {
+            let result = array.borrow()[start..start + count].to_vec();
+            Ok(RuntimeValue::from(result))
+        },
+        _ => unreachable!("slice of a value that is neither a string nor an array"),
+    }
+}
+
+fn slice_bound(interp: &Interpreter, value: &RuntimeValue, len: usize) -> Result<usize, RuntimeError> {
+    let bound: f64 = argument(interp, "slice", value)?;
+    if bound.fract() != 0_f64 {
+        let message = format!("slice: expected integer bounds, found {}", bound);
+        return Err(interp.invalid_argument(&message));
+    }
+
+    let len = len as f64;
+    let bound = if bound < 0_f64 { bound + len } else { bound };
+    Ok(bound.clamp(0_f64, len) as usize)
+}
+
+// json_parse(s) is the JSON document s as nested maps, arrays,
+// strings, numbers, booleans and nil, which 'null' becomes.
+fn json_parse(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let source: String = argument(interp, "json_parse", &args[0])?;
+    let value = json::parse(&source).map_err(|e| RuntimeError::InvalidJson {
+        call_site: interp.current_call_site(),
+        offset: e.offset,
+        message: e.message,
+    })?;
+    interp.charge_contents(&value, memory::data_bytes(&value))?;
+
+    Ok(value)
+}
+
+// json_stringify(v) is v as compact JSON. Instances are written as objects of their fields.
+fn json_stringify(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    json::stringify(&args[0])
+        .map(RuntimeValue::from)
+        .map_err(|message| interp.invalid_argument(&format!("json_stringify: {}", message)))
+}
+
+fn new_map(_interp: &mut Interpreter, _args: &[RuntimeValue]) -> NativeResult {
+    Ok(RuntimeValue::from(Map::default()))
+}
+
+// The key `value` is stored under in `map`. Instances of classes with a 'hash' method
+// can be keys too. They are stored under the first instance key with the same hash
+// code that their 'eq' method finds equal to them, or that is them without one.
+fn map_key(interp: &mut Interpreter, name: &str, map: &Gc<RefCell<Map>>, value: &RuntimeValue) -> Result<MapKey, RuntimeError> {
+    let RuntimeValue::Instance(instance) = value else {
+        return MapKey::new(value).ok_or_else(|| not_hashable(interp, name, value));
+    };
+    let code = instance_hash(interp, name, instance)?
+        .ok_or_else(|| not_hashable(interp, name, value))?;
+
+    // 'eq' methods may change the map
+    let keys: Vec<MapKey> = map.borrow().instance_keys(code).cloned().collect();
+    for key in keys {
+        let MapKey::Instance(_, other) = &key else {
+            unreachable!("instance_keys returns instance keys");
+        };
+        if Gc::ptr_eq(instance, other) || instances_equal(interp, instance, &key.to_value())? {
+            return Ok(key);
+        }
+    }
+
+    Ok(MapKey::Instance(code, instance.clone()))
+}
+
+// The hash code of the value the 'hash' method of `instance` returns, None if it has none.
+fn instance_hash(interp: &mut Interpreter, name: &str, instance: &Gc<RefCell<Instance>>) -> Result<Option<u64>, RuntimeError> {
+    let method = instance.borrow().class().borrow().find_method("hash");
+    let Some(method) = method.filter(|m| m.callable.check_arity(0).is_ok()) else {
+        return Ok(None);
+    };
+
+    let call_site = interp.current_call_site();
+    let value = interp.call_method(&method, instance, &[], &call_site)?;
+    match MapKey::new(&value) {
+        Some(key) => Ok(Some(key.hash_code())),
+        None => {
+            let message = format!("{}: 'hash' returned {}, which cannot be hashed", name, value.type_name());
+            Err(interp.invalid_argument(&message))
+        },
+    }
+}
+
+fn not_hashable(interp: &Interpreter, name: &str, value: &RuntimeValue) -> RuntimeError {
+    let message = format!(
+        "{}: expected a string, a number other than NaN, a boolean, nil or an instance with a 'hash' method, found {}",
+        name,
+        value.type_name(),
+    );
+    interp.invalid_argument(&message)
+}
+
+// hash(v) is the hash code maps use for the key v, a non-negative integer that is
+// the same for values that are equal with '=='. Instances of classes with a 'hash'
+// method have the hash code of the value it returns. Hash codes are the same in
+// every run of a version of the interpreter, but may change between versions.
+fn hash(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let code = match &args[0] {
+        RuntimeValue::Instance(instance) => instance_hash(interp, "hash", instance)?,
+        value => MapKey::new(value).map(|key| key.hash_code()),
+    };
+
+    code.map(|code| RuntimeValue::Number(code as f64))
+        .ok_or_else(|| not_hashable(interp, "hash", &args[0]))
+}
+
+// map_get(m, k) is nil for keys that are not in m.
+fn map_get(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let map: Gc<RefCell<Map>> = argument(interp, "map_get", &args[0])?;
+    let key = map_key(interp, "map_get", &map, &args[1])?;
+    let value = map.borrow().get(&key).cloned();
+
+    Ok(value.unwrap_or(RuntimeValue::Nil))
+}
+
+// map_set(m, k, v) returns v. Keys that are set again keep their place in the order.
+fn map_set(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let map: Gc<RefCell<Map>> = argument(interp, "map_set", &args[0])?;
+    let key = map_key(interp, "map_set", &map, &args[1])?;
+    interp.charge_memory(memory::ENTRY_BYTES, None)?;
+    map.borrow_mut().insert(key, args[2].clone());
+
+    Ok(args[2].clone())
+}
+
+fn map_has(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let map: Gc<RefCell<Map>> = argument(interp, "map_has", &args[0])?;
+    let key = map_key(interp, "map_has", &map, &args[1])?;
+    let has = map.borrow().contains_key(&key);
+
+    Ok(has.into())
+}
+
+// map_remove(m, k) returns whether k was in m.
+fn map_remove(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let map: Gc<RefCell<Map>> = argument(interp, "map_remove", &args[0])?;
+    let key = map_key(interp, "map_remove", &map, &args[1])?;
+    let removed = map.borrow_mut().remove(&key).is_some();
+
+    Ok(removed.into())
+}
+
+// map_keys(m) is a new array of the keys of m, in the order they were first set.
+fn map_keys(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let map: Gc<RefCell<Map>> = argument(interp, "map_keys", &args[0])?;
+    let keys: Vec<RuntimeValue> = map.borrow()
+        .iter()
+        .map(|(key, _)| key.to_value())
+        .collect();
+
+    Ok(RuntimeValue::from(keys))
+}
+
+fn map_len(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let map: Gc<RefCell<Map>> = argument(interp, "map_len", &args[0])?;
+    let len = map.borrow().len();
+
+    Ok(RuntimeValue::Number(len as f64))
+}
+
+// clone(value) deep copies instances, arrays and maps. Other values are returned as they are,
+// so functions, bound methods and classes are shared with the original.
+fn clone(interp: &mut Interpreter, args: &[RuntimeValue]) -> NativeResult {
+    let mut copier = Copier::data_only();
+    let copy = copier.value(&args[0]);
+    interp.charge_contents(&copy, copier.bytes())?;
+
+    Ok(copy)
+}
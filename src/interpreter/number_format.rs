@@ -0,0 +1,128 @@
+// Formatting of numbers to a number of decimals or significant digits.
+// Both round halfway cases away from zero, like the 'round' native, and
+// they do so on the exact value of the number, so 1.005, which is stored
+// as slightly less than that, has 1.00 as its two-decimal form.
+
+// the digits of every finite f64 after the decimal point
+const EXACT_DECIMALS: usize = 1074;
+
+// `n` with exactly `decimals` digits after the decimal point.
+// A negative number that rounds to zero loses its sign.
+pub(super) fn to_fixed(n: f64, decimals: usize) -> String {
+    if !n.is_finite() {
+        return non_finite(n);
+    }
+
+    let Digits { mut digits, mut int_len } = Digits::exact(n);
+    if round(&mut digits, int_len + decimals) {
+        int_len += 1;
+    }
+
+    let mut result = sign(n, &digits);
+    push_digits(&mut result, &digits[..int_len]);
+    if decimals > 0 {
+        result.push('.');
+        push_digits(&mut result, &digits[int_len..]);
+    }
+
+    result
+}
+
+// `n` with `significant` significant digits, in exponent form, like 1.23e-7,
+// if its exponent is below -6 or not below `significant`. Zero has a single
+// zero before the decimal point, which counts as significant.
+pub(super) fn to_precision(n: f64, significant: usize) -> String {
+    if !n.is_finite() {
+        return non_finite(n);
+    }
+
+    let Digits { digits, int_len } = Digits::exact(n);
+    let Some(first) = digits.iter().position(|&d| d != b'0') else {
+        return to_fixed(0_f64, significant - 1);
+    };
+
+    let mut exponent = int_len as i64 - first as i64 - 1;
+    let mut digits = digits[first..].to_vec();
+    if round(&mut digits, significant) {
+        // 9.99 became 10.0, which has one more digit than significant
+        digits.pop();
+        exponent += 1;
+    }
+
+    let mut result = sign(n, &digits);
+    if exponent < -6 || exponent >= significant as i64 {
+        push_digits(&mut result, &digits[..1]);
+        if significant > 1 {
+            result.push('.');
+            push_digits(&mut result, &digits[1..]);
+        }
+        result.push_str(&format!("e{}", exponent));
+    } else if exponent >= 0 {
+        let int_len = exponent as usize + 1;
+        push_digits(&mut result, &digits[..int_len]);
+        if significant > int_len {
+            result.push('.');
+            push_digits(&mut result, &digits[int_len..]);
+        }
+    } else {
+        result.push_str("0.");
+        result.extend(std::iter::repeat_n('0', (-exponent - 1) as usize));
+        push_digits(&mut result, &digits);
+    }
+
+    result
+}
+
+// as print shows them
+fn non_finite(n: f64) -> String {
+    if n.is_nan() { "nan".to_owned() } else { n.to_string() }
+}
+
+// The decimal digits of the absolute value of a number,
+// the first `int_len` of which are before the decimal point.
+struct Digits {
+    digits: Vec<u8>,
+    int_len: usize,
+}
+
+impl Digits {
+    fn exact(n: f64) -> Self {
+        let text = format!("{:.*}", EXACT_DECIMALS, n.abs());
+        let int_len = text.find('.').expect("a decimal point");
+        let digits = text.bytes().filter(|&b| b != b'.').collect();
+
+        Digits { digits, int_len }
+    }
+}
+
+// Keeps the first `len` digits, padding them with zeros if there are fewer,
+// and rounds the last one away from zero if the first dropped digit is 5 or
+// more. Returns whether the rounding added a digit at the front.
+fn round(digits: &mut Vec<u8>, len: usize) -> bool {
+    let round_up = digits.get(len).is_some_and(|&d| d >= b'5');
+    digits.resize(len, b'0');
+    if !round_up {
+        return false;
+    }
+
+    for d in digits.iter_mut().rev() {
+        if *d == b'9' {
+            *d = b'0';
+        } else {
+            *d += 1;
+            return false;
+        }
+    }
+
+    digits.insert(0, b'1');
+    true
+}
+
+fn sign(n: f64, digits: &[u8]) -> String {
+    let is_zero = digits.iter().all(|&d| d == b'0');
+    if n < 0_f64 && !is_zero { "-".to_owned() } else { String::new() }
+}
+
+fn push_digits(out: &mut String, digits: &[u8]) {
+    out.extend(digits.iter().map(|&d| d as char));
+}
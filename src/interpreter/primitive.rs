@@ -0,0 +1,180 @@
+use crate::{
+    RuntimeValue,
+    RuntimeError,
+    Callable,
+    CallableWrapper,
+    Instance,
+    Gc,
+    RefCell,
+};
+use std::{
+    any::Any,
+    fmt::Display,
+};
+use super::{
+    env::Environment,
+    native::argument,
+    Interpreter,
+};
+
+type MethodResult = Result<RuntimeValue, RuntimeError>;
+
+// A built-in method of strings, numbers or booleans.
+struct PrimitiveMethod {
+    name: &'static str,
+    arity: usize,
+    fun: fn(&mut Interpreter, &RuntimeValue, &[RuntimeValue]) -> MethodResult,
+}
+
+const STRING_METHODS: &[PrimitiveMethod] = &[
+    PrimitiveMethod { name: "len", arity: 0, fun: len },
+    PrimitiveMethod { name: "upper", arity: 0, fun: upper },
+    PrimitiveMethod { name: "lower", arity: 0, fun: lower },
+    PrimitiveMethod { name: "substr", arity: 2, fun: substr },
+    PrimitiveMethod { name: "contains", arity: 1, fun: contains },
+    PrimitiveMethod { name: "toString", arity: 0, fun: to_string },
+];
+
+const NUMBER_METHODS: &[PrimitiveMethod] = &[
+    PrimitiveMethod { name: "floor", arity: 0, fun: floor },
+    PrimitiveMethod { name: "ceil", arity: 0, fun: ceil },
+    PrimitiveMethod { name: "abs", arity: 0, fun: abs },
+    PrimitiveMethod { name: "sqrt", arity: 0, fun: sqrt },
+    PrimitiveMethod { name: "toString", arity: 0, fun: to_string },
+];
+
+const BOOL_METHODS: &[PrimitiveMethod] = &[
+    PrimitiveMethod { name: "toString", arity: 0, fun: to_string },
+];
+
+// A built-in method together with the value it was looked up on.
+#[derive(Clone)]
+pub struct BoundPrimitive {
+    receiver: RuntimeValue,
+    method: &'static PrimitiveMethod,
+}
+
+impl Display for BoundPrimitive {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native method {}>", self.method.name)
+    }
+}
+
+impl Callable for BoundPrimitive {
+    fn arity(&self) -> usize {
+        self.method.arity
+    }
+
+    fn call(
+        &self,
+        args: &[RuntimeValue],
+        interp: &mut Interpreter,
+        _closure: &Option<Gc<RefCell<Environment>>>,
+        _receiver: Option<&Gc<RefCell<Instance>>>,
+    ) -> MethodResult {
+        (self.method.fun)(interp, &self.receiver, args)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> String {
+        self.method.name.to_owned()
+    }
+}
+
+// The method `name` of a string, number or boolean, bound to it.
+// None for other values and for names that are not built-in methods.
+pub(super) fn method(value: &RuntimeValue, name: &str) -> Option<RuntimeValue> {
+    let methods = match value {
+        RuntimeValue::String(_) => STRING_METHODS,
+        RuntimeValue::Number(_) => NUMBER_METHODS,
+        RuntimeValue::Bool(_) => BOOL_METHODS,
+        _ => return None,
+    };
+
+    let method = methods.iter().find(|m| m.name == name)?;
+    Some(RuntimeValue::Callable(CallableWrapper {
+        callable: Box::new(BoundPrimitive {
+            receiver: value.clone(),
+            method,
+        }),
+        closure: None,
+        receiver: None,
+    }))
+}
+
+// Receivers always have the type of the table their method is in.
+fn receiver_str(receiver: &RuntimeValue) -> &str {
+    receiver.as_str().expect("string method bound to a non-string")
+}
+
+fn receiver_number(receiver: &RuntimeValue) -> f64 {
+    receiver.as_number().expect("number method bound to a non-number")
+}
+
+// the number of characters, consistent with `len` and indexing
+fn len(_interp: &mut Interpreter, receiver: &RuntimeValue, _args: &[RuntimeValue]) -> MethodResult {
+    Ok(RuntimeValue::Number(receiver_str(receiver).chars().count() as f64))
+}
+
+fn upper(_interp: &mut Interpreter, receiver: &RuntimeValue, _args: &[RuntimeValue]) -> MethodResult {
+    Ok(receiver_str(receiver).to_uppercase().into())
+}
+
+fn lower(_interp: &mut Interpreter, receiver: &RuntimeValue, _args: &[RuntimeValue]) -> MethodResult {
+    Ok(receiver_str(receiver).to_lowercase().into())
+}
+
+// substr(start, end) is the characters from `start` up to, but not including, `end`.
+fn substr(interp: &mut Interpreter, receiver: &RuntimeValue, args: &[RuntimeValue]) -> MethodResult {
+    let s = receiver_str(receiver);
+    let start: f64 = argument(interp, "substr", &args[0])?;
+    let end: f64 = argument(interp, "substr", &args[1])?;
+
+    let len = s.chars().count() as f64;
+    let is_valid = start.fract() == 0_f64 && end.fract() == 0_f64
+        && 0_f64 <= start && start <= end && end <= len;
+    if !is_valid {
+        return Err(interp.invalid_argument(&format!(
+            "substr: expected integers 0 <= start <= end <= {}, found {} and {}",
+            len, start, end,
+        )));
+    }
+
+    let result: String = s.chars()
+        .skip(start as usize)
+        .take((end - start) as usize)
+        .collect();
+    Ok(result.into())
+}
+
+fn contains(interp: &mut Interpreter, receiver: &RuntimeValue, args: &[RuntimeValue]) -> MethodResult {
+    let part: String = argument(interp, "contains", &args[0])?;
+    Ok(receiver_str(receiver).contains(part.as_str()).into())
+}
+
+fn floor(_interp: &mut Interpreter, receiver: &RuntimeValue, _args: &[RuntimeValue]) -> MethodResult {
+    Ok(receiver_number(receiver).floor().into())
+}
+
+fn ceil(_interp: &mut Interpreter, receiver: &RuntimeValue, _args: &[RuntimeValue]) -> MethodResult {
+    Ok(receiver_number(receiver).ceil().into())
+}
+
+fn abs(_interp: &mut Interpreter, receiver: &RuntimeValue, _args: &[RuntimeValue]) -> MethodResult {
+    Ok(receiver_number(receiver).abs().into())
+}
+
+fn sqrt(_interp: &mut Interpreter, receiver: &RuntimeValue, _args: &[RuntimeValue]) -> MethodResult {
+    Ok(receiver_number(receiver).sqrt().into())
+}
+
+// strings are returned as they are, without the quotes they are printed with
+fn to_string(_interp: &mut Interpreter, receiver: &RuntimeValue, _args: &[RuntimeValue]) -> MethodResult {
+    match receiver {
+        RuntimeValue::String(_) => Ok(receiver.clone()),
+        _ => Ok(receiver.to_string().into()),
+    }
+}
@@ -0,0 +1,94 @@
+use crate::scanner::Token;
+use crate::Rc;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+// The time spent in one function.
+#[derive(Clone, Debug)]
+pub struct ProfileEntry {
+    pub function: String,
+    pub declaration: Option<Rc<Token>>,
+    pub calls: u64,
+    // time between entering and leaving the function,
+    // counted once for nested recursive calls
+    pub total_time: Duration,
+    // total time minus the time spent in calls it made
+    pub self_time: Duration,
+}
+
+// functions are told apart by name and declaration position
+type Key = (String, Option<(u64, u64)>);
+
+// A call that has not returned yet.
+struct ActiveCall {
+    function: String,
+    declaration: Option<Rc<Token>>,
+    started: Instant,
+    children_time: Duration,
+}
+
+#[derive(Default)]
+pub(super) struct Profiler {
+    entries: HashMap<Key, ProfileEntry>,
+    active: Vec<ActiveCall>,
+    // number of active calls of each function
+    depth: HashMap<Key, usize>,
+}
+
+impl Profiler {
+    pub(super) fn enter(&mut self, function: &str, declaration: Option<&Rc<Token>>) {
+        *self.depth.entry(key(function, declaration)).or_default() += 1;
+        self.active.push(ActiveCall {
+            function: function.to_owned(),
+            declaration: declaration.cloned(),
+            started: Instant::now(),
+            children_time: Duration::ZERO,
+        });
+    }
+
+    // Tail calls reuse the frame of the call they are made from,
+    // so their time is counted for that call's function.
+    pub(super) fn exit(&mut self) {
+        let call = match self.active.pop() {
+            Some(call) => call,
+            None => return,
+        };
+        let elapsed = call.started.elapsed();
+
+        if let Some(parent) = self.active.last_mut() {
+            parent.children_time += elapsed;
+        }
+
+        let key = key(&call.function, call.declaration.as_ref());
+        let depth = self.depth.entry(key.clone()).or_default();
+        *depth -= 1;
+        let outermost = *depth == 0;
+
+        let entry = self.entries.entry(key).or_insert_with(|| ProfileEntry {
+            function: call.function,
+            declaration: call.declaration,
+            calls: 0,
+            total_time: Duration::ZERO,
+            self_time: Duration::ZERO,
+        });
+        entry.calls += 1;
+        entry.self_time += elapsed.saturating_sub(call.children_time);
+        if outermost {
+            entry.total_time += elapsed;
+        }
+    }
+
+    // The entries recorded so far, most self time first.
+    pub(super) fn take(&mut self) -> Vec<ProfileEntry> {
+        let mut entries: Vec<ProfileEntry> = self.entries.drain().map(|(_, e)| e).collect();
+        entries.sort_by_key(|e| std::cmp::Reverse(e.self_time));
+
+        entries
+    }
+}
+
+fn key(function: &str, declaration: Option<&Rc<Token>>) -> Key {
+    (function.to_owned(), declaration.map(|t| (t.line, t.column)))
+}
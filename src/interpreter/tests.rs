@@ -0,0 +1,5693 @@
+use super::*;
+use crate::{
+    ConversionError,
+    Lox,
+    LoxError,
+    Truthiness,
+    ast_printer::render_program,
+    compile,
+    compile_snippet,
+    parse_source,
+    run_snippet,
+    run_source,
+    run_source_new,
+    parser::Parser,
+    resolver::{ResolutionError, Resolver},
+    scanner::scan,
+};
+
+fn parse(source: &str) -> Vec<statement::Stmt> {
+    let tokens = scan(source).expect("scan failed");
+    let mut stmts = Parser::new(&tokens).parse().expect("parse failed");
+    let result = Resolver::new().resolve(&mut stmts);
+    assert!(result.errors.is_none(), "resolution failed");
+
+    stmts
+}
+
+fn run(source: &str) -> (Interpreter, ExecResult) {
+    let stmts = parse(source);
+    let mut interp = Interpreter::new();
+    let result = interp.execute(&stmts);
+
+    (interp, result)
+}
+
+fn global(interp: &Interpreter, name: &str) -> RuntimeValue {
+    interp.globals_env
+        .borrow()
+        .get(name)
+        .expect("undefined global")
+}
+
+// Deep lox recursion needs more native stack
+// than test threads get by default.
+fn with_large_stack<F: FnOnce() + Send + 'static>(f: F) {
+    std::thread::Builder::new()
+        .stack_size(64 * 1024 * 1024)
+        .spawn(f)
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+fn assert_str(interp: &Interpreter, name: &str, expected: &str) {
+    match global(interp, name) {
+        RuntimeValue::String(s) => assert_eq!(&*s, expected, "global '{}'", name),
+        v => panic!("expected string for '{}', found {}", name, v),
+    }
+}
+
+fn assert_bool(interp: &Interpreter, name: &str, expected: bool) {
+    match global(interp, name) {
+        RuntimeValue::Bool(b) => assert_eq!(b, expected, "global '{}'", name),
+        v => panic!("expected bool for '{}', found {}", name, v),
+    }
+}
+
+#[test]
+fn same_bound_method_from_same_instance_is_equal() {
+    let (interp, result) = run("
+        class A { m() { } }
+        var a = A();
+        var same = a.m == a.m;
+        var stored = a.m;
+        var same_stored = stored == a.m;
+    ");
+
+    assert!(result.is_ok());
+    assert_bool(&interp, "same", true);
+    assert_bool(&interp, "same_stored", true);
+}
+
+#[test]
+fn same_method_from_different_instances_is_not_equal() {
+    let (interp, result) = run("
+        class A { m() { } n() { } }
+        var a = A();
+        var b = A();
+        var different_receivers = a.m == b.m;
+        var different_methods = a.m == a.n;
+    ");
+
+    assert!(result.is_ok());
+    assert_bool(&interp, "different_receivers", false);
+    assert_bool(&interp, "different_methods", false);
+}
+
+#[test]
+fn bound_method_differs_from_unbound_class_method() {
+    let (interp, result) = run("
+        class A { m() { } }
+        var a = A();
+        var bound = a.m;
+    ");
+    assert!(result.is_ok());
+
+    let class = match global(&interp, "A") {
+        RuntimeValue::Class(c) => c,
+        _ => panic!("expected class"),
+    };
+    let unbound = class.borrow().find_method("m").unwrap();
+    let bound = match global(&interp, "bound") {
+        RuntimeValue::Callable(c) => c,
+        _ => panic!("expected callable"),
+    };
+
+    assert!(unbound.receiver().is_none());
+    assert!(!bound.is_same(&unbound));
+    assert!(unbound.is_same(&unbound));
+}
+
+#[test]
+fn functions_and_instances_compare_by_identity() {
+    let (interp, result) = run("
+        fun f() { }
+        fun g() { }
+        class A { }
+        var a = A();
+        var b = A();
+        var same_fun = f == f;
+        var different_fun = f == g;
+        var same_instance = a == a;
+        var different_instance = a == b;
+    ");
+
+    assert!(result.is_ok());
+    assert_bool(&interp, "same_fun", true);
+    assert_bool(&interp, "different_fun", false);
+    assert_bool(&interp, "same_instance", true);
+    assert_bool(&interp, "different_instance", false);
+}
+
+#[test]
+fn closures_declared_in_a_loop_capture_their_own_iteration() {
+    let source = "
+        var adders = Array();
+        for (var i = 0; i < 3; i = i + 1) {
+            var n = i * 10;
+            fun add(x) { return x + n; }
+            push(adders, add);
+        }
+        var first = adders[0](1);
+        var last = adders[2](1);
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_number(&interp, "first", 1.0);
+    assert_number(&interp, "last", 21.0);
+}
+
+#[test]
+fn functions_from_one_declaration_share_it() {
+    let source = "
+        fun make() {
+            fun f() {}
+            return f;
+        }
+        var a = make();
+        var b = make();
+        var c = a;
+
+        fun make_class() {
+            class C { m() {} }
+            return C;
+        }
+        var C1 = make_class();
+        var C2 = make_class();
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+
+    let decl = |name: &str| match global(&interp, name) {
+        RuntimeValue::Callable(c) => c.callable.as_any()
+            .downcast_ref::<crate::Function>()
+            .expect("a function")
+            .decl
+            .clone(),
+        _ => panic!("expected a callable for '{}'", name),
+    };
+    assert!(Rc::ptr_eq(&decl("a"), &decl("b")));
+    assert!(Rc::ptr_eq(&decl("a"), &decl("c")));
+
+    let method_decl = |name: &str| match global(&interp, name) {
+        RuntimeValue::Class(class) => class.borrow()
+            .find_method("m")
+            .expect("a method")
+            .callable
+            .as_any()
+            .downcast_ref::<crate::Function>()
+            .expect("a function")
+            .decl
+            .clone(),
+        _ => panic!("expected a class for '{}'", name),
+    };
+    assert!(Rc::ptr_eq(&method_decl("C1"), &method_decl("C2")));
+}
+
+// A coarse guard against slowdowns of ten times or more, for runs that do not
+// include the benchmarks. Each limit is about ten times what the program
+// takes in a debug build.
+#[test]
+fn representative_programs_run_in_reasonable_time() {
+    let programs = [
+        (4, "
+            fun fib(n) {
+                if (n < 2) return n;
+                return fib(n - 1) + fib(n - 2);
+            }
+            var result = fib(20);
+        "),
+        (10, "
+            var i = 0;
+            var result = 0;
+            while (i < 100000) {
+                result = result + i * 2 - i / 2;
+                i = i + 1;
+            }
+        "),
+        (2, "
+            var result = \"\";
+            for (var i = 0; i < 10000; i = i + 1) {
+                result = result + \"x\";
+            }
+        "),
+        (15, "
+            class Point {
+                init(x, y) {
+                    this.x = x;
+                    this.y = y;
+                }
+                add(other) {
+                    return Point(this.x + other.x, this.y + other.y);
+                }
+            }
+            var result = Point(0, 0);
+            for (var i = 0; i < 10000; i = i + 1) {
+                result = result.add(Point(i, 1));
+            }
+        "),
+        (4, "
+            fun counter(start) {
+                var count = start;
+                fun next() {
+                    count = count + 1;
+                    return count;
+                }
+                return next;
+            }
+            var result = 0;
+            for (var i = 0; i < 10000; i = i + 1) {
+                var next = counter(i);
+                result = result + next();
+            }
+        "),
+    ];
+
+    for (limit, source) in programs {
+        let start = std::time::Instant::now();
+        let (_, result) = run(source);
+        let elapsed = start.elapsed();
+
+        assert!(result.is_ok());
+        assert!(elapsed.as_secs() < limit, "took {:?}:{}", elapsed, source);
+    }
+}
+
+#[test]
+fn runtime_error_carries_call_stack() {
+    let (interp, result) = run("
+        fun h() { return undefined; }
+        fun g() { h(); }
+        fun f() { g(); }
+        f();
+    ");
+
+    assert!(matches!(result, Err(RuntimeError::UndefinedVariable(_))));
+
+    let stack = interp.stack_trace();
+    let names: Vec<&str> = stack.iter().map(|f| f.function.as_str()).collect();
+    assert_eq!(names, ["f", "g", "h"]);
+    assert_eq!(stack[0].call_site.line, 5);
+    assert_eq!(stack[2].call_site.line, 3);
+    assert_eq!(stack[2].call_site.column, 21);
+    assert_eq!(stack[2].declaration.as_ref().unwrap().line, 2);
+}
+
+#[test]
+fn runtime_error_in_global_code_has_empty_stack() {
+    let (interp, result) = run("
+        fun f() { }
+        f();
+        -nil;
+    ");
+
+    assert!(matches!(result, Err(RuntimeError::UnaryMinusExpectsNumber(_))));
+    assert!(interp.stack_trace().is_empty());
+}
+
+#[test]
+fn runtime_errors_returned_by_the_facade_carry_their_call_stack() {
+    let source = "
+        fun h() { return undefined; }
+        fun g() { h(); }
+        fun f() { g(); }
+        f();
+    ";
+
+    let err = match Lox::new().run(source) {
+        Err(LoxError::Runtime(e)) => e,
+        _ => panic!("expected a runtime error"),
+    };
+    let names: Vec<&str> = err.stack.iter().map(|f| f.function.as_str()).collect();
+    assert_eq!(names, ["f", "g", "h"]);
+    assert!(matches!(err.error, RuntimeError::UndefinedVariable(_)));
+
+    let lines: Vec<String> = match run_source_new(source) {
+        Err(e) => e.to_string().lines().map(str::to_owned).collect(),
+        Ok(_) => panic!("expected an error"),
+    };
+    assert_eq!(lines[1..], [
+        "    in 'h' called from line 3, column 21",
+        "    in 'g' called from line 4, column 21",
+        "    in 'f' called from line 5, column 11",
+    ]);
+
+    match run_source_new("-nil;") {
+        Err(LoxError::Runtime(e)) => assert!(e.stack.is_empty()),
+        _ => panic!("expected a runtime error"),
+    }
+}
+
+#[test]
+fn errors_and_copies_of_the_tree_share_its_tokens() {
+    let stmts = parse("print undefined;");
+    let copy = stmts.clone();
+    let mut interp = Interpreter::new();
+    let token = match interp.execute(&stmts) {
+        Err(RuntimeError::UndefinedVariable(token)) => token,
+        _ => panic!("expected an undefined variable"),
+    };
+    assert_eq!(token.lexeme, "undefined");
+    // the tree, its copy and the error
+    assert_eq!(Rc::strong_count(&token), 3);
+
+    drop(stmts);
+    drop(copy);
+    assert_eq!(Rc::strong_count(&token), 1);
+}
+
+#[test]
+fn recursion_shows_repeated_frames() {
+    let (interp, result) = run("
+        fun f(n) {
+            if (n == 0) return -nil;
+            f(n - 1);
+        }
+        f(3);
+    ");
+
+    assert!(result.is_err());
+
+    let stack = interp.stack_trace();
+    assert_eq!(stack.len(), 4);
+    assert!(stack.iter().all(|f| f.function == "f"));
+}
+
+#[test]
+fn unbounded_recursion_reports_stack_overflow() {
+    with_large_stack(|| {
+        let (interp, result) = run("
+            fun f(n) { return 1 + f(n + 1); }
+            f(0);
+        ");
+
+        assert!(matches!(result, Err(RuntimeError::StackOverflow(_))));
+        assert_eq!(interp.stack_trace().len(), InterpreterOptions::default().max_call_depth);
+    });
+}
+
+#[test]
+fn recursion_under_the_call_depth_limit_succeeds() {
+    let stmts = parse("
+        fun depth(n) {
+            if (n == 0) return 0;
+            return 1 + depth(n - 1);
+        }
+        var ok = depth(49);
+    ");
+
+    let mut interp = Interpreter::new().with_max_call_depth(50);
+    assert!(interp.execute(&stmts).is_ok());
+    assert!(matches!(global(&interp, "ok"), RuntimeValue::Number(n) if n == 49.0));
+
+    let stmts = parse("depth(50);");
+    assert!(matches!(interp.execute(&stmts), Err(RuntimeError::StackOverflow(_))));
+}
+
+#[test]
+fn call_depth_unwinds_after_errors() {
+    let stmts = parse("
+        fun deep(n) {
+            if (n == 0) return -nil;
+            return deep(n - 1);
+        }
+        fun shallow(n) {
+            if (n == 0) return 0;
+            return shallow(n - 1);
+        }
+    ");
+
+    let mut interp = Interpreter::new().with_max_call_depth(20);
+    assert!(interp.execute(&stmts).is_ok());
+
+    for _ in 0..5 {
+        let result = interp.execute(&parse("deep(15);"));
+        assert!(matches!(result, Err(RuntimeError::UnaryMinusExpectsNumber(_))));
+    }
+
+    assert!(interp.execute(&parse("shallow(19);")).is_ok());
+}
+
+#[test]
+fn tail_recursion_runs_in_constant_stack() {
+    let (interp, result) = run("
+        fun count(n, acc) {
+            if (n == 0) return acc;
+            return count(n - 1, acc + 1);
+        }
+        var total = count(1000000, 0);
+    ");
+
+    assert!(result.is_ok());
+    assert!(matches!(global(&interp, "total"), RuntimeValue::Number(n) if n == 1000000.0));
+}
+
+#[test]
+fn non_tail_recursion_still_builds_stack() {
+    with_large_stack(|| {
+        let (_, result) = run("
+            fun count(n) {
+                if (n == 0) return 0;
+                return 1 + count(n - 1);
+            }
+            count(1000000);
+        ");
+
+        assert!(matches!(result, Err(RuntimeError::StackOverflow(_))));
+    });
+}
+
+#[test]
+fn mutual_tail_recursion() {
+    let (interp, result) = run("
+        fun is_even(n) {
+            if (n == 0) return true;
+            return is_odd(n - 1);
+        }
+        fun is_odd(n) {
+            if (n == 0) return false;
+            return is_even(n - 1);
+        }
+        var even = is_even(100001);
+        var odd = is_odd(100001);
+    ");
+
+    assert!(result.is_ok());
+    assert_bool(&interp, "even", false);
+    assert_bool(&interp, "odd", true);
+}
+
+#[test]
+fn tail_calls_preserve_this_and_errors() {
+    let (interp, result) = run("
+        class Counter {
+            init() { this.n = 0; }
+            up(k) {
+                if (k == 0) return this;
+                this.n = this.n + 1;
+                return this.up(k - 1);
+            }
+        }
+        var c = Counter().up(5000);
+        var ok = c.n == 5000;
+        fun fails(n) { return nope(n); }
+    ");
+
+    assert!(result.is_ok());
+    assert_bool(&interp, "ok", true);
+
+    let (interp, result) = run("
+        fun g(n) { return -n; }
+        fun f() { return g(nil); }
+        f();
+    ");
+    assert!(matches!(result, Err(RuntimeError::UnaryMinusExpectsNumber(_))));
+
+    let names: Vec<&str> = interp.stack_trace().iter().map(|f| f.function.as_str()).collect();
+    assert_eq!(names, ["g"]);
+}
+
+#[test]
+fn deferred_statements_run_in_reverse_order() {
+    let (interp, result) = run("
+        var log = \"\";
+        {
+            defer log = log + \"1\";
+            defer log = log + \"2\";
+            log = log + \"body\";
+        }
+    ");
+
+    assert!(result.is_ok());
+    assert_str(&interp, "log", "body21");
+}
+
+#[test]
+fn defer_runs_on_return() {
+    let (interp, result) = run("
+        var log = \"\";
+        fun f() {
+            defer log = log + \"deferred\";
+            log = log + \"body,\";
+            return 1;
+            log = log + \"unreachable\";
+        }
+        var value = f();
+    ");
+
+    assert!(result.is_ok());
+    assert_str(&interp, "log", "body,deferred");
+    assert!(matches!(global(&interp, "value"), RuntimeValue::Number(n) if n == 1.0));
+}
+
+#[test]
+fn defer_runs_when_an_error_unwinds() {
+    let (interp, result) = run("
+        var log = \"\";
+        fun f() {
+            defer log = log + \"cleanup\";
+            defer -nil;
+            return -\"oops\";
+        }
+        f();
+    ");
+
+    assert!(matches!(result, Err(RuntimeError::UnaryMinusExpectsNumber(_))));
+    assert_str(&interp, "log", "cleanup");
+}
+
+#[test]
+fn defer_captures_loop_variables() {
+    let (interp, result) = run("
+        var log = \"\";
+        for (var i = 0; i < 3; i = i + 1) {
+            var c = i;
+            defer log = log + \"<\";
+            if (c == 1) { defer log = log + \"one\"; }
+            if (c == 2) break;
+        }
+    ");
+
+    assert!(result.is_ok());
+    assert_str(&interp, "log", "<one<<");
+}
+
+#[test]
+fn throw_is_caught_in_the_same_function() {
+    let (interp, result) = run("
+        var log = \"\";
+        fun f() {
+            try {
+                log = log + \"try,\";
+                throw \"boom\";
+                log = log + \"unreachable,\";
+            } catch (e) {
+                log = log + e;
+            }
+            return \"done\";
+        }
+        var value = f();
+    ");
+
+    assert!(result.is_ok());
+    assert_str(&interp, "log", "try,boom");
+    assert_str(&interp, "value", "done");
+}
+
+#[test]
+fn throw_propagates_through_calls() {
+    let (interp, result) = run("
+        var caught;
+        fun inner() { throw 42; }
+        fun outer() { inner(); return \"unreachable\"; }
+        try {
+            outer();
+        } catch (e) {
+            caught = e;
+        }
+    ");
+
+    assert!(result.is_ok());
+    assert!(interp.stack_trace().is_empty());
+    assert!(matches!(global(&interp, "caught"), RuntimeValue::Number(n) if n == 42.0));
+}
+
+#[test]
+fn finally_runs_on_both_paths() {
+    let (interp, result) = run("
+        var log = \"\";
+        fun f(fail) {
+            try {
+                if (fail) throw \"x\";
+                return \"ok\";
+            } catch (e) {
+                log = log + \"caught,\";
+            } finally {
+                log = log + \"finally,\";
+            }
+            return \"recovered\";
+        }
+        var ok = f(false);
+        var recovered = f(true);
+    ");
+
+    assert!(result.is_ok());
+    assert_str(&interp, "log", "finally,caught,finally,");
+    assert_str(&interp, "ok", "ok");
+    assert_str(&interp, "recovered", "recovered");
+}
+
+#[test]
+fn rethrow_from_catch() {
+    let (interp, result) = run("
+        var log = \"\";
+        fun f() {
+            try {
+                throw \"first\";
+            } catch (e) {
+                throw e + \" again\";
+            } finally {
+                log = log + \"finally\";
+            }
+        }
+        var caught;
+        try { f(); } catch (e) { caught = e; }
+    ");
+
+    assert!(result.is_ok());
+    assert_str(&interp, "log", "finally");
+    assert_str(&interp, "caught", "first again");
+}
+
+#[test]
+fn break_leaves_try_through_finally() {
+    let (interp, result) = run("
+        var log = \"\";
+        for (var i = 0; i < 5; i = i + 1) {
+            try {
+                if (i == 2) break;
+                log = log + \"body,\";
+            } finally {
+                log = log + \"finally,\";
+            }
+        }
+    ");
+
+    assert!(result.is_ok());
+    assert_str(&interp, "log", "body,finally,body,finally,finally,");
+}
+
+#[test]
+fn uncaught_throw_is_a_runtime_error() {
+    let (_, result) = run("
+        fun f() {
+            throw \"boom\";
+        }
+        f();
+    ");
+
+    match result {
+        Err(RuntimeError::Thrown { keyword, value }) => {
+            assert_eq!(keyword.line, 3);
+            assert!(matches!(*value, RuntimeValue::String(s) if &*s == "boom"));
+        },
+        _ => panic!("expected an uncaught throw"),
+    }
+
+    let (_, result) = run("throw nil;");
+    assert!(matches!(result, Err(RuntimeError::Thrown { .. })));
+}
+
+#[test]
+fn tail_call_inside_try_is_caught() {
+    let (interp, result) = run("
+        fun fail() { throw \"inner\"; }
+        fun f() {
+            try {
+                return fail();
+            } catch (e) {
+                return \"caught \" + e;
+            }
+        }
+        var value = f();
+    ");
+
+    assert!(result.is_ok());
+    assert_str(&interp, "value", "caught inner");
+}
+
+#[test]
+fn returned_call_runs_before_deferred_statements() {
+    let (interp, result) = run("
+        var log = \"\";
+        fun g() { log = log + \"g,\"; return 1; }
+        fun f() {
+            defer log = log + \"deferred\";
+            return g();
+        }
+        f();
+    ");
+
+    assert!(result.is_ok());
+    assert_str(&interp, "log", "g,deferred");
+}
+
+#[test]
+fn division_by_zero_is_caught_as_an_error_value() {
+    let (interp, result) = run("
+        var message;
+        var line;
+        try {
+            var x = 1 / 0;
+        } catch (e) {
+            message = e.message;
+            line = e.line;
+        }
+    ");
+
+    assert!(result.is_ok());
+    assert_str(&interp, "message", "Division by zero");
+    assert!(matches!(global(&interp, "line"), RuntimeValue::Number(n) if n == 5.0));
+}
+
+#[test]
+fn caught_error_can_be_rethrown() {
+    let (interp, result) = run("
+        fun f() {
+            try {
+                nil.field;
+            } catch (e) {
+                throw e;
+            }
+        }
+        var first;
+        var second;
+        try { f(); } catch (e) { first = e; }
+        try { f(); } catch (e) { second = e; }
+        var message = first.message;
+        var distinct = first == second;
+    ");
+
+    assert!(result.is_ok());
+    assert_str(&interp, "message", "Only instances have properties");
+    assert_bool(&interp, "distinct", false);
+
+    let (_, result) = run("
+        try { 1 / 0; } catch (e) { throw e; }
+    ");
+    match result {
+        Err(RuntimeError::Thrown { value, .. }) => {
+            assert!(matches!(*value, RuntimeValue::Instance(_)));
+        },
+        _ => panic!("expected the rethrown error"),
+    }
+}
+
+#[test]
+fn stack_overflow_is_not_catchable() {
+    let stmts = parse("
+        var log = \"\";
+        fun f(n) { f(n + 1); }
+        try {
+            f(0);
+        } catch (e) {
+            log = log + \"caught,\";
+        } finally {
+            log = log + \"finally\";
+        }
+    ");
+    let mut interp = Interpreter::new().with_max_call_depth(50);
+    let result = interp.execute(&stmts);
+
+    assert!(matches!(result, Err(RuntimeError::StackOverflow(_))));
+    assert_str(&interp, "log", "finally");
+}
+
+#[test]
+fn unresolved_break_outside_loop_is_an_error() {
+    let sources = [
+        "fun f() { break; } f();",
+        "{ break; }",
+    ];
+
+    for src in sources.iter() {
+        let tokens = scan(src).unwrap();
+        let stmts = Parser::new(&tokens).parse().unwrap();
+        let result = Interpreter::new().execute(&stmts);
+
+        assert!(matches!(result, Err(RuntimeError::BreakOutsideLoop(_))), "{}", src);
+    }
+}
+
+#[test]
+fn binding_a_callable_without_closure_provides_this() {
+    let (mut interp, result) = run("
+        class A { m() { return this.x; } }
+        var a = A();
+        a.x = \"field\";
+    ");
+    assert!(result.is_ok());
+
+    let method = match global(&interp, "A") {
+        RuntimeValue::Class(c) => c.borrow().find_method("m").unwrap(),
+        _ => panic!("expected a class"),
+    };
+    let instance = match global(&interp, "a") {
+        RuntimeValue::Instance(i) => i,
+        _ => panic!("expected an instance"),
+    };
+
+    let unbound = CallableWrapper {
+        closure: None,
+        ..(*method).clone()
+    };
+    let bound = crate::bind_method(&unbound, &instance);
+    let value = bound.callable.call(&Vec::new(), &mut interp, &bound.closure, bound.receiver());
+
+    assert!(matches!(value, Ok(RuntimeValue::String(s)) if &*s == "field"));
+}
+
+#[test]
+fn super_with_bogus_hops_is_an_error() {
+    use crate::scanner::TokenType;
+
+    let token = |token_type, lexeme: &str| Rc::new(Token {
+        token_type,
+        lexeme: lexeme.to_owned(),
+        literal: None,
+        line: 1,
+        column: 1,
+    });
+    let expr = expression::Expr::Super(expression::Super {
+        keyword: token(TokenType::Super, "super"),
+        method: token(TokenType::Identifier, "m"),
+        hops_to_super: None,
+        hops_to_this: None,
+    });
+
+    let (mut interp, _) = run("class A { m() { } }");
+    interp.globals_env.borrow_mut().define("super", RuntimeValue::Number(1.0));
+    assert!(matches!(interp.evaluate_expr(&expr), Err(RuntimeError::SuperNotAClass(_))));
+
+    let class = global(&interp, "A");
+    interp.globals_env.borrow_mut().define("super", class);
+    assert!(matches!(interp.evaluate_expr(&expr), Err(RuntimeError::ThisNotAnInstance(_))));
+}
+
+#[test]
+fn instances_with_call_method_are_callable() {
+    let (interp, result) = run("
+        class Base {
+            call(step) {
+                this.count = this.count + step;
+                return this;
+            }
+        }
+        class Counter < Base {
+            init() { this.count = 0; }
+        }
+
+        var c = Counter();
+        var same = c(1)(2) == c;
+        var count = c.count;
+    ");
+
+    assert!(result.is_ok());
+    assert_bool(&interp, "same", true);
+    assert!(matches!(global(&interp, "count"), RuntimeValue::Number(n) if n == 3.0));
+}
+
+#[test]
+fn calling_instances_checks_arity_and_call_method() {
+    let (_, result) = run("
+        class A { call(x) { } }
+        A()();
+    ");
+    assert!(matches!(
+        result,
+        Err(RuntimeError::CallableArityMismatch { expected: 1, found: 0, .. })
+    ));
+
+    let (_, result) = run("
+        class B { }
+        B()();
+    ");
+    assert!(matches!(result, Err(RuntimeError::NonCallableCalled(_))));
+}
+
+#[test]
+fn classes_can_be_called_through_the_api() {
+    let (mut interp, result) = run("
+        class Point { init(x, y) { this.x = x; this.y = y; } }
+        class Empty { }
+    ");
+    assert!(result.is_ok());
+
+    let point = interp.call(&global(&interp, "Point"), &[1.0.into(), 2.0.into()]);
+    match point {
+        Ok(RuntimeValue::Instance(point)) => {
+            assert!(matches!(point.borrow().field("y"), Some(RuntimeValue::Number(n)) if *n == 2.0));
+        },
+        _ => panic!("expected an instance"),
+    }
+    assert!(matches!(interp.call(&global(&interp, "Empty"), &[]), Ok(RuntimeValue::Instance(_))));
+    assert!(matches!(
+        interp.call(&global(&interp, "Point"), &[1.0.into()]),
+        Err(RuntimeError::CallableArityMismatch { expected: 2, found: 1, .. })
+    ));
+    assert!(matches!(
+        interp.call(&global(&interp, "Empty"), &[1.0.into()]),
+        Err(RuntimeError::CallableArityMismatch { expected: 0, found: 1, .. })
+    ));
+}
+
+#[test]
+fn inherited_methods_are_found_after_they_are_cached() {
+    let source = "
+        class A {
+            m() { return \"A\"; }
+            only_a() { return \"only A\"; }
+        }
+        class B < A {
+            m() { return \"B \" + super.m(); }
+        }
+        class C < B {}
+        class D < C {
+            m() { return \"D \" + super.m(); }
+        }
+
+        var c = C();
+        var first = c.m();
+        var second = c.m();
+        var d = D().m();
+        var b = B().m();
+        var inherited = c.only_a() + \" \" + D().only_a();
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_str(&interp, "first", "B A");
+    assert_str(&interp, "second", "B A");
+    assert_str(&interp, "d", "D B A");
+    assert_str(&interp, "b", "B A");
+    assert_str(&interp, "inherited", "only A only A");
+}
+
+#[test]
+fn super_skips_the_methods_cached_in_subclasses() {
+    let source = "
+        class A {
+            name() { return \"A\"; }
+        }
+        class B < A {
+            name() { return \"B\"; }
+            parent() { return super.name(); }
+        }
+        class C < B {}
+
+        var c = C();
+        var own = c.name();
+        var parent = c.parent();
+        var again = c.name();
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_str(&interp, "own", "B");
+    assert_str(&interp, "parent", "A");
+    assert_str(&interp, "again", "B");
+}
+
+#[test]
+fn property_accesses_alternating_between_classes_find_the_right_method() {
+    let source = "
+        class A { name() { return \"A\"; } }
+        class B { name() { return \"B\"; } }
+        class C < A {}
+
+        var objects = Array(A(), B(), C(), B(), A());
+        var called = \"\";
+        var bound = \"\";
+        for (var o in objects) {
+            called = called + o.name();
+            var m = o.name;
+            bound = bound + m();
+        }
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_str(&interp, "called", "ABABA");
+    assert_str(&interp, "bound", "ABABA");
+}
+
+#[test]
+fn fields_shadow_methods_found_at_the_same_access() {
+    let source = "
+        class A {
+            name() { return \"method\"; }
+        }
+        fun field() { return \"field\"; }
+
+        var plain = A();
+        var shadowed = A();
+        shadowed.name = field;
+
+        var seen = \"\";
+        for (var o in Array(plain, shadowed, plain)) {
+            seen = seen + o.name() + \" \";
+        }
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_str(&interp, "seen", "method field method ");
+}
+
+#[test]
+fn is_tests_class_and_superclasses() {
+    let (interp, result) = run("
+        class A { }
+        class B < A { }
+        class C { }
+        var b = B();
+
+        var direct = b is B;
+        var inherited = b is A;
+        var unrelated = b is C;
+        var not_an_instance = A is A;
+        var caught_error = false;
+        try { 1 / 0; } catch (e) { caught_error = e is Error; }
+    ");
+
+    assert!(result.is_ok());
+    assert_bool(&interp, "direct", true);
+    assert_bool(&interp, "inherited", true);
+    assert_bool(&interp, "unrelated", false);
+    assert_bool(&interp, "not_an_instance", false);
+    assert_bool(&interp, "caught_error", true);
+}
+
+#[test]
+fn is_tests_builtin_types() {
+    let (interp, result) = run("
+        fun f() { }
+        class A { m() { } }
+
+        var number = 1 is Number and !(\"1\" is Number);
+        var string = \"s\" is String and !(nil is String);
+        var boolean = false is Bool and !(0 is Bool);
+        var none = nil is Nil and !(false is Nil);
+        var function = f is Function and A().m is Function and !(A is Function);
+    ");
+
+    assert!(result.is_ok());
+    for name in ["number", "string", "boolean", "none", "function"] {
+        assert_bool(&interp, name, true);
+    }
+}
+
+#[test]
+fn is_requires_a_class_or_builtin_type() {
+    let (_, result) = run("
+        var x = 1;
+        x is x;
+    ");
+    assert!(matches!(result, Err(RuntimeError::IsOperandMustBeClass(_))));
+
+    let (_, result) = run("1 is Missing;");
+    assert!(matches!(result, Err(RuntimeError::UndefinedVariable(_))));
+}
+
+#[test]
+fn to_string_is_used_for_instances() {
+    let (mut interp, result) = run("
+        class Point {
+            init(x, y) { this.x = x; this.y = y; }
+            toString() { return \"(\" + \"x\" + \", \" + \"y\" + \")\"; }
+        }
+        class Plain { }
+
+        var p = Point(1, 2);
+        var prefixed = \"p = \" + p;
+        var suffixed = p + \"!\";
+        var plain = \"\" + Plain();
+    ");
+
+    assert!(result.is_ok());
+    assert_str(&interp, "prefixed", "p = (x, y)");
+    assert_str(&interp, "suffixed", "(x, y)!");
+    assert_str(&interp, "plain", "<instance of class Plain>");
+
+    let token = Rc::new(Token {
+        token_type: crate::scanner::TokenType::Print,
+        lexeme: "print".to_owned(),
+        literal: None,
+        line: 1,
+        column: 1,
+    });
+    let p = global(&interp, "p");
+    assert_eq!(interp.stringify(&p, &token).unwrap(), "(x, y)");
+}
+
+#[test]
+fn to_string_must_return_a_string() {
+    let (_, result) = run("
+        class A { toString() { return 1; } }
+        print A();
+    ");
+
+    assert!(matches!(result, Err(RuntimeError::ToStringMustReturnString(_))));
+}
+
+#[test]
+fn recursive_to_string_is_depth_limited() {
+    let (interp, result) = run("
+        class A { toString() { return \"<\" + this + \">\"; } }
+        var s = \"\" + A();
+    ");
+
+    assert!(result.is_ok());
+    let expected = format!(
+        "{}<instance of class A>{}",
+        "<".repeat(eval::MAX_TO_STRING_DEPTH),
+        ">".repeat(eval::MAX_TO_STRING_DEPTH),
+    );
+    assert_str(&interp, "s", &expected);
+}
+
+#[test]
+fn arithmetic_operators_dispatch_to_methods() {
+    let (interp, result) = run("
+        class Vec {
+            init(x, y) { this.x = x; this.y = y; }
+            plus(other) { return Vec(this.x + other.x, this.y + other.y); }
+            times(k) { return Vec(this.x * k, this.y * k); }
+        }
+
+        var v = (Vec(1, 2) + Vec(3, 4)) * 2;
+        var x = v.x;
+        var y = v.y;
+    ");
+
+    assert!(result.is_ok());
+    assert!(matches!(global(&interp, "x"), RuntimeValue::Number(n) if n == 8.0));
+    assert!(matches!(global(&interp, "y"), RuntimeValue::Number(n) if n == 12.0));
+}
+
+#[test]
+fn comparison_operators_dispatch_to_methods() {
+    let (interp, result) = run("
+        class Version {
+            init(n) { this.n = n; }
+            lt(other) { return this.n < other.n; }
+        }
+        class Node {
+            init(value, next) { this.value = value; this.next = next; }
+        }
+
+        // insertion sort into a linked list
+        fun insert(list, value) {
+            if (list == nil or value < list.value) return Node(value, list);
+            list.next = insert(list.next, value);
+            return list;
+        }
+
+        var list = nil;
+        list = insert(list, Version(3));
+        list = insert(list, Version(1));
+        list = insert(list, Version(2));
+
+        var order = \"\";
+        while (list != nil) {
+            if (list.value.n == 1) order = order + \"1\";
+            if (list.value.n == 2) order = order + \"2\";
+            if (list.value.n == 3) order = order + \"3\";
+            list = list.next;
+        }
+    ");
+
+    assert!(result.is_ok());
+    assert_str(&interp, "order", "123");
+}
+
+#[test]
+fn equality_prefers_eq_method() {
+    let (interp, result) = run("
+        class Money {
+            init(cents) { this.cents = cents; }
+            eq(other) { return other is Money and this.cents == other.cents; }
+        }
+        class Plain { }
+
+        var equal = Money(5) == Money(5);
+        var different = Money(5) != Money(6);
+        var other_type = Money(5) == 5;
+        var p = Plain();
+        var identity = p == p and Plain() != Plain();
+    ");
+
+    assert!(result.is_ok());
+    for name in ["equal", "different", "identity"] {
+        assert_bool(&interp, name, true);
+    }
+    assert_bool(&interp, "other_type", false);
+}
+
+#[test]
+fn operators_without_methods_keep_type_errors() {
+    let (_, result) = run("
+        class A { }
+        A() - A();
+    ");
+    assert!(matches!(result, Err(RuntimeError::BinaryOperatorExpectsNumbers(_))));
+
+    let (interp, result) = run("
+        class A { plus(other) { throw \"no\"; } }
+        var caught;
+        try { A() + 1; } catch (e) { caught = e; }
+    ");
+    assert!(result.is_ok());
+    assert_str(&interp, "caught", "no");
+}
+
+#[test]
+fn method_calls_resolve_this_and_parameters() {
+    let (interp, result) = run("
+        class Base {
+            init(name) { this.name = name; }
+            greet(greeting) { return greeting + \" \" + this.name; }
+        }
+        class Derived < Base {
+            init(name) { super.init(name); }
+            greet(greeting) { return super.greet(greeting) + \"!\"; }
+            greeter() {
+                fun g(greeting) { return this.greet(greeting); }
+                return g;
+            }
+        }
+
+        var d = Derived(\"lox\");
+        var direct = d.greet(\"hi\");
+        var method = d.greet;
+        var as_value = method(\"hey\");
+        var from_closure = d.greeter()(\"yo\");
+        var init_result = d.init(\"again\") == d;
+        var renamed = d.name;
+    ");
+
+    assert!(result.is_ok());
+    assert_str(&interp, "direct", "hi lox!");
+    assert_str(&interp, "as_value", "hey lox!");
+    assert_str(&interp, "from_closure", "yo lox!");
+    assert_bool(&interp, "init_result", true);
+    assert_str(&interp, "renamed", "again");
+}
+
+#[test]
+fn fields_shadow_methods_in_calls() {
+    let (interp, result) = run("
+        class A { m() { return \"method\"; } }
+        fun f() { return \"field\"; }
+
+        var a = A();
+        var before = a.m();
+        a.m = f;
+        var after = a.m();
+    ");
+
+    assert!(result.is_ok());
+    assert_str(&interp, "before", "method");
+    assert_str(&interp, "after", "field");
+
+    let (_, result) = run("
+        class A { }
+        A().missing();
+    ");
+    assert!(matches!(result, Err(RuntimeError::UndefinedProperty(_))));
+}
+
+#[test]
+fn calls_in_arguments_keep_the_arguments_evaluated_before_them() {
+    let (interp, result) = run("
+        fun list(a, b, c, d) { return a + b + c + d; }
+        fun wide(a, b, c, d, e, f) { return list(a, b, c, d) + e + f; }
+        fun shout(s) { return s.upper(); }
+        class Joiner {
+            join(a, b, c) { return a + b + c; }
+        }
+        var j = Joiner();
+
+        var nested = list(\"a\", list(\"b\", \"c\", \"d\", \"e\"), \"f\", list(\"g\", \"h\", \"i\", \"j\"));
+        var spilled = wide(\"a\", \"b\", wide(\"c\", \"d\", \"e\", \"f\", \"g\", \"h\"), \"i\", \"j\", \"k\");
+        var method = j.join(\"a\", j.join(\"b\", list(\"c\", \"d\", \"e\", \"f\"), \"g\"), \"h\");
+        var natives = list(\"a\", join(map(Array(\"b\", \"c\"), shout), \"\"), \"d\", tostring(max(1, 2, 3)));
+    ");
+
+    assert!(result.is_ok());
+    assert_str(&interp, "nested", "abcdefghij");
+    assert_str(&interp, "spilled", "abcdefghijk");
+    assert_str(&interp, "method", "abcdefgh");
+    assert_str(&interp, "natives", "aBCd3");
+}
+
+#[test]
+fn errors_in_arguments_leave_later_calls_unaffected() {
+    let (interp, result) = run("
+        fun pair(a, b) { return a + b; }
+        fun fail() { throw \"failed\"; }
+        var caught = nil;
+        try {
+            pair(\"a\", pair(\"b\", fail()));
+        }
+        catch (e) {
+            caught = e;
+        }
+        var after = pair(\"a\", pair(\"b\", \"c\"));
+    ");
+
+    assert!(result.is_ok());
+    assert_str(&interp, "caught", "failed");
+    assert_str(&interp, "after", "abc");
+}
+
+#[test]
+fn deleted_fields_are_undefined() {
+    let (interp, result) = run("
+        class A { }
+        var a = A();
+        a.x = 1;
+        var existed = delete a.x;
+        var again = delete a.x;
+        var never = delete a.y;
+    ");
+
+    assert!(result.is_ok());
+    assert_bool(&interp, "existed", true);
+    assert_bool(&interp, "again", false);
+    assert_bool(&interp, "never", false);
+
+    let (_, result) = run("
+        class A { }
+        var a = A();
+        a.x = 1;
+        delete a.x;
+        a.x;
+    ");
+    assert!(matches!(result, Err(RuntimeError::UndefinedProperty(_))));
+}
+
+#[test]
+fn delete_does_not_remove_methods() {
+    let (interp, result) = run("
+        class A { m() { return \"method\"; } }
+        var a = A();
+        var deleted_method = delete a.m;
+        a.m = \"field\";
+        var deleted_field = delete a.m;
+        var call = a.m();
+    ");
+
+    assert!(result.is_ok());
+    assert_bool(&interp, "deleted_method", false);
+    assert_bool(&interp, "deleted_field", true);
+    assert_str(&interp, "call", "method");
+}
+
+#[test]
+fn each_field_visits_fields_in_order() {
+    let (interp, result) = run("
+        class A { m() { } }
+        var a = A();
+        a.z = 1;
+        a.y = 2;
+        a.x = 3;
+        delete a.y;
+        a.y = 4;
+        a.z = 5;
+
+        var names = \"\";
+        fun collect(name, value) { names = names + name + \",\"; }
+        each_field(a, collect);
+
+        var empty = \"\";
+        fun never_called(name, value) { empty = \"called\"; }
+        each_field(A(), never_called);
+    ");
+
+    assert!(result.is_ok());
+    assert_str(&interp, "names", "z,x,y,");
+    assert_str(&interp, "empty", "");
+}
+
+#[test]
+fn each_field_callback_can_modify_the_instance() {
+    let (interp, result) = run("
+        class A { }
+        var a = A();
+        a.x = 1;
+        a.y = 2;
+
+        var calls = 0;
+        class Doubler {
+            call(name, value) {
+                calls = calls + 1;
+                if (name == \"x\") a.x = value * 2;
+                if (name == \"y\") a.y = value * 2;
+                a.added = true;
+            }
+        }
+        each_field(a, Doubler());
+        var x = a.x;
+        var y = a.y;
+    ");
+
+    assert!(result.is_ok());
+    assert!(matches!(global(&interp, "calls"), RuntimeValue::Number(n) if n == 2.0));
+    assert!(matches!(global(&interp, "x"), RuntimeValue::Number(n) if n == 2.0));
+    assert!(matches!(global(&interp, "y"), RuntimeValue::Number(n) if n == 4.0));
+}
+
+#[test]
+fn each_field_validates_its_arguments() {
+    let (_, result) = run("each_field(1, nil);");
+    assert!(matches!(result, Err(RuntimeError::InvalidArgument { .. })));
+
+    let (_, result) = run("
+        class A { }
+        var a = A();
+        a.x = 1;
+        fun f(name) { }
+        each_field(a, f);
+    ");
+    assert!(matches!(result, Err(RuntimeError::CallableArityMismatch { expected: 1, found: 2, .. })));
+}
+
+#[test]
+fn nan_semantics() {
+    let (interp, result) = run("
+        var inf = 1;
+        while (is_finite(inf)) inf = inf * 10;
+        var nan = inf - inf;
+
+        var detected = is_nan(nan) and !is_nan(inf) and !is_finite(nan);
+        var self_equal = nan == nan;
+        var not_equal = nan != nan;
+        var ordered = nan < 1 or nan > 1 or nan <= nan or nan >= nan;
+        var truthy = false;
+        if (nan) truthy = true;
+    ");
+
+    assert!(result.is_ok());
+    assert_bool(&interp, "detected", true);
+    assert_bool(&interp, "self_equal", false);
+    assert_bool(&interp, "not_equal", true);
+    assert_bool(&interp, "ordered", false);
+    assert_bool(&interp, "truthy", true);
+    assert_eq!(global(&interp, "nan").to_string(), "nan");
+
+    let (_, result) = run("is_nan(\"x\");");
+    assert!(matches!(result, Err(RuntimeError::InvalidArgument { .. })));
+}
+
+#[test]
+fn floor_division() {
+    let (interp, result) = run("
+        var a = 7 div 2;
+        var b = -7 div 2;
+        var c = 7 div -2;
+        var d = -7 div -2;
+        var e = -7 % 2;
+        var f = 7 % -2;
+        var g = 7.5 div 2;
+        var h = 2 * 7 div 4;
+    ");
+
+    assert!(result.is_ok());
+    for (name, expected) in [("a", 3.0), ("b", -4.0), ("c", -4.0), ("d", 3.0), ("e", 1.0), ("f", -1.0), ("g", 3.0), ("h", 3.0)] {
+        assert!(matches!(global(&interp, name), RuntimeValue::Number(n) if n == expected), "{}", name);
+    }
+
+    let (_, result) = run("1 div 0;");
+    assert!(matches!(result, Err(RuntimeError::DivisionByZero(_))));
+    let (_, result) = run("1 % 0;");
+    assert!(matches!(result, Err(RuntimeError::DivisionByZero(_))));
+    let (_, result) = run("\"a\" div 1;");
+    assert!(matches!(result, Err(RuntimeError::BinaryOperatorExpectsNumbers(_))));
+}
+
+#[test]
+fn floor_division_identity() {
+    let (interp, result) = run("
+        var holds = true;
+        for (var a = -7; a <= 7; a = a + 1) {
+            for (var b = -3; b <= 3; b = b + 1) {
+                if (b != 0 and a != (a div b) * b + a % b) holds = false;
+            }
+        }
+    ");
+
+    assert!(result.is_ok());
+    assert_bool(&interp, "holds", true);
+}
+
+#[test]
+fn passing_assertion_is_silent() {
+    let (interp, result) = run("
+        var evaluated = false;
+        fun message() { evaluated = true; return \"unused\"; }
+        assert 1 + 1 == 2;
+        assert \"non-empty\", message();
+    ");
+
+    assert!(result.is_ok());
+    assert_bool(&interp, "evaluated", false);
+}
+
+#[test]
+fn failing_assertion_reports_message_and_position() {
+    let (_, result) = run("
+        var x = 1;
+        assert x == 2, \"x is \" + \"not 2\";
+    ");
+
+    assert!(matches!(&result, Err(RuntimeError::AssertionFailed { message: Some(m), .. }) if m == "x is not 2"));
+    if let Err(err) = result {
+        assert_eq!(err.message(), "Assertion failed: x is not 2");
+        assert_eq!(err.token().line, 3);
+        assert_eq!(err.token().column, 9);
+    }
+
+    let (_, result) = run("assert nil;");
+    assert!(matches!(result, Err(RuntimeError::AssertionFailed { message: None, .. })));
+}
+
+#[test]
+fn failing_assertion_surfaces_through_calls() {
+    let (interp, result) = run("
+        var after = false;
+        fun check(n) { assert n > 0, n; }
+        fun f() { check(1); check(-1); after = true; }
+        f();
+    ");
+
+    assert!(matches!(&result, Err(RuntimeError::AssertionFailed { message: Some(m), .. }) if m == "-1"));
+    assert_bool(&interp, "after", false);
+
+    let names: Vec<String> = interp.stack_trace().iter().map(|f| f.function.clone()).collect();
+    assert_eq!(names, ["f", "check"]);
+}
+
+#[test]
+fn exit_stops_the_script_from_nested_calls() {
+    let (interp, result) = run("
+        var reached = false;
+        fun g() { exit(3.7); reached = true; }
+        fun f() { g(); reached = true; }
+        {
+            f();
+            reached = true;
+        }
+        reached = true;
+    ");
+
+    assert!(matches!(result, Err(RuntimeError::Exit { code: 3, .. })));
+    assert_bool(&interp, "reached", false);
+
+    let (_, result) = run("exit(\"1\");");
+    assert!(matches!(result, Err(RuntimeError::InvalidArgument { .. })));
+}
+
+#[test]
+fn exit_skips_catch_defer_and_finally() {
+    let (interp, result) = run("
+        var caught = false;
+        var deferred = false;
+        var finalized = false;
+        fun f() {
+            defer deferred = true;
+            try { exit(0); }
+            catch (e) { caught = true; }
+            finally { finalized = true; }
+        }
+        f();
+    ");
+
+    assert!(matches!(result, Err(RuntimeError::Exit { code: 0, .. })));
+    assert_bool(&interp, "caught", false);
+    assert_bool(&interp, "deferred", false);
+    assert_bool(&interp, "finalized", false);
+}
+
+#[test]
+fn step_limit_stops_infinite_loops() {
+    let stmts = parse("
+        var i = 0;
+        while (true) { i = i + 1; }
+    ");
+    let mut interp = Interpreter::new().with_step_limit(1000);
+    let result = interp.execute(&stmts);
+
+    assert!(matches!(&result, Err(RuntimeError::ExecutionBudgetExceeded(t)) if t.line == 3));
+
+    let stmts = parse("
+        try { for (;;) { } } catch (e) { }
+    ");
+    let mut interp = Interpreter::new().with_step_limit(1000);
+    assert!(matches!(interp.execute(&stmts), Err(RuntimeError::ExecutionBudgetExceeded(_))));
+}
+
+#[test]
+fn step_limit_applies_to_calls_and_native_callbacks() {
+    let stmts = parse("
+        fun f(n) { if (n > 0) f(n - 1); }
+        f(200);
+    ");
+    let mut interp = Interpreter::new().with_step_limit(100);
+    assert!(matches!(interp.execute(&stmts), Err(RuntimeError::ExecutionBudgetExceeded(_))));
+
+    let stmts = parse("
+        class A { }
+        var a = A();
+        a.x = 1;
+        fun spin(name, value) { while (true) { } }
+        each_field(a, spin);
+    ");
+    let mut interp = Interpreter::new().with_step_limit(100);
+    assert!(matches!(interp.execute(&stmts), Err(RuntimeError::ExecutionBudgetExceeded(_))));
+}
+
+#[test]
+fn generous_step_limit_lets_programs_complete() {
+    let stmts = parse("
+        var sum = 0;
+        for (var i = 0; i < 100; i = i + 1) sum = sum + i;
+    ");
+    let mut interp = Interpreter::new().with_step_limit(1_000_000);
+
+    assert!(interp.execute(&stmts).is_ok());
+    assert!(matches!(global(&interp, "sum"), RuntimeValue::Number(n) if n == 4950.0));
+}
+
+#[test]
+fn step_budget_resets_between_executions_only_if_configured() {
+    let stmts = parse("
+        for (var i = 0; i < 10; i = i + 1) { }
+    ");
+
+    let mut interp = Interpreter::new().with_step_limit(60);
+    assert!(interp.execute(&stmts).is_ok());
+    assert!(matches!(interp.execute(&stmts), Err(RuntimeError::ExecutionBudgetExceeded(_))));
+
+    interp.set_step_limit(None);
+    assert!(interp.execute(&stmts).is_ok());
+
+    let mut interp = Interpreter::new()
+        .with_step_limit(60)
+        .with_steps_reset_on_execute(true);
+    for _ in 0..3 {
+        assert!(interp.execute(&stmts).is_ok());
+    }
+}
+
+// An output sink the test can read after handing it to the interpreter.
+#[derive(Clone, Default)]
+struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SharedOutput {
+    fn text(&self) -> String {
+        String::from_utf8(self.0.borrow().clone()).unwrap()
+    }
+}
+
+#[test]
+fn print_writes_to_the_output_sink() {
+    let output = SharedOutput::default();
+    let mut interp = Interpreter::new().with_output(output.clone());
+    let result = interp.execute(&parse("print 1; print \"a\";"));
+
+    assert!(result.is_ok());
+    assert_eq!(output.text(), "1\n\"a\"\n");
+}
+
+#[test]
+fn write_and_eprint_go_to_their_own_sinks() {
+    let output = SharedOutput::default();
+    let errors = SharedOutput::default();
+    let mut interp = Interpreter::new()
+        .with_output(output.clone())
+        .with_error_output(errors.clone());
+    let source = "
+        write(\"loading\");
+        write(\".\");
+        write(1);
+        writeln(\" done\");
+        eprint(\"warning\");
+        eprint(nil);
+        writeln(Array(\"a\"));
+        print \"a\";
+    ";
+    let result = interp.execute(&parse(source));
+
+    assert!(result.is_ok());
+    assert_eq!(output.text(), "loading.1 done\n[\"a\"]\n\"a\"\n");
+    assert_eq!(errors.text(), "warning\nnil\n");
+}
+
+#[test]
+fn trace_logs_statements_and_calls() {
+    let stmts = parse("
+fun add(a, b) {
+    return a + b;
+}
+var i = 0;
+while (i < 2) i = i + 1;
+add(i, 3);
+print i;
+");
+    let output = SharedOutput::default();
+    let mut interp = Interpreter::new().with_output(output.clone());
+    interp.set_trace(true);
+    let result = interp.execute(&stmts);
+
+    assert!(result.is_ok());
+    assert_eq!(output.text(), "\
+fun at 2:5
+var at 5:5
+while at 6:1
+expression
+=> 1
+expression
+=> 2
+expression
+enter add(2, 3)
+  return at 3:5
+exit add => 5
+=> 5
+print at 8:1
+2
+");
+}
+
+#[test]
+fn trace_does_not_change_behavior() {
+    let source = "
+        var log = \"\";
+        fun f(x) { log = log + x; return x; }
+        fun g(n) { if (n == 0) return f(\"g\"); return g(n - 1); }
+        var r = f(\"a\") + f(\"b\");
+        g(2);
+    ";
+
+    let (plain, result) = run(source);
+    assert!(result.is_ok());
+
+    let output = SharedOutput::default();
+    let mut traced = Interpreter::new().with_output(output.clone());
+    traced.set_trace(true);
+    assert!(traced.execute(&parse(source)).is_ok());
+
+    assert_str(&plain, "log", "abg");
+    assert_str(&traced, "log", "abg");
+    assert!(output.text().contains("enter g(2)\n"));
+    assert!(output.text().contains("tail call g(1)\n"));
+}
+
+#[test]
+fn profile_counts_calls_per_function() {
+    let stmts = parse("
+        fun f(n) { return n + 1; }
+        fun g() { for (var i = 0; i < 100; i = i + 1) f(i); }
+        fun fact(n) { if (n < 2) return 1; var r = fact(n - 1); return n * r; }
+        g();
+        fact(10);
+    ");
+    let mut interp = Interpreter::new();
+    interp.set_profiling(true);
+    assert!(interp.execute(&stmts).is_ok());
+
+    let profile = interp.take_profile();
+    let entry = |name: &str| profile.iter().find(|e| e.function == name).expect("missing entry").clone();
+
+    assert_eq!(profile.len(), 3);
+    assert_eq!(entry("f").calls, 100);
+    assert_eq!(entry("g").calls, 1);
+    assert_eq!(entry("fact").calls, 10);
+    assert_eq!(entry("f").declaration.unwrap().line, 2);
+
+    let g = entry("g");
+    assert!(g.total_time >= g.self_time);
+    assert!(g.total_time >= entry("f").total_time);
+    let fact = entry("fact");
+    assert!(fact.total_time >= fact.self_time);
+
+    assert!(profile.windows(2).all(|w| w[0].self_time >= w[1].self_time));
+    assert!(interp.take_profile().is_empty());
+}
+
+#[test]
+fn profile_is_empty_when_disabled() {
+    let stmts = parse("
+        fun f() { }
+        f();
+    ");
+    let mut interp = Interpreter::new();
+    assert!(interp.execute(&stmts).is_ok());
+    assert!(interp.take_profile().is_empty());
+    assert!(interp.profiler.is_none());
+}
+
+#[test]
+fn run_returns_the_last_expression_value() {
+    let mut interp = Interpreter::new().with_output(SharedOutput::default());
+
+    let value = interp.run(&parse("var a = 1; a + 2;"));
+    assert!(matches!(value, Ok(Some(RuntimeValue::Number(n))) if n == 3.0));
+
+    let value = interp.run(&parse("1 + 2; var x = 1;"));
+    assert!(matches!(value, Ok(None)));
+
+    let value = interp.run(&parse("print 5;"));
+    assert!(matches!(value, Ok(None)));
+
+    let value = interp.run(&parse("fun f() { 10; } f();"));
+    assert!(matches!(value, Ok(Some(RuntimeValue::Nil))));
+
+    let value = interp.run(&parse("{ defer print 1; \"done\"; }"));
+    assert!(matches!(value, Ok(Some(RuntimeValue::String(s))) if &*s == "done"));
+
+    let value = interp.run(&parse("-nil;"));
+    assert!(matches!(value, Err(RuntimeError::UnaryMinusExpectsNumber(_))));
+}
+
+#[test]
+fn fork_shares_no_mutable_state() {
+    let mut original = Interpreter::new();
+    let result = original.execute(&parse("
+        class Counter {
+            init() { this.n = 0; }
+            add() { this.n = this.n + 1; return this.n; }
+        }
+        var c = Counter();
+        var add = c.add;
+        fun make() { var k = 0; fun inc() { k = k + 1; return k; } return inc; }
+        var inc = make();
+        var x = 1;
+    "));
+    assert!(result.is_ok());
+
+    let mut fork = original.fork();
+    let result = fork.execute(&parse("
+        x = 2;
+        c.add();
+        add();
+        inc();
+        c.extra = true;
+        var only_in_fork = 1;
+    "));
+    assert!(result.is_ok());
+
+    let result = original.execute(&parse("
+        var n = c.n;
+        var k = inc();
+        var same = add == c.add;
+    "));
+    assert!(result.is_ok());
+
+    assert!(matches!(global(&original, "x"), RuntimeValue::Number(n) if n == 1.0));
+    assert!(matches!(global(&original, "n"), RuntimeValue::Number(n) if n == 0.0));
+    assert!(matches!(global(&original, "k"), RuntimeValue::Number(n) if n == 1.0));
+    assert_bool(&original, "same", true);
+    assert!(original.globals_env.borrow().get("only_in_fork").is_none());
+
+    let result = fork.execute(&parse("
+        var n = c.n;
+        var k = inc();
+        var same = add == c.add;
+        var is_counter = c is Counter;
+    "));
+    assert!(result.is_ok());
+    assert!(matches!(global(&fork, "x"), RuntimeValue::Number(n) if n == 2.0));
+    assert!(matches!(global(&fork, "n"), RuntimeValue::Number(n) if n == 2.0));
+    assert!(matches!(global(&fork, "k"), RuntimeValue::Number(n) if n == 2.0));
+    assert_bool(&fork, "same", true);
+    assert_bool(&fork, "is_counter", true);
+}
+
+#[test]
+fn globals_redefined_mid_run_are_seen_by_every_reader() {
+    let source = "
+        fun f() { return 1; }
+        fun call_f() { return f(); }
+        var seen = \"\";
+        for (var i = 0; i < 2; i = i + 1) seen = seen + tostring(call_f());
+        fun f() { return 2; }
+        for (var i = 0; i < 2; i = i + 1) seen = seen + tostring(call_f());
+        fun three() { return 3; }
+        f = three;
+        seen = seen + tostring(call_f());
+        fun read_late() { return late; }
+        var before = pcall(read_late)[0];
+        var late = \"defined\";
+        var after = read_late();
+        var counter = 0;
+        fun bump() { counter = counter + 1; }
+        bump();
+        var counter = 10;
+        bump();
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_str(&interp, "seen", "11223");
+    assert_bool(&interp, "before", false);
+    assert_str(&interp, "after", "defined");
+    assert_number(&interp, "counter", 11.0);
+}
+
+#[test]
+fn global_lookups_work_across_interpreters_sharing_a_program() {
+    // the statements remember where globals were found in the first interpreter
+    let stmts = parse("var x = tostring(y); fun get() { return x; } var z = get();");
+    let mut first = Interpreter::new();
+    first.globals_env.borrow_mut().define("y", RuntimeValue::Number(1.0));
+    let mut second = Interpreter::new();
+    second.globals_env.borrow_mut().define("unused", RuntimeValue::Nil);
+    second.globals_env.borrow_mut().define("y", RuntimeValue::Number(2.0));
+
+    assert!(first.execute(&stmts).is_ok());
+    assert!(second.execute(&stmts).is_ok());
+    assert_str(&first, "z", "1");
+    assert_str(&second, "z", "2");
+
+    first.reset();
+    first.globals_env.borrow_mut().define("y", RuntimeValue::Number(3.0));
+    assert!(first.execute(&stmts).is_ok());
+    assert_str(&first, "z", "3");
+}
+
+#[test]
+fn reset_removes_user_globals_but_keeps_natives() {
+    let mut interp = Interpreter::new();
+    let result = interp.execute(&parse("
+        var x = 1;
+        fun make() { var k = 0; fun inc() { k = k + 1; return k; } return inc; }
+        var inc = make();
+        inc();
+    "));
+    assert!(result.is_ok());
+    let inc = global(&interp, "inc");
+
+    interp.reset();
+    assert!(interp.globals_env.borrow().get("x").is_none());
+    assert!(interp.globals_env.borrow().get("inc").is_none());
+
+    let result = interp.execute(&parse("
+        var nan_ok = is_nan(1) == false;
+        var caught = false;
+        try { -nil; } catch (e) { caught = e is Error; }
+    "));
+    assert!(result.is_ok());
+    assert_bool(&interp, "nan_ok", true);
+    assert_bool(&interp, "caught", true);
+
+    let value = interp.call(&inc, &Vec::new());
+    assert!(matches!(value, Ok(RuntimeValue::Number(n)) if n == 2.0));
+}
+
+#[test]
+fn values_convert_to_and_from_rust_types() {
+    assert!(matches!(RuntimeValue::from(1.5), RuntimeValue::Number(n) if n == 1.5));
+    assert!(matches!(RuntimeValue::from(true), RuntimeValue::Bool(true)));
+    assert!(matches!(RuntimeValue::from("a"), RuntimeValue::String(s) if &*s == "a"));
+    assert!(matches!(RuntimeValue::from("b".to_owned()), RuntimeValue::String(s) if &*s == "b"));
+    assert!(matches!(RuntimeValue::from(()), RuntimeValue::Nil));
+
+    assert_eq!(f64::try_from(RuntimeValue::from(2.0)), Ok(2.0));
+    assert_eq!(bool::try_from(RuntimeValue::from(false)), Ok(false));
+    assert_eq!(String::try_from(RuntimeValue::from("s")), Ok("s".to_owned()));
+    assert_eq!(Option::<f64>::try_from(RuntimeValue::Nil), Ok(None));
+    assert_eq!(Option::<f64>::try_from(RuntimeValue::from(3.0)), Ok(Some(3.0)));
+
+    assert_eq!(
+        f64::try_from(RuntimeValue::from("1")),
+        Err(ConversionError { expected: "Number", found: "String" })
+    );
+    assert_eq!(
+        Option::<bool>::try_from(RuntimeValue::from(1.0)),
+        Err(ConversionError { expected: "Bool", found: "Number" })
+    );
+    assert_eq!(
+        String::try_from(RuntimeValue::Nil).unwrap_err().to_string(),
+        "expected String, found Nil"
+    );
+
+    let value = RuntimeValue::from(4.0);
+    assert_eq!(value.as_number(), Some(4.0));
+    assert_eq!(value.as_str(), None);
+    assert!(value.as_instance().is_none());
+    assert_eq!(RuntimeValue::from("x").as_str(), Some("x"));
+}
+
+#[test]
+fn values_round_trip_through_natives() {
+    let (mut interp, result) = run("class A { } var a = A();");
+    assert!(result.is_ok());
+    assert!(global(&interp, "a").as_instance().is_some());
+
+    let is_nan = global(&interp, "is_nan");
+    let value = interp.call(&is_nan, &[f64::NAN.into()]);
+    assert!(matches!(value.map(bool::try_from), Ok(Ok(true))));
+
+    let value = interp.call(&is_nan, &["nan".into()]);
+    assert!(matches!(value, Err(RuntimeError::InvalidArgument { message, .. }) if message == "is_nan: expected Number, found String"));
+}
+
+struct FailingOutput;
+
+impl Write for FailingOutput {
+    fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+        Err(std::io::Error::new(std::io::ErrorKind::BrokenPipe, "closed"))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn output_is_captured_across_statements_and_runs() {
+    let output = SharedOutput::default();
+    let mut interp = Interpreter::new().with_output(output.clone());
+
+    let value = interp.run(&parse("
+        fun greet(name) { print \"hi \" + name; return name; }
+        print 1;
+        greet(\"a\");
+    "));
+    assert!(matches!(value, Ok(Some(RuntimeValue::String(s))) if &*s == "a"));
+    assert_eq!(output.text(), "1\n\"hi a\"\n");
+
+    let value = interp.run(&parse("print 2; 3;"));
+    assert!(matches!(value, Ok(Some(RuntimeValue::Number(n))) if n == 3.0));
+    assert_eq!(output.text(), "1\n\"hi a\"\n2\n");
+}
+
+#[test]
+fn output_errors_are_runtime_errors() {
+    let mut interp = Interpreter::new().with_output(FailingOutput);
+    let result = interp.execute(&parse("var x = 1;\nprint x;"));
+
+    assert!(matches!(&result, Err(RuntimeError::IoError { token, .. }) if token.line == 2));
+
+    let mut interp = Interpreter::new().with_output(FailingOutput);
+    interp.set_trace(true);
+    let result = interp.execute(&parse("var x = 1;"));
+    assert!(matches!(result, Err(RuntimeError::IoError { .. })));
+}
+
+#[test]
+fn instance_fields_keep_assignment_order() {
+    let (interp, result) = run("
+        class A { }
+        var a = A();
+        a.c = 1;
+        a.a = 2;
+        a.b = 3;
+        a.c = 4;
+        a.a = a.c;
+    ");
+    assert!(result.is_ok());
+
+    let a = global(&interp, "a");
+    let a = a.as_instance().unwrap().borrow();
+    let fields: Vec<String> = a.fields().map(|(name, value)| format!("{}={}", name, value)).collect();
+    assert_eq!(fields, ["c=4", "a=4", "b=3"]);
+}
+
+#[test]
+fn many_fields_keep_order_through_deletes() {
+    let mut source = String::from("class A { } var a = A();");
+    for i in 0..20 {
+        source += &format!("a.f{} = {};", i, i);
+    }
+    source += "delete a.f3; a.f0 = 100; a.f3 = 3; var f15 = a.f15; var missing = delete a.f99;";
+
+    let (interp, result) = run(&source);
+    assert!(result.is_ok());
+    assert!(matches!(global(&interp, "f15"), RuntimeValue::Number(n) if n == 15.0));
+    assert_bool(&interp, "missing", false);
+
+    let a = global(&interp, "a");
+    let a = a.as_instance().unwrap().borrow();
+    let names: Vec<&str> = a.fields().map(|(name, _)| name.as_str()).collect();
+    let mut expected: Vec<String> = (0..20).filter(|i| *i != 3).map(|i| format!("f{}", i)).collect();
+    expected.push("f3".to_owned());
+    assert_eq!(names, expected);
+    assert!(matches!(a.fields().next(), Some((_, RuntimeValue::Number(n))) if *n == 100.0));
+}
+
+#[test]
+fn fields_stay_reachable_as_instances_grow_past_the_index_threshold() {
+    let mut source = String::from("class A { } var a = A(); var sums = Array();");
+    for i in 0..12 {
+        source += &format!("a.f{} = {};", i, i);
+        let sum: Vec<String> = (0..=i).map(|j| format!("a.f{}", j)).collect();
+        source += &format!("push(sums, {});", sum.join(" + "));
+    }
+    source += "
+        a.f8 = 80;
+        delete a.f2;
+        delete a.f10;
+        delete a.f11;
+        delete a.f9;
+        var after_deletes = a.f0 + a.f1 + a.f8;
+        var has_f2 = has_field(a, \"f2\");
+        a.f2 = 2;
+        a.f12 = 12;
+        var shown = tostring(sums);
+    ";
+
+    let (interp, result) = run(&source);
+    assert!(result.is_ok());
+    assert_str(&interp, "shown", "[0, 1, 3, 6, 10, 15, 21, 28, 36, 45, 55, 66]");
+    assert_number(&interp, "after_deletes", 81.0);
+    assert_bool(&interp, "has_f2", false);
+
+    let a = global(&interp, "a");
+    let a = a.as_instance().unwrap().borrow();
+    let fields: Vec<String> = a.fields().map(|(name, value)| format!("{}={}", name, value)).collect();
+    assert_eq!(fields, ["f0=0", "f1=1", "f3=3", "f4=4", "f5=5", "f6=6", "f7=7", "f8=80", "f2=2", "f12=12"]);
+}
+
+#[test]
+fn clone_copies_instances_deeply() {
+    let (interp, result) = run("
+        class Point { }
+        class Line { }
+        var line = Line();
+        line.from = Point();
+        line.from.x = 1;
+        line.to = line.from;
+        line.name = \"l\";
+
+        var copy = clone(line);
+        copy.name = \"copy\";
+        copy.from.x = 2;
+
+        var original_name = line.name;
+        var original_x = line.from.x;
+        var shared_in_copy = copy.from == copy.to;
+        var independent = copy.from != line.from;
+        var same_class = copy is Line and copy.from is Point;
+        var primitive = clone(5) == 5;
+        var same_class_value = clone(Point) == Point;
+    ");
+
+    assert!(result.is_ok());
+    assert_str(&interp, "original_name", "l");
+    assert!(matches!(global(&interp, "original_x"), RuntimeValue::Number(n) if n == 1.0));
+    assert_bool(&interp, "shared_in_copy", true);
+    assert_bool(&interp, "independent", true);
+    assert_bool(&interp, "same_class", true);
+    assert_bool(&interp, "primitive", true);
+    assert_bool(&interp, "same_class_value", true);
+}
+
+#[test]
+fn clone_handles_cycles_and_keeps_bound_methods() {
+    let (interp, result) = run("
+        class Node { name() { return this.label; } }
+        var a = Node();
+        var b = Node();
+        a.label = \"a\";
+        b.label = \"b\";
+        a.next = b;
+        b.next = a;
+        a.get_name = a.name;
+
+        var c = clone(a);
+        c.label = \"c\";
+
+        var cyclic = c.next.next == c;
+        var bound = c.get_name();
+        var own = c.name();
+    ");
+
+    assert!(result.is_ok());
+    assert_bool(&interp, "cyclic", true);
+    assert_str(&interp, "bound", "a");
+    assert_str(&interp, "own", "c");
+}
+
+#[test]
+fn strict_booleans_reject_non_boolean_conditions() {
+    let source = "
+        var branch = \"\";
+        if (1) branch = \"then\"; else branch = \"else\";
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_str(&interp, "branch", "then");
+
+    let mut interp = Interpreter::new().with_strict_booleans(true);
+    let result = interp.execute(&parse(source));
+    assert!(matches!(&result, Err(RuntimeError::ConditionMustBeBoolean(t)) if t.line == 3 && t.column == 9));
+
+    for source in ["while (nil) { }", "!0;", "true and 1;", "nil or true;"] {
+        let mut interp = Interpreter::new().with_strict_booleans(true);
+        let result = interp.execute(&parse(source));
+        assert!(matches!(result, Err(RuntimeError::ConditionMustBeBoolean(_))), "{}", source);
+    }
+}
+
+#[test]
+fn strict_booleans_reject_non_boolean_assertions() {
+    let (_, result) = run("assert 1;");
+    assert!(result.is_ok());
+
+    let mut interp = Interpreter::new().with_strict_booleans(true);
+    let result = interp.execute(&parse("assert 1;"));
+    assert!(matches!(result, Err(RuntimeError::ConditionMustBeBoolean(t)) if t.lexeme == "assert"));
+
+    let mut interp = Interpreter::new().with_strict_booleans(true);
+    assert!(interp.execute(&parse("assert 1 == 1;")).is_ok());
+}
+
+#[test]
+fn strict_booleans_make_logical_operators_return_booleans() {
+    let source = "
+        var a = 1;
+        var or_value = a == 2 or a == 1;
+        var and_value = a == 1 and a == 2;
+        var not_value = !(a == 1);
+    ";
+
+    let mut interp = Interpreter::new().with_strict_booleans(true);
+    assert!(interp.execute(&parse(source)).is_ok());
+    assert_bool(&interp, "or_value", true);
+    assert_bool(&interp, "and_value", false);
+    assert_bool(&interp, "not_value", false);
+
+    let (interp, result) = run("var or_value = nil or 2;");
+    assert!(result.is_ok());
+    assert!(matches!(global(&interp, "or_value"), RuntimeValue::Number(n) if n == 2.0));
+}
+
+#[test]
+fn strings_are_indexed_by_character() {
+    let source = "
+        var s = \"hello\";
+        var first = s[0];
+        var second = s[1];
+        var last = s[len(s) - 1];
+        var length = len(s);
+        var empty_length = len(\"\");
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_str(&interp, "first", "h");
+    assert_str(&interp, "second", "e");
+    assert_str(&interp, "last", "o");
+    assert!(matches!(global(&interp, "length"), RuntimeValue::Number(n) if n == 5.0));
+    assert!(matches!(global(&interp, "empty_length"), RuntimeValue::Number(n) if n == 0.0));
+}
+
+#[test]
+fn string_indices_must_be_in_range_integers() {
+    let (_, result) = run("\"abc\"[3];");
+    assert!(matches!(result, Err(RuntimeError::IndexOutOfRange { index: 3, len: 3, .. })));
+
+    let (_, result) = run("\"abc\"[-1];");
+    assert!(matches!(result, Err(RuntimeError::IndexOutOfRange { index: -1, len: 3, .. })));
+
+    let (_, result) = run("\"\"[0];");
+    assert!(matches!(result, Err(RuntimeError::IndexOutOfRange { index: 0, len: 0, .. })));
+
+    for source in ["\"abc\"[0.5];", "\"abc\"[\"0\"];", "\"abc\"[nil];"] {
+        let (_, result) = run(source);
+        assert!(matches!(result, Err(RuntimeError::IndexMustBeInteger(_))), "{}", source);
+    }
+}
+
+#[test]
+fn strings_cannot_be_assigned_to_by_index() {
+    let (_, result) = run("var s = \"abc\"; s[0] = \"x\";");
+    assert!(matches!(&result, Err(RuntimeError::StringsAreImmutable(t)) if t.lexeme == "]"));
+
+    let (_, result) = run("var n = 1; n[0];");
+    assert!(matches!(result, Err(RuntimeError::NotIndexable(_))));
+
+    let (_, result) = run("len(1);");
+    assert!(matches!(result, Err(RuntimeError::InvalidArgument { .. })));
+}
+
+#[test]
+fn copied_strings_share_their_text_but_keep_value_semantics() {
+    let source = "
+        var a = \"ab\";
+        var b = a;
+        a = a + \"c\";
+        var c = b;
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_str(&interp, "a", "abc");
+    assert_str(&interp, "b", "ab");
+    match (global(&interp, "b"), global(&interp, "c")) {
+        (RuntimeValue::String(b), RuntimeValue::String(c)) => assert!(Rc::ptr_eq(&b, &c)),
+        _ => panic!("expected strings"),
+    }
+}
+
+#[test]
+fn strings_are_equal_by_their_contents() {
+    let source = "
+        var built = \"a\" + \"b\";
+        var equal = built == \"ab\";
+        var different = built != \"abc\";
+        var m = Map();
+        map_set(m, \"ab\", 1);
+        var found = map_get(m, built);
+        var sliced = slice(\"xaby\", 1, 3) == built;
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_bool(&interp, "equal", true);
+    assert_bool(&interp, "different", true);
+    assert_number(&interp, "found", 1.0);
+    assert_bool(&interp, "sliced", true);
+}
+
+#[test]
+fn c_style_truthiness_treats_zero_and_empty_strings_as_false() {
+    let source = "
+        var zero = \"\";
+        if (0) zero = \"true\"; else zero = \"false\";
+        var not_zero = !0;
+        var not_empty = !\"\";
+        var not_text = !\"text\";
+        var iterations = 0;
+        while (\"\") { iterations = iterations + 1; break; }
+        var or_value = 0 or \"fallback\";
+        var and_value = 1 and \"\";
+    ";
+
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_str(&interp, "zero", "true");
+    assert_bool(&interp, "not_zero", false);
+    assert_bool(&interp, "not_empty", false);
+    assert_bool(&interp, "not_text", false);
+    assert!(matches!(global(&interp, "iterations"), RuntimeValue::Number(n) if n == 1.0));
+    assert!(matches!(global(&interp, "or_value"), RuntimeValue::Number(n) if n == 0.0));
+    assert_str(&interp, "and_value", "");
+
+    let mut interp = Interpreter::new().with_truthiness(Truthiness::CStyle);
+    assert!(interp.execute(&parse(source)).is_ok());
+    assert_str(&interp, "zero", "false");
+    assert_bool(&interp, "not_zero", true);
+    assert_bool(&interp, "not_empty", true);
+    assert_bool(&interp, "not_text", false);
+    assert!(matches!(global(&interp, "iterations"), RuntimeValue::Number(n) if n == 0.0));
+    assert_str(&interp, "or_value", "fallback");
+    assert_str(&interp, "and_value", "");
+}
+
+#[test]
+fn primitives_have_built_in_methods() {
+    let source = "
+        var length = \"ab\".upper().len();
+        var upper = \"ab\".upper();
+        var lower = \"AB\".lower();
+        var part = \"hello\".substr(1, 3);
+        var contains = \"hello\".contains(\"ell\");
+        var floor = (2.7).floor();
+        var ceil = (2.2).ceil();
+        var abs = (-3).abs();
+        var sqrt = (9).sqrt();
+        var number = (2.5).toString();
+        var boolean = (true).toString();
+        var string = \"text\".toString();
+        var method = \"abc\".len;
+        var method_length = method();
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert!(matches!(global(&interp, "length"), RuntimeValue::Number(n) if n == 2.0));
+    assert_str(&interp, "upper", "AB");
+    assert_str(&interp, "lower", "ab");
+    assert_str(&interp, "part", "el");
+    assert_bool(&interp, "contains", true);
+    assert!(matches!(global(&interp, "floor"), RuntimeValue::Number(n) if n == 2.0));
+    assert!(matches!(global(&interp, "ceil"), RuntimeValue::Number(n) if n == 3.0));
+    assert!(matches!(global(&interp, "abs"), RuntimeValue::Number(n) if n == 3.0));
+    assert!(matches!(global(&interp, "sqrt"), RuntimeValue::Number(n) if n == 3.0));
+    assert_str(&interp, "number", "2.5");
+    assert_str(&interp, "boolean", "true");
+    assert_str(&interp, "string", "text");
+    assert!(matches!(global(&interp, "method_length"), RuntimeValue::Number(n) if n == 3.0));
+}
+
+#[test]
+fn primitive_methods_check_their_arguments() {
+    let (_, result) = run("\"ab\".len(1);");
+    assert!(matches!(result, Err(RuntimeError::CallableArityMismatch { expected: 0, found: 1, .. })));
+
+    let (_, result) = run("\"ab\".substr(1);");
+    assert!(matches!(result, Err(RuntimeError::CallableArityMismatch { expected: 2, found: 1, .. })));
+
+    for source in ["\"ab\".substr(1, 3);", "\"ab\".substr(2, 1);", "\"ab\".contains(1);"] {
+        let (_, result) = run(source);
+        assert!(matches!(result, Err(RuntimeError::InvalidArgument { .. })), "{}", source);
+    }
+
+    let (_, result) = run("\"ab\".missing();");
+    assert!(matches!(&result, Err(RuntimeError::UndefinedProperty(t)) if t.lexeme == "missing"));
+
+    let (_, result) = run("(1).upper();");
+    assert!(matches!(result, Err(RuntimeError::UndefinedProperty(_))));
+
+    let (_, result) = run("nil.toString();");
+    assert!(matches!(result, Err(RuntimeError::OnlyInstancesHaveProperties(_))));
+
+    let (_, result) = run("var s = \"ab\"; s.x = 1;");
+    assert!(matches!(result, Err(RuntimeError::OnlyInstancesHaveProperties(_))));
+}
+
+#[test]
+fn clock_returns_non_decreasing_seconds() {
+    let source = "
+        var first = clock();
+        var second = clock();
+        var shown = clock;
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+
+    let first = match global(&interp, "first") {
+        RuntimeValue::Number(n) => n,
+        value => panic!("expected a number, found {}", value),
+    };
+    let second = match global(&interp, "second") {
+        RuntimeValue::Number(n) => n,
+        value => panic!("expected a number, found {}", value),
+    };
+    assert!(first > 0.0 && first <= second);
+    assert_eq!(global(&interp, "shown").to_string(), "<native fn clock>");
+
+    let (_, result) = run("clock(1);");
+    assert!(matches!(result, Err(RuntimeError::CallableArityMismatch { expected: 0, found: 1, .. })));
+}
+
+#[test]
+fn embedders_can_define_natives() {
+    let mut interp = Interpreter::new();
+    interp.define_native("add", 2, |args, interp| {
+        match (&args[0], &args[1]) {
+            (RuntimeValue::Number(a), RuntimeValue::Number(b)) => Ok(RuntimeValue::Number(a + b)),
+            _ => Err(interp.invalid_argument("add expects two numbers")),
+        }
+    });
+    interp.define_native("twice", 2, |args, interp| {
+        let once = interp.call(&args[0], &[args[1].clone()])?;
+        interp.call(&args[0], &[once])
+    });
+
+    let source = "
+        var sum = add(1, 2);
+        fun increment(n) { return add(n, 1); }
+        var result = twice(increment, 5);
+        var shown = add;
+    ";
+    assert!(interp.execute(&parse(source)).is_ok());
+    assert!(matches!(global(&interp, "sum"), RuntimeValue::Number(n) if n == 3.0));
+    assert!(matches!(global(&interp, "result"), RuntimeValue::Number(n) if n == 7.0));
+    assert_eq!(global(&interp, "shown").to_string(), "<native fn add>");
+
+    let result = interp.execute(&parse("add(1, 2, 3);"));
+    assert!(matches!(result, Err(RuntimeError::CallableArityMismatch { expected: 2, found: 3, .. })));
+
+    interp.reset();
+    assert!(interp.execute(&parse("var sum = add(2, 2);")).is_ok());
+    assert!(matches!(global(&interp, "sum"), RuntimeValue::Number(n) if n == 4.0));
+}
+
+#[test]
+fn errors_of_embedder_natives_are_reported_at_the_call_site() {
+    let mut interp = Interpreter::new();
+    interp.define_native("fail", 1, |_, interp| {
+        Err(interp.invalid_argument("fail always fails"))
+    });
+
+    let source = "
+        var x = 1;
+        fail(x);
+    ";
+    let result = interp.execute(&parse(source));
+    match result {
+        Err(RuntimeError::InvalidArgument { call_site, message }) => {
+            assert_eq!(message, "fail always fails");
+            assert_eq!(call_site.line, 3);
+            assert_eq!(call_site.lexeme, ")");
+        },
+        _ => panic!("expected an invalid argument error"),
+    }
+}
+
+fn define_native_function(interp: &Interpreter, native: native::NativeFunction) {
+    let value = RuntimeValue::Callable(CallableWrapper {
+        callable: Box::new(native.clone()),
+        closure: None,
+        receiver: None,
+    });
+    interp.globals_env.borrow_mut().define(native.name, value);
+}
+
+fn sum(_interp: &mut Interpreter, args: &[RuntimeValue]) -> Result<RuntimeValue, RuntimeError> {
+    let total = args.iter().filter_map(|a| a.as_number()).sum::<f64>();
+    Ok(RuntimeValue::Number(total))
+}
+
+#[test]
+fn natives_can_take_a_variable_number_of_arguments() {
+    let mut interp = Interpreter::new();
+    define_native_function(&interp, native::NativeFunction {
+        name: "sum",
+        arity: native::Arity::AtLeast(0),
+        fun: sum,
+    });
+
+    let source = "
+        var none = sum();
+        var one = sum(1);
+        var many = sum(1, 2, 3, 4);
+        var shown = sum;
+    ";
+    assert!(interp.execute(&parse(source)).is_ok());
+    assert!(matches!(global(&interp, "none"), RuntimeValue::Number(n) if n == 0.0));
+    assert!(matches!(global(&interp, "one"), RuntimeValue::Number(n) if n == 1.0));
+    assert!(matches!(global(&interp, "many"), RuntimeValue::Number(n) if n == 10.0));
+    assert_eq!(global(&interp, "shown").to_string(), "<native fn sum/0+>");
+}
+
+#[test]
+fn natives_with_an_arity_range_reject_other_argument_counts() {
+    let mut interp = Interpreter::new();
+    define_native_function(&interp, native::NativeFunction {
+        name: "sum",
+        arity: native::Arity::Between(1, 2),
+        fun: sum,
+    });
+
+    assert!(interp.execute(&parse("var two = sum(1, 2);")).is_ok());
+    assert!(matches!(global(&interp, "two"), RuntimeValue::Number(n) if n == 3.0));
+
+    let result = interp.execute(&parse("sum(1, 2, 3);"));
+    assert!(matches!(result, Err(RuntimeError::CallableArityMismatch { expected: 2, found: 3, .. })));
+
+    let result = interp.execute(&parse("sum();"));
+    assert!(matches!(result, Err(RuntimeError::CallableArityMismatch { expected: 1, found: 0, .. })));
+
+    let result = interp.call(&global(&interp, "sum"), &[]);
+    assert!(matches!(result, Err(RuntimeError::CallableArityMismatch { expected: 1, found: 0, .. })));
+}
+
+#[test]
+fn exit_code_defaults_to_zero() {
+    let (_, result) = run("exit();");
+    assert!(matches!(result, Err(RuntimeError::Exit { code: 0, .. })));
+}
+
+fn assert_number(interp: &Interpreter, name: &str, expected: f64) {
+    match global(interp, name) {
+        RuntimeValue::Number(n) => assert!((n - expected).abs() < 1e-9, "{}: expected {}, found {}", name, expected, n),
+        value => panic!("{}: expected a number, found {}", name, value),
+    }
+}
+
+#[test]
+fn math_natives_compute_numbers() {
+    let source = "
+        var floor_value = floor(-2.5);
+        var ceil_value = ceil(2.1);
+        var round_value = round(2.5);
+        var round_negative = round(-2.5);
+        var abs_value = abs(-3);
+        var sqrt_value = sqrt(16);
+        var pow_value = pow(2, 10);
+        var log_value = log(E);
+        var exp_value = exp(0);
+        var pi = PI;
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_number(&interp, "floor_value", -3.0);
+    assert_number(&interp, "ceil_value", 3.0);
+    assert_number(&interp, "round_value", 3.0);
+    assert_number(&interp, "round_negative", -3.0);
+    assert_number(&interp, "abs_value", 3.0);
+    assert_number(&interp, "sqrt_value", 4.0);
+    assert_number(&interp, "pow_value", 1024.0);
+    assert_number(&interp, "log_value", 1.0);
+    assert_number(&interp, "exp_value", 1.0);
+    assert_number(&interp, "pi", std::f64::consts::PI);
+}
+
+#[test]
+fn math_natives_follow_ieee_outside_their_domain() {
+    let source = "
+        var negative_sqrt = is_nan(sqrt(-1));
+        var negative_log = is_nan(log(-1));
+        var zero_log = log(0);
+        var nan_max = is_nan(max(1, sqrt(-1), 2));
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_bool(&interp, "negative_sqrt", true);
+    assert_bool(&interp, "negative_log", true);
+    assert!(matches!(global(&interp, "zero_log"), RuntimeValue::Number(n) if n == f64::NEG_INFINITY));
+    assert_bool(&interp, "nan_max", true);
+
+    let (_, result) = run("sqrt(\"4\");");
+    assert!(matches!(result, Err(RuntimeError::InvalidArgument { .. })));
+}
+
+#[test]
+fn min_and_max_take_two_or_more_numbers() {
+    let source = "
+        var smallest = min(3, 1, 2);
+        var largest = max(3, 1, 5, 2);
+        var pair = max(-1, -2);
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_number(&interp, "smallest", 1.0);
+    assert_number(&interp, "largest", 5.0);
+    assert_number(&interp, "pair", -1.0);
+
+    let (_, result) = run("min(1);");
+    assert!(matches!(result, Err(RuntimeError::CallableArityMismatch { expected: 2, found: 1, .. })));
+
+    let (_, result) = run("max(1, nil);");
+    assert!(matches!(result, Err(RuntimeError::InvalidArgument { .. })));
+}
+
+#[test]
+fn math_natives_can_be_shadowed() {
+    let source = "
+        var floor = \"floor\";
+        var ceiling = ceil(1.5);
+        fun f() {
+            var round = 1;
+            return round + abs(-1);
+        }
+        var sum = f();
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_str(&interp, "floor", "floor");
+    assert_number(&interp, "ceiling", 2.0);
+    assert_number(&interp, "sum", 2.0);
+    assert!(matches!(global(&interp, "round"), RuntimeValue::Callable(_)));
+}
+
+#[test]
+fn tonumber_reverses_tostring() {
+    let source = "
+        var round_trips = true;
+        fun check(n) {
+            if (tonumber(tostring(n)) != n) round_trips = false;
+        }
+        check(0);
+        check(-2.5);
+        check(0.1 + 0.2);
+        check(1 / 3);
+        check(123456789012345678901234567890);
+        check(0.0000001);
+        var text = tostring(12.5);
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_bool(&interp, "round_trips", true);
+    assert_str(&interp, "text", "12.5");
+}
+
+#[test]
+fn tonumber_parses_literals_and_booleans() {
+    let source = "
+        var padded = tonumber(\"  42 \");
+        var negative = tonumber(\"-1.5\");
+        var positive = tonumber(\"+7\");
+        var yes = tonumber(true);
+        var no = tonumber(false);
+        var number = tonumber(3);
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_number(&interp, "padded", 42.0);
+    assert_number(&interp, "negative", -1.5);
+    assert_number(&interp, "positive", 7.0);
+    assert_number(&interp, "yes", 1.0);
+    assert_number(&interp, "no", 0.0);
+    assert_number(&interp, "number", 3.0);
+
+    for literal in ["\"\"", "\"abc\"", "\"1.\"", "\".5\"", "\"1e3\"", "\"- 1\"", "\"inf\"", "nil", "clock"] {
+        let (interp, result) = run(&format!("var n = tonumber({});", literal));
+        assert!(result.is_ok());
+        assert!(matches!(global(&interp, "n"), RuntimeValue::Nil), "{}", literal);
+    }
+}
+
+#[test]
+fn tostring_uses_the_user_facing_string() {
+    let source = "
+        class Point {
+            init(x) { this.x = x; }
+            toString() { return \"Point(\" + tostring(this.x) + \")\"; }
+        }
+        class Plain {}
+        var point = tostring(Point(1));
+        var plain = tostring(Plain());
+        var string = tostring(\"text\");
+        var boolean = tostring(true);
+        var nothing = tostring(nil);
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_str(&interp, "point", "Point(1)");
+    assert_str(&interp, "plain", "<instance of class Plain>");
+    assert_str(&interp, "string", "text");
+    assert_str(&interp, "boolean", "true");
+    assert_str(&interp, "nothing", "nil");
+}
+
+#[test]
+fn readline_reads_successive_lines_until_the_end_of_the_input() {
+    let input = std::io::Cursor::new("first\n   \n\r\nlast\r\n42\n 1.5 \nno number\n");
+    let mut interp = Interpreter::new().with_input(input);
+
+    let source = "
+        var first = readline();
+        var blank = readline();
+        var empty = readline();
+        var last = readline();
+        var number = readnumber();
+        var padded = readnumber();
+        var not_number = readnumber();
+        var end = readline();
+        var end_number = readnumber();
+    ";
+    assert!(interp.execute(&parse(source)).is_ok());
+    assert_str(&interp, "first", "first");
+    assert_str(&interp, "blank", "   ");
+    assert_str(&interp, "empty", "");
+    assert_str(&interp, "last", "last");
+    assert_number(&interp, "number", 42.0);
+    assert_number(&interp, "padded", 1.5);
+    assert!(matches!(global(&interp, "not_number"), RuntimeValue::Nil));
+    assert!(matches!(global(&interp, "end"), RuntimeValue::Nil));
+    assert!(matches!(global(&interp, "end_number"), RuntimeValue::Nil));
+}
+
+#[test]
+fn readline_interleaves_with_print() {
+    let input = std::io::Cursor::new("Ada\nLovelace");
+    let output = SharedOutput::default();
+    let mut interp = Interpreter::new()
+        .with_input(input)
+        .with_output(output.clone());
+
+    let source = "
+        print \"first name?\";
+        var first = readline();
+        print \"last name?\";
+        var last = readline();
+        print first + \" \" + last;
+    ";
+    assert!(interp.execute(&parse(source)).is_ok());
+    assert_eq!(output.text(), "\"first name?\"\n\"last name?\"\n\"Ada Lovelace\"\n");
+}
+
+// A directory of its own for each test that touches files.
+fn temp_dir(test: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("rlox-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn files_can_be_written_appended_and_read() {
+    let dir = temp_dir("file-natives");
+    let path = dir.join("notes.txt");
+    let source = format!("
+        var path = \"{}\";
+        var existed = file_exists(path);
+        write_file(path, \"first\");
+        append_file(path, \" second\");
+        var contents = read_file(path);
+        write_file(path, \"replaced\");
+        var replaced = read_file(path);
+        var exists = file_exists(path);
+    ", path.display());
+
+    let (interp, result) = run(&source);
+    assert!(result.is_ok());
+    assert_bool(&interp, "existed", false);
+    assert_str(&interp, "contents", "first second");
+    assert_str(&interp, "replaced", "replaced");
+    assert_bool(&interp, "exists", true);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn file_errors_are_reported_at_the_call_site() {
+    let dir = temp_dir("file-errors");
+    let path = dir.join("missing.txt");
+    let source = format!("
+        var path = \"{}\";
+        read_file(path);
+    ", path.display());
+
+    let (_, result) = run(&source);
+    match result {
+        Err(error @ RuntimeError::IoError { .. }) => {
+            assert_eq!(error.token().line, 3);
+            assert!(error.message().contains("missing.txt"));
+        },
+        _ => panic!("expected an I/O error"),
+    }
+
+    let (_, result) = run("write_file(1, \"contents\");");
+    assert!(matches!(result, Err(RuntimeError::InvalidArgument { .. })));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn environment_variables_can_be_read_and_set() {
+    // set by cargo for the tests it runs
+    let source = "
+        var from_process = getenv(\"CARGO_PKG_NAME\");
+        var missing = getenv(\"RLOX_TEST_MISSING\");
+        setenv(\"RLOX_TEST_FROM_LOX\", \"from lox\");
+        var from_lox = getenv(\"RLOX_TEST_FROM_LOX\");
+        setenv(\"CARGO_PKG_NAME\", \"shadowed\");
+        var shadowed = getenv(\"CARGO_PKG_NAME\");
+        var platform_name = platform();
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_str(&interp, "from_process", env!("CARGO_PKG_NAME"));
+    assert!(matches!(global(&interp, "missing"), RuntimeValue::Nil));
+    assert_str(&interp, "from_lox", "from lox");
+    assert_str(&interp, "shadowed", "shadowed");
+    assert_str(&interp, "platform_name", std::env::consts::OS);
+
+    // the process environment is left alone, so other interpreters don't see them
+    assert!(std::env::var("RLOX_TEST_FROM_LOX").is_err());
+    assert_eq!(std::env::var("CARGO_PKG_NAME").as_deref(), Ok(env!("CARGO_PKG_NAME")));
+    let mut forked = interp.fork();
+    assert!(forked.execute(&parse("var from_lox = getenv(\"RLOX_TEST_FROM_LOX\");")).is_ok());
+    assert_str(&forked, "from_lox", "from lox");
+    let (other, result) = run("var from_lox = getenv(\"RLOX_TEST_FROM_LOX\");");
+    assert!(result.is_ok());
+    assert!(matches!(global(&other, "from_lox"), RuntimeValue::Nil));
+
+    for source in ["getenv(1);", "setenv(\"RLOX_TEST\", nil);", "setenv(\"A=B\", \"c\");"] {
+        let (_, result) = run(source);
+        assert!(matches!(result, Err(RuntimeError::InvalidArgument { .. })), "{}", source);
+    }
+}
+
+#[test]
+fn sleep_blocks_for_the_given_seconds() {
+    let started = std::time::Instant::now();
+    let (_, result) = run("sleep(0.02); sleep(0);");
+    assert!(result.is_ok());
+    assert!(started.elapsed() >= std::time::Duration::from_millis(20));
+
+    for source in ["sleep(-1);", "sleep(\"1\");", "sleep(nil);"] {
+        let (_, result) = run(source);
+        assert!(matches!(result, Err(RuntimeError::InvalidArgument { .. })), "{}", source);
+    }
+}
+
+#[test]
+fn cancellation_interrupts_sleep() {
+    let mut interp = Interpreter::new();
+    let flag = interp.cancellation_flag();
+    let canceller = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        flag.store(true, std::sync::atomic::Ordering::Relaxed);
+    });
+
+    let source = "
+        var after = false;
+        try {
+            sleep(60);
+        } finally {
+            after = true;
+        }
+    ";
+    let started = std::time::Instant::now();
+    let result = interp.execute(&parse(source));
+    canceller.join().unwrap();
+
+    assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    assert!(matches!(&result, Err(RuntimeError::Cancelled(t)) if t.line == 4));
+    assert_bool(&interp, "after", false);
+}
+
+#[test]
+fn cancellation_stops_loops() {
+    let mut interp = Interpreter::new();
+    interp.cancellation_flag().store(true, std::sync::atomic::Ordering::Relaxed);
+
+    let result = interp.execute(&parse("while (true) { }"));
+    assert!(matches!(result, Err(RuntimeError::Cancelled(_))));
+
+    interp.cancellation_flag().store(false, std::sync::atomic::Ordering::Relaxed);
+    assert!(interp.execute(&parse("var x = 1;")).is_ok());
+}
+
+#[test]
+fn arrays_are_shared_between_variables() {
+    let source = "
+        var a = Array(1, 2);
+        var b = a;
+        push(b, 3);
+        b[0] = 10;
+        var length = len(a);
+        var first = a[0];
+        var same = a == b;
+        var equal_contents = Array(1) == Array(1);
+        var popped = pop(a);
+        var empty_pop = pop(Array());
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_number(&interp, "length", 3.0);
+    assert_number(&interp, "first", 10.0);
+    assert_bool(&interp, "same", true);
+    assert_bool(&interp, "equal_contents", false);
+    assert_number(&interp, "popped", 3.0);
+    assert!(matches!(global(&interp, "empty_pop"), RuntimeValue::Nil));
+}
+
+#[test]
+fn array_natives_edit_elements_in_place() {
+    let source = "
+        var a = Array(\"a\", \"c\");
+        insert(a, 1, \"b\");
+        insert(a, 3, \"d\");
+        var removed = remove(a, 0);
+        var assigned = set(a, 0, \"B\");
+        var second = get(a, 1);
+        var text = tostring(a);
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_str(&interp, "removed", "a");
+    assert_str(&interp, "assigned", "B");
+    assert_str(&interp, "second", "c");
+    assert_str(&interp, "text", "[\"B\", \"c\", \"d\"]");
+
+    for source in ["get(Array(1), 1);", "set(Array(), 0, 1);", "remove(Array(1), -1);", "Array(1)[1];", "Array()[0] = 1;"] {
+        let (_, result) = run(source);
+        assert!(matches!(result, Err(RuntimeError::IndexOutOfRange { .. })), "{}", source);
+    }
+
+    let (_, result) = run("insert(Array(1), 2, 0);");
+    assert!(matches!(result, Err(RuntimeError::IndexOutOfRange { index: 2, len: 1, .. })));
+
+    let (_, result) = run("get(Array(1), 0.5);");
+    assert!(matches!(result, Err(RuntimeError::IndexMustBeInteger(_))));
+
+    let (_, result) = run("push(\"a\", 1);");
+    assert!(matches!(result, Err(RuntimeError::InvalidArgument { .. })));
+}
+
+#[test]
+fn arrays_grow_and_nest() {
+    let source = "
+        var a = Array();
+        for (var i = 0; i < 1000; i = i + 1) {
+            push(a, i);
+        }
+        var length = len(a);
+        var last = a[999];
+
+        var grid = Array(Array(1, 2), Array(3, 4));
+        grid[1][0] = 30;
+        var cell = grid[1][0];
+        var is_array = grid is Array;
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_number(&interp, "length", 1000.0);
+    assert_number(&interp, "last", 999.0);
+    assert_number(&interp, "cell", 30.0);
+    assert_bool(&interp, "is_array", true);
+}
+
+#[test]
+fn arrays_print_their_elements() {
+    let output = SharedOutput::default();
+    let mut interp = Interpreter::new().with_output(output.clone());
+    let source = "
+        class Point {
+            init(x) { this.x = x; }
+            toString() { return \"P\" + tostring(this.x); }
+        }
+        var a = Array(1, \"a\", nil, Array(true), Point(2));
+        print a;
+        var cyclic = Array(1);
+        push(cyclic, cyclic);
+        print cyclic;
+        print Array();
+    ";
+    assert!(interp.execute(&parse(source)).is_ok());
+    assert_eq!(output.text(), "[1, \"a\", nil, [true], P2]\n[1, [...]]\n[]\n");
+}
+
+#[test]
+fn arrays_in_cycles_are_collected() {
+    let marker = Rc::new(());
+    {
+        let captured = marker.clone();
+        let native = native::ClosureNative::new("marker", 0, move |_, _| {
+            let _ = &captured;
+            Ok(RuntimeValue::Nil)
+        });
+
+        let class = Gc::new(RefCell::new(Class::new("Holder", None, HashMap::new())));
+        let instance = Gc::new(RefCell::new(Instance::new(&class)));
+        let array = Gc::new(RefCell::new(vec![
+            RuntimeValue::Instance(instance.clone()),
+            RuntimeValue::Callable(CallableWrapper {
+                callable: Box::new(native),
+                closure: None,
+                receiver: None,
+            }),
+        ]));
+        instance.borrow_mut().set("array", &RuntimeValue::Array(array));
+    }
+
+    crate::shared::collect();
+    assert_eq!(Rc::strong_count(&marker), 1);
+}
+
+#[test]
+fn empty_arrays_are_falsy_only_with_c_style_truthiness() {
+    let source = "var empty = !Array(); var full = !Array(0);";
+
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_bool(&interp, "empty", false);
+    assert_bool(&interp, "full", false);
+
+    let mut interp = Interpreter::new().with_truthiness(Truthiness::CStyle);
+    assert!(interp.execute(&parse(source)).is_ok());
+    assert_bool(&interp, "empty", true);
+    assert_bool(&interp, "full", false);
+}
+
+#[test]
+fn maps_set_overwrite_and_remove_entries() {
+    let source = "
+        var m = Map();
+        map_set(m, \"a\", 1);
+        map_set(m, 2, \"two\");
+        map_set(m, true, nil);
+        map_set(m, nil, false);
+        map_set(m, \"a\", 10);
+        var a = map_get(m, \"a\");
+        var two = map_get(m, 1 + 1);
+        var negative_zero = map_has(Map(), -0);
+        var missing = map_get(m, \"missing\");
+        var has_true = map_has(m, true);
+        var removed = map_remove(m, 2);
+        var removed_again = map_remove(m, 2);
+        var length = map_len(m);
+        var alias = m;
+        map_set(alias, \"b\", 3);
+        var shared_length = map_len(m);
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_number(&interp, "a", 10.0);
+    assert_str(&interp, "two", "two");
+    assert_bool(&interp, "negative_zero", false);
+    assert!(matches!(global(&interp, "missing"), RuntimeValue::Nil));
+    assert_bool(&interp, "has_true", true);
+    assert_bool(&interp, "removed", true);
+    assert_bool(&interp, "removed_again", false);
+    assert_number(&interp, "length", 3.0);
+    assert_number(&interp, "shared_length", 4.0);
+
+    let (interp, result) = run("var m = Map(); map_set(m, 0, \"zero\"); var v = map_get(m, -0);");
+    assert!(result.is_ok());
+    assert_str(&interp, "v", "zero");
+}
+
+#[test]
+fn map_keys_must_be_compared_by_value() {
+    let sources = [
+        "map_set(Map(), Array(), 1);",
+        "map_set(Map(), Map(), 1);",
+        "map_get(Map(), clock);",
+        "class A {} map_has(Map(), A());",
+        "map_set(Map(), sqrt(-1), 1);",
+        "map_get(1, 1);",
+    ];
+    for source in sources {
+        let (_, result) = run(source);
+        assert!(matches!(result, Err(RuntimeError::InvalidArgument { .. })), "{}", source);
+    }
+}
+
+#[test]
+fn hash_agrees_with_equality() {
+    let source = "
+        var numbers = hash(1) == hash(2 - 1);
+        var zeros = hash(0) == hash(-0);
+        var strings = hash(\"ab\") == hash(\"a\" + \"b\");
+        var different = hash(\"a\") != hash(\"b\");
+        var string_and_number = hash(\"1\") != hash(1);
+        var nil_and_false = hash(nil) != hash(false);
+        var code = hash(\"some key\");
+        var is_integer = code >= 0 and code == floor(code);
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    for name in ["numbers", "zeros", "strings", "different", "string_and_number", "nil_and_false", "is_integer"] {
+        assert_bool(&interp, name, true);
+    }
+
+    let cases = [
+        ("class A {} hash(A());", "hash: expected a string, a number other than NaN, a boolean, nil or an instance with a 'hash' method, found Instance"),
+        ("hash(Array());", "hash: expected a string, a number other than NaN, a boolean, nil or an instance with a 'hash' method, found Array"),
+        ("hash(clock);", "hash: expected a string, a number other than NaN, a boolean, nil or an instance with a 'hash' method, found Function"),
+        ("class A { hash() { return Array(); } } hash(A());", "hash: 'hash' returned Array, which cannot be hashed"),
+    ];
+    for (source, expected) in cases {
+        let (_, result) = run(source);
+        match result {
+            Err(e @ RuntimeError::InvalidArgument { .. }) => assert_eq!(e.message(), expected),
+            _ => panic!("{} should fail", source),
+        }
+    }
+}
+
+#[test]
+fn instances_with_a_hash_method_are_map_keys() {
+    let source = "
+        class Point {
+            init(x, y) { this.x = x; this.y = y; }
+            hash() { return tostring(this.x) + \",\" + tostring(this.y); }
+            eq(other) { return other is Point and this.x == other.x and this.y == other.y; }
+        }
+        class Token {
+            hash() { return 1; }
+        }
+        var same_hash = hash(Point(1, 2)) == hash(Point(1, 2));
+        var hash_of_result = hash(Point(1, 2)) == hash(\"1,2\");
+        var m = Map();
+        map_set(m, Point(1, 2), \"first\");
+        map_set(m, Point(1, 2), \"second\");
+        map_set(m, Point(2, 1), \"other\");
+        var found = map_get(m, Point(1, 2));
+        var missing = map_has(m, Point(3, 3));
+        var a = Token();
+        map_set(m, a, \"a\");
+        map_set(m, Token(), \"b\");
+        var by_identity = map_get(m, a);
+        var size = map_len(m);
+        var removed = map_remove(m, Point(2, 1));
+        var first_key = map_keys(m)[0];
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_bool(&interp, "same_hash", true);
+    assert_bool(&interp, "hash_of_result", true);
+    assert_str(&interp, "found", "second");
+    assert_bool(&interp, "missing", false);
+    assert_str(&interp, "by_identity", "a");
+    assert_number(&interp, "size", 4.0);
+    assert_bool(&interp, "removed", true);
+    assert_eq!(global(&interp, "first_key").to_string(), "<instance of class Point>");
+}
+
+#[test]
+fn maps_keep_insertion_order() {
+    let output = SharedOutput::default();
+    let mut interp = Interpreter::new().with_output(output.clone());
+    let source = "
+        var m = Map();
+        map_set(m, \"z\", 1);
+        map_set(m, \"a\", 2);
+        map_set(m, \"m\", 3);
+        map_set(m, \"z\", 4);
+        map_remove(m, \"a\");
+        map_set(m, \"a\", 5);
+        print map_keys(m);
+        print m;
+        print Map();
+    ";
+    assert!(interp.execute(&parse(source)).is_ok());
+    assert_eq!(output.text(), "[\"z\", \"m\", \"a\"]\n{\"z\": 4, \"m\": 3, \"a\": 5}\n{}\n");
+}
+
+#[test]
+fn maps_and_arrays_nest() {
+    let output = SharedOutput::default();
+    let mut interp = Interpreter::new().with_output(output.clone());
+    let source = "
+        var m = Map();
+        map_set(m, \"list\", Array(1, Map()));
+        var list = map_get(m, \"list\");
+        map_set(list[1], \"inner\", true);
+        var inner = map_get(map_get(m, \"list\")[1], \"inner\");
+        map_set(m, \"self\", m);
+        print m;
+        var copy = clone(m);
+        var copy_is_separate = copy != m and map_get(copy, \"self\") == copy;
+    ";
+    assert!(interp.execute(&parse(source)).is_ok());
+    assert_bool(&interp, "inner", true);
+    assert_bool(&interp, "copy_is_separate", true);
+    assert_eq!(output.text(), "{\"list\": [1, {\"inner\": true}], \"self\": {...}}\n");
+}
+
+#[test]
+fn maps_in_cycles_are_collected() {
+    let marker = Rc::new(());
+    {
+        let captured = marker.clone();
+        let native = native::ClosureNative::new("marker", 0, move |_, _| {
+            let _ = &captured;
+            Ok(RuntimeValue::Nil)
+        });
+
+        let map = Gc::new(RefCell::new(crate::Map::default()));
+        let value = RuntimeValue::Map(map.clone());
+        map.borrow_mut().insert(crate::MapKey::new(&"self".into()).unwrap(), value);
+        map.borrow_mut().insert(crate::MapKey::Nil, RuntimeValue::Callable(CallableWrapper {
+            callable: Box::new(native),
+            closure: None,
+            receiver: None,
+        }));
+    }
+
+    crate::shared::collect();
+    assert_eq!(Rc::strong_count(&marker), 1);
+}
+
+#[test]
+fn maps_have_a_builtin_type_and_c_style_truthiness() {
+    let source = "
+        var m = Map();
+        var is_map = m is Map and !(Array() is Map);
+        var empty = !m;
+        map_set(m, 1, 1);
+        var full = !m;
+    ";
+
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_bool(&interp, "is_map", true);
+    assert_bool(&interp, "empty", false);
+
+    let mut interp = Interpreter::new().with_truthiness(Truthiness::CStyle);
+    assert!(interp.execute(&parse(source)).is_ok());
+    assert_bool(&interp, "empty", true);
+    assert_bool(&interp, "full", false);
+}
+
+#[test]
+fn slice_copies_a_range_of_an_array() {
+    let output = SharedOutput::default();
+    let mut interp = Interpreter::new().with_output(output.clone());
+    let source = "
+        var a = Array(0, 1, 2, 3, 4);
+        var middle = slice(a, 1, 3);
+        push(middle, 10);
+        print middle;
+        print slice(a, -2);
+        print slice(a, 1, -1);
+        print slice(a, -10, 2);
+        print slice(a, 3, 100);
+        print slice(a, 4, 1);
+        print slice(a, 5);
+        print a;
+    ";
+    assert!(interp.execute(&parse(source)).is_ok());
+    assert_eq!(
+        output.text(),
+        "[1, 2, 10]\n[3, 4]\n[1, 2, 3]\n[0, 1]\n[3, 4]\n[]\n[]\n[0, 1, 2, 3, 4]\n",
+    );
+}
+
+#[test]
+fn slice_takes_substrings_by_characters() {
+    let source = "
+        var middle = slice(\"hello\", 1, 3);
+        var tail = slice(\"hello\", -3);
+        var empty = slice(\"hello\", 3, 1);
+        var clamped = slice(\"hello\", -100, 100);
+        var text = slice(accented(), 1, 4);
+    ";
+    let mut interp = Interpreter::new();
+    interp.define_native("accented", 0, |_, _| Ok("h\u{e9}l\u{f6}!".into()));
+    assert!(interp.execute(&parse(source)).is_ok());
+    assert_str(&interp, "middle", "el");
+    assert_str(&interp, "tail", "llo");
+    assert_str(&interp, "empty", "");
+    assert_str(&interp, "clamped", "hello");
+    assert_str(&interp, "text", "\u{e9}l\u{f6}");
+
+    let sources = ["slice(\"ab\", 0.5);", "slice(1, 0);", "slice(Array(), 0, \"1\");"];
+    for source in sources {
+        let (_, result) = run(source);
+        assert!(matches!(result, Err(RuntimeError::InvalidArgument { .. })), "{}", source);
+    }
+}
+
+#[test]
+fn for_in_iterates_builtin_collections() {
+    let output = SharedOutput::default();
+    let mut interp = Interpreter::new().with_output(output.clone());
+    let source = "
+        for (var x in Array(1, \"two\", nil)) print x;
+        var m = Map();
+        map_set(m, \"b\", 1);
+        map_set(m, \"a\", 2);
+        for (var key in m) print key;
+        for (var c in \"abc\") print c;
+        for (var x in Array()) print x;
+        for (var c in \"\") print c;
+    ";
+    assert!(interp.execute(&parse(source)).is_ok());
+    assert_eq!(output.text(), "1\n\"two\"\nnil\n\"b\"\n\"a\"\n\"a\"\n\"b\"\n\"c\"\n");
+}
+
+#[test]
+fn for_in_calls_iter_and_next_on_instances() {
+    let source = "
+        class Countdown {
+            init(n) { this.n = n; }
+            iter() { return CountdownIterator(this.n); }
+        }
+        class CountdownIterator {
+            init(n) { this.n = n; }
+            next() {
+                if (this.n == 0) return nil;
+                this.n = this.n - 1;
+                return this.n + 1;
+            }
+        }
+        var seen = \"\";
+        for (var i in Countdown(3)) seen = seen + tostring(i);
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_str(&interp, "seen", "321");
+
+    let sources = [
+        "for (var x in 1) {}",
+        "for (var x in nil) {}",
+        "class A {} for (var x in A()) {}",
+    ];
+    for source in sources {
+        let (_, result) = run(source);
+        assert!(matches!(result, Err(RuntimeError::NotIterable(_))), "{}", source);
+    }
+
+    let (_, result) = run("class A { iter() { return 1; } } for (var x in A()) {}");
+    assert!(matches!(result, Err(RuntimeError::IteratorWithoutNext(_))));
+    let (_, result) = run("class A { iter() { return this; } } for (var x in A()) {}");
+    assert!(matches!(result, Err(RuntimeError::IteratorWithoutNext(_))));
+}
+
+#[test]
+fn range_counts_in_steps() {
+    let source = "
+        fun collect(r) {
+            var seen = \"\";
+            for (var i in r) seen = seen + tostring(i) + \" \";
+            return seen;
+        }
+        var up = collect(range(0, 4));
+        var to_end = collect(range(3));
+        var by_two = collect(range(1, 8, 2));
+        var down = collect(range(3, -1, -1));
+        var fractions = collect(range(0, 0.35, 0.1));
+        var empty = collect(range(5, 5));
+        var backwards = collect(range(5, 0));
+        var shown = tostring(range(1, 5));
+        var same = range(0, 3) == range(0, 3, 1);
+        var is_range = range(1) is Range;
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_str(&interp, "up", "0 1 2 3 ");
+    assert_str(&interp, "to_end", "0 1 2 ");
+    assert_str(&interp, "by_two", "1 3 5 7 ");
+    assert_str(&interp, "down", "3 2 1 0 ");
+    assert_str(&interp, "fractions", "0 0.1 0.2 0.30000000000000004 ");
+    assert_str(&interp, "empty", "");
+    assert_str(&interp, "backwards", "");
+    assert_str(&interp, "shown", "range(1, 5, 1)");
+    assert_bool(&interp, "same", true);
+    assert_bool(&interp, "is_range", true);
+
+    for source in ["range(0, 5, 0);", "range(\"5\");", "range(0 / 1 * pow(10, 400), 1);"] {
+        let (_, result) = run(source);
+        assert!(matches!(result, Err(RuntimeError::InvalidArgument { .. })), "{}", source);
+    }
+}
+
+#[test]
+fn range_is_not_stored() {
+    // an array of this many numbers would not fit in memory
+    let source = "
+        var count = 0;
+        for (var i in range(0, pow(10, 15))) {
+            if (i == 1000) break;
+            count = count + 1;
+        }
+        var forever = 0;
+        for (var i in range(0, pow(10, 400), 5)) {
+            forever = i;
+            if (i >= 50) break;
+        }
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_number(&interp, "count", 1000.0);
+    assert_number(&interp, "forever", 50.0);
+}
+
+#[test]
+fn for_in_supports_break_and_return() {
+    let source = "
+        var sum = 0;
+        for (var x in Array(1, 2, 3, 4)) {
+            if (x == 3) break;
+            sum = sum + x;
+        }
+        fun first_upper(s) {
+            for (var c in s) {
+                if (c == c.upper() and c != c.lower()) return c;
+            }
+            return nil;
+        }
+        var upper = first_upper(\"abCd\");
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_number(&interp, "sum", 3.0);
+    assert_str(&interp, "upper", "C");
+}
+
+#[test]
+fn for_in_goes_through_a_snapshot_of_the_collection() {
+    let output = SharedOutput::default();
+    let mut interp = Interpreter::new().with_output(output.clone());
+    let source = "
+        var a = Array(1, 2);
+        for (var x in a) {
+            push(a, x * 10);
+            print x;
+        }
+        print a;
+        var m = Map();
+        map_set(m, \"a\", 1);
+        map_set(m, \"b\", 2);
+        for (var key in m) {
+            map_remove(m, \"b\");
+            print key;
+        }
+        print m;
+    ";
+    assert!(interp.execute(&parse(source)).is_ok());
+    assert_eq!(output.text(), "1\n2\n[1, 2, 10, 20]\n\"a\"\n\"b\"\n{\"a\": 1}\n");
+}
+
+#[test]
+fn for_in_binds_a_new_variable_each_iteration() {
+    let source = "
+        var fs = Array();
+        for (var x in Array(1, 2, 3)) {
+            fun f() { return x; }
+            push(fs, f);
+        }
+        var total = fs[0]() + fs[1]() * 10 + fs[2]() * 100;
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_number(&interp, "total", 321.0);
+}
+
+#[test]
+fn sort_orders_numbers_and_strings() {
+    let output = SharedOutput::default();
+    let mut interp = Interpreter::new().with_output(output.clone());
+    let source = "
+        var numbers = Array(3, -1, 2.5, 0, 10);
+        sort(numbers);
+        print numbers;
+        var strings = Array(\"pear\", \"apple\", \"Zebra\", \"app\");
+        sort(strings);
+        print strings;
+        var empty = Array();
+        sort(empty);
+        print empty;
+    ";
+    assert!(interp.execute(&parse(source)).is_ok());
+    assert_eq!(
+        output.text(),
+        "[-1, 0, 2.5, 3, 10]\n[\"Zebra\", \"app\", \"apple\", \"pear\"]\n[]\n",
+    );
+
+    let sources = [
+        "sort(Array(1, \"a\"));",
+        "sort(Array(1, sqrt(-1)));",
+        "sort(Array(nil, nil));",
+        "sort(\"ba\");",
+    ];
+    for source in sources {
+        let (_, result) = run(source);
+        assert!(matches!(result, Err(RuntimeError::InvalidArgument { .. })), "{}", source);
+    }
+}
+
+#[test]
+fn sort_uses_a_comparator() {
+    let output = SharedOutput::default();
+    let mut interp = Interpreter::new().with_output(output.clone());
+    let source = "
+        fun descending(a, b) { return b - a; }
+        var numbers = Array(3, 1, 2);
+        sort(numbers, descending);
+        print numbers;
+
+        class Person {
+            init(name, age) {
+                this.name = name;
+                this.age = age;
+            }
+        }
+        var people = Array(Person(\"a\", 30), Person(\"b\", 20), Person(\"c\", 30), Person(\"d\", 10));
+        fun by_age(x, y) { return x.age - y.age; }
+        sort(people, by_age);
+        var names = \"\";
+        for (var p in people) names = names + p.name;
+        print names;
+    ";
+    assert!(interp.execute(&parse(source)).is_ok());
+    // stable: equal ages keep their order
+    assert_eq!(output.text(), "[3, 2, 1]\n\"dbac\"\n");
+}
+
+#[test]
+fn sort_stops_at_comparator_errors() {
+    let source = "
+        var numbers = Array(3, 1, 2);
+        fun fail(a, b) { throw \"no\"; }
+        var message;
+        try {
+            sort(numbers, fail);
+        }
+        catch (e) {
+            message = e;
+        }
+        var unchanged = numbers[0] == 3 and numbers[1] == 1 and numbers[2] == 2;
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_str(&interp, "message", "no");
+    assert_bool(&interp, "unchanged", true);
+
+    let (_, result) = run("fun cmp(a, b) { return \"x\"; } sort(Array(1, 2), cmp);");
+    assert!(matches!(result, Err(RuntimeError::InvalidArgument { .. })));
+}
+
+#[test]
+fn map_filter_and_reduce_chain() {
+    let source = "
+        fun is_odd(x) { return x % 2 == 1; }
+        fun square(x) { return x * x; }
+        fun add(acc, x) { return acc + x; }
+        var numbers = Array(1, 2, 3, 4, 5);
+        var total = reduce(map(filter(numbers, is_odd), square), add, 0);
+        var empty = reduce(map(filter(Array(), is_odd), square), add, 10);
+        var first = map(numbers, square)[4];
+        var kept = len(filter(numbers, is_odd));
+        var unchanged = len(numbers);
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_number(&interp, "total", 35.0);
+    assert_number(&interp, "empty", 10.0);
+    assert_number(&interp, "first", 25.0);
+    assert_number(&interp, "kept", 3.0);
+    assert_number(&interp, "unchanged", 5.0);
+}
+
+#[test]
+fn map_callbacks_see_their_closures() {
+    let source = "
+        fun scaled_by(factor) {
+            fun scale(x) { return x * factor; }
+            return scale;
+        }
+        var count = 0;
+        fun counting(x) {
+            count = count + 1;
+            return x;
+        }
+        var scaled = map(Array(1, 2), scaled_by(10));
+        var sum = scaled[0] + scaled[1];
+        map(Array(1, 2, 3), counting);
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_number(&interp, "sum", 30.0);
+    assert_number(&interp, "count", 3.0);
+}
+
+#[test]
+fn classes_can_be_callbacks() {
+    let source = "
+        class Box { init(value) { this.value = value; } }
+        var seen = 0;
+        class Seen { init(x) { seen = seen + x; } }
+        var boxes = map(Array(1, 2, 3), Box);
+        var second = boxes[1].value;
+        // instances are truthy, so all of them are kept
+        var kept = len(filter(Array(1, 2, 3), Seen));
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_number(&interp, "second", 2.0);
+    assert_number(&interp, "kept", 3.0);
+    assert_number(&interp, "seen", 6.0);
+
+    // classes are checked against their initializer
+    let sources = [
+        "class Empty { } map(Array(1), Empty);",
+        "class Pair { init(a, b) { } } map(Array(1), Pair);",
+    ];
+    for source in sources {
+        let (_, result) = run(source);
+        match result {
+            Err(e @ RuntimeError::InvalidArgument { .. }) => assert!(e.message().starts_with("map: expected a function taking 1 argument"), "{}", source),
+            _ => panic!("{} should fail", source),
+        }
+    }
+}
+
+#[test]
+fn map_callback_errors_propagate() {
+    let source = "
+        var calls = 0;
+        fun fail(x) {
+            calls = calls + 1;
+            if (x == 2) return x + nil;
+            return x;
+        }
+        map(Array(1, 2, 3), fail);
+    ";
+    let (interp, result) = run(source);
+    let error = result.err().expect("the callback fails");
+    assert!(matches!(error, RuntimeError::BinaryPlusExpectsTwoNumbersOrTwoStrings(_)));
+    assert_eq!(error.token().line, 5);
+    assert_number(&interp, "calls", 2.0);
+
+    // callbacks are checked before they are called
+    let sources = [
+        "var calls = 0; fun f(a, b) { calls = calls + 1; } map(Array(1), f);",
+        "var calls = 0; fun f(a) { calls = calls + 1; } reduce(Array(1), f, 0);",
+        "var calls = 0; filter(Array(1), 1);",
+    ];
+    for source in sources {
+        let (interp, result) = run(source);
+        assert!(matches!(result, Err(RuntimeError::InvalidArgument { .. })), "{}", source);
+        assert_number(&interp, "calls", 0.0);
+    }
+}
+
+fn define_document(interp: &mut Interpreter, document: &'static str) {
+    interp.define_native("document", 0, move |_, _| Ok(document.into()));
+}
+
+#[test]
+fn json_parse_builds_nested_values() {
+    let mut interp = Interpreter::new();
+    define_document(&mut interp, r#"
+        {
+            "name": "rlox",
+            "version": 1.5,
+            "tags": ["lox", "tree-walking", null],
+            "owner": {"login": "IDragnev", "admin": true},
+            "escaped": "a\"b\\c\né😀",
+            "empty": {}
+        }
+    "#);
+    let source = "
+        var doc = json_parse(document());
+        var name = map_get(doc, \"name\");
+        var version = map_get(doc, \"version\");
+        var tag = map_get(doc, \"tags\")[1];
+        var missing = map_get(doc, \"tags\")[2];
+        var admin = map_get(map_get(doc, \"owner\"), \"admin\");
+        var escaped = map_get(doc, \"escaped\");
+        var keys = map_keys(doc);
+        var empty = map_len(map_get(doc, \"empty\"));
+        var scalar = json_parse(\" -12.5e1 \");
+    ";
+    assert!(interp.execute(&parse(source)).is_ok());
+    assert_str(&interp, "name", "rlox");
+    assert_number(&interp, "version", 1.5);
+    assert_str(&interp, "tag", "tree-walking");
+    assert!(matches!(global(&interp, "missing"), RuntimeValue::Nil));
+    assert_bool(&interp, "admin", true);
+    assert_str(&interp, "escaped", "a\"b\\c\n\u{e9}\u{1F600}");
+    assert_eq!(global(&interp, "keys").to_string(), r#"["name", "version", "tags", "owner", "escaped", "empty"]"#);
+    assert_number(&interp, "empty", 0.0);
+    assert_number(&interp, "scalar", -125.0);
+}
+
+#[test]
+fn json_parse_reports_the_offset_of_errors() {
+    let cases = [
+        (r#"{"a": 1,}"#, 8),
+        (r#"[1, 2"#, 5),
+        (r#"{"a" 1}"#, 5),
+        (r#"01"#, 1),
+        (r#"[1] 2"#, 4),
+        (r#""\x""#, 2),
+        (r#"tru"#, 0),
+        (r#""#, 0),
+    ];
+    for (document, expected) in cases {
+        let mut interp = Interpreter::new();
+        define_document(&mut interp, document);
+        let result = interp.execute(&parse("json_parse(document());"));
+        match result {
+            Err(RuntimeError::InvalidJson { offset, .. }) => assert_eq!(offset, expected, "{}", document),
+            _ => panic!("{} should not parse", document),
+        }
+    }
+
+    let (interp, result) = run("var message; try { json_parse(\"[\"); } catch (e) { message = e.message; }");
+    assert!(result.is_ok());
+    assert_str(&interp, "message", "Invalid JSON at byte 1: unexpected end of input");
+}
+
+#[test]
+fn json_stringify_writes_nested_values() {
+    let source = "
+        class Point {
+            init(x, y) {
+                this.x = x;
+                this.y = y;
+            }
+            norm() { return this.x + this.y; }
+        }
+        var m = Map();
+        map_set(m, \"points\", Array(Point(1, 2), Point(-0.5, 3)));
+        map_set(m, \"ok\", true);
+        map_set(m, \"none\", nil);
+        map_set(m, \"name\", \"a b\");
+        var text = json_stringify(m);
+        var round_trip = json_stringify(json_parse(text));
+        var integers = json_stringify(json_parse(\"[1, 1.0, 100, -3, 1e3]\"));
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    let expected = r#"{"points":[{"x":1,"y":2},{"x":-0.5,"y":3}],"ok":true,"none":null,"name":"a b"}"#;
+    assert_str(&interp, "text", expected);
+    assert_str(&interp, "round_trip", expected);
+    assert_str(&interp, "integers", "[1,1,100,-3,1000]");
+}
+
+#[test]
+fn json_stringify_rejects_values_json_cannot_hold() {
+    let sources = [
+        "json_stringify(clock);",
+        "class A {} json_stringify(A);",
+        "json_stringify(Array(sqrt(-1)));",
+        "json_stringify(pow(10, 400));",
+        "var m = Map(); map_set(m, 1, 1); json_stringify(m);",
+        "var a = Array(); push(a, a); json_stringify(a);",
+        "class A {} var a = A(); a.self = a; json_stringify(a);",
+    ];
+    for source in sources {
+        let (_, result) = run(source);
+        assert!(matches!(result, Err(RuntimeError::InvalidArgument { .. })), "{}", source);
+    }
+}
+
+#[test]
+fn split_returns_the_parts_between_separators() {
+    let output = SharedOutput::default();
+    let mut interp = Interpreter::new().with_output(output.clone());
+    let source = "
+        print split(\"a,b,,c\", \",\");
+        print split(\",a,\", \",\");
+        print split(\"a--b--c\", \"--\");
+        print split(\"abc\", \";\");
+        print split(\"abc\", \"\");
+        print split(\"\", \",\");
+        print split(\"\", \"\");
+    ";
+    assert!(interp.execute(&parse(source)).is_ok());
+    assert_eq!(
+        output.text(),
+        "[\"a\", \"b\", \"\", \"c\"]\n\
+         [\"\", \"a\", \"\"]\n\
+         [\"a\", \"b\", \"c\"]\n\
+         [\"abc\"]\n\
+         [\"a\", \"b\", \"c\"]\n\
+         [\"\"]\n\
+         []\n",
+    );
+}
+
+#[test]
+fn join_concatenates_stringified_elements() {
+    let source = "
+        class Point {
+            init(x) { this.x = x; }
+            toString() { return \"P\" + tostring(this.x); }
+        }
+        var mixed = join(Array(1, \"two\", nil, true, Point(3)), \", \");
+        var empty = join(Array(), \",\");
+        var single = join(Array(\"a\"), \",\");
+        var glued = join(Array(\"a\", \"b\"), \"\");
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_str(&interp, "mixed", "1, two, nil, true, P3");
+    assert_str(&interp, "empty", "");
+    assert_str(&interp, "single", "a");
+    assert_str(&interp, "glued", "ab");
+
+    let sources = [
+        "split(1, \",\");",
+        "split(\"a\", nil);",
+        "join(\"ab\", \",\");",
+        "join(Array(), 1);",
+    ];
+    for source in sources {
+        let (_, result) = run(source);
+        assert!(matches!(result, Err(RuntimeError::InvalidArgument { .. })), "{}", source);
+    }
+}
+
+#[test]
+fn join_undoes_split() {
+    let strings = ["a,b,c", "", ",", ",,a,,", "abc", "a,,b"];
+    for s in strings {
+        let source = format!("var s = \"{}\"; var same = join(split(s, \",\"), \",\") == s;", s);
+        let (interp, result) = run(&source);
+        assert!(result.is_ok());
+        assert_bool(&interp, "same", true);
+    }
+}
+
+#[test]
+fn index_of_finds_the_first_occurrence() {
+    let source = "
+        var found = index_of(\"hello world\", \"o\");
+        var missing = index_of(\"hello\", \"z\");
+        var overlapping = index_of(\"aaaa\", \"aa\");
+        var empty = index_of(\"abc\", \"\");
+        var in_empty = index_of(\"\", \"a\");
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_number(&interp, "found", 4.0);
+    assert_number(&interp, "missing", -1.0);
+    assert_number(&interp, "overlapping", 0.0);
+    assert_number(&interp, "empty", 0.0);
+    assert_number(&interp, "in_empty", -1.0);
+}
+
+#[test]
+fn index_of_counts_characters_like_substr() {
+    let mut interp = Interpreter::new();
+    interp.define_native("text", 0, |_, _| Ok("caf\u{e9} cr\u{e8}me br\u{fb}l\u{e9}e".into()));
+    let source = "
+        var s = text();
+        var needle = \"me\";
+        var i = index_of(s, needle);
+        var consistent = s.substr(i, i + len(needle)) == needle;
+    ";
+    assert!(interp.execute(&parse(source)).is_ok());
+    assert_number(&interp, "i", 8.0);
+    assert_bool(&interp, "consistent", true);
+}
+
+#[test]
+fn replace_changes_occurrences() {
+    let source = "
+        var all = replace(\"a-b-c\", \"-\", \" + \");
+        var first = replace_first(\"a-b-c\", \"-\", \"\");
+        var overlapping = replace(\"aaa\", \"aa\", \"b\");
+        var none = replace(\"abc\", \"x\", \"y\");
+        var longer = replace(\"ab\", \"b\", \"bbb\");
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_str(&interp, "all", "a + b + c");
+    assert_str(&interp, "first", "ab-c");
+    assert_str(&interp, "overlapping", "ba");
+    assert_str(&interp, "none", "abc");
+    assert_str(&interp, "longer", "abbb");
+
+    let sources = [
+        "replace(\"abc\", \"\", \"x\");",
+        "replace_first(\"abc\", \"\", \"x\");",
+        "replace(\"abc\", \"a\", 1);",
+        "index_of(1, \"a\");",
+    ];
+    for source in sources {
+        let (_, result) = run(source);
+        assert!(matches!(result, Err(RuntimeError::InvalidArgument { .. })), "{}", source);
+    }
+}
+
+#[test]
+fn ord_and_chr_convert_code_points() {
+    let mut interp = Interpreter::new();
+    interp.define_native("text", 0, |_, _| Ok("\u{e9}\u{1F600}".into()));
+    let source = "
+        var a = ord(\"a\");
+        var newline = chr(10);
+        var z = chr(ord(\"a\") + 25);
+        var s = text();
+        var accent = ord(s[0]);
+        var emoji = ord(s[1]);
+        var round_trip = chr(ord(s[0])) == s[0] and chr(ord(s[1])) == s[1];
+        var ascii_round_trip = true;
+        for (var c in \"Hello, World!\") {
+            ascii_round_trip = ascii_round_trip and chr(ord(c)) == c;
+        }
+    ";
+    assert!(interp.execute(&parse(source)).is_ok());
+    assert_number(&interp, "a", 97.0);
+    assert_str(&interp, "newline", "\n");
+    assert_str(&interp, "z", "z");
+    assert_number(&interp, "accent", 233.0);
+    assert_number(&interp, "emoji", 128512.0);
+    assert_bool(&interp, "round_trip", true);
+    assert_bool(&interp, "ascii_round_trip", true);
+}
+
+#[test]
+fn ord_and_chr_reject_invalid_arguments() {
+    let sources = [
+        "ord(\"\");",
+        "ord(\"ab\");",
+        "ord(97);",
+        "chr(55296);",
+        "chr(57343);",
+        "chr(1114112);",
+        "chr(-1);",
+        "chr(97.5);",
+        "chr(\"a\");",
+    ];
+    for source in sources {
+        let (_, result) = run(source);
+        assert!(matches!(result, Err(RuntimeError::InvalidArgument { .. })), "{}", source);
+    }
+}
+
+#[test]
+fn format_substitutes_placeholders() {
+    let output = SharedOutput::default();
+    let mut interp = Interpreter::new().with_output(output.clone());
+    let source = "
+        class Point {
+            init(x) { this.x = x; }
+            toString() { return format(\"P({})\", this.x); }
+        }
+        print format(\"{} + {} = {}\", 1, \"two\", nil);
+        print format(\"[{:8}] [{:<8}] [{:^8}] [{:>8}]\", 42, 42, 42, \"ab\");
+        print format(\"[{:.2}] [{:8.3}] [{:.0}] [{:.2}]\", PI, 2.5, 2.5, \"abc\");
+        print format(\"[{:*^7}] [{:0>5}] [{:.<4}]\", \"mid\", 7, true);
+        print format(\"{{}} {{{}}}\", Point(1));
+        print format(\"no placeholders\");
+        print format(\"[{:.1000}]\", \"long precisions only cut strings\");
+    ";
+    assert!(interp.execute(&parse(source)).is_ok());
+    assert_eq!(
+        output.text(),
+        "\"1 + two = nil\"\n\
+         \"[      42] [42      ] [   42   ] [      ab]\"\n\
+         \"[3.14] [   2.500] [2] [ab]\"\n\
+         \"[**mid**] [00007] [true]\"\n\
+         \"{} {P(1)}\"\n\
+         \"no placeholders\"\n\
+         \"[long precisions only cut strings]\"\n",
+    );
+}
+
+#[test]
+fn format_checks_placeholders_against_arguments() {
+    let cases = [
+        ("format(\"{} {}\", 1);", "format: placeholder 1 has no argument"),
+        ("format(\"{}\", 1, 2);", "format: 2 arguments for 1 placeholders"),
+        ("format(\"a {} {\", 1, 2);", "format: placeholder 1 is not closed"),
+        ("format(\"{} }\", 1);", "format: unmatched '}' after placeholder 1"),
+        ("format(\"{:x}\", 1);", "format: placeholder 0 has an invalid spec"),
+        ("format(\"{x}\", 1);", "format: placeholder 0 has an invalid spec"),
+        ("format(1);", "format: expected String, found Number"),
+        ("format(\"{:1001}\", 1);", "format: placeholder 0 has a width above 1000"),
+        ("format(\"{} {:.101}\", 1, 2);", "format: placeholder 1 has a precision above 100"),
+        ("format(\"{:99999999999}\", 1);", "format: placeholder 0 has a width above 1000"),
+    ];
+    for (source, expected) in cases {
+        let (_, result) = run(source);
+        match result {
+            Err(e @ RuntimeError::InvalidArgument { .. }) => assert_eq!(e.message(), expected, "{}", source),
+            _ => panic!("{} should fail", source),
+        }
+    }
+}
+
+#[test]
+fn blocks_without_declarations_create_no_environments() {
+    let source = "
+        var total = 0;
+        fun count(n) {
+            var i = 0;
+            while (i < n) {
+                total = total + i;
+                i = i + 1;
+            }
+        }
+        var before = map_get(gc_stats(), \"environments_created\");
+        count(100000);
+        var created = map_get(gc_stats(), \"environments_created\") - before;
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_number(&interp, "total", 4999950000.0);
+    // the call's own environment
+    assert_number(&interp, "created", 1.0);
+}
+
+#[test]
+fn blocks_that_declare_keep_their_own_scope() {
+    let source = "
+        var a = \"outer\";
+        var seen = \"\";
+        {
+            seen = seen + a;
+            {
+                var a = \"inner\";
+                seen = seen + \" \" + a;
+                {
+                    seen = seen + \" \" + a;
+                    a = \"changed\";
+                }
+                seen = seen + \" \" + a;
+            }
+            seen = seen + \" \" + a;
+        }
+        var captured;
+        {
+            var x = 1;
+            fun get() { return x; }
+            captured = get;
+        }
+        var deferred = \"\";
+        fun run_defers() {
+            { defer deferred = deferred + \"block \"; }
+            deferred = deferred + \"after\";
+        }
+        run_defers();
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_str(&interp, "seen", "outer inner inner changed outer");
+    let value = interp.globals_env.borrow().get("captured").unwrap();
+    let mut interp = interp;
+    assert!(matches!(interp.call(&value, &Vec::new()), Ok(RuntimeValue::Number(n)) if n == 1.0));
+    assert_str(&interp, "deferred", "block after");
+}
+
+#[test]
+fn lookups_through_thousands_of_scopes() {
+    with_large_stack(|| {
+        const DEPTH: usize = 5000;
+        let mut source = String::from("var captured;\n");
+        for i in 0..DEPTH {
+            source.push_str(&format!("{{ var v{} = {};\n", i, i));
+        }
+        source.push_str(&format!("fun get() {{ return v0 + v{}; }}\n", DEPTH - 1));
+        source.push_str("captured = get;\nv0 = 1;\n");
+        source.push_str(&"}".repeat(DEPTH));
+        source.push_str("\nvar result = captured();\n");
+
+        let (interp, result) = run(&source);
+        assert!(result.is_ok());
+        assert_number(&interp, "result", DEPTH as f64);
+    });
+}
+
+#[test]
+fn gc_collect_frees_cyclic_instances() {
+    let source = "
+        class Node {}
+        fun churn(n) {
+            for (var i = 0; i < n; i = i + 1) {
+                var a = Node();
+                var b = Node();
+                a.next = b;
+                b.next = a;
+            }
+        }
+        var before = gc_stats();
+        churn(500);
+        gc_collect();
+        var after = gc_stats();
+        var created = map_get(after, \"instances_created\") - map_get(before, \"instances_created\");
+        var leaked = map_get(after, \"live_instances\") - map_get(before, \"live_instances\");
+        var collections = map_get(after, \"collections\") - map_get(before, \"collections\");
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_number(&interp, "created", 1000.0);
+    assert_number(&interp, "leaked", 0.0);
+    assert_number(&interp, "collections", 1.0);
+}
+
+#[test]
+fn superclasses_reachable_only_from_subclasses_survive_collection() {
+    let source = "
+        class Base {
+            greet() { return \"hello from \" + this.name; }
+            describe() { return \"base\"; }
+        }
+        class Derived < Base {
+            init(name) { this.name = name; }
+            describe() { return \"derived of \" + super.describe(); }
+        }
+        var Base = nil;
+        gc_collect();
+        var d = Derived(\"d\");
+        var greeting = d.greet();
+        var description = d.describe();
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_str(&interp, "greeting", "hello from d");
+    assert_str(&interp, "description", "derived of base");
+}
+
+#[test]
+fn gc_collect_frees_cycles_through_classes() {
+    let source = "
+        fun churn(n) {
+            for (var i = 0; i < n; i = i + 1) {
+                class Base {}
+                class Local < Base {
+                    owner() { return instance; }
+                }
+                var instance = Local();
+                instance.kind = Local;
+            }
+        }
+        var before = gc_stats();
+        churn(100);
+        gc_collect();
+        var after = gc_stats();
+        var leaked = map_get(after, \"live_instances\") - map_get(before, \"live_instances\");
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_number(&interp, "leaked", 0.0);
+}
+
+#[test]
+fn gc_collect_is_safe_in_nested_calls() {
+    let source = "
+        class Box {
+            init(value) {
+                this.value = value;
+                this.self = this;
+            }
+        }
+        fun nest(depth) {
+            var local = Box(depth);
+            var inner = 0;
+            if (depth == 0) gc_collect();
+            else inner = nest(depth - 1);
+            return inner + local.self.value;
+        }
+        var total = nest(100);
+    ";
+    with_large_stack(move || {
+        let (interp, result) = run(source);
+        assert!(result.is_ok());
+        assert_number(&interp, "total", 5050.0);
+    });
+}
+
+#[test]
+fn gc_stats_has_the_documented_keys() {
+    let output = SharedOutput::default();
+    let mut interp = Interpreter::new().with_output(output.clone());
+    assert!(interp.execute(&parse("print map_keys(gc_stats());")).is_ok());
+    assert_eq!(
+        output.text(),
+        "[\"live_instances\", \"instances_created\", \"live_environments\", \"environments_created\", \"collections\"]\n",
+    );
+}
+
+#[test]
+fn backtrace_lists_the_calls_in_progress() {
+    let output = SharedOutput::default();
+    let mut interp = Interpreter::new().with_output(output.clone());
+    // the calls are not in tail position, which would replace the frame of their caller
+    let source = "fun inner() {
+    var calls = backtrace();
+    return calls;
+}
+fun middle() {
+    var calls = inner();
+    return calls;
+}
+fun outer() {
+    var calls = middle();
+    return calls;
+}
+var calls = outer();
+for (var call in calls) print call;
+print backtrace();
+fun each(x) { var calls = inner(); return calls; }
+print map(Array(1), each)[0][1];
+";
+    assert!(interp.execute(&parse(source)).is_ok());
+    assert_eq!(
+        output.text(),
+        "{\"function\": \"inner\", \"line\": 6, \"column\": 23, \"declared\": 1}\n\
+         {\"function\": \"middle\", \"line\": 10, \"column\": 24, \"declared\": 5}\n\
+         {\"function\": \"outer\", \"line\": 13, \"column\": 19, \"declared\": 9}\n\
+         []\n\
+         {\"function\": \"each\", \"line\": 17, \"column\": 25, \"declared\": 16}\n",
+    );
+}
+
+#[test]
+fn error_fails_at_its_call() {
+    let source = "
+        fun check(x) {
+            if (x < 0)
+                error(\"negative: {x} isn't allowed\");
+            return x;
+        }
+        fun outer() { return check(-1); }
+        outer();
+    ";
+    let (_, result) = run(source);
+    match result {
+        Err(e @ RuntimeError::UserError { .. }) => {
+            assert_eq!(e.message(), "negative: {x} isn't allowed");
+            assert_eq!(e.token().line, 4);
+        },
+        _ => panic!("expected a user error"),
+    }
+
+    let mut interp = Interpreter::new();
+    interp.define_native("quoted", 0, |_, _| Ok("say \"hi\"".into()));
+    match interp.execute(&parse("error(quoted());")) {
+        Err(e @ RuntimeError::UserError { .. }) => assert_eq!(e.message(), "say \"hi\""),
+        _ => panic!("expected a user error"),
+    }
+}
+
+#[test]
+fn error_stringifies_its_argument_and_can_be_caught() {
+    let source = "
+        class Code {
+            toString() { return \"code 7\"; }
+        }
+        var number;
+        var instance;
+        try { error(42); } catch (e) { number = e.message; }
+        try { error(Code()); } catch (e) { instance = e.message; }
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_str(&interp, "number", "42");
+    assert_str(&interp, "instance", "code 7");
+}
+
+#[test]
+fn pcall_returns_the_result_or_the_error() {
+    let source = "
+        fun divide(a, b) { return a / b; }
+        fun thrower() { throw \"thrown\"; }
+        var ok = pcall(divide, 6, 3);
+        var by_zero = pcall(divide, 1, 0);
+        var arity = pcall(divide, 1);
+        var not_callable = pcall(1);
+        var user = pcall(error, \"custom\");
+        var thrown = pcall(thrower);
+        var after = \"reached\";
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    let show = |name| global(&interp, name).to_string();
+    assert_eq!(show("ok"), "[true, 2]");
+    assert_eq!(show("by_zero"), "[false, \"Division by zero\"]");
+    assert_eq!(show("arity"), "[false, \"Arity mismatch. Expected 2 arguments, found 1\"]");
+    assert_eq!(show("not_callable"), "[false, \"Non-callable called\"]");
+    assert_eq!(show("user"), "[false, \"custom\"]");
+    assert_eq!(show("thrown"), "[false, \"thrown\"]");
+    assert_str(&interp, "after", "reached");
+}
+
+#[test]
+fn pcall_lets_cancellation_through() {
+    let mut interp = Interpreter::new();
+    let flag = interp.cancellation_flag();
+    interp.define_native("cancel", 0, move |_, _| {
+        flag.store(true, std::sync::atomic::Ordering::SeqCst);
+        Ok(RuntimeValue::Nil)
+    });
+    let source = "
+        fun work() {
+            cancel();
+            while (true) {}
+        }
+        var result = pcall(work);
+        var after = true;
+    ";
+    let result = interp.execute(&parse(source));
+    assert!(matches!(result, Err(RuntimeError::Cancelled(_))));
+    assert!(interp.globals_env.borrow().get("after").is_none());
+}
+
+#[test]
+fn assert_eq_passes_on_equal_values() {
+    let source = "
+        class Point {
+            init(x) { this.x = x; }
+            eq(other) { return this.x == other.x; }
+        }
+        var p = Point(1);
+        assert_eq(1 + 1, 2);
+        assert_eq(\"a\" + \"b\", \"ab\", \"concatenation\");
+        assert_eq(nil, nil);
+        assert_eq(Array(1, Array(2, 3)), Array(1, Array(2, 3)));
+        var a = Map();
+        map_set(a, \"k\", Array(1));
+        var b = Map();
+        map_set(b, \"k\", Array(1));
+        assert_eq(a, b);
+        assert_eq(Point(1), Point(1));
+        assert_eq(Array(p), Array(p));
+        var result = assert_eq(true, true);
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert!(matches!(global(&interp, "result"), RuntimeValue::Nil));
+}
+
+#[test]
+fn assert_eq_shows_where_values_differ() {
+    let cases = [
+        ("assert_eq(1, 2);", "assert_eq failed: expected 2, found 1"),
+        ("assert_eq(\"a\", 1);", "assert_eq failed: expected 1, found a"),
+        ("assert_eq(Array(1, 2, 3), Array(1, 5, 3));", "assert_eq failed at [1]: expected 5, found 2"),
+        ("assert_eq(Array(Array(1), Array(2)), Array(Array(1), Array(3)));", "assert_eq failed at [1][0]: expected 3, found 2"),
+        ("assert_eq(Array(1, 2), Array(1));", "assert_eq failed: expected [1], found [1, 2]"),
+        ("assert_eq(2, 3, \"sum\");", "assert_eq failed (sum): expected 3, found 2"),
+        ("class A {} assert_eq(A(), A());", "assert_eq failed: expected <instance of class A>, found <instance of class A>"),
+    ];
+    for (source, message) in cases {
+        let (_, result) = run(source);
+        match result {
+            Err(e @ RuntimeError::UserError { .. }) => assert_eq!(e.message(), message, "{}", source),
+            _ => panic!("expected a user error from {}", source),
+        }
+    }
+}
+
+#[test]
+fn assert_eq_failures_can_be_caught() {
+    let source = "
+        var caught;
+        try { assert_eq(Array(1, 2), Array(1, 3), \"pairs\"); } catch (e) { caught = e.message; }
+        var protected = pcall(assert_eq, 1, 2);
+        var passed = pcall(assert_eq, 1, 1);
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_str(&interp, "caught", "assert_eq failed (pairs) at [1]: expected 3, found 2");
+    let show = |name| global(&interp, name).to_string();
+    assert_eq!(show("protected"), "[false, \"assert_eq failed: expected 2, found 1\"]");
+    assert_eq!(show("passed"), "[true, nil]");
+}
+
+#[test]
+fn fields_can_be_accessed_by_name() {
+    let source = "
+        class Point {
+            init(x) { this.x = x; }
+            norm() { return this.x; }
+        }
+        var p = Point(1);
+        var has_x = has_field(p, \"x\");
+        var has_norm = has_field(p, \"norm\");
+        var x = get_field(p, \"x\");
+        var norm = get_field(p, \"norm\");
+        var missing = get_field(p, \"y\");
+        var assigned = set_field(p, \"y\", \"new\");
+        var y = p.y;
+        set_field(p, \"x\", 5);
+        var norm_after = p.norm();
+        var has_y = has_field(p, \"y\");
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_bool(&interp, "has_x", true);
+    assert_bool(&interp, "has_norm", false);
+    assert_number(&interp, "x", 1.0);
+    assert!(matches!(global(&interp, "norm"), RuntimeValue::Nil));
+    assert!(matches!(global(&interp, "missing"), RuntimeValue::Nil));
+    assert_str(&interp, "assigned", "new");
+    assert_str(&interp, "y", "new");
+    assert_number(&interp, "norm_after", 5.0);
+    assert_bool(&interp, "has_y", true);
+}
+
+#[test]
+fn field_natives_validate_their_arguments() {
+    let cases = [
+        ("has_field(1, \"x\");", "has_field: expected Instance, found Number"),
+        ("class A {} get_field(A, \"x\");", "get_field: expected Instance, found Class"),
+        ("class A {} set_field(A(), 1, 2);", "set_field: expected String, found Number"),
+        ("each_field(1, nil);", "each_field: expected Instance, found Number"),
+    ];
+    for (source, expected) in cases {
+        let (_, result) = run(source);
+        match result {
+            Err(e @ RuntimeError::InvalidArgument { .. }) => assert_eq!(e.message(), expected),
+            _ => panic!("{} should fail", source),
+        }
+    }
+}
+
+#[test]
+fn classes_can_be_inspected() {
+    let output = SharedOutput::default();
+    let mut interp = Interpreter::new().with_output(output.clone());
+    let source = "
+        class Animal {
+            speak() { return \"...\"; }
+            name() { return \"animal\"; }
+        }
+        class Dog < Animal {
+            speak() { return \"woof\"; }
+            fetch() {}
+        }
+        class Puppy < Dog {}
+
+        var chain = \"\";
+        var current = class_of(Puppy());
+        while (current != nil) {
+            chain = chain + class_name(current) + \" \";
+            current = superclass(current);
+        }
+        print chain;
+
+        print method_names(Dog);
+        print method_names(Dog, true);
+        print method_names(Puppy);
+        print method_names(Puppy, true);
+
+        var d = Dog();
+        print d is class_of(d);
+        print d is class_of(Animal());
+        print Animal() is class_of(d);
+        print class_of(d) == Dog;
+    ";
+    assert!(interp.execute(&parse(source)).is_ok());
+    assert_eq!(
+        output.text(),
+        "\"Puppy Dog Animal \"\n\
+         [\"fetch\", \"speak\"]\n\
+         [\"fetch\", \"name\", \"speak\"]\n\
+         []\n\
+         [\"fetch\", \"name\", \"speak\"]\n\
+         true\ntrue\nfalse\ntrue\n",
+    );
+}
+
+#[test]
+fn class_natives_validate_their_arguments() {
+    let cases = [
+        ("class_of(1);", "class_of: expected Instance, found Number"),
+        ("class A {} class_of(A);", "class_of: expected Instance, found Class"),
+        ("class A {} superclass(A());", "superclass: expected Class, found Instance"),
+        ("class_name(\"A\");", "class_name: expected Class, found String"),
+        ("class A {} method_names(A, 1);", "method_names: expected Bool, found Number"),
+    ];
+    for (source, expected) in cases {
+        let (_, result) = run(source);
+        match result {
+            Err(e @ RuntimeError::InvalidArgument { .. }) => assert_eq!(e.message(), expected),
+            _ => panic!("{} should fail", source),
+        }
+    }
+}
+
+#[test]
+fn bind_rebinds_methods_to_other_instances() {
+    let source = "
+        class Greeter {
+            init(name) { this.name = name; }
+            greet(greeting) { return greeting + \" \" + this.name; }
+        }
+        class Person {
+            init(name) { this.name = name; }
+        }
+        var alice = Greeter(\"alice\");
+        var bob = Person(\"bob\");
+        var greet_bob = bind(alice.greet, bob);
+        var greeting = greet_bob(\"hi\");
+        var rebound = bind(greet_bob, Greeter(\"carol\"))(\"hello\");
+        var original = alice.greet(\"hey\");
+        var same_as_get = bind(alice.greet, alice) == alice.greet;
+        var different = greet_bob == alice.greet;
+        fun call_with(f, x) { return f(x); }
+        var as_callback = call_with(greet_bob, \"yo\");
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_str(&interp, "greeting", "hi bob");
+    assert_str(&interp, "rebound", "hello carol");
+    assert_str(&interp, "original", "hey alice");
+    assert_bool(&interp, "same_as_get", true);
+    assert_bool(&interp, "different", false);
+    assert_str(&interp, "as_callback", "yo bob");
+
+    // 'this' outside of a class is a resolution error, so there is nothing to bind in functions
+    let cases = [
+        ("fun f() {} class A {} bind(f, A());", "bind: expected a method, found <fun f>"),
+        ("class A {} bind(len, A());", "bind: expected a method, found <native fn len>"),
+        ("class A { m() {} } bind(A().m, 1);", "bind: expected Instance, found Number"),
+    ];
+    for (source, expected) in cases {
+        let (_, result) = run(source);
+        match result {
+            Err(e @ RuntimeError::InvalidArgument { .. }) => assert_eq!(e.message(), expected),
+            _ => panic!("{} should fail", source),
+        }
+    }
+}
+
+#[test]
+fn arity_and_param_names_describe_callables() {
+    let source = "
+        fun add(a, b) { return a + b; }
+        class Point {
+            init(x, y) { this.x = x; this.y = y; }
+            move(dx, dy) {}
+        }
+        class Empty {}
+        var p = Point(1, 2);
+        var function_arity = arity(add);
+        var method_arity = arity(p.move);
+        var native_arity = arity(len);
+        var variadic_arity = arity(max);
+        var class_arity = arity(Point);
+        var empty_arity = arity(Empty);
+        var primitive_arity = arity(\"a\".upper);
+        var function_params = param_names(add);
+        var method_params = param_names(p.move);
+        var class_params = param_names(Point);
+        var empty_params = param_names(Empty);
+        var native_params = param_names(len);
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_number(&interp, "function_arity", 2.0);
+    assert_number(&interp, "method_arity", 2.0);
+    assert_number(&interp, "native_arity", 1.0);
+    assert!(matches!(global(&interp, "variadic_arity"), RuntimeValue::Nil));
+    assert_number(&interp, "class_arity", 2.0);
+    assert_number(&interp, "empty_arity", 0.0);
+    assert_number(&interp, "primitive_arity", 0.0);
+    let show = |name| global(&interp, name).to_string();
+    assert_eq!(show("function_params"), "[\"a\", \"b\"]");
+    assert_eq!(show("method_params"), "[\"dx\", \"dy\"]");
+    assert_eq!(show("class_params"), "[\"x\", \"y\"]");
+    assert_eq!(show("empty_params"), "[]");
+    assert_eq!(show("native_params"), "nil");
+
+    for (source, expected) in [
+        ("arity(1);", "arity: expected a function or class, found Number"),
+        ("class A {} param_names(A());", "param_names: expected a function or class, found Instance"),
+    ] {
+        let (_, result) = run(source);
+        match result {
+            Err(e @ RuntimeError::InvalidArgument { .. }) => assert_eq!(e.message(), expected),
+            _ => panic!("{} should fail", source),
+        }
+    }
+}
+
+#[test]
+fn parse_int_reads_integers_in_a_radix() {
+    let source = "
+        var hex = parse_int(\"ff\", 16);
+        var upper_hex = parse_int(\"FF\", 16);
+        var binary = parse_int(\"1010\", 2);
+        var octal = parse_int(\"777\", 8);
+        var base36 = parse_int(\"zz\", 36);
+        var negative = parse_int(\"-10\", 16);
+        var positive = parse_int(\"+10\", 8);
+        var padded = parse_int(\"  42 \", 10);
+        var bad_digit = parse_int(\"12\", 2);
+        var empty = parse_int(\"\", 10);
+        var sign_only = parse_int(\" - \", 10);
+        var inner_space = parse_int(\"1 0\", 10);
+        var fraction = parse_int(\"1.5\", 10);
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_number(&interp, "hex", 255.0);
+    assert_number(&interp, "upper_hex", 255.0);
+    assert_number(&interp, "binary", 10.0);
+    assert_number(&interp, "octal", 511.0);
+    assert_number(&interp, "base36", 1295.0);
+    assert_number(&interp, "negative", -16.0);
+    assert_number(&interp, "positive", 8.0);
+    assert_number(&interp, "padded", 42.0);
+    for name in ["bad_digit", "empty", "sign_only", "inner_space", "fraction"] {
+        assert!(matches!(global(&interp, name), RuntimeValue::Nil), "{}", name);
+    }
+}
+
+#[test]
+fn parse_int_rejects_invalid_radixes() {
+    let sources = [
+        "parse_int(\"1\", 1);",
+        "parse_int(\"1\", 37);",
+        "parse_int(\"1\", 10.5);",
+        "parse_int(\"1\", \"10\");",
+        "parse_int(1, 10);",
+    ];
+    for source in sources {
+        let (_, result) = run(source);
+        assert!(matches!(result, Err(RuntimeError::InvalidArgument { .. })), "{}", source);
+    }
+}
+
+#[test]
+fn to_fixed_rounds_half_away_from_zero() {
+    let cases = [
+        ("to_fixed(2.5, 0)", "3"),
+        ("to_fixed(-2.5, 0)", "-3"),
+        ("to_fixed(0.125, 2)", "0.13"),
+        ("to_fixed(-0.125, 2)", "-0.13"),
+        // stored as slightly less than 1.005
+        ("to_fixed(1.005, 2)", "1.00"),
+        ("to_fixed(9.995, 2)", "9.99"),
+        ("to_fixed(99.5, 0)", "100"),
+        ("to_fixed(0, 3)", "0.000"),
+        ("to_fixed(-0.001, 2)", "0.00"),
+        ("to_fixed(1234.5678, 1)", "1234.6"),
+        ("to_fixed(pow(10, 21), 2)", "1000000000000000000000.00"),
+        ("to_fixed(0.1, 20)", "0.10000000000000000555"),
+        ("to_fixed(1, 1000)", &format!("1.{}", "0".repeat(100))),
+        ("to_fixed(sqrt(-1), 2)", "nan"),
+        ("to_fixed(-pow(10, 400), 2)", "-inf"),
+    ];
+    for (expr, expected) in cases {
+        let (interp, result) = run(&format!("var s = {};", expr));
+        assert!(result.is_ok(), "{}", expr);
+        assert_str(&interp, "s", expected);
+    }
+}
+
+#[test]
+fn to_precision_keeps_significant_digits() {
+    let cases = [
+        ("to_precision(123.456, 4)", "123.5"),
+        ("to_precision(123.456, 3)", "123"),
+        ("to_precision(123.456, 2)", "1.2e2"),
+        ("to_precision(-0.000123456, 2)", "-0.00012"),
+        ("to_precision(0.00000012345, 3)", "1.23e-7"),
+        ("to_precision(2.5, 1)", "3"),
+        ("to_precision(9.96, 2)", "10"),
+        ("to_precision(99.96, 3)", "100"),
+        ("to_precision(999.6, 3)", "1.00e3"),
+        ("to_precision(0, 3)", "0.00"),
+        ("to_precision(1, 1)", "1"),
+        ("to_precision(pow(10, 21), 3)", "1.00e21"),
+        ("to_precision(pow(10, -300), 1)", "1e-300"),
+    ];
+    for (expr, expected) in cases {
+        let (interp, result) = run(&format!("var s = {};", expr));
+        assert!(result.is_ok(), "{}", expr);
+        assert_str(&interp, "s", expected);
+    }
+}
+
+#[test]
+fn number_formatting_checks_digit_counts() {
+    let sources = [
+        "to_fixed(1, -1);",
+        "to_fixed(1, 1.5);",
+        "to_fixed(\"1\", 1);",
+        "to_precision(1, 0);",
+        "to_precision(1, 2.5);",
+        "to_precision(1, nil);",
+    ];
+    for source in sources {
+        let (_, result) = run(source);
+        assert!(matches!(result, Err(RuntimeError::InvalidArgument { .. })), "{}", source);
+    }
+}
+
+#[test]
+fn calls_that_create_no_closures_reuse_their_environment() {
+    let source = "
+        fun add(a, b) {
+            var sum = a + b;
+            return sum;
+        }
+        var total = 0;
+        var before = map_get(gc_stats(), \"environments_created\");
+        for (var i = 0; i < 1000; i = i + 1) {
+            total = total + add(i, 1);
+        }
+        var created = map_get(gc_stats(), \"environments_created\") - before;
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_number(&interp, "total", 500500.0);
+    // the loop's scope and the one call environment all the calls reuse
+    assert_number(&interp, "created", 2.0);
+}
+
+#[test]
+fn closures_keep_the_environment_of_the_call_that_made_them() {
+    let source = "
+        fun make(n) {
+            var value = n * 2;
+            if (true) {
+                fun get() { return value; }
+                return get;
+            }
+        }
+        fun wrap(n) {
+            class Box {
+                get() { return n; }
+            }
+            return Box();
+        }
+        fun plain(n) {
+            var value = n + 100;
+            return value;
+        }
+        var first = make(1);
+        var second = make(2);
+        var box = wrap(3);
+        plain(4);
+        plain(5);
+        var a = first();
+        var b = second();
+        var c = box.get();
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_number(&interp, "a", 2.0);
+    assert_number(&interp, "b", 4.0);
+    assert_number(&interp, "c", 3.0);
+}
+
+#[test]
+fn recursive_calls_each_get_their_own_environment() {
+    let source = "
+        fun fib(n) {
+            var m = n;
+            if (m < 2) return m;
+            var left = fib(m - 1);
+            var right = fib(m - 2);
+            return left + right;
+        }
+        fun sum(n) {
+            var local = n;
+            if (n == 0) return 0;
+            var rest = sum(n - 1);
+            return local + rest;
+        }
+        var a = fib(15);
+        var b = sum(50);
+        var c = fib(10);
+    ";
+    let (interp, result) = run(source);
+    assert!(result.is_ok());
+    assert_number(&interp, "a", 610.0);
+    assert_number(&interp, "b", 1275.0);
+    assert_number(&interp, "c", 55.0);
+}
+
+fn run_cyclic_pairs(gc_interval: Option<usize>) -> Interpreter {
+    let stmts = parse("
+        class Node {}
+        var peak = 0;
+        for (var i = 0; i < 100000; i = i + 1) {
+            var a = Node();
+            var b = Node();
+            a.other = b;
+            b.other = a;
+            var live = map_get(gc_stats(), \"live_instances\");
+            if (live > peak) peak = live;
+        }
+    ");
+    let mut interp = Interpreter::new().with_gc_interval(gc_interval);
+    assert!(interp.execute(&stmts).is_ok());
+
+    interp
+}
+
+#[test]
+fn collections_are_paced_by_the_objects_created() {
+    let before = Interpreter::new().gc_stats();
+    let interp = run_cyclic_pairs(Some(1000));
+    let after = interp.gc_stats();
+
+    // each iteration creates two instances and the map of gc_stats
+    assert!(after.allocations - before.allocations >= 300000);
+    assert!(after.collections - before.collections >= 299);
+    match global(&interp, "peak") {
+        RuntimeValue::Number(peak) => assert!(peak <= 1000.0, "peak: {}", peak),
+        value => panic!("peak: expected a number, found {}", value),
+    }
+}
+
+#[test]
+fn collections_are_not_paced_without_an_interval() {
+    let before = Interpreter::new().gc_stats();
+    let interp = run_cyclic_pairs(None);
+    let after = interp.gc_stats();
+
+    assert_eq!(after.collections, before.collections);
+    // cycles pile up until the collector decides to run
+    match global(&interp, "peak") {
+        RuntimeValue::Number(peak) => assert!(peak > 1000.0, "peak: {}", peak),
+        value => panic!("peak: expected a number, found {}", value),
+    }
+}
+
+#[test]
+fn gc_stats_count_the_objects_created() {
+    let mut interp = Interpreter::new();
+    let before = interp.gc_stats();
+    let stmts = parse("
+        class Point {}
+        class Line {}
+        var points = Array();
+        for (var i = 0; i < 250; i = i + 1) push(points, Point());
+    ");
+    assert!(interp.execute(&stmts).is_ok());
+    let after = interp.gc_stats();
+
+    assert_eq!(after.classes_created - before.classes_created, 2);
+    assert_eq!(after.instances_created - before.instances_created, 250);
+    assert_eq!(after.live_instances - before.live_instances, 250);
+    // the classes, the instances and the array
+    assert!(after.allocations - before.allocations >= 253);
+}
+
+#[test]
+fn collecting_garbage_frees_unreachable_cycles() {
+    let mut interp = Interpreter::new().with_gc_interval(None);
+    let stmts = parse("
+        class Node {}
+        var nodes = Array();
+        for (var i = 0; i < 100; i = i + 1) {
+            var a = Node();
+            var b = Node();
+            a.other = b;
+            b.other = a;
+            push(nodes, a);
+        }
+    ");
+    assert!(interp.execute(&stmts).is_ok());
+    interp.collect_garbage();
+    let before = interp.gc_stats();
+    assert!(before.live_instances >= 200);
+
+    assert!(interp.execute(&parse("nodes = nil;")).is_ok());
+    // the cycles keep each other alive until collected
+    assert_eq!(interp.gc_stats().live_instances, before.live_instances);
+    interp.collect_garbage();
+    let after = interp.gc_stats();
+
+    assert_eq!(after.collections, before.collections + 1);
+    assert_eq!(before.live_instances - after.live_instances, 200);
+    assert!(after.last_collection_freed >= 200);
+}
+
+#[test]
+fn collection_thresholds_can_be_changed_while_running() {
+    let stmts = parse("
+        class Node {}
+        for (var i = 0; i < 10000; i = i + 1) {
+            var a = Node();
+            a.other = a;
+        }
+    ");
+    let mut interp = Interpreter::new().with_gc_interval(None);
+    let before = interp.gc_stats();
+    assert!(interp.execute(&stmts).is_ok());
+    let unpaced = interp.gc_stats();
+    assert_eq!(unpaced.collections, before.collections);
+
+    interp.set_collection_threshold(Some(100));
+    assert!(interp.execute(&stmts).is_ok());
+    let paced = interp.gc_stats();
+    assert!(paced.collections - unpaced.collections >= 99);
+
+    interp.set_collection_threshold(None);
+    assert!(interp.execute(&stmts).is_ok());
+    assert_eq!(interp.gc_stats().collections, paced.collections);
+}
+
+// Runs `source` the way the REPL does, keeping its value in `_`.
+#[test]
+fn underscore_holds_the_last_repl_result() {
+    let mut lox = Lox::new();
+    assert!(lox.run_repl_input("1 + 2").is_ok());
+    assert!(matches!(lox.run_repl_input("_ + 1;"), Ok(Some(RuntimeValue::Number(n))) if n == 4.0));
+    assert_number(lox.interpreter(), "_", 4.0);
+
+    // statements that are not expressions leave it as it is
+    assert!(matches!(lox.run_repl_input("var x = 10"), Ok(None)));
+    assert_number(lox.interpreter(), "_", 4.0);
+}
+
+#[test]
+fn failed_repl_inputs_leave_underscore_as_it_is() {
+    let mut lox = Lox::new();
+    assert!(lox.run_repl_input("\"kept\"").is_ok());
+    assert!(matches!(lox.run_repl_input("nil + 1"), Err(LoxError::Runtime(_))));
+    assert!(matches!(lox.run_repl_input("var = 1"), Err(LoxError::Parse(_))));
+    assert_str(lox.interpreter(), "_", "kept");
+}
+
+#[test]
+fn underscore_can_be_assigned_to() {
+    let mut lox = Lox::new();
+    assert!(lox.run_repl_input("1;").is_ok());
+    assert!(lox.run_repl_input("_ = 5; var y = _ * 2;").is_ok());
+    assert_number(lox.interpreter(), "y", 10.0);
+    assert!(lox.run_repl_input("var _ = \"declared\";").is_ok());
+    assert_str(lox.interpreter(), "_", "declared");
+}
+
+#[test]
+fn repl_inputs_keep_the_warnings_of_the_last_input() {
+    let mut lox = Lox::new();
+    assert!(lox.run_repl_input("fun f() { var unused; return 1; }").is_ok());
+    assert_eq!(lox.warnings().len(), 1);
+    assert!(lox.run_repl_input("f()").is_ok());
+    assert!(lox.warnings().is_empty());
+}
+
+#[test]
+fn run_source_returns_the_value_of_the_last_expression() {
+    let mut interp = Interpreter::new();
+    assert!(matches!(run_source("var x = 20;", &mut interp), Ok(None)));
+    assert!(matches!(run_source("x * 2 + 2;", &mut interp), Ok(Some(RuntimeValue::Number(n))) if n == 42.0));
+    assert!(matches!(run_source_new("\"a\" + \"b\";"), Ok(Some(RuntimeValue::String(s))) if s.as_str() == "ab"));
+}
+
+#[test]
+fn run_source_reports_the_phase_that_failed() {
+    let mut interp = Interpreter::new();
+    assert!(matches!(run_source("var s = \"open;", &mut interp), Err(LoxError::Scan(_))));
+    assert!(matches!(run_source("var = 1;", &mut interp), Err(LoxError::Parse(_))));
+    assert!(matches!(run_source("return 1;", &mut interp), Err(LoxError::Resolution(_))));
+    assert!(matches!(run_source("nil + 1;", &mut interp), Err(LoxError::Runtime(Traced { error: RuntimeError::BinaryPlusExpectsTwoNumbersOrTwoStrings(_), .. }))));
+}
+
+#[test]
+fn interpreters_can_be_used_after_a_runtime_error() {
+    let mut interp = Interpreter::new();
+    let result = run_source("var before = 1; fun fail() { return nil.field; } fail();", &mut interp);
+    assert!(matches!(result, Err(LoxError::Runtime(Traced { error: RuntimeError::OnlyInstancesHaveProperties(_), .. }))));
+    assert_eq!(interp.stack_trace().len(), 1);
+
+    // nothing is executed when compiling fails
+    assert!(run_source("before = 5; var = 1;", &mut interp).is_err());
+    assert!(matches!(run_source("before + 1;", &mut interp), Ok(Some(RuntimeValue::Number(n))) if n == 2.0));
+}
+
+fn error_message(source: &str) -> String {
+    match run_source_new(source) {
+        Err(e) => e.to_string(),
+        Ok(_) => panic!("{}: expected an error", source),
+    }
+}
+
+#[test]
+fn errors_of_every_phase_are_rendered_with_their_positions() {
+    assert_eq!(error_message("var s = \"open;"), "Scan error at line 1, column 9: Unterminated string.");
+    assert_eq!(error_message("var a = 1 @ 2;"), "Scan error at line 1, column 11: Unexpected character found.");
+    assert_eq!(
+        error_message("var = 1;\nprint (1;"),
+        "Parse error at line 1, column 5: Expected Identifier, found Equal.\n\
+         Parse error at line 2, column 9: Expected RightParen, found Semicolon.",
+    );
+    assert_eq!(
+        error_message("fun f() {\n  var a = 1;\n  var a = 2;\n}\nreturn 1;"),
+        "Resolution error at line 3, column 7: Variable 'a' already declared.\n\
+         Resolution error at line 5, column 1: 'return' outside function.",
+    );
+    assert_eq!(error_message("var x = 1;\nx / 0;"), "Runtime error at line 2, column 3: Division by zero.");
+}
+
+#[test]
+fn lox_errors_can_be_boxed_and_returned_with_question_marks() {
+    fn run_boxed(source: &str) -> Result<Option<RuntimeValue>, Box<dyn std::error::Error>> {
+        Ok(run_source_new(source)?)
+    }
+
+    let boxed = match run_boxed("nil.field;") {
+        Err(e) => e,
+        Ok(_) => panic!("expected an error"),
+    };
+    assert_eq!(boxed.to_string(), "Runtime error at line 1, column 5: Only instances have properties.");
+    let source = boxed.source().expect("a source");
+    assert!(source.downcast_ref::<RuntimeError>().is_some());
+    match boxed.downcast::<LoxError>() {
+        Ok(e) => assert!(matches!(*e, LoxError::Runtime(Traced { error: RuntimeError::OnlyInstancesHaveProperties(_), .. }))),
+        Err(_) => panic!("expected a LoxError"),
+    }
+}
+
+#[test]
+fn lox_sessions_keep_what_earlier_programs_defined() {
+    let output = SharedOutput::default();
+    let mut lox = Lox::new().with_output(output.clone());
+    assert!(lox.run("fun square(x) { var unused; return x * x; }").is_ok());
+    assert_eq!(lox.warnings().len(), 1);
+
+    assert!(matches!(lox.run("square(7);"), Ok(Some(RuntimeValue::Number(n))) if n == 49.0));
+    assert!(lox.warnings().is_empty());
+    // a program that fails leaves the session usable
+    assert!(matches!(lox.run("fun f() { return; } return;"), Err(LoxError::Resolution(_))));
+    assert!(lox.run("print square(3);").is_ok());
+    assert_eq!(output.text(), "9\n");
+}
+
+#[test]
+fn lox_sessions_leave_no_class_context_behind_a_failed_program() {
+    let mut lox = Lox::new();
+    assert!(matches!(lox.run("class A < A {}"), Err(LoxError::Resolution(_))));
+
+    match lox.run("print this;") {
+        Err(LoxError::Resolution(errs)) => {
+            assert!(matches!(errs.as_slice(), [ResolutionError::ThisNotInsideClass(_)]));
+        },
+        _ => panic!("expected `this` outside of a class to be rejected"),
+    }
+}
+
+#[test]
+fn functions_declared_at_the_same_place_in_different_inputs_differ() {
+    let mut lox = Lox::new();
+    assert!(lox.run("fun f() { } var first = f;").is_ok());
+
+    let result = lox.run("fun f() { } first == f;");
+    assert!(matches!(result, Ok(Some(RuntimeValue::Bool(false)))));
+}
+
+#[test]
+fn lox_globals_can_be_set_and_read_from_rust() {
+    let mut lox = Lox::new();
+    lox.define_global("limit", RuntimeValue::Number(10.0));
+    assert!(lox.run("var doubled = limit * 2;").is_ok());
+
+    assert!(matches!(lox.get_global("doubled"), Some(RuntimeValue::Number(n)) if n == 20.0));
+    assert!(lox.get_global("missing").is_none());
+}
+
+#[test]
+fn lox_sessions_call_natives_defined_for_them() {
+    let mut lox = Lox::new().with_strict_booleans(true).with_step_limit(1000);
+    lox.define_native("add", 2, |args, interp| {
+        match (&args[0], &args[1]) {
+            (RuntimeValue::Number(a), RuntimeValue::Number(b)) => Ok(RuntimeValue::Number(a + b)),
+            _ => Err(interp.invalid_argument("add expects two numbers")),
+        }
+    });
+
+    assert!(matches!(lox.run("add(1, 2);"), Ok(Some(RuntimeValue::Number(n))) if n == 3.0));
+    assert!(matches!(lox.run("add(1, nil);"), Err(LoxError::Runtime(Traced { error: RuntimeError::InvalidArgument { .. }, .. }))));
+    assert!(matches!(lox.run("if (1) {}"), Err(LoxError::Runtime(Traced { error: RuntimeError::ConditionMustBeBoolean(_), .. }))));
+}
+
+const CLOSURES: &str = "var total = 0;
+fun make_counter(step) {
+    var count = 0;
+    fun counter() {
+        count = count + step;
+        total = total + 1;
+        return count;
+    }
+    return counter;
+}
+class Base { get() { return this.value; } }
+class Twice < Base { get() { return super.get() * 2; } }
+";
+
+#[test]
+fn rendered_programs_show_hops_of_captured_variables_once_resolved() {
+    let statements = compile(CLOSURES, &mut Resolver::new()).expect("compile failed").statements;
+    assert_eq!(render_program(&statements, true), "\
+var total 0
+fun make_counter(step)
+  var count 0
+  fun counter()
+    expression (= count@1 (+ count@1 step@1))
+    expression (= total (+ total 1))
+    return count@1
+  return counter@0
+class Base
+  fun get()
+    return (. this@0 value)
+class Twice < Base
+  fun get()
+    return (* (call (. super@1 get)) 2)
+");
+}
+
+#[test]
+fn rendered_programs_show_no_hops_before_resolving() {
+    let statements = match parse_source(CLOSURES) {
+        Ok(statements) => statements,
+        Err(e) => panic!("{}", e),
+    };
+    let rendered = render_program(&statements, false);
+
+    assert!(rendered.contains("expression (= count (+ count step))"));
+    assert!(rendered.contains("return (* (call (. super get)) 2)"));
+    assert!(!rendered.contains('@'));
+    // resolved statements show none either unless asked to
+    let resolved = compile(CLOSURES, &mut Resolver::new()).expect("compile failed").statements;
+    assert_eq!(render_program(&resolved, false), rendered);
+}
+
+#[test]
+fn snippets_ending_in_expressions_return_their_values() {
+    let output = SharedOutput::default();
+    let mut interp = Interpreter::new().with_output(output.clone());
+    assert!(matches!(run_snippet("print 1 + 2;", &mut interp), Ok(None)));
+    assert!(matches!(run_snippet("var a = 2;\na * 3;", &mut interp), Ok(Some(RuntimeValue::Number(n))) if n == 6.0));
+    assert_eq!(output.text(), "3\n");
+}
+
+#[test]
+fn snippets_can_leave_out_their_last_semicolon() {
+    let mut interp = Interpreter::new();
+    assert!(matches!(run_snippet("1 + 2", &mut interp), Ok(Some(RuntimeValue::Number(n))) if n == 3.0));
+    assert!(matches!(run_snippet("var a = 4;\na * 2 // doubled", &mut interp), Ok(Some(RuntimeValue::Number(n))) if n == 8.0));
+    assert!(matches!(run_snippet("var b = a", &mut interp), Ok(None)));
+    assert_number(&interp, "b", 4.0);
+}
+
+#[test]
+fn snippets_compile_with_the_resolver_they_are_given() {
+    let mut resolver = Resolver::new();
+    let mut interp = Interpreter::new();
+    for source in ["var a = 1", "a + 2"] {
+        let program = compile_snippet(source, &mut resolver).expect("compile failed");
+        interp.run(&program.statements).expect("run failed");
+    }
+    assert!(matches!(compile_snippet("var = 1", &mut resolver), Err(LoxError::Parse(_))));
+    assert_number(&interp, "a", 1.0);
+}
+
+#[test]
+fn snippets_report_the_errors_of_the_source_as_given() {
+    let mut interp = Interpreter::new();
+    match run_snippet("var = 1", &mut interp) {
+        Err(e @ LoxError::Parse(_)) => assert_eq!(e.to_string(), "Parse error at line 1, column 5: Expected Identifier, found Equal."),
+        _ => panic!("expected a parse error"),
+    }
+    assert!(matches!(run_snippet("return 1;", &mut interp), Err(LoxError::Resolution(_))));
+    assert!(matches!(run_snippet("1 / nil", &mut interp), Err(LoxError::Runtime(_))));
+}
+
+#[test]
+fn snapshots_of_closures_show_their_captured_variables() {
+    let (interp, result) = run("
+        fun make_counter() {
+            var count = 41;
+            fun counter() {
+                count = count + 1;
+                return count;
+            }
+            return counter;
+        }
+        var counter = make_counter();
+        counter();
+    ");
+    assert!(result.is_ok());
+
+    let closure = match global(&interp, "counter") {
+        RuntimeValue::Callable(callable) => callable.closure.expect("closure"),
+        value => panic!("counter: expected a function, found {}", value),
+    };
+    let scopes = closure.borrow().snapshot();
+
+    assert_eq!(scopes.len(), 2);
+    assert_eq!(scopes[0].bindings, vec![
+        ("count".to_owned(), "42".to_owned()),
+        ("counter".to_owned(), "<fun counter>".to_owned()),
+    ]);
+    assert!(!scopes[0].is_global);
+    assert!(scopes[1].is_global);
+    assert!(scopes[1].bindings.iter().any(|(name, _)| name == "make_counter"));
+    assert!(scopes[1].bindings.iter().all(|(name, _)| name != "dump_env"));
+}
+
+#[test]
+fn dump_env_shows_shadowed_variables_in_their_own_scopes() {
+    let output = SharedOutput::default();
+    let mut interp = Interpreter::new().with_output(output.clone());
+    let result = interp.execute(&parse("
+        var a = \"global\";
+        fun f(a) {
+            {
+                var a = \"inner\";
+                dump_env();
+            }
+        }
+        f(1);
+    "));
+
+    assert!(result.is_ok());
+    assert_eq!(output.text(), "\
+scope 0:
+  a = \"inner\"
+scope 1:
+  a = 1
+globals:
+  E = 2.718281828459045
+  Error = <class Error>
+  PI = 3.141592653589793
+  a = \"global\"
+  f = <fun f>
+");
+}
+
+#[test]
+fn dump_env_in_methods_shows_this() {
+    let output = SharedOutput::default();
+    let mut interp = Interpreter::new().with_output(output.clone());
+    let result = interp.execute(&parse("
+        class Point {
+            show(label) {
+                dump_env();
+            }
+        }
+        Point().show(\"p\");
+    "));
+
+    assert!(result.is_ok());
+    assert!(output.text().starts_with("\
+scope 0:
+  label = \"p\"
+  this = <instance of class Point>
+"), "{}", output.text());
+}
+
+#[test]
+fn snapshots_cut_off_long_values() {
+    let (interp, result) = run("
+        var numbers = Array();
+        for (var i = 0; i < 10000; i = i + 1) push(numbers, i);
+    ");
+    assert!(result.is_ok());
+
+    let scopes = interp.dump_environment();
+    let (_, numbers) = scopes[0].bindings.iter()
+        .find(|(name, _)| name == "numbers")
+        .expect("numbers");
+    assert!(numbers.starts_with("[0, 1, 2, "));
+    assert!(numbers.ends_with("..."));
+    assert_eq!(numbers.chars().count(), 83);
+}
+
+#[cfg(feature = "sync-gc")]
+#[test]
+fn interpreters_can_move_to_other_threads() {
+    let stmts = parse("
+        class Counter {
+            init() { this.count = 0; }
+            add(n) { this.count = this.count + n; return this; }
+        }
+        var counter = Counter();
+        for (var i = 1; i <= 100; i = i + 1) counter.add(i);
+        var total = counter.count;
+    ");
+    let mut interp = Interpreter::new();
+    interp.define_native("twice", 1, |args, interp| match &args[0] {
+        RuntimeValue::Number(n) => Ok(RuntimeValue::Number(n * 2.0)),
+        _ => Err(interp.invalid_argument("twice expects a number")),
+    });
+
+    let interp = std::thread::spawn(move || {
+        assert!(interp.execute(&stmts).is_ok());
+        assert!(interp.execute(&parse("var doubled = twice(total);")).is_ok());
+        interp
+    }).join().unwrap();
+
+    assert_number(&interp, "total", 5050.0);
+    assert_number(&interp, "doubled", 10100.0);
+}
+
+#[cfg(feature = "sync-gc")]
+#[test]
+fn collections_walk_long_chains_on_threads_with_small_stacks() {
+    // parsing, running and dropping the chain take a large stack
+    with_large_stack(|| {
+        const DEPTH: usize = 5000;
+        let mut source = String::from("var captured;\n");
+        source.push_str(&"{ var v = 1;\n".repeat(DEPTH));
+        source.push_str("fun get() { return v; }\ncaptured = get;\n");
+        source.push_str(&"}".repeat(DEPTH));
+
+        // the thread leaves the scopes it dropped references to for the next
+        // collection when it ends, wherever that collection runs
+        let mut interp = std::thread::Builder::new()
+            .stack_size(64 * 1024 * 1024)
+            .spawn(move || {
+                let mut interp = Interpreter::new().with_gc_interval(None);
+                assert!(interp.execute(&parse(&source)).is_ok());
+                interp
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+
+        // the default size of the stacks of spawned threads
+        std::thread::Builder::new()
+            .stack_size(2 * 1024 * 1024)
+            .spawn(crate::gc_stats::collect)
+            .unwrap()
+            .join()
+            .unwrap();
+
+        assert!(interp.execute(&parse("var result = captured();")).is_ok());
+        assert_number(&interp, "result", 1.0);
+    });
+}
+
+#[test]
+fn appending_to_a_string_stops_at_the_memory_limit() {
+    let stmts = parse(r#"
+        var piece = "0123456789012345678901234567890123456789012345678901234567890123456789";
+        var s = "";
+        while (true) {
+            s = s + piece;
+        }
+    "#);
+    let mut interp = Interpreter::new().with_max_memory_bytes(100_000);
+    let result = interp.execute(&stmts);
+
+    assert!(matches!(&result, Err(RuntimeError::MemoryLimitExceeded(t)) if t.line == 5 && t.lexeme == "+"));
+    let len = match global(&interp, "s") {
+        RuntimeValue::String(s) => s.len(),
+        _ => panic!("s is not a string"),
+    };
+    assert!(len <= 100_000 && len > 50_000, "stopped at {} bytes", len);
+}
+
+#[test]
+fn appending_to_an_array_stops_at_the_same_point_every_time() {
+    let source = "
+        var a = Array();
+        fun fill() {
+            while (true) push(a, len(a));
+        }
+        try { fill(); } catch (e) { }
+    ";
+    let stopped_at = || {
+        let mut interp = Interpreter::new().with_max_memory_bytes(100_000);
+        let result = interp.execute(&parse(source));
+
+        match result {
+            Err(e @ RuntimeError::MemoryLimitExceeded(_)) => {
+                assert_eq!(e.token().line, 4);
+                assert_eq!(e.message(), "Memory limit exceeded");
+            },
+            _ => panic!("the memory limit was not reached"),
+        }
+        match global(&interp, "a") {
+            RuntimeValue::Array(a) => a.borrow().len(),
+            _ => panic!("a is not an array"),
+        }
+    };
+
+    let len = stopped_at();
+    assert!(len * std::mem::size_of::<RuntimeValue>() <= 100_000 && len > 1000, "stopped at {} elements", len);
+    assert_eq!(stopped_at(), len);
+}
+
+#[test]
+fn cloned_and_parsed_values_count_against_the_memory_limit() {
+    // an array of `ROWS` arrays of `COLUMNS` numbers
+    const ROWS: usize = 50;
+    const COLUMNS: usize = 50;
+    let rows: Vec<RuntimeValue> = (0..ROWS)
+        .map(|_| RuntimeValue::from(vec![RuntimeValue::Number(1.0); COLUMNS]))
+        .collect();
+    let bytes = memory::ARRAY_BYTES + ROWS * (memory::VALUE_BYTES + memory::ARRAY_BYTES + COLUMNS * memory::VALUE_BYTES);
+    let row = format!("[{}]", vec!["1"; COLUMNS].join(","));
+    let document = format!("[{}]", vec![row; ROWS].join(",")).leak();
+
+    // one copy fits under the limit, a second one does not
+    let mut interp = Interpreter::new().with_max_memory_bytes(bytes * 3 / 2);
+    interp.define_global("nested", RuntimeValue::from(rows));
+    let result = interp.execute(&parse("
+        var a = clone(nested);
+        var b = clone(nested);
+    "));
+    assert!(matches!(&result, Err(RuntimeError::MemoryLimitExceeded(t)) if t.line == 3));
+
+    let mut interp = Interpreter::new().with_max_memory_bytes(bytes * 3 / 2);
+    define_document(&mut interp, document);
+    let result = interp.execute(&parse("
+        var a = json_parse(document());
+        var b = json_parse(document());
+    "));
+    assert!(matches!(&result, Err(RuntimeError::MemoryLimitExceeded(t)) if t.line == 3));
+}
+
+#[test]
+fn what_callers_hold_counts_against_the_memory_limit() {
+    // each call holds an array of about a tenth of the limit
+    // while it makes the next one, so recursing far enough fails
+    let source = "
+        fun hold(depth) {
+            var a = Array();
+            for (var i = 0; i < 400; i = i + 1) push(a, i);
+            if (depth > 0) hold(depth - 1);
+        }
+        hold(50);
+    ";
+    let limit = 4000 * memory::VALUE_BYTES;
+
+    let mut interp = Interpreter::new().with_max_memory_bytes(limit);
+    let result = interp.execute(&parse(source));
+    assert!(matches!(&result, Err(RuntimeError::MemoryLimitExceeded(_))), "the limit was not reached");
+
+    // the arrays of calls that returned are not held anymore
+    let mut interp = Interpreter::new().with_max_memory_bytes(limit);
+    assert!(interp.execute(&parse(&source.replace("hold(50);", "for (var i = 0; i < 50; i = i + 1) hold(5);"))).is_ok());
+}
+
+#[test]
+fn new_fields_count_against_the_memory_limit() {
+    let mut interp = Interpreter::new().with_max_memory_bytes(100_000);
+    let result = interp.execute(&parse("
+        class Bag {}
+        var bag = Bag();
+        for (var i = 0; i < 200000; i = i + 1) set_field(bag, \"f\" + tostring(i), i);
+    "));
+    assert!(matches!(&result, Err(RuntimeError::MemoryLimitExceeded(_))));
+
+    // the instances and the array holding them take half of the limit, their fields the rest
+    const COUNT: usize = 100;
+    let limit = 2 * COUNT * (memory::INSTANCE_BYTES + memory::VALUE_BYTES);
+    let source = format!("
+        class Wide {{
+            init() {{
+                this.a = 1; this.b = 2; this.c = 3; this.d = 4; this.e = 5;
+                this.f = 6; this.g = 7; this.h = 8; this.i = 9; this.j = 10;
+            }}
+        }}
+        var all = Array();
+        for (var i = 0; i < {}; i = i + 1) push(all, Wide());
+    ", COUNT);
+    let mut interp = Interpreter::new().with_max_memory_bytes(limit);
+    let result = interp.execute(&parse(&source));
+    assert!(matches!(&result, Err(RuntimeError::MemoryLimitExceeded(t)) if t.line == 4 || t.line == 5));
+
+    // setting a field again costs nothing
+    let mut interp = Interpreter::new().with_max_memory_bytes(100_000);
+    let source = "
+        class Point {}
+        var p = Point();
+        for (var i = 0; i < 100000; i = i + 1) { p.x = i; set_field(p, \"y\", i); }
+    ";
+    assert!(interp.execute(&parse(source)).is_ok());
+}
+
+#[test]
+fn block_scopes_count_against_the_memory_limit() {
+    with_large_stack(|| {
+        // the calls reuse their environments, so only the scopes in them count
+        let limit = 100 * memory::ENV_BYTES;
+        let scopes = [
+            ("{ var a;", "}"),
+            ("for (var c in \"a\") {", "}"),
+            ("try {", "} finally { }"),
+            ("try { throw 1; } catch (e) {", "}"),
+            ("try { } finally {", "}"),
+        ];
+
+        for (open, close) in scopes {
+            let source = format!("fun nest(n) {{ {} if (n > 0) nest(n - 1); {} }} nest(500);", open, close);
+            let mut interp = Interpreter::new().with_max_memory_bytes(limit);
+            let result = interp.execute(&parse(&source));
+            assert!(matches!(&result, Err(RuntimeError::MemoryLimitExceeded(_))), "{}", open);
+        }
+    });
+}
+
+#[test]
+fn programs_under_the_memory_limit_are_unaffected() {
+    let stmts = parse(r#"
+        class Point {
+            init(x, y) { this.x = x; this.y = y; }
+        }
+        fun adder(n) { fun add(x) { return x + n; } return add; }
+        var total = 0;
+        for (var i = 0; i < 2000; i = i + 1) {
+            var points = Array();
+            for (var j = 0; j < 20; j = j + 1) push(points, Point(i, j));
+            var text = "";
+            for (var j = 0; j < 4; j = j + 1) text = text + "0123456789012345678901234567890123456789";
+            total = adder(len(points))(total) + len(text);
+        }
+    "#);
+    // far more than the limit is created over the whole loop,
+    // but only a little of it is held at any time
+    let mut interp = Interpreter::new().with_max_memory_bytes(100_000);
+
+    assert!(interp.execute(&stmts).is_ok());
+    assert_number(&interp, "total", 2000.0 * 180.0);
+}
+
+#[test]
+fn strings_appended_to_in_place_behave_like_values() {
+    let output = SharedOutput::default();
+    let stmts = parse(r#"
+        var s = "ab";
+        var copy = s;
+        for (var i = 0; i < 3; i = i + 1) s = s + "c";
+        var before = s;
+        s = s + "d";
+        print s;
+        print before;
+
+        fun build(n) {
+            var t = "";
+            for (var i = 0; i < n; i = i + 1) t = t + "x";
+            return t;
+        }
+        var built = build(5);
+        var m = Map();
+        map_set(m, built, 1);
+    "#);
+    let mut interp = Interpreter::new().with_output(output.clone());
+
+    assert!(interp.execute(&stmts).is_ok());
+    assert_eq!(output.text(), "\"abcccd\"\n\"abccc\"\n");
+    assert_str(&interp, "copy", "ab");
+    assert_str(&interp, "built", "xxxxx");
+
+    let stmts = parse(r#"
+        var equal = built == "xxx" + "xx";
+        var length = len(built);
+        var found = map_get(m, "xxxxx");
+    "#);
+    assert!(interp.execute(&stmts).is_ok());
+    assert_bool(&interp, "equal", true);
+    assert_number(&interp, "length", 5.0);
+    assert_number(&interp, "found", 1.0);
+}
+
+#[test]
+fn appending_sees_assignments_made_by_the_right_operand() {
+    let stmts = parse(r#"
+        var s = "a";
+        fun change() { s = "z"; return "b"; }
+        s = s + change();
+
+        fun f() {
+            var t = "a";
+            fun keep() { return t; }
+            t = t + "b";
+            var kept = keep;
+            t = t + "c";
+            return kept() + t;
+        }
+        var both = f();
+    "#);
+    let mut interp = Interpreter::new();
+
+    assert!(interp.execute(&stmts).is_ok());
+    // the left operand is read before the right one
+    assert_str(&interp, "s", "ab");
+    assert_str(&interp, "both", "abcabc");
+}
+
+#[test]
+fn shared_methods_still_override_bind_and_reach_super() {
+    let stmts = parse(r#"
+        class A {
+            name() { return "A"; }
+            greet() { return "hi " + this.name(); }
+        }
+        class B < A {
+            name() { return "B" + super.name(); }
+        }
+        class C < B { }
+
+        var a = A();
+        var c = C();
+        var greet_a = a.greet;
+        var greet_c = c.greet;
+        var from_a = greet_a();
+        var from_c = greet_c();
+        var same = c.greet == c.greet;
+        var different = greet_a == greet_c;
+        var again = C().greet();
+    "#);
+    let mut interp = Interpreter::new();
+
+    assert!(interp.execute(&stmts).is_ok());
+    assert_str(&interp, "from_a", "hi A");
+    assert_str(&interp, "from_c", "hi BA");
+    assert_bool(&interp, "same", true);
+    assert_bool(&interp, "different", false);
+    assert_str(&interp, "again", "hi BA");
+}
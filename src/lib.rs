@@ -4,23 +4,239 @@ pub mod parser;
 pub mod statement;
 pub mod interpreter;
 pub mod resolver;
+pub mod ast_printer;
+mod convert;
+mod fields;
+mod gc_stats;
+mod lox;
+mod map;
+mod range;
+mod run;
 
 use scanner::Token;
 use statement::StmtEffect;
 use std::fmt::Display;
-use dumpster::unsync::Gc;
 use interpreter::env::Environment;
-use std::cell::RefCell;
+use std::any::Any;
 use std::collections::HashMap;
+use fields::Fields;
 
-pub trait Callable: dyn_clone::DynClone + Display {
+pub use convert::ConversionError;
+pub use map::{Map, MapKey};
+pub use range::Range;
+pub use gc_stats::GcStats;
+pub use lox::Lox;
+pub use run::{LoxError, Program, parse_source, compile, compile_snippet, run_source, run_source_new, run_snippet};
+pub use shared::{Gc, Rc, RefCell, Cell, OnceCell, MaybeSendSync};
+
+// The pointers and cells values and syntax trees are built from, which the
+// rest of the crate uses instead of those of std and dumpster. By default
+// they are those of a single thread. With the `sync-gc` feature they can be
+// shared between threads, so an interpreter can be moved to another thread,
+// at the cost of locking every borrow.
+#[cfg(not(feature = "sync-gc"))]
+mod shared {
+    pub use std::{
+        cell::{Cell, OnceCell, RefCell},
+        rc::Rc,
+    };
+    pub use dumpster::unsync::{collect, Gc};
+
+    // What natives, callables and output sinks must be to be
+    // kept by an interpreter: anything, on a single thread.
+    pub trait MaybeSendSync {}
+
+    impl<T: ?Sized> MaybeSendSync for T {}
+
+    pub(crate) type DynWrite = dyn std::io::Write;
+    pub(crate) type DynBufRead = dyn std::io::BufRead;
+}
+
+#[cfg(feature = "sync-gc")]
+mod shared {
+    use dumpster::{Trace, Visitor};
+    use std::sync::{
+        Mutex,
+        PoisonError,
+        RwLock,
+        RwLockReadGuard,
+        RwLockWriteGuard,
+        TryLockResult,
+    };
+
+    pub use std::sync::{Arc as Rc, OnceLock as OnceCell};
+    pub use dumpster::sync::{collect, Gc};
+
+    pub trait MaybeSendSync: Send + Sync {}
+
+    impl<T: Send + Sync + ?Sized> MaybeSendSync for T {}
+
+    pub(crate) type DynWrite = dyn std::io::Write + Send + Sync;
+    pub(crate) type DynBufRead = dyn std::io::BufRead + Send + Sync;
+
+    // `std::cell::RefCell` over a lock. Borrowing mutably while the value
+    // is borrowed blocks instead of panicking.
+    #[derive(Default)]
+    pub struct RefCell<T: ?Sized>(RwLock<T>);
+
+    impl<T> RefCell<T> {
+        pub fn new(value: T) -> Self {
+            Self(RwLock::new(value))
+        }
+    }
+
+    impl<T: ?Sized> RefCell<T> {
+        pub fn borrow(&self) -> RwLockReadGuard<'_, T> {
+            self.0.read().unwrap_or_else(PoisonError::into_inner)
+        }
+
+        pub fn borrow_mut(&self) -> RwLockWriteGuard<'_, T> {
+            self.0.write().unwrap_or_else(PoisonError::into_inner)
+        }
+
+        pub fn try_borrow(&self) -> TryLockResult<RwLockReadGuard<'_, T>> {
+            self.0.try_read()
+        }
+    }
+
+    impl<T: Clone> Clone for RefCell<T> {
+        fn clone(&self) -> Self {
+            Self::new(self.borrow().clone())
+        }
+    }
+
+    // The collector walks the objects it examines recursively, a few frames
+    // per object, and the collection runs on whichever thread needs it. The
+    // long chains scripts build, e.g. thousands of nested scopes, would then
+    // overflow the stack of any thread that collects. Every collected object
+    // is behind a `RefCell`, so the walk counts them here and every
+    // `WALK_OBJECTS_PER_THREAD` objects deep carries on on a thread with a
+    // stack of its own, while the thread that got there waits for it. What
+    // the collector does with the objects once walked still recurses through
+    // such chains on the thread that collects, but with little stack per object.
+    const WALK_OBJECTS_PER_THREAD: usize = 128;
+    const WALK_STACK_SIZE: usize = 16 * 1024 * 1024;
+
+    thread_local! {
+        // how deep the walk running on this thread is
+        static WALK_DEPTH: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    }
+
+    // What the walk needs to carry on on another thread. The collector's
+    // visitors are not `Send`, but only one thread uses them at a time,
+    // as the thread that hands them over waits until they are done.
+    struct Handover<T>(T);
+
+    unsafe impl<T> Send for Handover<T> {}
+
+    impl<T> Handover<T> {
+        // Taking the handover whole keeps closures from
+        // capturing only its contents, which are not `Send`.
+        fn into_inner(self) -> T {
+            self.0
+        }
+    }
+
+    unsafe impl<T: Trace + ?Sized> Trace for RefCell<T> {
+        fn accept<V: Visitor>(&self, visitor: &mut V) -> Result<(), ()> {
+            let depth = WALK_DEPTH.get();
+            if depth == WALK_OBJECTS_PER_THREAD {
+                let handover = Handover((self, visitor));
+                return std::thread::scope(|scope| {
+                    let walk = std::thread::Builder::new()
+                        .stack_size(WALK_STACK_SIZE)
+                        .spawn_scoped(scope, move || {
+                            let (cell, visitor) = handover.into_inner();
+                            cell.accept(visitor)
+                        })
+                        .expect("failed to start a thread for the collector");
+
+                    walk.join().unwrap_or_else(|e| std::panic::resume_unwind(e))
+                });
+            }
+
+            WALK_DEPTH.set(depth + 1);
+            let result = self.0.accept(visitor);
+            WALK_DEPTH.set(depth);
+
+            result
+        }
+    }
+
+    // `std::cell::Cell` over a lock.
+    #[derive(Default)]
+    pub struct Cell<T>(Mutex<T>);
+
+    impl<T: Copy> Cell<T> {
+        pub fn new(value: T) -> Self {
+            Self(Mutex::new(value))
+        }
+
+        pub fn get(&self) -> T {
+            *self.0.lock().unwrap_or_else(PoisonError::into_inner)
+        }
+
+        pub fn set(&self, value: T) {
+            *self.0.lock().unwrap_or_else(PoisonError::into_inner) = value;
+        }
+    }
+
+    impl<T: Copy> Clone for Cell<T> {
+        fn clone(&self) -> Self {
+            Self::new(self.get())
+        }
+    }
+}
+
+pub trait Callable: dyn_clone::DynClone + Display + MaybeSendSync {
     fn arity(&self) -> usize;
+
+    // Whether the callable accepts `found` arguments.
+    fn check_arity(&self, found: usize) -> Result<(), ArityError> {
+        if found == self.arity() {
+            Ok(())
+        }
+        else {
+            Err(ArityError { expected: self.arity(), found })
+        }
+    }
+
+    // `receiver` is the instance a method is called on.
     fn call(
         &self,
-        args: &Vec<RuntimeValue>,
+        args: &[RuntimeValue],
         interp: &mut interpreter::Interpreter,
         closure: &Option<Gc<RefCell<Environment>>>,
+        receiver: Option<&Gc<RefCell<Instance>>>,
     ) -> Result<RuntimeValue, RuntimeError>;
+
+    fn as_any(&self) -> &dyn Any;
+
+    // The name shown in stack traces.
+    fn name(&self) -> String {
+        self.to_string()
+    }
+
+    // The token this callable was declared with, if any.
+    fn declaration(&self) -> Option<&Rc<Token>> {
+        None
+    }
+
+    // The number of arguments the callable takes, None if it accepts different numbers.
+    fn fixed_arity(&self) -> Option<usize> {
+        Some(self.arity())
+    }
+
+    // The names of the parameters of callables declared in lox.
+    fn param_names(&self) -> Option<Vec<String>> {
+        None
+    }
+
+    // Whether `other` was created from the same declaration.
+    // Used for equality of callables, so the default is conservative.
+    fn same_declaration(&self, _other: &dyn Callable) -> bool {
+        false
+    }
 }
 
 #[derive(Clone)]
@@ -29,54 +245,385 @@ pub struct CallableWrapper {
     // not behind a `Callable` implementation because
     // then `Callable` cannot be made into an object
     closure: Option<Gc<RefCell<Environment>>>,
+    // the instance a method is bound to,
+    // kept explicitly for equality checks
+    receiver: Option<Gc<RefCell<Instance>>>,
+}
+
+impl CallableWrapper {
+    pub fn receiver(&self) -> Option<&Gc<RefCell<Instance>>> {
+        self.receiver.as_ref()
+    }
+
+    // Binding a method does not change the arguments it takes.
+    pub fn fixed_arity(&self) -> Option<usize> {
+        self.callable.fixed_arity()
+    }
+
+    pub fn param_names(&self) -> Option<Vec<String>> {
+        self.callable.param_names()
+    }
+
+    // The token the callable was declared with, None for natives.
+    pub fn declaration(&self) -> Option<&Rc<Token>> {
+        self.callable.declaration()
+    }
+
+    // Two callables are equal when they come from the same declaration and
+    // - are bound to the same instance, if they are bound methods
+    // - share the same closure, otherwise
+    pub fn is_same(&self, other: &CallableWrapper) -> bool {
+        if !self.callable.same_declaration(other.callable.as_ref()) {
+            return false;
+        }
+
+        match (&self.receiver, &other.receiver) {
+            (Some(a), Some(b)) => Gc::ptr_eq(a, b),
+            (None, None) => match (&self.closure, &other.closure) {
+                (Some(a), Some(b)) => Gc::ptr_eq(a, b),
+                (None, None) => true,
+                _ => false,
+            },
+            _ => false,
+        }
+    }
 }
 
 dyn_clone::clone_trait_object!(Callable);
 
+// A callable was given `found` arguments. `expected` is
+// the closest number of arguments it would have accepted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ArityError {
+    pub expected: usize,
+    pub found: usize,
+}
+
+impl ArityError {
+    pub fn at(self, right_paren: &Rc<Token>) -> RuntimeError {
+        RuntimeError::CallableArityMismatch {
+            right_paren: right_paren.clone(),
+            expected: self.expected,
+            found: self.found,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub enum RuntimeValue {
     Nil,
     Bool(bool),
     Number(f64),
-    String(String),
+    // shared by every value it is copied to, as strings cannot change.
+    // A string nothing else holds may be appended to in place, see `concat`.
+    String(Rc<String>),
     Callable(CallableWrapper),
     Class(Gc<RefCell<Class>>),
     Instance(Gc<RefCell<Instance>>),
+    // shared by every value it is copied to, like instances
+    Array(Gc<RefCell<Vec<RuntimeValue>>>),
+    Map(Gc<RefCell<Map>>),
+    // compared by its bounds and step, like numbers
+    Range(Range),
 }
 
 #[derive(Debug)]
 pub enum RuntimeError {
-    UnknownUnaryExpression(Token),
-    UnknownBinaryExpression(Token),
-    UnaryMinusExpectsNumber(Token),
-    BinaryOperatorExpectsNumbers(Token),
-    BinaryPlusExpectsTwoNumbersOrTwoStrings(Token),
-    DivisionByZero(Token),
-    UndefinedVariable(Token),
-    NonCallableCalled(Token),
+    UnknownUnaryExpression(Rc<Token>),
+    UnknownBinaryExpression(Rc<Token>),
+    UnaryMinusExpectsNumber(Rc<Token>),
+    BinaryOperatorExpectsNumbers(Rc<Token>),
+    BinaryPlusExpectsTwoNumbersOrTwoStrings(Rc<Token>),
+    DivisionByZero(Rc<Token>),
+    UndefinedVariable(Rc<Token>),
+    NonCallableCalled(Rc<Token>),
     CallableArityMismatch{
-        right_paren: Token,
+        right_paren: Rc<Token>,
         expected: usize,
         found: usize,
     },
-    OnlyInstancesHaveProperties(Token),
-    UndefinedProperty(Token),
-    SuperClassMustBeAClass(Token),
+    OnlyInstancesHaveProperties(Rc<Token>),
+    UndefinedProperty(Rc<Token>),
+    SuperClassMustBeAClass(Rc<Token>),
+    StackOverflow(Rc<Token>),
+    // only reachable when executing unresolved statements
+    BreakOutsideLoop(Rc<Token>),
+    // 'super' or 'this' did not evaluate to the expected kind of value,
+    // only reachable when executing unresolved statements
+    SuperNotAClass(Rc<Token>),
+    ThisNotAnInstance(Rc<Token>),
+    IsOperandMustBeClass(Rc<Token>),
+    ToStringMustReturnString(Rc<Token>),
+    // raised by natives given arguments they cannot handle
+    InvalidArgument {
+        call_site: Rc<Token>,
+        message: String,
+    },
+    // a thrown value that was not caught
+    // inside the function it was thrown in
+    Thrown {
+        keyword: Rc<Token>,
+        value: Box<RuntimeValue>,
+    },
+    AssertionFailed {
+        keyword: Rc<Token>,
+        message: Option<String>,
+    },
+    // raised once the step limit of the interpreter is used up
+    ExecutionBudgetExceeded(Rc<Token>),
+    // a condition or logical operand that is
+    // not a boolean while booleans are strict
+    ConditionMustBeBoolean(Rc<Token>),
+    // the input could not be read or the output could not be written
+    IoError {
+        token: Rc<Token>,
+        error: std::io::Error,
+    },
+    // raised by 'error', whose message is reported as it is
+    UserError {
+        token: Rc<Token>,
+        message: String,
+    },
+    // raised by 'json_parse', with the byte offset in the document
+    InvalidJson {
+        call_site: Rc<Token>,
+        offset: usize,
+        message: String,
+    },
+    // raised by 'exit' to stop the script
+    Exit {
+        call_site: Rc<Token>,
+        code: i32,
+    },
+    // negative indices are out of range too
+    IndexOutOfRange {
+        index: i64,
+        len: usize,
+        bracket: Rc<Token>,
+    },
+    IndexMustBeInteger(Rc<Token>),
+    StringsAreImmutable(Rc<Token>),
+    NotIndexable(Rc<Token>),
+    NotIterable(Rc<Token>),
+    // 'iter' returned something other than an instance with a 'next' method
+    IteratorWithoutNext(Rc<Token>),
+    // raised once the host sets the cancellation flag of the interpreter
+    Cancelled(Rc<Token>),
+    // raised where an object would take the script over
+    // the memory limit of the interpreter
+    MemoryLimitExceeded(Rc<Token>),
+}
+
+impl RuntimeError {
+    // The token the error is reported at.
+    pub fn token(&self) -> &Token {
+        match self {
+            RuntimeError::UnknownUnaryExpression(token) |
+            RuntimeError::UnknownBinaryExpression(token) |
+            RuntimeError::UnaryMinusExpectsNumber(token) |
+            RuntimeError::BinaryOperatorExpectsNumbers(token) |
+            RuntimeError::BinaryPlusExpectsTwoNumbersOrTwoStrings(token) |
+            RuntimeError::DivisionByZero(token) |
+            RuntimeError::UndefinedVariable(token) |
+            RuntimeError::NonCallableCalled(token) |
+            RuntimeError::OnlyInstancesHaveProperties(token) |
+            RuntimeError::UndefinedProperty(token) |
+            RuntimeError::SuperClassMustBeAClass(token) |
+            RuntimeError::StackOverflow(token) |
+            RuntimeError::BreakOutsideLoop(token) |
+            RuntimeError::SuperNotAClass(token) |
+            RuntimeError::ThisNotAnInstance(token) |
+            RuntimeError::IsOperandMustBeClass(token) |
+            RuntimeError::ToStringMustReturnString(token) |
+            RuntimeError::ExecutionBudgetExceeded(token) |
+            RuntimeError::ConditionMustBeBoolean(token) |
+            RuntimeError::IndexMustBeInteger(token) |
+            RuntimeError::StringsAreImmutable(token) |
+            RuntimeError::NotIndexable(token) |
+            RuntimeError::NotIterable(token) |
+            RuntimeError::IteratorWithoutNext(token) |
+            RuntimeError::Cancelled(token) |
+            RuntimeError::MemoryLimitExceeded(token) => token,
+            RuntimeError::CallableArityMismatch { right_paren, .. } => right_paren,
+            RuntimeError::IndexOutOfRange { bracket, .. } => bracket,
+            RuntimeError::Thrown { keyword, .. } |
+            RuntimeError::AssertionFailed { keyword, .. } |
+            RuntimeError::IoError { token: keyword, .. } |
+            RuntimeError::UserError { token: keyword, .. } => keyword,
+            RuntimeError::InvalidArgument { call_site, .. } |
+            RuntimeError::InvalidJson { call_site, .. } |
+            RuntimeError::Exit { call_site, .. } => call_site,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            RuntimeError::UnknownUnaryExpression(_) => {
+                "Unknown unary expression".to_owned()
+            },
+            RuntimeError::UnknownBinaryExpression(_) => {
+                "Unknown binary expression".to_owned()
+            },
+            RuntimeError::UnaryMinusExpectsNumber(_) => {
+                "Unary '-' expects number".to_owned()
+            },
+            RuntimeError::BinaryOperatorExpectsNumbers(token) => {
+                format!("Operator '{:?}' expects numbers", token.token_type)
+            },
+            RuntimeError::BinaryPlusExpectsTwoNumbersOrTwoStrings(_) => {
+                "'+' expects two numbers or two strings".to_owned()
+            },
+            RuntimeError::DivisionByZero(_) => {
+                "Division by zero".to_owned()
+            },
+            RuntimeError::UndefinedVariable(token) => {
+                format!("Undefined variable '{}'", token.lexeme)
+            },
+            RuntimeError::NonCallableCalled(_) => {
+                "Non-callable called".to_owned()
+            },
+            RuntimeError::CallableArityMismatch { expected, found, .. } => {
+                format!("Arity mismatch. Expected {} arguments, found {}", expected, found)
+            },
+            RuntimeError::OnlyInstancesHaveProperties(_) => {
+                "Only instances have properties".to_owned()
+            },
+            RuntimeError::UndefinedProperty(token) => {
+                format!("Undefined property '{}'", &token.lexeme)
+            },
+            RuntimeError::SuperClassMustBeAClass(token) => {
+                format!("Superclass must be a class: '{}'", &token.lexeme)
+            },
+            RuntimeError::StackOverflow(_) => {
+                "Stack overflow".to_owned()
+            },
+            RuntimeError::BreakOutsideLoop(_) => {
+                "'break' outside of a loop".to_owned()
+            },
+            RuntimeError::SuperNotAClass(_) => {
+                "'super' is not a class".to_owned()
+            },
+            RuntimeError::ThisNotAnInstance(_) => {
+                "'this' is not an instance".to_owned()
+            },
+            RuntimeError::IsOperandMustBeClass(_) => {
+                "Right operand of 'is' must be a class or a builtin type".to_owned()
+            },
+            RuntimeError::ToStringMustReturnString(_) => {
+                "'toString' must return a string".to_owned()
+            },
+            RuntimeError::Thrown { value, .. } => {
+                format!("Uncaught exception {}", value)
+            },
+            RuntimeError::InvalidArgument { message, .. } |
+            RuntimeError::UserError { message, .. } => {
+                message.clone()
+            },
+            RuntimeError::AssertionFailed { message: Some(message), .. } => {
+                format!("Assertion failed: {}", message)
+            },
+            RuntimeError::AssertionFailed { message: None, .. } => {
+                "Assertion failed".to_owned()
+            },
+            RuntimeError::ExecutionBudgetExceeded(_) => {
+                "Execution step budget exceeded".to_owned()
+            },
+            RuntimeError::ConditionMustBeBoolean(_) => {
+                "Condition must be a boolean".to_owned()
+            },
+            RuntimeError::IoError { error, .. } => {
+                format!("I/O error: {}", error)
+            },
+            RuntimeError::InvalidJson { offset, message, .. } => {
+                format!("Invalid JSON at byte {}: {}", offset, message)
+            },
+            RuntimeError::Exit { code, .. } => {
+                format!("Exited with code {}", code)
+            },
+            RuntimeError::IndexOutOfRange { index, len, .. } => {
+                format!("Index {} out of range for length {}", index, len)
+            },
+            RuntimeError::IndexMustBeInteger(_) => {
+                "Index must be an integer".to_owned()
+            },
+            RuntimeError::StringsAreImmutable(_) => {
+                "Strings are immutable".to_owned()
+            },
+            RuntimeError::NotIndexable(_) => {
+                "Only strings and arrays can be indexed".to_owned()
+            },
+            RuntimeError::NotIterable(_) => {
+                "Only arrays, maps, strings and instances with an 'iter' method can be iterated".to_owned()
+            },
+            RuntimeError::IteratorWithoutNext(_) => {
+                "'iter' must return an instance with a 'next' method".to_owned()
+            },
+            RuntimeError::Cancelled(_) => {
+                "Execution cancelled".to_owned()
+            },
+            RuntimeError::MemoryLimitExceeded(_) => {
+                "Memory limit exceeded".to_owned()
+            },
+        }
+    }
+
+    // Whether a 'try' can catch the error.
+    pub fn is_catchable(&self) -> bool {
+        !matches!(
+            self,
+            RuntimeError::StackOverflow(_) |
+            RuntimeError::ExecutionBudgetExceeded(_) |
+            RuntimeError::Exit { .. } |
+            RuntimeError::Cancelled(_) |
+            RuntimeError::MemoryLimitExceeded(_)
+        )
+    }
 }
 
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let token = self.token();
+        write!(f, "Runtime error at line {}, column {}: {}.", token.line, token.column, self.message())
+    }
+}
+
+impl std::error::Error for RuntimeError { }
+
 pub type RuntimeResult = Result<RuntimeValue, RuntimeError>;
 
-pub fn is_truthy(value: &RuntimeValue) -> bool {
-    match value {
-        RuntimeValue::Nil => false,
-        RuntimeValue::Bool(b) => *b,
-        _ => true,
+// Which values count as true in conditions and logical operators.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Truthiness {
+    // only nil and false are falsy
+    #[default]
+    Lox,
+    // 0, "", empty arrays and empty maps are falsy too
+    CStyle,
+}
+
+impl Truthiness {
+    pub fn is_truthy(&self, value: &RuntimeValue) -> bool {
+        match (self, value) {
+            (_, RuntimeValue::Nil) => false,
+            (_, RuntimeValue::Bool(b)) => *b,
+            (Truthiness::CStyle, RuntimeValue::Number(n)) => *n != 0_f64,
+            (Truthiness::CStyle, RuntimeValue::String(s)) => !s.is_empty(),
+            (Truthiness::CStyle, RuntimeValue::Array(a)) => !a.borrow().is_empty(),
+            (Truthiness::CStyle, RuntimeValue::Map(m)) => !m.borrow().is_empty(),
+            (Truthiness::CStyle, RuntimeValue::Range(r)) => !r.is_empty(),
+            _ => true,
+        }
     }
 }
 
+pub fn is_truthy(value: &RuntimeValue) -> bool {
+    Truthiness::Lox.is_truthy(value)
+}
+
 #[derive(Clone)]
 pub struct Function {
-    pub decl: statement::Function,
+    // shared by every copy of the function
+    pub decl: Rc<statement::Function>,
     is_initializer: bool,
 }
 
@@ -84,7 +631,12 @@ pub struct Function {
 pub struct Class {
     pub name: String,
     super_class: Option<Gc<RefCell<Class>>>,
-    methods: HashMap<String, CallableWrapper>,
+    // shared with whatever looks them up, which only
+    // copies a method when it binds it to an instance
+    methods: HashMap<String, Rc<CallableWrapper>>,
+    // The inherited methods found so far. Classes do not change once
+    // they are created, so what is found stays valid.
+    inherited: RefCell<HashMap<String, Rc<CallableWrapper>>>,
 }
 
 impl Class {
@@ -93,36 +645,81 @@ impl Class {
         super_class: Option<Gc<RefCell<Class>>>,
         methods: HashMap<String, CallableWrapper>,
     ) -> Self {
+        gc_stats::class_created();
         Self {
             name: name.to_owned(),
             super_class,
-            methods,
+            methods: methods.into_iter().map(|(name, method)| (name, Rc::new(method))).collect(),
+            inherited: RefCell::new(HashMap::new()),
         }
     }
 
-    pub fn find_method(&self, name: &str) -> Option<CallableWrapper> {
-        let mut method = self.methods.get(name).map(|m| m.clone());
-        if method.is_none() {
-            if let Some(sup) = &self.super_class {
-                method = sup.borrow().find_method(name);
-            }
+    pub fn super_class(&self) -> Option<&Gc<RefCell<Class>>> {
+        self.super_class.as_ref()
+    }
+
+    // The 'init' method declared in the class itself, which calls of the class run.
+    pub fn initializer(&self) -> Option<&CallableWrapper> {
+        self.methods.get("init").map(|init| &**init)
+    }
+
+    // The names of the methods declared in the class itself, in no particular order.
+    pub fn method_names(&self) -> impl Iterator<Item = &String> {
+        self.methods.keys()
+    }
+
+    pub fn find_method(&self, name: &str) -> Option<Rc<CallableWrapper>> {
+        if let Some(method) = self.methods.get(name) {
+            return Some(method.clone());
+        }
+        if let Some(method) = self.inherited.borrow().get(name) {
+            return Some(method.clone());
+        }
+
+        let method = self.super_class.as_ref()?.borrow().find_method(name)?;
+        self.inherited.borrow_mut().insert(name.to_owned(), method.clone());
+
+        Some(method)
+    }
+}
+
+// The method a property access found last, along with the class it was
+// found in. Accesses to instances of the same class find the same method,
+// as classes do not change once they are created. Holding on to the class
+// keeps any other class from being created at its address. Boxed, as it
+// is rarely set and would otherwise make every expression larger.
+#[derive(Clone, Default)]
+pub struct MethodCache(RefCell<Option<Box<CachedMethod>>>);
+
+type CachedMethod = (Gc<RefCell<Class>>, Rc<CallableWrapper>);
+
+impl MethodCache {
+    pub fn get(&self, class: &Gc<RefCell<Class>>) -> Option<Rc<CallableWrapper>> {
+        match &*self.0.borrow() {
+            Some(entry) if Gc::ptr_eq(&entry.0, class) => Some(entry.1.clone()),
+            _ => None,
         }
+    }
 
-        method
+    pub fn set(&self, class: &Gc<RefCell<Class>>, method: &Rc<CallableWrapper>) {
+        *self.0.borrow_mut() = Some(Box::new((class.clone(), method.clone())));
     }
 }
 
 #[derive(Clone)]
 pub struct Instance {
     class: Gc<RefCell<Class>>,
-    fields: HashMap<String, RuntimeValue>, 
+    // kept in insertion order so they can be enumerated deterministically
+    fields: Fields,
+    _tracked: gc_stats::Tracked,
 }
 
 impl Instance {
     pub fn new(class: &Gc<RefCell<Class>>) -> Self {
         Self {
             class: class.clone(),
-            fields: HashMap::new(),
+            fields: Fields::default(),
+            _tracked: gc_stats::Tracked::new(gc_stats::Kind::Instance),
         }
     }
 
@@ -140,8 +737,33 @@ impl Instance {
             })
     }
 
+    pub fn class(&self) -> &Gc<RefCell<Class>> {
+        &self.class
+    }
+
+    // Only fields, unlike `get`, which also finds methods.
+    pub fn field(&self, name: &str) -> Option<&RuntimeValue> {
+        self.fields.get(name)
+    }
+
+    pub fn has_field(&self, name: &str) -> bool {
+        self.fields.contains_key(name)
+    }
+
+    // Setting an existing field keeps its position.
     pub fn set(&mut self, name: &str, v: &RuntimeValue) {
-        self.fields.insert(name.to_owned(), v.clone());
+        self.fields.insert(name, v.clone());
+    }
+
+    // The fields in the order they were first set.
+    pub fn fields(&self) -> impl Iterator<Item = (&String, &RuntimeValue)> {
+        self.fields.iter()
+    }
+
+    // Removes a field, returning whether it existed.
+    // Methods are not fields and cannot be removed.
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.fields.remove(name)
     }
 }
 
@@ -149,19 +771,11 @@ pub fn bind_method(
     callable_wrapper: &CallableWrapper,
     instance: &Gc<RefCell<Instance>>,
 ) -> CallableWrapper {
-    let mut env = match &callable_wrapper.closure {
-        Some(closure) => Environment::child(closure.clone()),
-        None => {
-            panic!("Trying to bind a global function.");
-        }
-    };
-
-    env.define("this", &RuntimeValue::Instance(instance.clone()));
-    let env = Gc::new(RefCell::new(env));
-
+    // 'this' is defined when the method is called
     CallableWrapper {
-        closure: Some(env),
+        closure: callable_wrapper.closure.clone(),
         callable: callable_wrapper.callable.clone(),
+        receiver: Some(instance.clone()),
     }
 }
 
@@ -188,13 +802,84 @@ impl Callable for Function {
         self.decl.params.len()
     }
 
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> String {
+        self.decl.name.lexeme.clone()
+    }
+
+    fn declaration(&self) -> Option<&Rc<Token>> {
+        Some(&self.decl.name)
+    }
+
+    fn param_names(&self) -> Option<Vec<String>> {
+        Some(self.decl.params.iter().map(|p| p.lexeme.clone()).collect())
+    }
+
+    fn same_declaration(&self, other: &dyn Callable) -> bool {
+        match other.as_any().downcast_ref::<Function>() {
+            Some(f) => Rc::ptr_eq(&self.decl, &f.decl),
+            None => false,
+        }
+    }
+
     fn call(
         &self,
-        args: &Vec<RuntimeValue>,
+        args: &[RuntimeValue],
         interp: &mut interpreter::Interpreter,
-        closure: &Option<Gc<RefCell<Environment>>>
+        closure: &Option<Gc<RefCell<Environment>>>,
+        receiver: Option<&Gc<RefCell<Instance>>>,
         ) -> Result<RuntimeValue, RuntimeError> {
+        let mut outcome = self.execute_body(args, interp, closure, receiver)?;
+
+        // calls in tail position reuse the current frame
+        // instead of recursing into `Callable::call`
+        loop {
+            match outcome {
+                CallOutcome::Value(v) => return Ok(v),
+                CallOutcome::TailCall(tail) => {
+                    let function = tail.callee.callable
+                        .as_any()
+                        .downcast_ref::<Function>()
+                        .expect("tail call to a non-lox function");
+
+                    interp.replace_frame(function, &tail.args, &tail.call_site)?;
+                    outcome = function.execute_body(
+                        &tail.args,
+                        interp,
+                        &tail.callee.closure,
+                        tail.callee.receiver.as_ref(),
+                    )?;
+                },
+            }
+        }
+    }
+}
+
+enum CallOutcome {
+    Value(RuntimeValue),
+    TailCall(statement::TailCall),
+}
+
+impl Function {
+    fn execute_body(
+        &self,
+        args: &[RuntimeValue],
+        interp: &mut interpreter::Interpreter,
+        closure: &Option<Gc<RefCell<Environment>>>,
+        receiver: Option<&Gc<RefCell<Instance>>>,
+        ) -> Result<CallOutcome, RuntimeError> {
+        // nothing can refer to the environment of a call that creates
+        // no closures once it returns, so it is reused by later calls
+        let reuse_env = !self.decl.creates_closures && closure.is_some();
+        if !reuse_env {
+            let bytes = interpreter::ENV_BYTES + args.len() * interpreter::VALUE_BYTES;
+            interp.charge_memory(bytes, None)?;
+        }
         let fun_env = match closure {
+            Some(c) if reuse_env => interp.call_env(c),
             Some(c) => {
                 Gc::new(RefCell::new(
                     Environment::child(c.clone())
@@ -207,46 +892,57 @@ impl Callable for Function {
             }
         };
 
+        // methods get 'this' in the same environment as their parameters
+        if let Some(instance) = receiver {
+            fun_env.borrow_mut().define("this", RuntimeValue::Instance(instance.clone()));
+        }
+
         for (i, a) in args.iter().enumerate() {
             let name = &self.decl.params[i].lexeme;
-            fun_env.borrow_mut().define(name, a);
+            fun_env.borrow_mut().define(name, a.clone());
+        }
+
+        let effect = interp.execute_block(&self.decl.body, fun_env.clone());
+        if reuse_env {
+            interp.recycle_env(fun_env);
         }
 
-        let effect = interp.execute_block(&self.decl.body, fun_env)?;
-        match effect {
-            Some(StmtEffect::Break) => panic!("break propagated to fuction"),
+        match effect? {
+            Some(StmtEffect::Break(keyword)) => {
+                Err(RuntimeError::BreakOutsideLoop(keyword))
+            },
+            Some(StmtEffect::TailCall(tail)) => {
+                Ok(CallOutcome::TailCall(tail))
+            },
+            Some(StmtEffect::Throw(value, keyword)) => {
+                Err(RuntimeError::Thrown { keyword, value: Box::new(value) })
+            },
             Some(StmtEffect::Return(v)) => {
                 if self.is_initializer {
                     // workaround: initializer must always return 'this'
-                    if let Some(cl) = closure {
-                        let instance = cl.borrow()
-                            .get("this")
-                            .expect("initializer closure without 'this'");
-                        return Ok(instance);
+                    if let Some(instance) = receiver {
+                        Ok(CallOutcome::Value(RuntimeValue::Instance(instance.clone())))
                     }
                     else {
-                        panic!("initializer without closure");
+                        panic!("initializer called without an instance");
                     }
                 }
                 else {
-                    return Ok(v)
+                    Ok(CallOutcome::Value(v))
                 }
             }
             None => {
                 if self.is_initializer {
                     // workaround: initializer must always return 'this'
-                    if let Some(cl) = closure {
-                        let instance = cl.borrow()
-                            .get("this")
-                            .expect("initializer closure without 'this'");
-                        return Ok(instance);
+                    if let Some(instance) = receiver {
+                        Ok(CallOutcome::Value(RuntimeValue::Instance(instance.clone())))
                     }
                     else {
-                        panic!("initializer without closure");
+                        panic!("initializer called without an instance");
                     }
                 }
                 else {
-                    return Ok(RuntimeValue::Nil)
+                    Ok(CallOutcome::Value(RuntimeValue::Nil))
                 }
             }
         }
@@ -255,27 +951,91 @@ impl Callable for Function {
 
 impl Display for RuntimeValue {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.fmt_nested(f, &mut Vec::new())
+    }
+}
+
+impl RuntimeValue {
+    // `open` holds the arrays and maps being shown, which are
+    // shown as `[...]` and `{...}` where they contain themselves.
+    fn fmt_nested(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        open: &mut Vec<*const ()>,
+    ) -> std::fmt::Result {
         match self {
             RuntimeValue::Nil => write!(f, "nil"),
             RuntimeValue::Bool(b) => write!(f, "{}", b),
+            RuntimeValue::Number(n) if n.is_nan() => write!(f, "nan"),
             RuntimeValue::Number(n) => write!(f, "{}", n),
             RuntimeValue::String(s) => write!(f, "\"{}\"", s),
-            RuntimeValue::Callable(CallableWrapper { closure: _, callable }) => callable.fmt(f),
+            RuntimeValue::Callable(CallableWrapper { callable, .. }) => callable.fmt(f),
             RuntimeValue::Class(c) => c.borrow().fmt(f),
             RuntimeValue::Instance(i) => i.borrow().fmt(f),
+            RuntimeValue::Range(range) => range.fmt(f),
+            RuntimeValue::Array(array) => {
+                let ptr = Gc::as_ptr(array) as *const ();
+                if open.contains(&ptr) {
+                    return write!(f, "[...]");
+                }
+
+                open.push(ptr);
+                write!(f, "[")?;
+                for (i, element) in array.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    element.fmt_nested(f, open)?;
+                }
+                open.pop();
+                write!(f, "]")
+            },
+            RuntimeValue::Map(map) => {
+                let ptr = Gc::as_ptr(map) as *const ();
+                if open.contains(&ptr) {
+                    return write!(f, "{{...}}");
+                }
+
+                open.push(ptr);
+                write!(f, "{{")?;
+                for (i, (key, value)) in map.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: ", key.to_value())?;
+                    value.fmt_nested(f, open)?;
+                }
+                open.pop();
+                write!(f, "}}")
+            },
         }
     }
 }
 
+impl std::fmt::Debug for RuntimeValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
 unsafe impl dumpster::Trace for RuntimeValue {
     fn accept<V: dumpster::Visitor>(&self, visitor: &mut V) -> Result<(), ()> {
         match self {
             RuntimeValue::Callable(c) => { 
                 c.accept(visitor)?
             },
+            RuntimeValue::Class(class) => {
+                class.accept(visitor)?
+            },
             RuntimeValue::Instance(instance) => {
                 instance.accept(visitor)?
             },
+            RuntimeValue::Array(array) => {
+                array.accept(visitor)?
+            },
+            RuntimeValue::Map(map) => {
+                map.accept(visitor)?
+            },
             _ => {},
         }
 
@@ -283,20 +1043,33 @@ unsafe impl dumpster::Trace for RuntimeValue {
     }
 }
 
+// Functions reach their environment only through `closure`. The callable
+// is not visited: natives hold no objects, and the receiver a bound
+// primitive method keeps is seen as referenced from outside the heap,
+// so it is kept alive rather than ever freed too early.
 unsafe impl dumpster::Trace for CallableWrapper {
     fn accept<V: dumpster::Visitor>(&self, visitor: &mut V) -> Result<(), ()> {
         if let Some(cl) = &self.closure {
             cl.accept(visitor)?
         }
+        if let Some(r) = &self.receiver {
+            r.accept(visitor)?
+        }
         Ok(())
     }
 }
 
 unsafe impl dumpster::Trace for Class {
     fn accept<V: dumpster::Visitor>(&self, visitor: &mut V) -> Result<(), ()> {
+        if let Some(super_class) = &self.super_class {
+            super_class.accept(visitor)?;
+        }
         for (_, value) in &self.methods {
-            value.accept(visitor)?;
+            (**value).accept(visitor)?;
         }
+        // the inherited methods are the ones of the super classes, which
+        // are traced through them, as visiting them twice would count
+        // the Gc pointers in them twice
 
         Ok(())
     }
@@ -306,7 +1079,7 @@ unsafe impl dumpster::Trace for Instance {
     fn accept<V: dumpster::Visitor>(&self, visitor: &mut V) -> Result<(), ()> {
         self.class.accept(visitor)?;
 
-        for (_, value) in &self.fields {
+        for (_, value) in self.fields.iter() {
             value.accept(visitor)?;
         }
 
@@ -0,0 +1,116 @@
+use crate::{
+    interpreter::Interpreter,
+    resolver::{Resolver, Warning},
+    compile,
+    compile_snippet,
+    LoxError,
+    MaybeSendSync,
+    RuntimeError,
+    RuntimeValue,
+};
+use std::io::Write;
+
+// An interpreter with the resolver programs go through before it runs them.
+// What a program defines stays defined for the programs run after it, like
+// in the REPL. The resolver keeps no state between programs, as every scope
+// and context it enters is left by the end of each one, failed or not.
+pub struct Lox {
+    resolver: Resolver,
+    interp: Interpreter,
+    // what the resolver warned about in the last program
+    warnings: Vec<Warning>,
+}
+
+impl Lox {
+    pub fn new() -> Self {
+        Self::with_interpreter(Interpreter::new())
+    }
+
+    // Runs programs with `interp`, e.g. one made with `Interpreter::with_options`.
+    pub fn with_interpreter(interp: Interpreter) -> Self {
+        Self {
+            resolver: Resolver::new(),
+            interp,
+            warnings: Vec::new(),
+        }
+    }
+
+    pub fn with_output(mut self, output: impl Write + MaybeSendSync + 'static) -> Self {
+        self.interp = self.interp.with_output(output);
+        self
+    }
+
+    pub fn with_step_limit(mut self, limit: u64) -> Self {
+        self.interp = self.interp.with_step_limit(limit);
+        self
+    }
+
+    pub fn with_strict_booleans(mut self, strict: bool) -> Self {
+        self.interp = self.interp.with_strict_booleans(strict);
+        self
+    }
+
+    // Like `run_source`, with the resolver and interpreter of this session.
+    pub fn run(&mut self, source: &str) -> Result<Option<RuntimeValue>, LoxError> {
+        self.warnings.clear();
+        let program = compile(source, &mut self.resolver)?;
+        self.warnings = program.warnings;
+
+        let result = self.interp.run(&program.statements);
+        Ok(result.map_err(|e| self.interp.traced(e))?)
+    }
+
+    // Runs one input of the REPL: like `run`, with the last semicolon optional
+    // as in `compile_snippet`. The value to echo is also kept in the global
+    // `_`, so the next input can use it; inputs without one leave `_` as it is.
+    pub fn run_repl_input(&mut self, source: &str) -> Result<Option<RuntimeValue>, LoxError> {
+        self.warnings.clear();
+        let program = compile_snippet(source, &mut self.resolver)?;
+        self.warnings = program.warnings;
+
+        let result = self.interp.run(&program.statements);
+        let value = result.map_err(|e| self.interp.traced(e))?;
+        if let Some(value) = &value {
+            self.interp.define_global("_", value.clone());
+        }
+
+        Ok(value)
+    }
+
+    // The warnings found in the last program run, if it compiled.
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    pub fn define_global(&mut self, name: &str, value: RuntimeValue) {
+        self.interp.define_global(name, value);
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<RuntimeValue> {
+        self.interp.get_global(name)
+    }
+
+    // See `Interpreter::define_native`.
+    pub fn define_native(
+        &mut self,
+        name: &str,
+        arity: usize,
+        f: impl Fn(&[RuntimeValue], &mut Interpreter) -> Result<RuntimeValue, RuntimeError> + MaybeSendSync + 'static,
+    ) {
+        self.interp.define_native(name, arity, f);
+    }
+
+    pub fn interpreter(&self) -> &Interpreter {
+        &self.interp
+    }
+
+    pub fn interpreter_mut(&mut self) -> &mut Interpreter {
+        &mut self.interp
+    }
+}
+
+impl Default for Lox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
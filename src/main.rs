@@ -1,69 +1,207 @@
 mod error;
 
 use rlox::{
-    interpreter::Interpreter,
-    parser::Parser,
-    resolver::Resolver,
-    statement::Stmt,
+    interpreter::{
+        Interpreter,
+        Traced,
+        env::format_snapshot,
+        profile::ProfileEntry,
+    },
+    resolver::{Resolver, Warning},
+    ast_printer::render_program,
     scanner,
-    statement,
+    parse_source,
+    compile,
+    run_snippet,
+    Lox,
+    LoxError,
     RuntimeError,
 };
 use std::{
     env, 
-    path::PathBuf,
+    io::Read,
+    path::{Path, PathBuf},
 };
 
 use error::Error;
 
+const USAGE: &str = "[--trace] [--profile] [--ast[=resolved] | --tokens] [script | - | -e code...]";
+
+// What to do with the script.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    Run,
+    // print the program instead of running it, resolved or as parsed
+    Ast { resolved: bool },
+    // print the tokens of the program instead of parsing it
+    Tokens,
+}
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    trace: bool,
+    profile: bool,
+    mode: Mode,
+    // a path, or `-` for stdin; the REPL starts without one
+    script: Option<String>,
+    // the code given with each `-e`, run instead of a script
+    code: Vec<String>,
+}
+
+// The options given by `args`, without the name of the program.
+// None if they are not understood.
+fn parse_args(args: &[String]) -> Option<Options> {
+    let mut options = Options {
+        trace: false,
+        profile: false,
+        mode: Mode::Run,
+        script: None,
+        code: Vec::new(),
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--trace" => options.trace = true,
+            "--profile" => options.profile = true,
+            "--ast" => options.mode = Mode::Ast { resolved: false },
+            "--ast=resolved" => options.mode = Mode::Ast { resolved: true },
+            "--tokens" => options.mode = Mode::Tokens,
+            "-e" => options.code.push(args.next()?.clone()),
+            flag if flag.starts_with('-') && flag != "-" => return None,
+            _ if options.script.is_some() => return None,
+            script => options.script = Some(script.to_owned()),
+        }
+    }
+
+    if options.script.is_some() && !options.code.is_empty() {
+        return None;
+    }
+    if options.mode != Mode::Run && options.script.is_none() && options.code.is_empty() {
+        return None;
+    }
+
+    Some(options)
+}
+
 fn main() -> Result<(), Error> {
     let args: Vec<String> = env::args().collect();
-    let argc = args.len();
+    let options = match parse_args(&args[1..]) {
+        Some(options) => options,
+        None => {
+            println!("Usage {} {}", args[0], USAGE);
+            std::process::exit(64);
+        },
+    };
 
-    if argc > 2 {
-        println!("Usage {} [script]", args[0]);
-        std::process::exit(64);
-    }
+    // with `-` the program is all of stdin, so scripts read an empty input
+    let from_stdin = options.script.as_deref() == Some("-");
+    let (contents, file) = match &options.script {
+        _ if !options.code.is_empty() => (options.code.join("\n"), None),
+        None => return repl(options.trace),
+        Some(_) if from_stdin => (read_program(std::io::stdin())?, Some("<stdin>")),
+        Some(script) => (read_file(&PathBuf::from(script))?, None),
+    };
 
-    if argc == 1 {
-        repl()?;
+    match options.mode {
+        Mode::Ast { resolved } => {
+            print_ast(&contents, file, resolved);
+            return Ok(());
+        },
+        Mode::Tokens => {
+            print_tokens(&contents, file);
+            return Ok(());
+        },
+        Mode::Run => { },
     }
-    else if argc == 2 {
-        let filename = args[1].clone();
 
-        let contents = read_file(&PathBuf::from(filename))?;
+    let mut interp = Interpreter::new();
+    if from_stdin {
+        interp = interp.with_input(std::io::empty());
+    }
+    interp.set_trace(options.trace);
+    interp.set_profiling(options.profile);
 
-        if let Some(mut stmts) = scan_parse(&contents) {
-            let mut resolver = Resolver::new();
-            if resolve(&mut resolver, &mut stmts) == false {
-                std::process::exit(1);
+    // code given with `-e` has the value of a bare expression echoed, like in the REPL
+    let result = if options.code.is_empty() {
+        run_script(&contents, file, &mut interp)
+    }
+    else {
+        run_snippet(&contents, &mut interp).map(|value| {
+            if let Some(value) = value {
+                println!("{}", value);
             }
+        })
+    };
+    if options.profile && !matches!(result, Err(LoxError::Scan(_) | LoxError::Parse(_) | LoxError::Resolution(_))) {
+        report_profile(&interp.take_profile());
+    }
 
-            let mut interp = Interpreter::new();
-            if let Err(e) =  interp.execute(&stmts) {
-                report_runtime_error(&e);
-                std::process::exit(70);
-            }
+    if let Err(e) = result {
+        report_error(&e, file);
+        match e {
+            LoxError::Runtime(_) => std::process::exit(70),
+            _ => std::process::exit(65),
         }
     }
 
     Ok(())
 }
 
+// Prints the tokens of the program without parsing it.
+fn print_tokens(source: &str, file: Option<&str>) {
+    match scanner::scan(source) {
+        Ok(tokens) => print!("{}", scanner::format_tokens(&tokens)),
+        Err(e) => {
+            report_lines(&e.to_string(), file);
+            std::process::exit(65);
+        },
+    }
+}
+
+// Prints the program without running it.
+fn print_ast(source: &str, file: Option<&str>, resolved: bool) {
+    let statements = if resolved {
+        compile(source, &mut Resolver::new()).map(|program| program.statements)
+    }
+    else {
+        parse_source(source)
+    };
+
+    match statements {
+        Ok(statements) => print!("{}", render_program(&statements, resolved)),
+        Err(e) => {
+            report_lines(&e.to_string(), file);
+            std::process::exit(65);
+        },
+    }
+}
+
 fn read_file(filename: &PathBuf) -> Result<String, Error> {
-    use std::fs::File;
-    use std::io::prelude::*;
+    read_program(std::fs::File::open(filename)?)
+}
 
-    let mut file = File::open(filename)?;
+// Reads `input` until its end.
+fn read_program(mut input: impl Read) -> Result<String, Error> {
     let mut contents = String::new();
-    file.read_to_string(&mut contents)?;
+    input.read_to_string(&mut contents)?;
 
     Ok(contents)
 }
 
-fn repl() -> Result<(), Error> {
-    let mut interp = Interpreter::new();
-    let mut resolver = Resolver::new();
+// Compiles and executes a whole program, reporting the warnings
+// found in it. Errors are left to the caller to report.
+fn run_script(source: &str, file: Option<&str>, interp: &mut Interpreter) -> Result<(), LoxError> {
+    let program = compile(source, &mut Resolver::new())?;
+    report_warnings(&program.warnings, file);
+
+    interp.execute(&program.statements).map_err(|e| interp.traced(e))?;
+    Ok(())
+}
+
+fn repl(trace: bool) -> Result<(), Error> {
+    let mut lox = Lox::new();
+    lox.interpreter_mut().set_trace(trace);
 
     loop {
         let mut input = String::new();
@@ -73,331 +211,206 @@ fn repl() -> Result<(), Error> {
         if input == ":q" {
             break;
         }
-
-        if let Some(tokens) = scan_input(&input) {
-            // Try to parse an expression first.
-            // If this fails, try to parse statements.
-            let parser = Parser::new(&tokens);
-            match parser.parse_single_expr() {
-                Ok(mut expr) => {
-                    if let Err(e) = resolver.resolve_single_expr(&mut expr) {
-                        report_resolution_errors(&e);
-                    }
-                    else {
-                        match interp.evaluate_expr(&expr) {
-                            Ok(v) => {
-                                println!("{}", &v);
-                            },
-                            Err(e) => {
-                                report_runtime_error(&e);
-                            }
-                        }
-                    }
-                }
-                Err(_) => {
-                    match parser.parse() {
-                        Ok(mut statements) => {
-                            if resolve(&mut resolver, &mut statements) {
-                                if let Err(e) =  interp.execute(&statements) {
-                                    report_runtime_error(&e);
-                                }
-                            }
-                        },
-                        Err(errs) => {
-                            report_parse_errors(&errs);
-                        }
-                    }
-                }
+        if input == ":env" {
+            print!("{}", format_snapshot(&lox.interpreter().dump_environment()));
+            continue;
+        }
+        if let Some(path) = input.strip_prefix(":load ") {
+            let path = path.trim();
+            match load(Path::new(path), &mut lox) {
+                Ok(()) => { },
+                Err(Error::IO(e)) => println!("Can't load {}: {}", path, e),
+                Err(Error::Lox(e)) => report_error(&e, Some(path)),
             }
+            continue;
         }
 
+        let result = lox.run_repl_input(&input);
+        report_warnings(lox.warnings(), None);
+        match result {
+            Ok(Some(v)) => println!("{}", &v),
+            Ok(None) => { },
+            Err(e) => report_error(&e, None),
+        }
     }
 
     Ok(())
 }
 
-fn resolve(r: &mut Resolver, stmts: &mut Vec<Box<dyn Stmt>>) -> bool {
-    let result = r.resolve(stmts);
+// Runs the file at `path` in the REPL session, so what it defines
+// can be used by the input after it. Warnings are reported once it has run.
+fn load(path: &Path, lox: &mut Lox) -> Result<(), Error> {
+    let contents = read_file(&path.to_path_buf())?;
+    let result = lox.run(&contents);
+    report_warnings(lox.warnings(), Some(&path.display().to_string()));
 
-    if let Some(warning) = result.warnings {
-        report_warnings(&warning);
-    }
+    result?;
+    Ok(())
+}
 
-    if let Some(errs) = result.errors {
-        report_resolution_errors(&errs);
-        return false;
+// Errors in a file loaded into the REPL are prefixed with its name.
+fn report_error(err: &LoxError, file: Option<&str>) {
+    match err {
+        LoxError::Runtime(e) => report_runtime_error_or_exit(e, file),
+        e => report_lines(&e.to_string(), file),
     }
-    
-    true
 }
 
-fn scan_parse(input: &str) -> Option<Vec<Box<dyn statement::Stmt>>> {
-    if let Some(tokens) = scan_input(&input) {
-        let parser = Parser::new(&tokens);
-        match parser.parse() {
-            Ok(statements) => {
-                return Some(statements)
-            },
-            Err(errs) => {
-                report_parse_errors(&errs);
-            }
+fn report_lines(text: &str, file: Option<&str>) {
+    for line in text.lines() {
+        match file {
+            Some(file) => println!("{}: {}", file, line),
+            None => println!("{}", line),
         }
     }
-
-    None
 }
 
-fn scan_input(input: &str) -> Option<Vec<scanner::Token>> {
-    match scanner::scan(&input) {
-        Ok(tokens) => Some(tokens),
-        Err(e) => {
-            report_scan_errors(&e);
-            None
-        }
+fn report_warnings(warnings: &[Warning], file: Option<&str>) {
+    for w in warnings {
+        report_lines(&w.to_string(), file);
     }
 }
 
-fn report_scan_errors(e: &rlox::scanner::ScanError) {
-    use scanner::ScanError;
-    use scanner::TokenErrorType;
+// A script that called 'exit' ends the process with its code.
+fn report_runtime_error_or_exit(err: &Traced<RuntimeError>, file: Option<&str>) {
+    if let RuntimeError::Exit { code, .. } = err.error {
+        std::process::exit(code);
+    }
 
-    println!("Scanner error.");
+    report_lines(&err.to_string(), file);
+}
 
-    match e {
-        ScanError::NonAsciiCharacterFound => {
-            println!("Only ASCII characters are supported.");
-        },
-        ScanError::TokenError(token_errs) => {
-            for te in token_errs {
-                let err_type = match te.error {
-                    TokenErrorType::UnexpectedCharacter => "Unexpected character found.",
-                    TokenErrorType::UnterminatedString => "Unterminated string.",
-                };
-                println!("Error at line {}, column {}: {}", te.line, te.column, err_type);
-            }
-        },
+fn report_profile(profile: &[ProfileEntry]) {
+    println!("{:<24} {:>10} {:>12} {:>12}", "function", "calls", "total (ms)", "self (ms)");
+    for entry in profile {
+        let name = match &entry.declaration {
+            Some(token) => format!("{} ({}:{})", entry.function, token.line, token.column),
+            None => entry.function.clone(),
+        };
+        println!(
+            "{:<24} {:>10} {:>12.3} {:>12.3}",
+            name,
+            entry.calls,
+            entry.total_time.as_secs_f64() * 1000.0,
+            entry.self_time.as_secs_f64() * 1000.0,
+        );
     }
 }
 
-fn report_parse_errors(errs: &Vec<rlox::parser::ParseError>) {
-    use rlox::parser::ParseErrorType;
-
-    println!("Parse error.");
-
-    for e in errs {
-        let mut line = None;
-        let mut column = None;
-        let mut err_type = None;
-        let _ = err_type.is_some(); // silence warning
-
-        match e.error_type {
-            ParseErrorType::ExpectedExpression => {
-                if let Some(t) = &e.token {
-                    line = Some(t.line);
-                    column = Some(t.column);
-                }
-                err_type = Some("Expected expression.".to_owned());
-            },
-            ParseErrorType::ExpectedForLoopInitializerOrSemiColon => {
-                if let Some(t) = &e.token {
-                    line = Some(t.line);
-                    column = Some(t.column + 1);
-                }
-                err_type = Some("Expected for loop initializer or semicolon.".to_owned());
-            },
-            ParseErrorType::ExpectedForLoopConditionOrSemiColon => {
-                if let Some(t) = &e.token {
-                    line = Some(t.line);
-                    column = Some(t.column);
-                }
-                err_type = Some("Expected for loop condition or semicolon after initializer.".to_owned());
-            },
-            ParseErrorType::ExpectedStatement => {
-                if let Some(t) = &e.token {
-                    line = Some(t.line);
-                    column = Some(t.column);
-                }
-                err_type = Some("Expected statement.".to_owned());
-            },
-            ParseErrorType::ExpectedToken { expected, found } => {
-                if let Some(t) = &e.token {
-                    line = Some(t.line);
-                    column = Some(t.column);
-                }
-
-                if found.is_some() {
-                    err_type = Some(format!("Expected {:?}, found {:?}.", expected, found.unwrap()));
-                }
-                else {
-                    err_type = Some(format!("Expected {:?}.", expected));
-                }
-            },
-            ParseErrorType::InvalidAssignment => {
-                if let Some(t) = &e.token {
-                    line = Some(t.line);
-                    column = Some(t.column);
-                }
-                err_type = Some("Invalid assignment.".to_owned());
-            },
-            ParseErrorType::ExpectedRightBraceAfterClassBody => {
-                if let Some(t) = &e.token {
-                    line = Some(t.line);
-                    column = Some(t.column);
-                }
-                err_type = Some("Expected } after class body.".to_owned());
-            }
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rlox::RuntimeValue;
+
+    fn temp_file(name: &str, contents: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("rlox-main-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("{}.lox", name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
 
-        if let Some(msg) = err_type {
-            if line.is_some() && column.is_some() {
-                println!(
-                    "Error at line {}, column {}: {}",
-                    line.unwrap(),
-                    column.unwrap(),
-                    msg,
-                );
-            }
-            else {
-                println!("Error: {}", msg);
-            }
+    fn eval(source: &str, interp: &mut Interpreter) -> RuntimeValue {
+        rlox::run_source(source, interp).unwrap().expect("a value")
+    }
+
+    #[test]
+    fn loaded_files_define_globals_for_later_input() {
+        let path = temp_file("helpers", "
+            var loads = 0;
+            loads = loads + 1;
+            fun twice(x) { return x * 2; }
+        ");
+        let mut lox = Lox::new();
+
+        assert!(load(&path, &mut lox).is_ok());
+        assert!(matches!(eval("twice(21);", lox.interpreter_mut()), RuntimeValue::Number(n) if n == 42.0));
+
+        // loading it again runs it again
+        eval("loads = 5;", lox.interpreter_mut());
+        assert!(load(&path, &mut lox).is_ok());
+        assert!(matches!(eval("loads;", lox.interpreter_mut()), RuntimeValue::Number(n) if n == 1.0));
+    }
+
+    #[test]
+    fn files_that_do_not_parse_are_not_loaded() {
+        let path = temp_file("broken", "fun f() { return 1; }\nvar x = ;");
+        let mut lox = Lox::new();
+
+        match load(&path, &mut lox) {
+            Err(Error::Lox(LoxError::Parse(errs))) => assert_eq!(errs[0].token.as_ref().unwrap().line, 2),
+            _ => panic!("expected a parse error"),
         }
     }
-}
 
-fn report_warnings(warnings: &Vec<rlox::resolver::Warning>) {
-    use rlox::resolver::Warning;
+    #[test]
+    fn programs_read_from_stdin_run_to_the_end() {
+        let stdin = std::io::Cursor::new("var x = 1;\nfun f() { return x + 1; }\nvar y = f();\n");
+        let source = read_program(stdin).unwrap();
+        let mut interp = Interpreter::new().with_input(std::io::empty());
 
-    for w in warnings {
-        match w {
-            Warning::UnusedLocalVar(v) => {
-                println!("Warning: Unused local variable '{}' at line {}, column {}", v.lexeme, v.line, v.column);
-            }
+        assert!(run_script(&source, Some("<stdin>"), &mut interp).is_ok());
+        assert!(matches!(interp.get_global("y"), Some(RuntimeValue::Number(n)) if n == 2.0));
+        // nothing is left for the script to read
+        assert!(matches!(eval("readline();", &mut interp), RuntimeValue::Nil));
+    }
+
+    #[test]
+    fn errors_in_programs_read_from_stdin_are_returned() {
+        let mut interp = Interpreter::new();
+        let source = read_program(std::io::Cursor::new("print (1;")).unwrap();
+        assert!(matches!(run_script(&source, Some("<stdin>"), &mut interp), Err(LoxError::Parse(_))));
+
+        let source = read_program(std::io::Cursor::new("var a = 1;\nprint a / nil;")).unwrap();
+        match run_script(&source, Some("<stdin>"), &mut interp) {
+            Err(LoxError::Runtime(e)) => assert_eq!(e.error.token().line, 2),
+            _ => panic!("expected a runtime error"),
         }
+        // what ran before the error is kept
+        assert!(interp.get_global("a").is_some());
     }
-}
 
-fn report_resolution_errors(errs: &Vec<rlox::resolver::ResolutionError>) {
-    use rlox::resolver::ResolutionError;
-
-    for e in errs {
-        let (err_msg, line, col) = match e {
-            ResolutionError::BreakNotInLoop(err) => {
-                ("'break' outside loop".to_owned(), err.line, err.column)
-            },
-            ResolutionError::CantReadLocalVarInItsInitializer(err) => {
-                ("Can't read a local variable in its initializer".to_owned(),
-                 err.line,
-                 err.column,
-                )
-            },
-            ResolutionError::ReturnNotInFunction(err) => {
-                ("'return' outside function".to_owned(), err.line, err.column)
-            },
-            ResolutionError::VariableAlreadyDeclared(err) => {
-                (format!("Variable '{}' already declared", err.lexeme),
-                 err.line,
-                 err.column,
-                )
-            },
-            ResolutionError::ThisNotInsideClass(err) => {
-                ("Can't use 'this' outside of a class".to_owned(),
-                 err.line,
-                 err.column,
-                )
-            },
-            ResolutionError::CantReturnValueFromAnInitializer(err) => {
-                ("Can't return a value from an initializer".to_owned(),
-                 err.line,
-                 err.column,
-                )
-            },
-            ResolutionError::ClassCantInheritFromItself(err) => {
-                ("A Class can't inherit from itself".to_owned(),
-                 err.line,
-                 err.column,
-                )
-            },
-            ResolutionError::SuperOutsideClass(err) => {
-                ("Can't use 'super' outside of class".to_owned(),
-                 err.line,
-                 err.column,
-                )
-            },
-            ResolutionError::SuperInsideClassWithNoSuperClass(err) => {
-                ("Can't use 'super' in a class with no superclass".to_owned(),
-                 err.line,
-                 err.column,
-                )
-            },
-        };
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
 
-        println!("Compile Error: {}, line {}, column {}.", err_msg, line, col);
+    #[test]
+    fn options_are_parsed_in_any_order() {
+        assert_eq!(parse_args(&args(&["--ast=resolved", "script.lox", "--trace"])), Some(Options {
+            trace: true,
+            profile: false,
+            mode: Mode::Ast { resolved: true },
+            script: Some("script.lox".to_owned()),
+            code: Vec::new(),
+        }));
+        assert_eq!(parse_args(&args(&["--profile", "-"])), Some(Options {
+            trace: false,
+            profile: true,
+            mode: Mode::Run,
+            script: Some("-".to_owned()),
+            code: Vec::new(),
+        }));
+        assert!(matches!(parse_args(&args(&[])), Some(Options { script: None, .. })));
+
+        assert!(matches!(parse_args(&args(&["--tokens", "a.lox"])), Some(Options { mode: Mode::Tokens, .. })));
+        assert_eq!(
+            parse_args(&args(&["-e", "var a = 1;", "--trace", "-e", "a + 1"])).map(|o| o.code),
+            Some(args(&["var a = 1;", "a + 1"])),
+        );
+        assert_eq!(parse_args(&args(&["-e"])), None);
+        assert_eq!(parse_args(&args(&["-e", "1;", "a.lox"])), None);
+
+        assert_eq!(parse_args(&args(&["a.lox", "b.lox"])), None);
+        assert_eq!(parse_args(&args(&["--unknown", "a.lox"])), None);
+        // there is nothing to print without a script
+        assert_eq!(parse_args(&args(&["--ast"])), None);
     }
-}
 
-fn report_runtime_error(err: &RuntimeError) {
-    let (msg, line, col) = match err {
-        RuntimeError::UnknownUnaryExpression(token) => {
-            ("Unknown unary expression".to_owned(), token.line, token.column)
-        },
-        RuntimeError::UnknownBinaryExpression(token) => {
-            ("Unknown binary expression".to_owned(), token.line, token.column)
-        },
-        RuntimeError::UnaryMinusExpectsNumber(token) => {
-            ("Unary '-' expects number".to_owned(), token.line, token.column)
-        },
-        RuntimeError::BinaryOperatorExpectsNumbers(token) => {
-            (format!("Operator '{:?}' expects numbers", token.token_type),
-             token.line,
-             token.column,
-            )
-        },
-        RuntimeError::BinaryPlusExpectsTwoNumbersOrTwoStrings(token) => {
-            ("'+' expects two numbers or two strings".to_owned(),
-             token.line,
-             token.column,
-            )
-        },
-        RuntimeError::DivisionByZero(token) => {
-            ("Division by zero".to_owned(), token.line, token.column)
-        },
-        RuntimeError::UndefinedVariable(token) => {
-            (format!("Undefined variable '{}'", token.lexeme),
-             token.line,
-             token.column,
-            )
-        },
-        RuntimeError::NonCallableCalled(token) => {
-            ("Non-callable called".to_owned(), token.line, token.column)
-        },
-        RuntimeError::CallableArityMismatch { right_paren, expected, found } => {
-            (format!(
-                "Arity mismatch. Expected {} arguments, found {}",
-                expected,
-                found),
-             right_paren.line,
-             right_paren.column,
-            )
-        },
-        RuntimeError::OnlyInstancesHaveProperties(token) => {
-            ("Only instances have properties".to_owned(),
-             token.line,
-             token.column,
-            )
-        },
-        RuntimeError::UndefinedProperty(token) => {
-            (format!("Undefined property '{}'", &token.lexeme),
-             token.line,
-             token.column,
-            )
-        },
-        RuntimeError::SuperClassMustBeAClass(token) => {
-            (format!("Superclass must be a class: '{}'", &token.lexeme),
-             token.line,
-             token.column,
-            )
-        },
-    };
+    #[test]
+    fn missing_files_are_reported() {
+        let path = env::temp_dir().join(format!("rlox-main-missing-{}.lox", std::process::id()));
+        let mut lox = Lox::new();
 
-    println!("Runtime error: {}, line {}, column {}.", msg, line, col);
-}
\ No newline at end of file
+        assert!(matches!(load(&path, &mut lox), Err(Error::IO(_))));
+    }
+}
@@ -0,0 +1,168 @@
+use crate::{
+    Instance,
+    RuntimeValue,
+    Gc,
+    Rc,
+    RefCell,
+};
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+};
+
+// The values maps can be keyed by. Keys are equal exactly when
+// the values they come from are equal with '=='.
+#[derive(Clone)]
+pub enum MapKey {
+    Nil,
+    Bool(bool),
+    // the bits of a number that is not NaN, with -0 stored as 0
+    Number(u64),
+    String(Rc<String>),
+    // An instance of a class with a 'hash' method and the hash code of what
+    // it returned. These keys are equal only to themselves, so the interpreter
+    // looks for an equal key with the 'eq' method before using one, see
+    // `Map::instance_keys`.
+    Instance(u64, Gc<RefCell<Instance>>),
+}
+
+impl MapKey {
+    // None for values that cannot be keys: NaN, which is not equal
+    // to itself, and values that are compared by identity.
+    pub fn new(value: &RuntimeValue) -> Option<Self> {
+        match value {
+            RuntimeValue::Nil => Some(MapKey::Nil),
+            RuntimeValue::Bool(b) => Some(MapKey::Bool(*b)),
+            RuntimeValue::Number(n) if n.is_nan() => None,
+            RuntimeValue::Number(n) => Some(MapKey::Number((n + 0_f64).to_bits())),
+            RuntimeValue::String(s) => Some(MapKey::String(s.clone())),
+            _ => None,
+        }
+    }
+
+    pub fn to_value(&self) -> RuntimeValue {
+        match self {
+            MapKey::Nil => RuntimeValue::Nil,
+            MapKey::Bool(b) => RuntimeValue::Bool(*b),
+            MapKey::Number(bits) => RuntimeValue::Number(f64::from_bits(*bits)),
+            MapKey::String(s) => RuntimeValue::String(s.clone()),
+            MapKey::Instance(_, instance) => RuntimeValue::Instance(instance.clone()),
+        }
+    }
+
+    // A non-negative integer that fits in a number without losing precision,
+    // the same for equal keys. It is the same in every run of a build of the
+    // interpreter, but may change between versions.
+    pub fn hash_code(&self) -> u64 {
+        if let MapKey::Instance(code, _) = self {
+            return *code;
+        }
+
+        let mut hasher = DefaultHasher::new();
+        self.hash(&mut hasher);
+        hasher.finish() & ((1 << f64::MANTISSA_DIGITS) - 1)
+    }
+}
+
+impl PartialEq for MapKey {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (MapKey::Nil, MapKey::Nil) => true,
+            (MapKey::Bool(a), MapKey::Bool(b)) => a == b,
+            (MapKey::Number(a), MapKey::Number(b)) => a == b,
+            (MapKey::String(a), MapKey::String(b)) => a == b,
+            (MapKey::Instance(_, a), MapKey::Instance(_, b)) => Gc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for MapKey { }
+
+impl Hash for MapKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            MapKey::Nil => {},
+            MapKey::Bool(b) => b.hash(state),
+            MapKey::Number(bits) => bits.hash(state),
+            MapKey::String(s) => s.hash(state),
+            MapKey::Instance(code, _) => code.hash(state),
+        }
+    }
+}
+
+// Entries in the order their keys were first set.
+#[derive(Clone, Default)]
+pub struct Map {
+    entries: Vec<(MapKey, RuntimeValue)>,
+    // the position of each entry
+    index: HashMap<MapKey, usize>,
+}
+
+impl Map {
+    pub fn get(&self, key: &MapKey) -> Option<&RuntimeValue> {
+        self.index.get(key).map(|&i| &self.entries[i].1)
+    }
+
+    pub fn contains_key(&self, key: &MapKey) -> bool {
+        self.index.contains_key(key)
+    }
+
+    // Setting an existing key keeps its position.
+    pub fn insert(&mut self, key: MapKey, value: RuntimeValue) {
+        match self.index.get(&key) {
+            Some(&i) => self.entries[i].1 = value,
+            None => {
+                self.index.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+            },
+        }
+    }
+
+    // Returns the value of the removed entry.
+    pub fn remove(&mut self, key: &MapKey) -> Option<RuntimeValue> {
+        let i = self.index.remove(key)?;
+        let (_, value) = self.entries.remove(i);
+        for position in self.index.values_mut() {
+            if *position > i {
+                *position -= 1;
+            }
+        }
+
+        Some(value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&MapKey, &RuntimeValue)> {
+        self.entries.iter().map(|(key, value)| (key, value))
+    }
+
+    // The instances keys with the hash code `code`, which
+    // are the only keys an instance with that code may equal.
+    pub fn instance_keys(&self, code: u64) -> impl Iterator<Item = &MapKey> {
+        self.entries.iter()
+            .map(|(key, _)| key)
+            .filter(move |key| matches!(key, MapKey::Instance(c, _) if *c == code))
+    }
+}
+
+unsafe impl dumpster::Trace for Map {
+    fn accept<V: dumpster::Visitor>(&self, visitor: &mut V) -> Result<(), ()> {
+        for (key, value) in &self.entries {
+            if let MapKey::Instance(_, instance) = key {
+                instance.accept(visitor)?;
+            }
+            value.accept(visitor)?;
+        }
+
+        Ok(())
+    }
+}
@@ -17,11 +17,16 @@ use crate::expression::{
     Set,
     This,
     Super,
+    Index,
+    SetIndex,
 };
 use crate::statement::{
     self,
     Stmt,
 };
+use crate::MethodCache;
+use crate::{Rc, Cell, OnceCell};
+use std::fmt::Display;
 use std::iter::Peekable;
 use core::slice::Iter;
 
@@ -37,26 +42,71 @@ pub enum ParseErrorType {
     ExpectedForLoopInitializerOrSemiColon,
     ExpectedForLoopConditionOrSemiColon,
     ExpectedRightBraceAfterClassBody,
+    ExpectedCatchOrFinally,
+    InvalidDeleteTarget,
 }
 
 #[derive(Clone, Debug)]
 pub struct ParseError {
     pub error_type: ParseErrorType,
-    pub token: Option<Token>,
+    pub token: Option<Rc<Token>>,
 }
 
+impl Display for ParseErrorType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseErrorType::ExpectedToken { expected, found: Some(found) } => {
+                write!(f, "Expected {:?}, found {:?}", expected, found)
+            },
+            ParseErrorType::ExpectedToken { expected, found: None } => {
+                write!(f, "Expected {:?}", expected)
+            },
+            ParseErrorType::ExpectedExpression => write!(f, "Expected expression"),
+            ParseErrorType::ExpectedStatement => write!(f, "Expected statement"),
+            ParseErrorType::InvalidAssignment => write!(f, "Invalid assignment"),
+            ParseErrorType::ExpectedForLoopInitializerOrSemiColon => {
+                write!(f, "Expected for loop initializer or semicolon")
+            },
+            ParseErrorType::ExpectedForLoopConditionOrSemiColon => {
+                write!(f, "Expected for loop condition or semicolon after initializer")
+            },
+            ParseErrorType::ExpectedRightBraceAfterClassBody => write!(f, "Expected }} after class body"),
+            ParseErrorType::ExpectedCatchOrFinally => write!(f, "Expected 'catch' or 'finally' after try block"),
+            ParseErrorType::InvalidDeleteTarget => write!(f, "Only properties can be deleted"),
+        }
+    }
+}
+
+// A missing for loop initializer is reported right after the token before it.
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.token {
+            Some(token) => {
+                let column = match self.error_type {
+                    ParseErrorType::ExpectedForLoopInitializerOrSemiColon => token.column + 1,
+                    _ => token.column,
+                };
+                write!(f, "Parse error at line {}, column {}: {}.", token.line, column, self.error_type)
+            },
+            None => write!(f, "Parse error: {}.", self.error_type),
+        }
+    }
+}
+
+impl std::error::Error for ParseError { }
+
 pub struct Parser {
-    tokens: Vec<Token>,
+    tokens: Vec<Rc<Token>>,
 }
 
 impl Parser {
-    pub fn new(tokens: &[Token]) -> Self {
+    pub fn new(tokens: &[Rc<Token>]) -> Self {
         Self {
             tokens: Vec::from(tokens)
         }
     }
 
-    pub fn parse(&self) -> Result<Vec<Box<dyn Stmt>>, Vec<ParseError>> {
+    pub fn parse(&self) -> Result<Vec<Stmt>, Vec<ParseError>> {
         let mut iter = self.tokens.iter().peekable();
 
         let mut errors = vec![];
@@ -90,7 +140,7 @@ impl Parser {
 
     // Parses exactly one expression. If any input is left, it fails.
     // Useful for tests and REPL mode.
-    pub fn parse_single_expr(&self) -> Result<Box<dyn Expr>, ParseError> {
+    pub fn parse_single_expr(&self) -> Result<Expr, ParseError> {
         let mut iter = self.tokens.iter().peekable();
         let expr = self.parse_expr(&mut iter)?;
 
@@ -107,8 +157,8 @@ impl Parser {
 
     fn parse_declaration(
         &self,
-        iter: &mut Peekable<Iter<'_, Token>>,
-    ) -> Result<Box<dyn Stmt>, ParseError> {
+        iter: &mut Peekable<Iter<'_, Rc<Token>>>,
+    ) -> Result<Stmt, ParseError> {
         if let Some(&token) = iter.peek() {
             match token.token_type {
                 TokenType::Var => self.parse_var_decl(iter),
@@ -124,8 +174,8 @@ impl Parser {
 
     fn parse_class_decl(
         &self,
-        iter: &mut Peekable<Iter<'_, Token>>,
-    ) -> Result<Box<dyn Stmt>, ParseError> {
+        iter: &mut Peekable<Iter<'_, Rc<Token>>>,
+    ) -> Result<Stmt, ParseError> {
         let _ = self.consume_token(iter, TokenType::Class)?;
         let name = self.consume_token(iter, TokenType::Identifier)?;
         let mut super_class = None;
@@ -133,6 +183,7 @@ impl Parser {
             super_class = Some(expression::Variable {
                 name: self.consume_token(iter, TokenType::Identifier)?,
                 hops: None,
+                slot: Cell::new(None),
             });
         }
         let _ = self.consume_token(iter, TokenType::LeftBrace)?;
@@ -159,7 +210,7 @@ impl Parser {
 
         let _ = self.consume_token(iter, TokenType::RightBrace)?;
 
-        Ok(Box::new(statement::Class {
+        Ok(Stmt::Class(statement::Class {
             name,
             super_class,
             methods,
@@ -168,17 +219,17 @@ impl Parser {
 
     fn parse_fun_decl(
         &self,
-        iter: &mut Peekable<Iter<'_, Token>>,
-    ) -> Result<Box<dyn Stmt>, ParseError> {
+        iter: &mut Peekable<Iter<'_, Rc<Token>>>,
+    ) -> Result<Stmt, ParseError> {
         let _ = self.consume_token(iter, TokenType::Fun)?;
 
         let f = self.parse_function(iter)?;
-        Ok(Box::new(f))
+        Ok(Stmt::Function(f))
     }
 
     fn parse_function(
         &self,
-        iter: &mut Peekable<Iter<'_, Token>>,
+        iter: &mut Peekable<Iter<'_, Rc<Token>>>,
     ) -> Result<statement::Function, ParseError> {
         // todo: add context to consume_token so error messages
         // are more specific -> 'expected function name' instead of
@@ -193,13 +244,15 @@ impl Parser {
             name,
             params,
             body,
+            creates_closures: true,
+            shared: OnceCell::new(),
         })
     }
 
     fn parse_params(
         &self,
-        iter: &mut Peekable<Iter<'_, Token>>,
-    ) -> Result<Vec<Token>, ParseError> {
+        iter: &mut Peekable<Iter<'_, Rc<Token>>>,
+    ) -> Result<Vec<Rc<Token>>, ParseError> {
         let mut params = Vec::new();
 
         if let Some(&token) = iter.peek() {
@@ -222,8 +275,8 @@ impl Parser {
 
     fn parse_var_decl(
         &self,
-        iter: &mut Peekable<Iter<'_, Token>>,
-    ) -> Result<Box<dyn Stmt>, ParseError> {
+        iter: &mut Peekable<Iter<'_, Rc<Token>>>,
+    ) -> Result<Stmt, ParseError> {
         let _var = self.consume_token(iter, TokenType::Var)?;
         let name = self.consume_token(iter, TokenType::Identifier)?;
 
@@ -233,7 +286,7 @@ impl Parser {
         }
         let _ = self.consume_token(iter, TokenType::Semicolon)?;
 
-        Ok(Box::new(statement::Variable{
+        Ok(Stmt::Variable(statement::Variable {
             name,
             initializer,
         }))
@@ -241,8 +294,8 @@ impl Parser {
 
     fn parse_statement(
         &self,
-        iter: &mut Peekable<Iter<'_, Token>>,
-    ) -> Result<Box<dyn Stmt>, ParseError> {
+        iter: &mut Peekable<Iter<'_, Rc<Token>>>,
+    ) -> Result<Stmt, ParseError> {
         if let Some(&token) = iter.peek() {
             match token.token_type {
                 TokenType::If => self.parse_if_statement(iter),
@@ -251,6 +304,10 @@ impl Parser {
                 TokenType::Print => self.parse_print_statement(iter),
                 TokenType::Break => self.parse_break_statement(iter),
                 TokenType::Return => self.parse_return_statement(iter),
+                TokenType::Defer => self.parse_defer_statement(iter),
+                TokenType::Throw => self.parse_throw_statement(iter),
+                TokenType::Try => self.parse_try_statement(iter),
+                TokenType::Assert => self.parse_assert_statement(iter),
                 TokenType::LeftBrace => self.parse_block_statement(iter),
                 _ => self.parse_expr_statement(iter),
             }
@@ -265,33 +322,34 @@ impl Parser {
 
     fn parse_print_statement(
         &self,
-        iter: &mut Peekable<Iter<'_, Token>>,
-    ) -> Result<Box<dyn Stmt>, ParseError> {
-        let _ = self.consume_token(iter, TokenType::Print)?;
+        iter: &mut Peekable<Iter<'_, Rc<Token>>>,
+    ) -> Result<Stmt, ParseError> {
+        let keyword = self.consume_token(iter, TokenType::Print)?;
         let expr = self.parse_expr(iter)?;
         let _ = self.consume_token(iter, TokenType::Semicolon)?;
 
-        Ok(Box::new(statement::Print{
+        Ok(Stmt::Print(statement::Print {
+            keyword,
             expr,
         }))
     }
 
     fn parse_break_statement(
         &self,
-        iter: &mut Peekable<Iter<'_, Token>>,
-    ) -> Result<Box<dyn Stmt>, ParseError> {
+        iter: &mut Peekable<Iter<'_, Rc<Token>>>,
+    ) -> Result<Stmt, ParseError> {
         let brk = self.consume_token(iter, TokenType::Break)?;
         let _ = self.consume_token(iter, TokenType::Semicolon)?;
 
-        Ok(Box::new(statement::Break{
+        Ok(Stmt::Break(statement::Break {
             keyword: brk,
         }))
     }
 
     fn parse_return_statement(
         &self,
-        iter: &mut Peekable<Iter<'_, Token>>,
-    ) -> Result<Box<dyn Stmt>, ParseError> {
+        iter: &mut Peekable<Iter<'_, Rc<Token>>>,
+    ) -> Result<Stmt, ParseError> {
         let ret = self.consume_token(iter, TokenType::Return)?;
 
         let mut value = None;
@@ -302,44 +360,131 @@ impl Parser {
             let _ = self.consume_token(iter, TokenType::Semicolon)?;
         }
 
-        Ok(Box::new(statement::Return{
+        Ok(Stmt::Return(statement::Return {
             keyword: ret,
             value,
         }))
     }
 
+    fn parse_defer_statement(
+        &self,
+        iter: &mut Peekable<Iter<'_, Rc<Token>>>,
+    ) -> Result<Stmt, ParseError> {
+        let keyword = self.consume_token(iter, TokenType::Defer)?;
+        let body = self.parse_statement(iter)?;
+
+        Ok(Stmt::Defer(statement::Defer {
+            keyword,
+            body: Box::new(body),
+        }))
+    }
+
+    fn parse_throw_statement(
+        &self,
+        iter: &mut Peekable<Iter<'_, Rc<Token>>>,
+    ) -> Result<Stmt, ParseError> {
+        let keyword = self.consume_token(iter, TokenType::Throw)?;
+        let value = self.parse_expr(iter)?;
+        let _ = self.consume_token(iter, TokenType::Semicolon)?;
+
+        Ok(Stmt::Throw(statement::Throw {
+            keyword,
+            value,
+        }))
+    }
+
+    fn parse_assert_statement(
+        &self,
+        iter: &mut Peekable<Iter<'_, Rc<Token>>>,
+    ) -> Result<Stmt, ParseError> {
+        let keyword = self.consume_token(iter, TokenType::Assert)?;
+        let condition = self.parse_expr(iter)?;
+
+        let mut message = None;
+        if iter.next_if(|t| t.token_type == TokenType::Comma).is_some() {
+            message = Some(Box::new(self.parse_expr(iter)?));
+        }
+        let _ = self.consume_token(iter, TokenType::Semicolon)?;
+
+        Ok(Stmt::Assert(statement::Assert {
+            keyword,
+            condition,
+            message,
+        }))
+    }
+
+    fn parse_try_statement(
+        &self,
+        iter: &mut Peekable<Iter<'_, Rc<Token>>>,
+    ) -> Result<Stmt, ParseError> {
+        let keyword = self.consume_token(iter, TokenType::Try)?;
+        let body = self.parse_block(iter)?;
+
+        let mut catch = None;
+        if iter.next_if(|t| t.token_type == TokenType::Catch).is_some() {
+            let _ = self.consume_token(iter, TokenType::LeftParen)?;
+            let name = self.consume_token(iter, TokenType::Identifier)?;
+            let _ = self.consume_token(iter, TokenType::RightParen)?;
+
+            catch = Some(statement::Catch {
+                name,
+                body: self.parse_block(iter)?,
+            });
+        }
+
+        let mut finally = None;
+        if iter.next_if(|t| t.token_type == TokenType::Finally).is_some() {
+            finally = Some(self.parse_block(iter)?);
+        }
+
+        if catch.is_none() && finally.is_none() {
+            return Err(ParseError {
+                error_type: ParseErrorType::ExpectedCatchOrFinally,
+                token: iter.peek().map(|&t| t.clone()),
+            });
+        }
+
+        Ok(Stmt::Try(statement::Try {
+            keyword,
+            body,
+            catch,
+            finally,
+        }))
+    }
+
     fn parse_expr_statement(
         &self,
-        iter: &mut Peekable<Iter<'_, Token>>,
-    ) -> Result<Box<dyn Stmt>, ParseError> {
+        iter: &mut Peekable<Iter<'_, Rc<Token>>>,
+    ) -> Result<Stmt, ParseError> {
         let expr = self.parse_expr(iter)?;
         let _ = self.consume_token(iter, TokenType::Semicolon)?;
 
-        Ok(Box::new(statement::Expression{
+        Ok(Stmt::Expression(statement::Expression {
             expr,
         }))
     }
 
     fn parse_if_statement(
         &self,
-        iter: &mut Peekable<Iter<'_, Token>>,
-    ) -> Result<Box<dyn Stmt>, ParseError> {
-        let _ = self.consume_token(iter, TokenType::If)?;
+        iter: &mut Peekable<Iter<'_, Rc<Token>>>,
+    ) -> Result<Stmt, ParseError> {
+        let keyword = self.consume_token(iter, TokenType::If)?;
         let _ = self.consume_token(iter, TokenType::LeftParen)?;
 
         let cond = self.parse_expr(iter)?;
 
         let _ = self.consume_token(iter, TokenType::RightParen)?;
 
-        let then_branch = self.parse_statement(iter)?;
+        let then_branch = Box::new(self.parse_statement(iter)?);
 
         let mut else_branch = None;
         if let Some(&_) = iter.next_if(|t| t.token_type == TokenType::Else) {
             let else_stmt = self.parse_statement(iter)?;
-            else_branch = Some(else_stmt);
+            else_branch = Some(Box::new(else_stmt));
         }
 
-        Ok(Box::new(statement::If {
+        Ok(Stmt::If(statement::If {
+            keyword,
             cond,
             then_branch,
             else_branch,
@@ -348,11 +493,15 @@ impl Parser {
 
     fn parse_for_statement(
         &self,
-        iter: &mut Peekable<Iter<'_, Token>>,
-    ) -> Result<Box<dyn Stmt>, ParseError> {
-        let _ = self.consume_token(iter, TokenType::For)?;
+        iter: &mut Peekable<Iter<'_, Rc<Token>>>,
+    ) -> Result<Stmt, ParseError> {
+        let keyword = self.consume_token(iter, TokenType::For)?;
         let left_paren = self.consume_token(iter, TokenType::LeftParen)?;
 
+        if Self::is_for_in(iter) {
+            return self.parse_for_in_statement(iter, keyword);
+        }
+
         let initializer = match iter.peek() {
             None => {
                 return Err(ParseError {
@@ -400,10 +549,10 @@ impl Parser {
 
         // desugar the for loop into a while loop
         if let Some(inc) = increment {
-            body = Box::new(statement::Block {
+            body = Stmt::Block(statement::Block {
                 statements: vec![
                     body,
-                    Box::new(statement::Expression {
+                    Stmt::Expression(statement::Expression {
                         expr: inc,
                     }),
                 ]
@@ -411,16 +560,17 @@ impl Parser {
         }
 
         let cond = match cond {
-            None => Box::new(Literal::True),
+            None => Expr::Literal(Literal::True),
             Some(c) => c,
         };
-        body = Box::new(statement::While {
+        body = Stmt::While(statement::While {
+            keyword,
             cond,
-            body,
+            body: Box::new(body),
         });
 
         if let Some(init) = initializer {
-            body = Box::new(statement::Block {
+            body = Stmt::Block(statement::Block {
                 statements: vec![
                     init,
                     body,
@@ -431,20 +581,48 @@ impl Parser {
         Ok(body)
     }
 
+    // Whether the loop after '(' is 'var name in ...'.
+    fn is_for_in(iter: &Peekable<Iter<'_, Rc<Token>>>) -> bool {
+        let mut ahead = iter.clone();
+        let types = [TokenType::Var, TokenType::Identifier, TokenType::In];
+        types.iter().all(|t| ahead.next().is_some_and(|token| token.token_type == *t))
+    }
+
+    fn parse_for_in_statement(
+        &self,
+        iter: &mut Peekable<Iter<'_, Rc<Token>>>,
+        keyword: Rc<Token>,
+    ) -> Result<Stmt, ParseError> {
+        let _ = self.consume_token(iter, TokenType::Var)?;
+        let name = self.consume_token(iter, TokenType::Identifier)?;
+        let _ = self.consume_token(iter, TokenType::In)?;
+        let iterable = self.parse_expr(iter)?;
+        let _ = self.consume_token(iter, TokenType::RightParen)?;
+        let body = Box::new(self.parse_statement(iter)?);
+
+        Ok(Stmt::ForIn(statement::ForIn {
+            keyword,
+            name,
+            iterable,
+            body,
+        }))
+    }
+
     fn parse_while_statement(
         &self,
-        iter: &mut Peekable<Iter<'_, Token>>,
-    ) -> Result<Box<dyn Stmt>, ParseError> {
-        let _ = self.consume_token(iter, TokenType::While)?;
+        iter: &mut Peekable<Iter<'_, Rc<Token>>>,
+    ) -> Result<Stmt, ParseError> {
+        let keyword = self.consume_token(iter, TokenType::While)?;
         let _ = self.consume_token(iter, TokenType::LeftParen)?;
 
         let cond = self.parse_expr(iter)?;
 
         let _ = self.consume_token(iter, TokenType::RightParen)?;
 
-        let body = self.parse_statement(iter)?;
+        let body = Box::new(self.parse_statement(iter)?);
 
-        Ok(Box::new(statement::While {
+        Ok(Stmt::While(statement::While {
+            keyword,
             cond,
             body,
         }))
@@ -452,19 +630,19 @@ impl Parser {
 
     fn parse_block_statement(
         &self,
-        iter: &mut Peekable<Iter<'_, Token>>,
-    ) -> Result<Box<dyn Stmt>, ParseError> {
+        iter: &mut Peekable<Iter<'_, Rc<Token>>>,
+    ) -> Result<Stmt, ParseError> {
         let statements = self.parse_block(iter)?;
 
-        Ok(Box::new(statement::Block {
+        Ok(Stmt::Block(statement::Block {
             statements,
         }))
     }
 
     fn parse_block(
         &self,
-        iter: &mut Peekable<Iter<'_, Token>>,
-    ) -> Result<Vec<Box<dyn Stmt>>, ParseError> {
+        iter: &mut Peekable<Iter<'_, Rc<Token>>>,
+    ) -> Result<Vec<Stmt>, ParseError> {
         let _ = self.consume_token(iter, TokenType::LeftBrace)?;
 
         let mut statements = Vec::new();
@@ -485,44 +663,49 @@ impl Parser {
 
     fn parse_expr(
         &self,
-        iter: &mut Peekable<Iter<'_, Token>>,
-    ) -> Result<Box<dyn Expr>, ParseError> {
+        iter: &mut Peekable<Iter<'_, Rc<Token>>>,
+    ) -> Result<Expr, ParseError> {
         self.parse_assignment(iter)
     }
 
     fn parse_assignment(
         &self,
-        iter: &mut Peekable<Iter<'_, Token>>,
-    ) -> Result<Box<dyn Expr>, ParseError> {
+        iter: &mut Peekable<Iter<'_, Rc<Token>>>,
+    ) -> Result<Expr, ParseError> {
         let left = self.parse_logic_or(iter)?;
 
         if let Some(eq) = iter.next_if(|t| t.token_type == TokenType::Equal) {
             let right = self.parse_assignment(iter)?;
 
-            use crate::expression::AssignTarget;
-            if let Some(target) = left.as_assign_target() {
-                match target {
-                    AssignTarget::Var { name } => {
-                        Ok(Box::new(Assignment {
-                            name,
-                            value: right,
-                            hops: None,
-                        }))
-                    },
-                    AssignTarget::Get { object, name } => {
-                        Ok(Box::new(Set {
-                            name,
-                            object,
-                            value: right,
-                        }))
-                    }
-                }
-            }
-            else {
-                Err(ParseError {
+            let value = Box::new(right);
+            match left {
+                Expr::Variable(Variable { name, .. }) => {
+                    Ok(Expr::Assignment(Assignment {
+                        name,
+                        value,
+                        hops: None,
+                        slot: Cell::new(None),
+                    }))
+                },
+                Expr::Get(Get { object, name, .. }) => {
+                    Ok(Expr::Set(Set {
+                        name,
+                        object,
+                        value,
+                    }))
+                },
+                Expr::Index(Index { object, bracket, index }) => {
+                    Ok(Expr::SetIndex(SetIndex {
+                        object,
+                        bracket,
+                        index,
+                        value,
+                    }))
+                },
+                _ => Err(ParseError {
                     error_type: ParseErrorType::InvalidAssignment,
                     token: Some(eq.clone()),
-                })
+                }),
             }
         }
         else {
@@ -532,8 +715,8 @@ impl Parser {
 
     fn parse_logic_or(
         &self,
-        iter: &mut Peekable<Iter<'_, Token>>,
-    ) -> Result<Box<dyn Expr>, ParseError> {
+        iter: &mut Peekable<Iter<'_, Rc<Token>>>,
+    ) -> Result<Expr, ParseError> {
         let mut result = self.parse_logic_and(iter)?;
 
         while let Some(&token) = iter.peek() {
@@ -541,9 +724,9 @@ impl Parser {
                 TokenType::Or => {
                     let operator = iter.next().unwrap().clone();
                     let right = self.parse_logic_and(iter)?;
-                    let expr = Box::new(Logical {
-                        left: result,
-                        right,
+                    let expr = Expr::Logical(Logical {
+                        left: Box::new(result),
+                        right: Box::new(right),
                         operator,
                     });
                     result = expr;
@@ -559,8 +742,8 @@ impl Parser {
 
     fn parse_logic_and(
         &self,
-        iter: &mut Peekable<Iter<'_, Token>>,
-    ) -> Result<Box<dyn Expr>, ParseError> {
+        iter: &mut Peekable<Iter<'_, Rc<Token>>>,
+    ) -> Result<Expr, ParseError> {
         let mut result = self.parse_equality(iter)?;
 
         while let Some(&token) = iter.peek() {
@@ -568,9 +751,9 @@ impl Parser {
                 TokenType::And => {
                     let operator = iter.next().unwrap().clone();
                     let right = self.parse_equality(iter)?;
-                    let expr = Box::new(Logical {
-                        left: result,
-                        right,
+                    let expr = Expr::Logical(Logical {
+                        left: Box::new(result),
+                        right: Box::new(right),
                         operator,
                     });
                     result = expr;
@@ -586,8 +769,8 @@ impl Parser {
 
     fn parse_equality(
         &self,
-        iter: &mut Peekable<Iter<'_, Token>>,
-    ) -> Result<Box<dyn Expr>, ParseError> {
+        iter: &mut Peekable<Iter<'_, Rc<Token>>>,
+    ) -> Result<Expr, ParseError> {
         let mut result = self.parse_comparison(iter)?;
 
         while let Some(&token) = iter.peek() {
@@ -595,9 +778,9 @@ impl Parser {
                 TokenType::EqualEqual | TokenType::BangEqual => {
                     let operator = iter.next().unwrap().clone();
                     let right = self.parse_comparison(iter)?;
-                    let binary = Box::new(Binary {
-                        left: result,
-                        right,
+                    let binary = Expr::Binary(Binary {
+                        left: Box::new(result),
+                        right: Box::new(right),
                         operator,
                     });
                     result = binary;
@@ -613,8 +796,8 @@ impl Parser {
 
     fn parse_comparison(
         &self,
-        iter: &mut Peekable<Iter<'_, Token>>,
-    ) -> Result<Box<dyn Expr>, ParseError> {
+        iter: &mut Peekable<Iter<'_, Rc<Token>>>,
+    ) -> Result<Expr, ParseError> {
         let mut result = self.parse_term(iter)?;
 
         while let Some(&token) = iter.peek() {
@@ -625,13 +808,22 @@ impl Parser {
                 TokenType::GreaterEqual => {
                     let operator = iter.next().unwrap().clone();
                     let right = self.parse_term(iter)?;
-                    let binary = Box::new(Binary {
-                        left: result,
-                        right,
+                    let binary = Expr::Binary(Binary {
+                        left: Box::new(result),
+                        right: Box::new(right),
                         operator,
                     });
                     result = binary;
                 },
+                TokenType::Is => {
+                    let keyword = iter.next().unwrap().clone();
+                    let type_expr = self.parse_term(iter)?;
+                    result = Expr::TypeTest(expression::TypeTest {
+                        value: Box::new(result),
+                        keyword,
+                        type_expr: Box::new(type_expr),
+                    });
+                },
                 _ => {
                     break;
                 }
@@ -643,8 +835,8 @@ impl Parser {
     
     fn parse_term(
         &self,
-        iter: &mut Peekable<Iter<'_, Token>>,
-    ) -> Result<Box<dyn Expr>, ParseError> {
+        iter: &mut Peekable<Iter<'_, Rc<Token>>>,
+    ) -> Result<Expr, ParseError> {
         let mut result = self.parse_factor(iter)?;
 
         while let Some(&token) = iter.peek() {
@@ -652,9 +844,9 @@ impl Parser {
                 TokenType::Plus | TokenType::Minus => {
                     let operator = iter.next().unwrap().clone();
                     let right = self.parse_factor(iter)?;
-                    let binary = Box::new(Binary {
-                        left: result,
-                        right,
+                    let binary = Expr::Binary(Binary {
+                        left: Box::new(result),
+                        right: Box::new(right),
                         operator,
                     });
                     result = binary;
@@ -670,18 +862,18 @@ impl Parser {
 
     fn parse_factor(
         &self,
-        iter: &mut Peekable<Iter<'_, Token>>,
-    ) -> Result<Box<dyn Expr>, ParseError> {
+        iter: &mut Peekable<Iter<'_, Rc<Token>>>,
+    ) -> Result<Expr, ParseError> {
         let mut result = self.parse_unary(iter)?;
 
         while let Some(&token) = iter.peek() {
             match token.token_type {
-                TokenType::Star | TokenType::Slash => {
+                TokenType::Star | TokenType::Slash | TokenType::Percent | TokenType::Div => {
                     let operator = iter.next().unwrap().clone();
                     let right = self.parse_unary(iter)?;
-                    let binary = Box::new(Binary {
-                        left: result,
-                        right,
+                    let binary = Expr::Binary(Binary {
+                        left: Box::new(result),
+                        right: Box::new(right),
                         operator,
                     });
                     result = binary;
@@ -697,20 +889,38 @@ impl Parser {
 
     fn parse_unary(
         &self,
-        iter: &mut Peekable<Iter<'_, Token>>,
-    ) -> Result<Box<dyn Expr>, ParseError> {
+        iter: &mut Peekable<Iter<'_, Rc<Token>>>,
+    ) -> Result<Expr, ParseError> {
         if let Some(&token) = iter.peek() {
             match token.token_type {
                 TokenType::Bang | TokenType::Minus => {
                     let operator = iter.next().unwrap().clone();
                     let right = self.parse_unary(iter)?;
-                    let unary = Box::new(Unary {
+                    let unary = Expr::Unary(Unary {
                         operator,
-                        right,
+                        right: Box::new(right),
                     });
 
                     return Ok(unary);
                 },
+                TokenType::Delete => {
+                    let keyword = iter.next().unwrap().clone();
+                    let target = self.parse_unary(iter)?;
+
+                    if let Expr::Get(Get { object, name, .. }) = target {
+                        return Ok(Expr::Delete(expression::Delete {
+                            keyword,
+                            object,
+                            name,
+                        }));
+                    }
+                    else {
+                        return Err(ParseError {
+                            error_type: ParseErrorType::InvalidDeleteTarget,
+                            token: Some(keyword),
+                        });
+                    }
+                },
                 _ => { }
             }
         }
@@ -720,8 +930,8 @@ impl Parser {
 
     fn parse_call(
         &self,
-        iter: &mut Peekable<Iter<'_, Token>>,
-    ) -> Result<Box<dyn Expr>, ParseError> {
+        iter: &mut Peekable<Iter<'_, Rc<Token>>>,
+    ) -> Result<Expr, ParseError> {
         let mut expr = self.parse_primary(iter)?;
 
         while let Some(&token) = iter.peek() {
@@ -731,18 +941,29 @@ impl Parser {
                     let args = self.parse_args(iter)?;
                     let right_paren = self.consume_token(iter, TokenType::RightParen)?;
 
-                    expr = Box::new(Call {
+                    expr = Expr::Call(Call {
                         right_paren,
-                        callee: expr,
+                        callee: Box::new(expr),
                         args,
                     })
                 },
                 TokenType::Dot => {
                     let _ = self.consume_token(iter, TokenType::Dot)?;
                     let name = self.consume_token(iter, TokenType::Identifier)?;
-                    expr = Box::new(Get {
+                    expr = Expr::Get(Get {
                         name,
-                        object: expr,
+                        object: Box::new(expr),
+                        method: MethodCache::default(),
+                    })
+                },
+                TokenType::LeftBracket => {
+                    let _ = self.consume_token(iter, TokenType::LeftBracket)?;
+                    let index = self.parse_expr(iter)?;
+                    let bracket = self.consume_token(iter, TokenType::RightBracket)?;
+                    expr = Expr::Index(Index {
+                        object: Box::new(expr),
+                        bracket,
+                        index: Box::new(index),
                     })
                 },
                 _ => break,
@@ -754,8 +975,8 @@ impl Parser {
 
     fn parse_args(
         &self,
-        iter: &mut Peekable<Iter<'_, Token>>,
-    ) -> Result<Vec<Box<dyn Expr>>, ParseError> {
+        iter: &mut Peekable<Iter<'_, Rc<Token>>>,
+    ) -> Result<Vec<Expr>, ParseError> {
         let mut args = Vec::new();
 
         if let Some(&token) = iter.peek() {
@@ -778,34 +999,34 @@ impl Parser {
 
     fn parse_primary(
         &self,
-        iter: &mut Peekable<Iter<'_, Token>>,
-    ) -> Result<Box<dyn Expr>, ParseError> {
+        iter: &mut Peekable<Iter<'_, Rc<Token>>>,
+    ) -> Result<Expr, ParseError> {
         use crate::scanner::Literal as ScanLiteral;
 
         if let Some(token) = iter.next() {
             match token.token_type {
                 TokenType::False => {
-                    return Ok(Box::new(Literal::False));
+                    return Ok(Expr::Literal(Literal::False));
                 },
                 TokenType::True => {
-                    return Ok(Box::new(Literal::True));
+                    return Ok(Expr::Literal(Literal::True));
                 },
                 TokenType::Nil => {
-                    return Ok(Box::new(Literal::Nil));
+                    return Ok(Expr::Literal(Literal::Nil));
                 },
                 TokenType::String => {
-                    let literal = token.clone().literal.unwrap();
+                    let literal = token.literal.clone().unwrap();
                     if let ScanLiteral::String(s) = literal {
-                        return Ok(Box::new(Literal::String(s)));
+                        return Ok(Expr::Literal(Literal::String(s.into())));
                     }
                     else {
                         panic!("Expected string literal");
                     }
                 },
                 TokenType::Number => {
-                    let literal = token.clone().literal.unwrap();
+                    let literal = token.literal.clone().unwrap();
                     if let ScanLiteral::Number(n) = literal {
-                        return Ok(Box::new(Literal::Number(n)));
+                        return Ok(Expr::Literal(Literal::Number(n)));
                     }
                     else {
                         panic!("Expected number literal");
@@ -814,16 +1035,17 @@ impl Parser {
                 TokenType::LeftParen => {
                     let nested = self.parse_expr(iter)?;
                     let _ = self.consume_token(iter, TokenType::RightParen)?;
-                    return Ok(Box::new(Grouping(nested)));
+                    return Ok(Expr::Grouping(Grouping(Box::new(nested))));
                 },
                 TokenType::Identifier => {
-                    return Ok(Box::new(Variable {
+                    return Ok(Expr::Variable(Variable {
                         name: token.clone(),
                         hops: None,
+                        slot: Cell::new(None),
                     }));
                 },
                 TokenType::This => {
-                    return Ok(Box::new(This {
+                    return Ok(Expr::This(This {
                         keyword: token.clone(),
                         hops: None,
                     }))
@@ -831,7 +1053,7 @@ impl Parser {
                 TokenType::Super => {
                     let _ = self.consume_token(iter, TokenType::Dot)?;
                     let method = self.consume_token(iter, TokenType::Identifier)?;
-                    return Ok(Box::new(Super {
+                    return Ok(Expr::Super(Super {
                         keyword: token.clone(),
                         method,
                         hops_to_super: None,
@@ -855,9 +1077,9 @@ impl Parser {
 
     fn consume_token(
         &self,
-        iter: &mut Peekable<Iter<'_, Token>>,
+        iter: &mut Peekable<Iter<'_, Rc<Token>>>,
         expected: TokenType,
-    ) -> Result<Token, ParseError> {
+    ) -> Result<Rc<Token>, ParseError> {
         if let Some(token) = iter.next_if(|token| token.token_type == expected) {
             Ok(token.clone())
         }
@@ -875,7 +1097,7 @@ impl Parser {
     }
 }
 
-fn synchronize(iter: &mut Peekable<Iter<'_, Token>>) {
+fn synchronize(iter: &mut Peekable<Iter<'_, Rc<Token>>>) {
     let error_token = iter.next();
     if let Some(token) = error_token {
         if token.token_type == TokenType::Semicolon {
@@ -893,6 +1115,10 @@ fn synchronize(iter: &mut Peekable<Iter<'_, Token>>) {
             TokenType::While |
             TokenType::Print |
             TokenType::Class |
+            TokenType::Defer |
+            TokenType::Throw |
+            TokenType::Try |
+            TokenType::Assert |
             TokenType::Return => {
                 // next statement reached
                 return;
@@ -931,7 +1157,7 @@ mod tests {
 
             match e {
                 Literal::Number(n) => n.to_string(),
-                Literal::String(s) => s.clone(),
+                Literal::String(s) => s.to_string(),
                 Literal::True => "true".to_owned(),
                 Literal::False => "false".to_owned(),
                 Literal::Nil => "nil".to_owned(),
@@ -941,28 +1167,28 @@ mod tests {
         fn visit_unary(&mut self, e: &expression::Unary) -> String {
             format!("({} {})",
                     e.operator.lexeme,
-                    e.right.accept_string(self),
+                    e.right.accept(self),
             )
         }
 
         fn visit_binary(&mut self, e: &expression::Binary) -> String {
             format!("({} {} {})",
                     e.operator.lexeme,
-                    e.left.accept_string(self),
-                    e.right.accept_string(self),
+                    e.left.accept(self),
+                    e.right.accept(self),
             )
         }
 
         fn visit_logical(&mut self, e: &expression::Logical) -> String {
             format!("({} {} {})",
                     e.operator.lexeme,
-                    e.left.accept_string(self),
-                    e.right.accept_string(self),
+                    e.left.accept(self),
+                    e.right.accept(self),
             )
         }
 
         fn visit_grouping(&mut self, e: &expression::Grouping) -> String {
-            format!("(group {})", e.0.accept_string(self))
+            format!("(group {})", e.0.accept(self))
         }
 
         fn visit_variable(&mut self, e: &expression::Variable) -> String {
@@ -970,12 +1196,12 @@ mod tests {
         }
 
         fn visit_assignment(&mut self, e: &Assignment) -> String {
-            format!("(:= {} {})", e.name.lexeme, e.value.accept_string(self))
+            format!("(:= {} {})", e.name.lexeme, e.value.accept(self))
         }
 
         fn visit_call(&mut self, e: &Call) -> String {
             let args_str = e.args.iter()
-                .map(|a| a.accept_string(self))
+                .map(|a| a.accept(self))
                 .fold(None, |acc, x| {
                     match acc {
                         None => Some(x),
@@ -986,7 +1212,7 @@ mod tests {
 
             format!(
                 "(call {} {})",
-                e.callee.accept_string(self),
+                e.callee.accept(self),
                 args_str,
             )
         }
@@ -994,7 +1220,7 @@ mod tests {
         fn visit_get(&mut self, e: &Get) -> String {
             format!(
                 "(get {} {})",
-                e.object.accept_string(self),
+                e.object.accept(self),
                 &e.name.lexeme,
             )
         }
@@ -1002,9 +1228,9 @@ mod tests {
         fn visit_set(&mut self, e: &Set) -> String {
             format!(
                 "(set {} {} {})",
-                e.object.accept_string(self),
+                e.object.accept(self),
                 &e.name.lexeme,
-                e.value.accept_string(self),
+                e.value.accept(self),
             )
         }
 
@@ -1015,6 +1241,39 @@ mod tests {
         fn visit_super(&mut self, _: &Super) -> String {
             "super".to_owned()
         }
+
+        fn visit_delete(&mut self, e: &expression::Delete) -> String {
+            format!(
+                "(delete {} {})",
+                e.object.accept(self),
+                &e.name.lexeme,
+            )
+        }
+
+        fn visit_type_test(&mut self, e: &expression::TypeTest) -> String {
+            format!(
+                "(is {} {})",
+                e.value.accept(self),
+                e.type_expr.accept(self),
+            )
+        }
+
+        fn visit_index(&mut self, e: &Index) -> String {
+            format!(
+                "(index {} {})",
+                e.object.accept(self),
+                e.index.accept(self),
+            )
+        }
+
+        fn visit_set_index(&mut self, e: &SetIndex) -> String {
+            format!(
+                "(set-index {} {} {})",
+                e.object.accept(self),
+                e.index.accept(self),
+                e.value.accept(self),
+            )
+        }
     }
 
     #[test]
@@ -1057,7 +1316,7 @@ mod tests {
 
         assert!(expr.is_ok());
         if expr.is_ok() {
-            let str = expr.unwrap().accept_string(&mut PrintVisitor{});
+            let str = expr.unwrap().accept(&mut PrintVisitor{});
             assert_eq!(str, "(group nil)");
         }
     }
@@ -1069,7 +1328,7 @@ mod tests {
 
         assert!(expr.is_ok());
         if expr.is_ok() {
-            let str = expr.unwrap().accept_string(&mut PrintVisitor{});
+            let str = expr.unwrap().accept(&mut PrintVisitor{});
             assert_eq!(str, "(- (- (- 12.5)))");
         }
     }
@@ -1081,7 +1340,7 @@ mod tests {
 
         assert!(expr.is_ok());
         if expr.is_ok() {
-            let str = expr.unwrap().accept_string(&mut PrintVisitor{});
+            let str = expr.unwrap().accept(&mut PrintVisitor{});
             assert_eq!(str, "(/ (* 2 3) (- 2))");
         }
     }
@@ -1093,7 +1352,7 @@ mod tests {
 
         assert!(expr.is_ok());
         if expr.is_ok() {
-            let str = expr.unwrap().accept_string(&mut PrintVisitor{});
+            let str = expr.unwrap().accept(&mut PrintVisitor{});
             assert_eq!(str, "(or true (and false true))");
         }
     }
@@ -1105,7 +1364,7 @@ mod tests {
 
         assert!(expr.is_ok());
         if expr.is_ok() {
-            let str = expr.unwrap().accept_string(&mut PrintVisitor{});
+            let str = expr.unwrap().accept(&mut PrintVisitor{});
             assert_eq!(str, "(+ (- 2 3) (* 5 (- 2)))");
         }
     }
@@ -1117,11 +1376,52 @@ mod tests {
 
         assert!(expr.is_ok());
         if expr.is_ok() {
-            let str = expr.unwrap().accept_string(&mut PrintVisitor{});
+            let str = expr.unwrap().accept(&mut PrintVisitor{});
             assert_eq!(str, "(> 2 (- (* 3 2) 10))");
         }
     }
 
+    #[test]
+    fn parse_type_test() {
+        let parser = Parser::new(&scan("a is B == 1 + 2 > 3 is C").unwrap());
+        let expr = parser.parse_single_expr();
+
+        assert!(expr.is_ok());
+        if let Ok(expr) = expr {
+            let str = expr.accept(&mut PrintVisitor{});
+            assert_eq!(str, "(== (is a B) (is (> (+ 1 2) 3) C))");
+        }
+    }
+
+    #[test]
+    fn parse_div_and_mod() {
+        let parser = Parser::new(&scan("a * b div c % d").unwrap());
+        let expr = parser.parse_single_expr();
+
+        assert!(expr.is_ok());
+        if let Ok(expr) = expr {
+            let str = expr.accept(&mut PrintVisitor{});
+            assert_eq!(str, "(% (div (* a b) c) d)");
+        }
+    }
+
+    #[test]
+    fn parse_delete() {
+        let parser = Parser::new(&scan("!delete a.b.c").unwrap());
+        let expr = parser.parse_single_expr();
+
+        assert!(expr.is_ok());
+        if let Ok(expr) = expr {
+            let str = expr.accept(&mut PrintVisitor{});
+            assert_eq!(str, "(! (delete (get a b) c))");
+        }
+
+        for src in ["delete a;", "delete a.f();", "delete;"].iter() {
+            let tokens = scan(src).unwrap();
+            assert!(Parser::new(&tokens).parse().is_err());
+        }
+    }
+
     #[test]
     fn parse_equality() {
         let parser = Parser::new(&scan("2 > 3 * 2 - 10 == false").unwrap());
@@ -1129,7 +1429,7 @@ mod tests {
 
         assert!(expr.is_ok());
         if expr.is_ok() {
-            let str = expr.unwrap().accept_string(&mut PrintVisitor{});
+            let str = expr.unwrap().accept(&mut PrintVisitor{});
             assert_eq!(str, "(== (> 2 (- (* 3 2) 10)) false)");
         }
     }
@@ -1252,6 +1552,9 @@ mod tests {
             "for (;; i = i + 1) print i;",
 
             "for (;;) print i;",
+
+            "for (var x in xs) print x;",
+            "for (var x in f(1, 2)) { print x; }",
         ];
 
         for src in valid_sources.iter() {
@@ -1268,6 +1571,9 @@ mod tests {
             "for i = 0; i > 0; i = i + 1) {}",
             "for (i = 0 i > 0; i = i + 1) {}",
             "for () {}",
+            "for (x in xs) {}",
+            "for (var x in) {}",
+            "for (var x in xs {}",
         ];
 
         for src in invalid_sources.iter() {
@@ -1297,7 +1603,7 @@ mod tests {
         let expr = Parser::new(&tokens).parse_single_expr();
 
         assert!(expr.is_ok());
-        let s = expr.unwrap().accept_string(&mut PrintVisitor{});
+        let s = expr.unwrap().accept(&mut PrintVisitor{});
         assert_eq!(s, "(call (call my_fun 1) 2)");
     }
 
@@ -1349,4 +1655,78 @@ mod tests {
             assert!(Parser::new(&tokens).parse().is_err());
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn parse_defer_stmt() {
+        let valid_sources = [
+            "defer print x;",
+            "defer { print x; }",
+            "defer close(f);",
+        ];
+
+        for src in valid_sources.iter() {
+            let tokens = scan(src).unwrap();
+            assert!(Parser::new(&tokens).parse().is_ok());
+        }
+
+        let tokens = scan("defer;").unwrap();
+        assert!(Parser::new(&tokens).parse().is_err());
+    }
+
+    #[test]
+    fn parse_try_stmt() {
+        let valid_sources = [
+            "throw x;",
+            "try { f(); } catch (e) { print e; }",
+            "try { f(); } finally { g(); }",
+            "try { f(); } catch (e) { } finally { g(); }",
+        ];
+
+        for src in valid_sources.iter() {
+            let tokens = scan(src).unwrap();
+            assert!(Parser::new(&tokens).parse().is_ok());
+        }
+
+        let invalid_sources = [
+            "throw;",
+            "try { f(); }",
+            "try f(); catch (e) { }",
+            "try { f(); } catch { }",
+            "try { f(); } catch (e) print e;",
+        ];
+
+        for src in invalid_sources.iter() {
+            let tokens = scan(src).unwrap();
+            assert!(Parser::new(&tokens).parse().is_err());
+        }
+    }
+
+    #[test]
+    fn parse_assert_stmt() {
+        for src in ["assert x;", "assert x == 1, \"message\";"].iter() {
+            let tokens = scan(src).unwrap();
+            assert!(Parser::new(&tokens).parse().is_ok());
+        }
+
+        for src in ["assert;", "assert x,;", "assert x, \"a\", \"b\";", "assert x"].iter() {
+            let tokens = scan(src).unwrap();
+            assert!(Parser::new(&tokens).parse().is_err());
+        }
+    }
+
+    #[test]
+    fn parse_subscripts() {
+        let parser = Parser::new(&scan("s[i + 1][0]").unwrap());
+        let str = parser.parse_single_expr().unwrap().accept(&mut PrintVisitor{});
+        assert_eq!(str, "(index (index s (+ i 1)) 0)");
+
+        let parser = Parser::new(&scan("a.b[0] = c").unwrap());
+        let str = parser.parse_single_expr().unwrap().accept(&mut PrintVisitor{});
+        assert_eq!(str, "(set-index (get a b) 0 c)");
+
+        for src in ["s[];", "s[0;", "s[0]] = 1;"].iter() {
+            let tokens = scan(src).unwrap();
+            assert!(Parser::new(&tokens).parse().is_err());
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use std::fmt::Display;
+
+// The numbers from `start` up to, but not including, `end`, `step` apart.
+// They are counted down when `step` is negative. Ranges are computed as they
+// are iterated rather than stored, so their size does not cost memory.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Range {
+    start: f64,
+    end: f64,
+    step: f64,
+}
+
+impl Range {
+    // `start` and `step` must be finite and `step` not zero,
+    // `end` may be infinite for a range that does not end.
+    pub fn new(start: f64, end: f64, step: f64) -> Self {
+        debug_assert!(start.is_finite() && step.is_finite() && step != 0_f64 && !end.is_nan());
+        Range { start, end, step }
+    }
+
+    // The number at `index`, None past the end of the range.
+    // Computed from the start so steps like 0.1 don't add up rounding errors.
+    pub fn get(&self, index: usize) -> Option<f64> {
+        let n = self.start + index as f64 * self.step;
+        let in_range = if self.step > 0_f64 { n < self.end } else { n > self.end };
+        in_range.then_some(n)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.get(0).is_none()
+    }
+}
+
+impl Display for Range {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "range({}, {}, {})", self.start, self.end, self.step)
+    }
+}
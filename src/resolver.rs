@@ -11,8 +11,10 @@ use crate::{
         TokenType,
         Token,
     },
+    Rc,
 };
 use std::collections::HashMap;
+use std::fmt::Display;
 
 #[derive(Copy, Clone, PartialEq)]
 enum VarInitializerState {
@@ -22,7 +24,7 @@ enum VarInitializerState {
 
 #[derive(Clone)]
 struct LocalVarState {
-    var_name: Token,
+    var_name: Rc<Token>,
     init_state: VarInitializerState,
     used: bool,
 }
@@ -42,24 +44,94 @@ pub struct Resolver {
     errors: Vec<ResolutionError>,
     warnings: Vec<Warning>,
     context: Vec<Context>,
+    // for each function being resolved, whether it declares a function or class
+    creates_closures: Vec<bool>,
 }
 
 #[derive(Debug, Clone)]
 pub enum ResolutionError {
-    VariableAlreadyDeclared(Token),
-    CantReadLocalVarInItsInitializer(Token),
-    ReturnNotInFunction(Token),
-    CantReturnValueFromAnInitializer(Token),
-    BreakNotInLoop(Token),
-    ThisNotInsideClass(Token),
-    ClassCantInheritFromItself(Token),
-    SuperOutsideClass(Token),
-    SuperInsideClassWithNoSuperClass(Token),
+    VariableAlreadyDeclared(Rc<Token>),
+    CantReadLocalVarInItsInitializer(Rc<Token>),
+    ReturnNotInFunction(Rc<Token>),
+    CantReturnValueFromAnInitializer(Rc<Token>),
+    BreakNotInLoop(Rc<Token>),
+    ThisNotInsideClass(Rc<Token>),
+    ClassCantInheritFromItself(Rc<Token>),
+    SuperOutsideClass(Rc<Token>),
+    SuperInsideClassWithNoSuperClass(Rc<Token>),
 }
 
 #[derive(Debug, Clone)]
 pub enum Warning {
-    UnusedLocalVar(Token),
+    UnusedLocalVar(Rc<Token>),
+}
+
+impl ResolutionError {
+    // The token the error is reported at.
+    pub fn token(&self) -> &Token {
+        match self {
+            ResolutionError::VariableAlreadyDeclared(token) |
+            ResolutionError::CantReadLocalVarInItsInitializer(token) |
+            ResolutionError::ReturnNotInFunction(token) |
+            ResolutionError::CantReturnValueFromAnInitializer(token) |
+            ResolutionError::BreakNotInLoop(token) |
+            ResolutionError::ThisNotInsideClass(token) |
+            ResolutionError::ClassCantInheritFromItself(token) |
+            ResolutionError::SuperOutsideClass(token) |
+            ResolutionError::SuperInsideClassWithNoSuperClass(token) => token,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            ResolutionError::VariableAlreadyDeclared(token) => {
+                format!("Variable '{}' already declared", token.lexeme)
+            },
+            ResolutionError::CantReadLocalVarInItsInitializer(_) => {
+                "Can't read a local variable in its initializer".to_owned()
+            },
+            ResolutionError::ReturnNotInFunction(_) => {
+                "'return' outside function".to_owned()
+            },
+            ResolutionError::CantReturnValueFromAnInitializer(_) => {
+                "Can't return a value from an initializer".to_owned()
+            },
+            ResolutionError::BreakNotInLoop(_) => {
+                "'break' outside loop".to_owned()
+            },
+            ResolutionError::ThisNotInsideClass(_) => {
+                "Can't use 'this' outside of a class".to_owned()
+            },
+            ResolutionError::ClassCantInheritFromItself(_) => {
+                "A Class can't inherit from itself".to_owned()
+            },
+            ResolutionError::SuperOutsideClass(_) => {
+                "Can't use 'super' outside of class".to_owned()
+            },
+            ResolutionError::SuperInsideClassWithNoSuperClass(_) => {
+                "Can't use 'super' in a class with no superclass".to_owned()
+            },
+        }
+    }
+}
+
+impl Display for ResolutionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let token = self.token();
+        write!(f, "Resolution error at line {}, column {}: {}.", token.line, token.column, self.message())
+    }
+}
+
+impl std::error::Error for ResolutionError { }
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Warning::UnusedLocalVar(v) => {
+                write!(f, "Warning at line {}, column {}: Unused local variable '{}'.", v.line, v.column, v.lexeme)
+            },
+        }
+    }
 }
 
 pub struct ResolutionResult {
@@ -74,10 +146,11 @@ impl Resolver {
             errors: Vec::new(),
             warnings: Vec::new(),
             context: Vec::new(),
+            creates_closures: Vec::new(),
         }
     }
 
-    pub fn resolve_single_expr(&mut self, expr: &mut Box<dyn Expr>) -> Result<(), Vec<ResolutionError>> {
+    pub fn resolve_single_expr(&mut self, expr: &mut Expr) -> Result<(), Vec<ResolutionError>> {
         self.resolve_expr(expr);
         self.warnings.clear();
 
@@ -89,7 +162,7 @@ impl Resolver {
         }
     }
 
-    pub fn resolve(&mut self, stmts: &mut Vec<Box<dyn Stmt>>) -> ResolutionResult {
+    pub fn resolve(&mut self, stmts: &mut Vec<Stmt>) -> ResolutionResult {
         self.resolve_stmts(stmts);
 
         let mut result = ResolutionResult {
@@ -111,18 +184,18 @@ impl Resolver {
         result
     }
 
-    fn resolve_stmts(&mut self, stmts: &mut Vec<Box<dyn Stmt>>) {
+    fn resolve_stmts(&mut self, stmts: &mut Vec<Stmt>) {
         for s in stmts {
             self.resolve_stmt(s)
         }
     }
 
-    fn resolve_stmt(&mut self, stmt: &mut Box<dyn Stmt>) {
-        stmt.accept_resolve(self)
+    fn resolve_stmt(&mut self, stmt: &mut Stmt) {
+        stmt.accept_mut(self)
     }
 
-    fn resolve_expr(&mut self, expr: &mut Box<dyn Expr>) {
-        expr.accept_resolve(self)
+    fn resolve_expr(&mut self, expr: &mut Expr) {
+        expr.accept_mut(self)
     }
 
     fn begin_scope(&mut self) {
@@ -150,7 +223,7 @@ impl Resolver {
         self.errors.push(e);
     }
 
-    fn declare(&mut self, name: &Token) {
+    fn declare(&mut self, name: &Rc<Token>) {
         match self.scopes.last_mut() {
             Some(scope) => {
                 if scope.contains_key(&name.lexeme) {
@@ -171,7 +244,7 @@ impl Resolver {
         }
     }
 
-    fn define(&mut self, name: &Token) {
+    fn define(&mut self, name: &Rc<Token>) {
         if let Some(scope) = self.scopes.last_mut() {
             scope.insert(
                 name.lexeme.clone(),
@@ -185,13 +258,13 @@ impl Resolver {
     }
 
     fn define_this(&mut self) {
-        let name = Token {
+        let name = Rc::new(Token {
             token_type: TokenType::This,
             lexeme: "this".to_owned(),
             literal: None,
             line: 0,
             column: 0,
-        };
+        });
 
         if let Some(scope) = self.scopes.last_mut() {
             scope.insert(
@@ -206,13 +279,13 @@ impl Resolver {
     }
 
     fn define_super(&mut self) {
-        let name = Token {
+        let name = Rc::new(Token {
             token_type: TokenType::Super,
             lexeme: "super".to_owned(),
             literal: None,
             line: 0,
             column: 0,
-        };
+        });
 
         if let Some(scope) = self.scopes.last_mut() {
             scope.insert(
@@ -242,13 +315,40 @@ impl Resolver {
 
     fn resolve_function(&mut self, f: &mut statement::Function) {
         self.begin_scope();
+        self.declare_params(f);
+        self.resolve_body(f);
+        self.end_scope();
+    }
+
+    // 'this' lives in the same scope as the parameters of a method
+    fn resolve_method(&mut self, f: &mut statement::Function) {
+        self.begin_scope();
+        self.define_this();
+        self.declare_params(f);
+        self.resolve_body(f);
+        self.end_scope();
+    }
+
+    fn resolve_body(&mut self, f: &mut statement::Function) {
+        self.creates_closures.push(false);
+        self.resolve_stmts(&mut f.body);
+        f.creates_closures = self.creates_closures.pop().unwrap_or(true);
+    }
+
+    // Marks the function being resolved as one whose calls
+    // can have their environment captured.
+    fn closure_declared(&mut self) {
+        if let Some(creates) = self.creates_closures.last_mut() {
+            *creates = true;
+        }
+    }
+
+    fn declare_params(&mut self, f: &statement::Function) {
         for p in &f.params {
             self.declare(p);
             self.define(p);
         }
-        self.resolve_stmts(&mut f.body);
-        self.end_scope();
-     }
+    }
 }
 
 impl expression::MutVisitor<()> for Resolver {
@@ -345,10 +445,36 @@ impl expression::MutVisitor<()> for Resolver {
             column: 0
         });
     }
+
+    fn visit_delete(&mut self, e: &mut expression::Delete) {
+        self.resolve_expr(&mut e.object);
+    }
+
+    fn visit_type_test(&mut self, e: &mut expression::TypeTest) {
+        self.resolve_expr(&mut e.value);
+        self.resolve_expr(&mut e.type_expr);
+    }
+
+    fn visit_index(&mut self, e: &mut expression::Index) {
+        self.resolve_expr(&mut e.object);
+        self.resolve_expr(&mut e.index);
+    }
+
+    fn visit_set_index(&mut self, e: &mut expression::SetIndex) {
+        self.resolve_expr(&mut e.value);
+        self.resolve_expr(&mut e.object);
+        self.resolve_expr(&mut e.index);
+    }
 }
 
 impl statement::MutVisitor<()> for Resolver {
     fn visit_block(&mut self, s: &mut statement::Block) {
+        // must agree with the interpreter on which blocks have a scope
+        if !s.needs_scope() {
+            self.resolve_stmts(&mut s.statements);
+            return;
+        }
+
         self.begin_scope();
         self.resolve_stmts(&mut s.statements);
         self.end_scope();
@@ -363,6 +489,7 @@ impl statement::MutVisitor<()> for Resolver {
     }
 
     fn visit_function(&mut self, s: &mut statement::Function) {
+        self.closure_declared();
         self.context.push(Context::Function);
 
         self.declare(&s.name);
@@ -439,7 +566,57 @@ impl statement::MutVisitor<()> for Resolver {
         self.context.pop();
     }
 
+    fn visit_for_in(&mut self, s: &mut statement::ForIn) {
+        self.resolve_expr(&mut s.iterable);
+
+        self.context.push(Context::Loop);
+        // the loop variable lives in a scope around the body
+        self.begin_scope();
+        self.declare(&s.name);
+        self.define(&s.name);
+        self.resolve_stmt(&mut s.body);
+        self.end_scope();
+        self.context.pop();
+    }
+
+    fn visit_defer(&mut self, s: &mut statement::Defer) {
+        self.resolve_stmt(&mut s.body);
+    }
+
+    fn visit_throw(&mut self, s: &mut statement::Throw) {
+        self.resolve_expr(&mut s.value);
+    }
+
+    fn visit_assert(&mut self, s: &mut statement::Assert) {
+        self.resolve_expr(&mut s.condition);
+        if let Some(message) = &mut s.message {
+            self.resolve_expr(message);
+        }
+    }
+
+    fn visit_try(&mut self, s: &mut statement::Try) {
+        self.begin_scope();
+        self.resolve_stmts(&mut s.body);
+        self.end_scope();
+
+        if let Some(catch) = &mut s.catch {
+            // the caught value lives in the scope of the catch block
+            self.begin_scope();
+            self.declare(&catch.name);
+            self.define(&catch.name);
+            self.resolve_stmts(&mut catch.body);
+            self.end_scope();
+        }
+
+        if let Some(finally) = &mut s.finally {
+            self.begin_scope();
+            self.resolve_stmts(finally);
+            self.end_scope();
+        }
+    }
+
     fn visit_class(&mut self, s: &mut statement::Class) {
+        self.closure_declared();
         if s.super_class.is_none() {
             self.context.push(Context::Class);
         }
@@ -454,6 +631,7 @@ impl statement::MutVisitor<()> for Resolver {
         if let Some(sup) = &mut s.super_class {
             if sup.name.lexeme == s.name.lexeme {
                 self.add_err(ResolutionError::ClassCantInheritFromItself(sup.name.clone()));
+                self.context.pop(); // class
                 return;
             }
 
@@ -465,9 +643,6 @@ impl statement::MutVisitor<()> for Resolver {
             self.define_super();
         }
 
-        self.begin_scope(); // this
-        self.define_this();
-
         for m in &mut s.methods {
             let method_context = 
                 if m.name.lexeme != "init" {
@@ -480,13 +655,11 @@ impl statement::MutVisitor<()> for Resolver {
 
             // self.declare(&s.name);
             // self.define(&s.name);
-            self.resolve_function(m);
+            self.resolve_method(m);
 
             self.context.pop(); // method
         }
 
-        self.end_scope(); // this
-
         if s.super_class.is_some() {
             self.end_scope(); // super
         }
@@ -0,0 +1,131 @@
+use crate::{
+    interpreter::{Interpreter, Traced},
+    parser::{Parser, ParseError},
+    resolver::{Resolver, ResolutionError, Warning},
+    scanner::{self, ScanError, write_lines},
+    statement::Stmt,
+    RuntimeError,
+    RuntimeValue,
+};
+use std::fmt::Display;
+
+// What stops source code from running to the end, by the phase that found it.
+#[derive(Debug)]
+pub enum LoxError {
+    Scan(ScanError),
+    Parse(Vec<ParseError>),
+    Resolution(Vec<ResolutionError>),
+    Runtime(Traced<RuntimeError>),
+}
+
+// One line per error, each with its position. A runtime error is
+// followed by a line per call it was raised in.
+impl Display for LoxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoxError::Scan(e) => write!(f, "{}", e),
+            LoxError::Parse(errs) => write_lines(f, errs),
+            LoxError::Resolution(errs) => write_lines(f, errs),
+            LoxError::Runtime(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+// The phases that can find several errors at once have no single source.
+impl std::error::Error for LoxError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoxError::Scan(e) => Some(e),
+            LoxError::Runtime(e) => Some(&e.error),
+            LoxError::Parse(_) | LoxError::Resolution(_) => None,
+        }
+    }
+}
+
+impl From<ScanError> for LoxError {
+    fn from(e: ScanError) -> Self {
+        LoxError::Scan(e)
+    }
+}
+
+impl From<Vec<ParseError>> for LoxError {
+    fn from(errs: Vec<ParseError>) -> Self {
+        LoxError::Parse(errs)
+    }
+}
+
+impl From<Vec<ResolutionError>> for LoxError {
+    fn from(errs: Vec<ResolutionError>) -> Self {
+        LoxError::Resolution(errs)
+    }
+}
+
+impl From<Traced<RuntimeError>> for LoxError {
+    fn from(e: Traced<RuntimeError>) -> Self {
+        LoxError::Runtime(e)
+    }
+}
+
+// Statements ready to be executed, with what the resolver warned about.
+pub struct Program {
+    pub statements: Vec<Stmt>,
+    pub warnings: Vec<Warning>,
+}
+
+// Scans and parses `source`, leaving it unresolved.
+pub fn parse_source(source: &str) -> Result<Vec<Stmt>, LoxError> {
+    let tokens = scanner::scan(source)?;
+    Ok(Parser::new(&tokens).parse()?)
+}
+
+// Scans, parses and resolves `source`. The warnings of programs
+// that do not resolve are dropped along with the program.
+pub fn compile(source: &str, resolver: &mut Resolver) -> Result<Program, LoxError> {
+    let mut statements = parse_source(source)?;
+
+    let result = resolver.resolve(&mut statements);
+    if let Some(errs) = result.errors {
+        return Err(LoxError::Resolution(errs));
+    }
+
+    Ok(Program {
+        statements,
+        warnings: result.warnings.unwrap_or_default(),
+    })
+}
+
+// Runs `source` with `interp`, which keeps what it defines, and returns the
+// value of its last statement if that is an expression statement, like
+// `Interpreter::run`. Nothing is executed if the source does not compile.
+pub fn run_source(source: &str, interp: &mut Interpreter) -> Result<Option<RuntimeValue>, LoxError> {
+    let program = compile(source, &mut Resolver::new())?;
+    Ok(interp.run(&program.statements).map_err(|e| interp.traced(e))?)
+}
+
+// Compiles `source` the way the REPL reads its input. Source missing only its
+// last semicolon, e.g. `1 + 2`, is compiled as if it had it, on a line of its
+// own in case the source ends in a comment. Otherwise the errors are those of
+// the source as given. Parsing fails before `resolver` sees any of it.
+pub fn compile_snippet(source: &str, resolver: &mut Resolver) -> Result<Program, LoxError> {
+    match compile(source, resolver) {
+        Err(LoxError::Parse(errs)) => {
+            match compile(&format!("{}\n;", source), resolver) {
+                Ok(program) => Ok(program),
+                Err(_) => Err(LoxError::Parse(errs)),
+            }
+        },
+        result => result,
+    }
+}
+
+// Runs `source` the way the REPL runs its input, returning the value to echo
+// if it ends in an expression statement. See `compile_snippet`.
+pub fn run_snippet(source: &str, interp: &mut Interpreter) -> Result<Option<RuntimeValue>, LoxError> {
+    let program = compile_snippet(source, &mut Resolver::new())?;
+    Ok(interp.run(&program.statements).map_err(|e| interp.traced(e))?)
+}
+
+// Like `run_source`, with an interpreter of its own.
+pub fn run_source_new(source: &str) -> Result<Option<RuntimeValue>, LoxError> {
+    run_source(source, &mut Interpreter::new())
+}
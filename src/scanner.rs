@@ -1,4 +1,9 @@
-use std::collections::HashMap;
+use crate::Rc;
+use std::{
+    fmt::{Display, Write},
+    iter::{Enumerate, Peekable},
+    str::Chars,
+};
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum TokenType {
@@ -7,6 +12,8 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
     Dot,
     Minus,
@@ -14,6 +21,7 @@ pub enum TokenType {
     Semicolon,
     Slash,
     Star,
+    Percent,
 
     // one or two character tokens
     Bang,
@@ -48,6 +56,16 @@ pub enum TokenType {
     True,
     Var,
     While,
+    Defer,
+    Throw,
+    Try,
+    Catch,
+    Finally,
+    Is,
+    Delete,
+    Div,
+    Assert,
+    In,
 }
 
 #[derive(Clone, Debug)]
@@ -121,7 +139,67 @@ pub enum ScanError {
     TokenError(Vec<TokenError>)
 }
 
-pub fn scan(source: &str) -> Result<Vec<Token>, ScanError> {
+impl Display for TokenErrorType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TokenErrorType::UnexpectedCharacter => write!(f, "Unexpected character found"),
+            TokenErrorType::UnterminatedString => write!(f, "Unterminated string"),
+        }
+    }
+}
+
+impl Display for TokenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Scan error at line {}, column {}: {}.", self.line, self.column, self.error)
+    }
+}
+
+// One line per error.
+impl Display for ScanError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScanError::NonAsciiCharacterFound => {
+                write!(f, "Scan error: Only ASCII characters are supported.")
+            },
+            ScanError::TokenError(errs) => write_lines(f, errs),
+        }
+    }
+}
+
+impl std::error::Error for ScanError { }
+
+// One token per line, with its type, lexeme, literal
+// value if it has one and position, e.g. `Number 1.50 1.5 2:7`.
+pub fn format_tokens(tokens: &[Rc<Token>]) -> String {
+    let mut out = String::new();
+    for token in tokens {
+        let _ = write!(out, "{:?} {}", token.token_type, token.lexeme);
+        let _ = match &token.literal {
+            Some(Literal::String(s)) => write!(out, " {:?}", s),
+            Some(Literal::Number(n)) => write!(out, " {}", n),
+            Some(Literal::Identifier(name)) => write!(out, " {}", name),
+            None => Ok(()),
+        };
+        let _ = writeln!(out, " {}:{}", token.line, token.column);
+    }
+
+    out
+}
+
+// Writes each of `items` on a line of its own.
+pub(crate) fn write_lines<T: Display>(f: &mut std::fmt::Formatter<'_>, items: &[T]) -> std::fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            writeln!(f)?;
+        }
+        write!(f, "{}", item)?;
+    }
+
+    Ok(())
+}
+
+// The tokens are shared by the trees parsed from them and the errors found in them.
+pub fn scan(source: &str) -> Result<Vec<Rc<Token>>, ScanError> {
     if source.is_ascii() {
         scan_ascii(source).map_err(|v| ScanError::TokenError(v))
     }
@@ -130,46 +208,15 @@ pub fn scan(source: &str) -> Result<Vec<Token>, ScanError> {
     }
 }
 
-fn scan_ascii(source: &str) -> Result<Vec<Token>, Vec<TokenError>> {
+fn scan_ascii(source: &str) -> Result<Vec<Rc<Token>>, Vec<TokenError>> {
     assert!(source.is_ascii(), "expected ascii source");
 
-    let keywords = HashMap::from([
-        ("and".to_owned(),    TokenType::And),
-        ("class".to_owned(),  TokenType::Class),
-        ("else".to_owned(),   TokenType::Else),
-        ("false".to_owned(),  TokenType::False),
-        ("for".to_owned(),    TokenType::For),
-        ("fun".to_owned(),    TokenType::Fun),
-        ("if".to_owned(),     TokenType::If),
-        ("nil".to_owned(),    TokenType::Nil),
-        ("or".to_owned(),     TokenType::Or),
-        ("print".to_owned(),  TokenType::Print),
-        ("return".to_owned(), TokenType::Return),
-        ("break".to_owned(),  TokenType::Break),
-        ("super".to_owned(),  TokenType::Super),
-        ("this".to_owned(),   TokenType::This),
-        ("true".to_owned(),   TokenType::True),
-        ("var".to_owned(),    TokenType::Var),
-        ("while".to_owned(),  TokenType::While),
-    ]);
-
     let mut token_result = Vec::new();
     let mut error_result = Vec::new();
 
+    // the tokens are only returned if no line has errors
     for (line_num, line) in source.lines().enumerate() {
-        let line_result = scan_ascii_line(line_num as u64, line, &keywords);
-
-        if error_result.len() > 0 {
-            if let Err(v) = line_result {
-                error_result.extend(v.into_iter());
-            }
-        }
-        else {
-            match line_result {
-                Ok(tokens) => token_result.extend(tokens.into_iter()),
-                Err(errors) => error_result.extend(errors.into_iter()),
-            }
-        }
+        scan_ascii_line(line_num as u64, line, &mut token_result, &mut error_result);
     }
 
     if error_result.len() > 0 {
@@ -180,18 +227,18 @@ fn scan_ascii(source: &str) -> Result<Vec<Token>, Vec<TokenError>> {
     }
 }
 
+// Adds the tokens of `line` to `token_result` and the errors in it to `error_result`.
 fn scan_ascii_line(
     line_num: u64,
     line: &str,
-    keywords: &HashMap<String, TokenType>,
-) -> Result<Vec<Token>, Vec<TokenError>> {
+    token_result: &mut Vec<Rc<Token>>,
+    error_result: &mut Vec<TokenError>,
+) {
     assert!(line.is_ascii(), "expected ascii source");
 
     let mut chars = line.chars().enumerate().peekable();
 
-    let mut token_result = Vec::new();
-    let mut error_result = Vec::new();
-    let mut push_token = |t| { token_result.push(t) };
+    let mut push_token = |t| { token_result.push(Rc::new(t)) };
     let mut push_error = |l, c, e| { 
         error_result.push(TokenError {
             line: l,
@@ -222,6 +269,12 @@ fn scan_ascii_line(
             '}' => {
                 push_token(Token::single_character(TokenType::RightBrace, c, user_line, user_col))
             },
+            '[' => {
+                push_token(Token::single_character(TokenType::LeftBracket, c, user_line, user_col))
+            },
+            ']' => {
+                push_token(Token::single_character(TokenType::RightBracket, c, user_line, user_col))
+            },
             ',' => {
                 push_token(Token::single_character(TokenType::Comma, c, user_line, user_col))
             },
@@ -240,6 +293,9 @@ fn scan_ascii_line(
             '*' => {
                 push_token(Token::single_character(TokenType::Star, c, user_line, user_col))
             },
+            '%' => {
+                push_token(Token::single_character(TokenType::Percent, c, user_line, user_col))
+            },
             '!' => {
                 if let Some(&(_, '=')) = chars.peek() {
                     let (_, cc) = chars.next().unwrap();
@@ -289,38 +345,27 @@ fn scan_ascii_line(
                 // ignore whitespace
             },
             '"' => {
-                let mut terminated = false;
-                let mut lexeme = c.to_string();
-                while let Some((_, cc)) = chars.next() {
-                    lexeme.push(cc);
-                    if cc == '"' {
-                        terminated = true;
-                        break;
-                    }
-                }
-
-                if terminated == false {
-                    push_error(user_line, user_col, TokenErrorType::UnterminatedString);
-                }
-                else {
-                    let literal = lexeme[1..lexeme.len() - 1].to_string();
-                    push_token(Token{
-                        token_type: TokenType::String,
-                        lexeme,
-                        literal: Some(Literal::String(literal)),
-                        line: user_line,
-                        column: user_col,
-                    })
+                let closing_quote = chars.find(|&(_, cc)| cc == '"');
+
+                match closing_quote {
+                    None => {
+                        push_error(user_line, user_col, TokenErrorType::UnterminatedString);
+                    },
+                    Some((end, _)) => {
+                        push_token(Token{
+                            token_type: TokenType::String,
+                            lexeme: line[col..=end].to_owned(),
+                            literal: Some(Literal::String(line[col + 1..end].to_owned())),
+                            line: user_line,
+                            column: user_col,
+                        })
+                    },
                 }
             },
             _ => {
                 if c.is_ascii_digit() {
-                    let mut lexeme = c.to_string();
-
                     // consume all digits
-                    while let Some((_,d)) = chars.next_if(|&(_, d)| d.is_ascii_digit()) {
-                        lexeme.push(d);
-                    }
+                    while chars.next_if(|&(_, d)| d.is_ascii_digit()).is_some() { }
 
                     if let Some(&(_, '.')) = chars.peek() {
                         let mut chars2 = chars.clone();
@@ -329,40 +374,33 @@ fn scan_ascii_line(
                         // look past the dot if there are more digits to consume
                         if let Some(&(_, d)) = chars2.peek() {
                             if d.is_ascii_digit() {
-                                let (_, dot) = chars.next().unwrap();
-                                lexeme.push(dot);
-
-                                while let Some((_,d)) = chars.next_if(|&(_, d)| d.is_ascii_digit()) {
-                                    lexeme.push(d);
-                                }
+                                let _ = chars.next();
+                                while chars.next_if(|&(_, d)| d.is_ascii_digit()).is_some() { }
                             }
                         }
                     }
 
-                    let value = lexeme.clone().parse::<f64>().unwrap();
+                    // the line is ascii, so the columns are byte offsets
+                    let lexeme = &line[col..end_of(&mut chars, line)];
+                    let value = lexeme.parse::<f64>().unwrap();
 
                     push_token(Token {
                         token_type: TokenType::Number,
-                        lexeme: lexeme,
+                        lexeme: lexeme.to_owned(),
                         literal: Some(Literal::Number(value)),
                         line: user_line,
                         column: user_col,
                     });
                 }
                 else if is_ascii_alpha(c) {
-                    let mut lexeme = c.to_string();
-                    while let Some((_,d)) = chars.next_if(|&(_, d)| is_ascii_alphanumeric(d)) {
-                        lexeme.push(d);
-                    }
+                    while chars.next_if(|&(_, d)| is_ascii_alphanumeric(d)).is_some() { }
 
-                    let mut token_type = TokenType::Identifier;
-                    if let Some((_, t)) = keywords.get_key_value(&lexeme) {
-                        token_type = *t;
-                    }
+                    let lexeme = &line[col..end_of(&mut chars, line)];
+                    let token_type = keyword(lexeme).unwrap_or(TokenType::Identifier);
 
                     push_token(Token {
                         token_type,
-                        lexeme,
+                        lexeme: lexeme.to_owned(),
                         literal: None,
                         line: user_line,
                         column: user_col,
@@ -374,14 +412,46 @@ fn scan_ascii_line(
             },
         };
     }
+}
 
-    if error_result.len() > 0 {
-        Err(error_result)
-    }
-    else {
-        Ok(token_result)
-    }
-    
+// The offset in `line` of the next character, the length of the line if there is none.
+fn end_of(chars: &mut Peekable<Enumerate<Chars>>, line: &str) -> usize {
+    chars.peek().map_or(line.len(), |&(i, _)| i)
+}
+
+fn keyword(lexeme: &str) -> Option<TokenType> {
+    let token_type = match lexeme {
+        "and"     => TokenType::And,
+        "class"   => TokenType::Class,
+        "else"    => TokenType::Else,
+        "false"   => TokenType::False,
+        "for"     => TokenType::For,
+        "fun"     => TokenType::Fun,
+        "if"      => TokenType::If,
+        "nil"     => TokenType::Nil,
+        "or"      => TokenType::Or,
+        "print"   => TokenType::Print,
+        "return"  => TokenType::Return,
+        "break"   => TokenType::Break,
+        "super"   => TokenType::Super,
+        "this"    => TokenType::This,
+        "true"    => TokenType::True,
+        "var"     => TokenType::Var,
+        "while"   => TokenType::While,
+        "defer"   => TokenType::Defer,
+        "throw"   => TokenType::Throw,
+        "try"     => TokenType::Try,
+        "catch"   => TokenType::Catch,
+        "finally" => TokenType::Finally,
+        "is"      => TokenType::Is,
+        "delete"  => TokenType::Delete,
+        "div"     => TokenType::Div,
+        "assert"  => TokenType::Assert,
+        "in"      => TokenType::In,
+        _ => return None,
+    };
+
+    Some(token_type)
 }
 
 fn is_ascii_alpha(c: char) -> bool {
@@ -468,4 +538,29 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn tokens_are_formatted_one_per_line() {
+        let tokens = scan("var s = \"hi there\";\nif (s != nil and 1.50 >= 2) print s;").expect("scan failed");
+        assert_eq!(format_tokens(&tokens), "\
+Var var 1:1
+Identifier s 1:5
+Equal = 1:7
+String \"hi there\" \"hi there\" 1:9
+Semicolon ; 1:19
+If if 2:1
+LeftParen ( 2:4
+Identifier s 2:5
+BangEqual != 2:7
+Nil nil 2:10
+And and 2:14
+Number 1.50 1.5 2:18
+GreaterEqual >= 2:23
+Number 2 2 2:26
+RightParen ) 2:27
+Print print 2:29
+Identifier s 2:35
+Semicolon ; 2:36
+");
+    }
 }
\ No newline at end of file
@@ -1,69 +1,159 @@
 use crate::{
     expression::{self, Expr},
     scanner::Token,
-    RuntimeError,
+    CallableWrapper,
     RuntimeValue,
+    Rc,
+    OnceCell,
 };
 
 #[derive(Clone)]
 pub struct Expression {
-    pub expr: Box<dyn Expr>,
+    pub expr: Expr,
 }
 
 #[derive(Clone)]
 pub struct Print {
-    pub expr: Box<dyn Expr>,
+    pub keyword: Rc<Token>,
+    pub expr: Expr,
 }
 
 #[derive(Clone)]
 pub struct Variable {
-    pub name: Token,
-    pub initializer: Option<Box<dyn Expr>>, 
+    pub name: Rc<Token>,
+    pub initializer: Option<Expr>,
 }
 
 #[derive(Clone)]
 pub struct Block {
-    pub statements: Vec<Box<dyn Stmt>>,
+    pub statements: Vec<Stmt>,
+}
+
+impl Block {
+    // Blocks that declare nothing run in the scope around them,
+    // which saves creating an environment each time they run.
+    pub fn needs_scope(&self) -> bool {
+        self.statements.iter().any(|s| s.declares())
+    }
 }
 
 #[derive(Clone)]
 pub struct If {
-    pub cond: Box<dyn Expr>,
-    pub then_branch: Box<dyn Stmt>,
-    pub else_branch: Option<Box<dyn Stmt>>,
+    pub keyword: Rc<Token>,
+    pub cond: Expr,
+    pub then_branch: Box<Stmt>,
+    pub else_branch: Option<Box<Stmt>>,
 }
 
 #[derive(Clone)]
 pub struct While {
-    pub cond: Box<dyn Expr>,
-    pub body: Box<dyn Stmt>,
+    // the 'while' or 'for' keyword
+    pub keyword: Rc<Token>,
+    pub cond: Expr,
+    pub body: Box<Stmt>,
+}
+
+// for (var name in iterable) body
+#[derive(Clone)]
+pub struct ForIn {
+    // the 'for' keyword
+    pub keyword: Rc<Token>,
+    pub name: Rc<Token>,
+    pub iterable: Expr,
+    pub body: Box<Stmt>,
 }
 
 #[derive(Clone)]
 pub struct Function {
-    pub name: Token,
-    pub params: Vec<Token>,
-    pub body: Vec<Box<dyn Stmt>>,
+    pub name: Rc<Token>,
+    pub params: Vec<Rc<Token>>,
+    pub body: Vec<Stmt>,
+    // Whether the body declares functions or classes, whose closures can
+    // keep the environment of a call alive after it returns. Assumed
+    // until the resolver finds otherwise.
+    pub creates_closures: bool,
+    // the copy shared by the functions created from this declaration
+    pub shared: OnceCell<Rc<Function>>,
+}
+
+impl Function {
+    // Copied the first time a function is created from the declaration,
+    // which happens after it was resolved, and shared from then on.
+    pub fn shared(&self) -> Rc<Function> {
+        self.shared.get_or_init(|| Rc::new(self.clone())).clone()
+    }
 }
 
 #[derive(Clone)]
 pub struct Break {
-    pub keyword: Token,
+    pub keyword: Rc<Token>,
 }
 
 #[derive(Clone)]
 pub struct Return {
-    pub keyword: Token,
-    pub value: Option<Box<dyn Expr>>,
+    pub keyword: Rc<Token>,
+    pub value: Option<Expr>,
+}
+
+#[derive(Clone)]
+pub struct Defer {
+    pub keyword: Rc<Token>,
+    pub body: Box<Stmt>,
+}
+
+#[derive(Clone)]
+pub struct Throw {
+    pub keyword: Rc<Token>,
+    pub value: Expr,
+}
+
+#[derive(Clone)]
+pub struct Assert {
+    pub keyword: Rc<Token>,
+    pub condition: Expr,
+    pub message: Option<Box<Expr>>,
+}
+
+#[derive(Clone)]
+pub struct Catch {
+    pub name: Rc<Token>,
+    pub body: Vec<Stmt>,
+}
+
+#[derive(Clone)]
+pub struct Try {
+    pub keyword: Rc<Token>,
+    pub body: Vec<Stmt>,
+    pub catch: Option<Catch>,
+    pub finally: Option<Vec<Stmt>>,
 }
 
 #[derive(Clone)]
 pub struct Class {
-    pub name: Token,
+    pub name: Rc<Token>,
     pub super_class: Option<expression::Variable>,
     pub methods: Vec<Function>,
 }
 
+#[derive(Clone)]
+pub enum Stmt {
+    Expression(Expression),
+    Print(Print),
+    Variable(Variable),
+    Block(Block),
+    If(If),
+    While(While),
+    ForIn(ForIn),
+    Break(Break),
+    Return(Return),
+    Function(Function),
+    Class(Class),
+    Defer(Defer),
+    Throw(Throw),
+    Try(Try),
+    Assert(Assert),
+}
+
 pub trait Visitor<T> {
     fn visit_expr(&mut self, s: &Expression) -> T;
     fn visit_print(&mut self, s: &Print) -> T;
@@ -71,10 +161,15 @@ pub trait Visitor<T> {
     fn visit_block(&mut self, s: &Block) -> T;
     fn visit_if(&mut self, s: &If) -> T;
     fn visit_while(&mut self, s: &While) -> T;
+    fn visit_for_in(&mut self, s: &ForIn) -> T;
     fn visit_break(&mut self, s: &Break) -> T;
     fn visit_return(&mut self, s: &Return) -> T;
     fn visit_function(&mut self, s: &Function) -> T;
     fn visit_class(&mut self, s: &Class) -> T;
+    fn visit_defer(&mut self, s: &Defer) -> T;
+    fn visit_throw(&mut self, s: &Throw) -> T;
+    fn visit_try(&mut self, s: &Try) -> T;
+    fn visit_assert(&mut self, s: &Assert) -> T;
 }
 
 pub trait MutVisitor<T> {
@@ -84,113 +179,120 @@ pub trait MutVisitor<T> {
     fn visit_block(&mut self, s: &mut Block) -> T;
     fn visit_if(&mut self, s: &mut If) -> T;
     fn visit_while(&mut self, s: &mut While) -> T;
+    fn visit_for_in(&mut self, s: &mut ForIn) -> T;
     fn visit_break(&mut self, s: &mut Break) -> T;
     fn visit_return(&mut self, s: &mut Return) -> T;
     fn visit_function(&mut self, s: &mut Function) -> T;
     fn visit_class(&mut self, s: &mut Class) -> T;
+    fn visit_defer(&mut self, s: &mut Defer) -> T;
+    fn visit_throw(&mut self, s: &mut Throw) -> T;
+    fn visit_try(&mut self, s: &mut Try) -> T;
+    fn visit_assert(&mut self, s: &mut Assert) -> T;
 }
 
-#[derive(Clone)]
-pub enum StmtEffect {
-    Return(RuntimeValue),
-    Break,
-}
-
-type ExecResult = Result<Option<StmtEffect>, RuntimeError>;
-
-pub trait Stmt: dyn_clone::DynClone {
-    fn accept_exec(&self, v: &mut dyn Visitor<ExecResult>) -> ExecResult;
-    fn accept_resolve(&mut self, v: &mut dyn MutVisitor<()>);
-}
-
-dyn_clone::clone_trait_object!(Stmt);
-
-impl Stmt for Print {
-    fn accept_exec(&self, v: &mut dyn Visitor<ExecResult>) -> ExecResult {
-        v.visit_print(self)
-    }
-    fn accept_resolve(&mut self, v: &mut dyn MutVisitor<()>) {
-        v.visit_print(self)
-    }
-}
-
-impl Stmt for Expression {
-    fn accept_exec(&self, v: &mut dyn Visitor<ExecResult>) -> ExecResult {
-        v.visit_expr(self)
+impl Stmt {
+    // used to trace execution
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Stmt::Expression(_) => "expression",
+            Stmt::Print(_) => "print",
+            Stmt::Variable(_) => "var",
+            Stmt::Block(_) => "block",
+            Stmt::If(_) => "if",
+            Stmt::While(_) => "while",
+            Stmt::ForIn(_) => "for-in",
+            Stmt::Break(_) => "break",
+            Stmt::Return(_) => "return",
+            Stmt::Function(_) => "fun",
+            Stmt::Class(_) => "class",
+            Stmt::Defer(_) => "defer",
+            Stmt::Throw(_) => "throw",
+            Stmt::Try(_) => "try",
+            Stmt::Assert(_) => "assert",
+        }
     }
-    fn accept_resolve(&mut self, v: &mut dyn MutVisitor<()>) {
-        v.visit_expr(self)
-    }
-}
-
-impl Stmt for Variable {
-    fn accept_exec(&self, v: &mut dyn Visitor<ExecResult>) -> ExecResult {
-        v.visit_variable(self)
-    }
-    fn accept_resolve(&mut self, v: &mut dyn MutVisitor<()>) {
-        v.visit_variable(self)
-    }
-}
 
-impl Stmt for Block {
-    fn accept_exec(&self, v: &mut dyn Visitor<ExecResult>) -> ExecResult {
-        v.visit_block(self)
+    pub fn position(&self) -> Option<&Token> {
+        match self {
+            Stmt::Print(s) => Some(&s.keyword),
+            Stmt::Variable(s) => Some(&s.name),
+            Stmt::If(s) => Some(&s.keyword),
+            Stmt::While(s) => Some(&s.keyword),
+            Stmt::ForIn(s) => Some(&s.keyword),
+            Stmt::Break(s) => Some(&s.keyword),
+            Stmt::Return(s) => Some(&s.keyword),
+            Stmt::Function(s) => Some(&s.name),
+            Stmt::Class(s) => Some(&s.name),
+            Stmt::Defer(s) => Some(&s.keyword),
+            Stmt::Throw(s) => Some(&s.keyword),
+            Stmt::Try(s) => Some(&s.keyword),
+            Stmt::Assert(s) => Some(&s.keyword),
+            Stmt::Expression(_) | Stmt::Block(_) => None,
+        }
     }
-    fn accept_resolve(&mut self, v: &mut dyn MutVisitor<()>) {
-        v.visit_block(self)
-    }
-}
 
-impl Stmt for If {
-    fn accept_exec(&self, v: &mut dyn Visitor<ExecResult>) -> ExecResult {
-        v.visit_if(self)
+    // whether the statement defines a name in the scope it is in
+    pub fn declares(&self) -> bool {
+        matches!(self, Stmt::Variable(_) | Stmt::Function(_) | Stmt::Class(_))
     }
-    fn accept_resolve(&mut self, v: &mut dyn MutVisitor<()>) {
-        v.visit_if(self)
-    }
-}
 
-impl Stmt for While {
-    fn accept_exec(&self, v: &mut dyn Visitor<ExecResult>) -> ExecResult {
-        v.visit_while(self)
+    pub fn accept<T>(&self, v: &mut dyn Visitor<T>) -> T {
+        match self {
+            Stmt::Expression(s) => v.visit_expr(s),
+            Stmt::Print(s) => v.visit_print(s),
+            Stmt::Variable(s) => v.visit_variable(s),
+            Stmt::Block(s) => v.visit_block(s),
+            Stmt::If(s) => v.visit_if(s),
+            Stmt::While(s) => v.visit_while(s),
+            Stmt::ForIn(s) => v.visit_for_in(s),
+            Stmt::Break(s) => v.visit_break(s),
+            Stmt::Return(s) => v.visit_return(s),
+            Stmt::Function(s) => v.visit_function(s),
+            Stmt::Class(s) => v.visit_class(s),
+            Stmt::Defer(s) => v.visit_defer(s),
+            Stmt::Throw(s) => v.visit_throw(s),
+            Stmt::Try(s) => v.visit_try(s),
+            Stmt::Assert(s) => v.visit_assert(s),
+        }
     }
-    fn accept_resolve(&mut self, v: &mut dyn MutVisitor<()>) {
-        v.visit_while(self)
-    }
-}
 
-impl Stmt for Function {
-    fn accept_exec(&self, v: &mut dyn Visitor<ExecResult>) -> ExecResult {
-        v.visit_function(self)
-    }
-    fn accept_resolve(&mut self, v: &mut dyn MutVisitor<()>) {
-        v.visit_function(self)
+    pub fn accept_mut<T>(&mut self, v: &mut dyn MutVisitor<T>) -> T {
+        match self {
+            Stmt::Expression(s) => v.visit_expr(s),
+            Stmt::Print(s) => v.visit_print(s),
+            Stmt::Variable(s) => v.visit_variable(s),
+            Stmt::Block(s) => v.visit_block(s),
+            Stmt::If(s) => v.visit_if(s),
+            Stmt::While(s) => v.visit_while(s),
+            Stmt::ForIn(s) => v.visit_for_in(s),
+            Stmt::Break(s) => v.visit_break(s),
+            Stmt::Return(s) => v.visit_return(s),
+            Stmt::Function(s) => v.visit_function(s),
+            Stmt::Class(s) => v.visit_class(s),
+            Stmt::Defer(s) => v.visit_defer(s),
+            Stmt::Throw(s) => v.visit_throw(s),
+            Stmt::Try(s) => v.visit_try(s),
+            Stmt::Assert(s) => v.visit_assert(s),
+        }
     }
 }
 
-impl Stmt for Break {
-    fn accept_exec(&self, v: &mut dyn Visitor<ExecResult>) -> ExecResult {
-        v.visit_break(self)
-    }
-    fn accept_resolve(&mut self, v: &mut dyn MutVisitor<()>) {
-        v.visit_break(self)
-    }
+#[derive(Clone)]
+pub enum StmtEffect {
+    Return(RuntimeValue),
+    // carries the 'break' keyword
+    Break(Rc<Token>),
+    // a call in tail position, left for the
+    // calling function to execute in its own frame
+    TailCall(TailCall),
+    // a thrown value along with the 'throw' keyword,
+    // unwinding until a 'try' catches it
+    Throw(RuntimeValue, Rc<Token>),
 }
 
-impl Stmt for Return {
-    fn accept_exec(&self, v: &mut dyn Visitor<ExecResult>) -> ExecResult {
-        v.visit_return(self)
-    }
-    fn accept_resolve(&mut self, v: &mut dyn MutVisitor<()>) {
-        v.visit_return(self)
-    }
+#[derive(Clone)]
+pub struct TailCall {
+    pub callee: CallableWrapper,
+    pub args: Vec<RuntimeValue>,
+    pub call_site: Rc<Token>,
 }
-
-impl Stmt for Class {
-    fn accept_exec(&self, v: &mut dyn Visitor<ExecResult>) -> ExecResult {
-        v.visit_class(self)
-    }
-    fn accept_resolve(&mut self, v: &mut dyn MutVisitor<()>) {
-        v.visit_class(self)
-    }
-}
\ No newline at end of file
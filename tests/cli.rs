@@ -0,0 +1,57 @@
+use std::{
+    io::Write,
+    process::{Command, Output, Stdio},
+};
+
+fn rlox(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .args(args)
+        .output()
+        .expect("failed to run rlox")
+}
+
+fn repl(input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("failed to run rlox");
+    child.stdin.take().unwrap().write_all(input.as_bytes()).expect("failed to write the input");
+    child.wait_with_output().expect("failed to run rlox")
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn code_given_with_e_is_run() {
+    let output = rlox(&["-e", "print 1 + 2;"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "3\n");
+}
+
+#[test]
+fn code_given_with_several_e_is_run_in_order_and_bare_expressions_are_echoed() {
+    let output = rlox(&["-e", "var a = \"lox\";", "-e", "a + \"!\""]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "\"lox!\"\n");
+}
+
+#[test]
+fn code_given_with_e_exits_like_scripts_on_errors() {
+    let output = rlox(&["-e", "var = 1;"]);
+    assert_eq!(output.status.code(), Some(65));
+    assert_eq!(stdout(&output), "Parse error at line 1, column 5: Expected Identifier, found Equal.\n");
+
+    let output = rlox(&["-e", "print 1;", "-e", "nil.field;"]);
+    assert_eq!(output.status.code(), Some(70));
+    assert_eq!(stdout(&output), "1\nRuntime error at line 2, column 5: Only instances have properties.\n");
+}
+
+#[test]
+fn the_repl_echoes_bare_expressions() {
+    let output = repl("1 + 2\n_ * 2;\nvar a = 1\n:q\n");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "3\n6\n");
+}